@@ -0,0 +1,129 @@
+//! Distance queries on a 13 824 atom system through the spatial index, and
+//! as they went before it, comparing every pair of atoms or checking every
+//! atom. Run with `cargo bench -p lme-core`.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use lme_core::{
+    elements,
+    entity::{Atom, Molecule},
+    graph,
+    selection::Region,
+    spatial::SpatialIndex,
+};
+use nalgebra::Point3;
+use pair::Pair;
+
+/// Atoms per edge of the simple cubic lattice benchmarked
+const EDGE: usize = 24;
+const SPACING: f64 = 1.5;
+const RUNS: u32 = 5;
+
+/// A lattice of alternating carbons and hydrogens, shaken a little so
+/// distances differ.
+fn lattice() -> Molecule {
+    let mut state = 0x2545_f491_u64;
+    let mut jitter = move || {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((state >> 33) as f64 / (1u64 << 31) as f64 - 0.5) * 0.2
+    };
+    let mut atoms = HashMap::new();
+    for x in 0..EDGE {
+        for y in 0..EDGE {
+            for z in 0..EDGE {
+                let element = if (x + y + z) % 2 == 0 { 6 } else { 1 };
+                let position = Point3::new(
+                    x as f64 * SPACING + jitter(),
+                    y as f64 * SPACING + jitter(),
+                    z as f64 * SPACING + jitter(),
+                );
+                atoms.insert(atoms.len(), Some(Atom::new(element, position)));
+            }
+        }
+    }
+    Molecule::default().set_atoms(atoms)
+}
+
+fn all_pairs_bonds(molecule: &Molecule, tolerance: f64) -> Vec<Pair<usize>> {
+    let atoms = molecule.atoms();
+    let mut bonds = vec![];
+    for (nth, (a, first)) in atoms.iter().enumerate() {
+        for (b, second) in &atoms[nth + 1..] {
+            let limit = elements::covalent_radius(first.element())
+                + elements::covalent_radius(second.element())
+                + tolerance;
+            if (first.position() - second.position()).norm() < limit {
+                bonds.push(Pair::new_ordered(*a, *b));
+            }
+        }
+    }
+    bonds
+}
+
+fn all_pairs_overlaps(molecule: &Molecule, overlap: f64) -> Vec<Pair<usize>> {
+    let atoms = molecule.atoms();
+    let mut overlaps = vec![];
+    for (nth, (a, first)) in atoms.iter().enumerate() {
+        for (b, second) in &atoms[nth + 1..] {
+            if (first.position() - second.position()).norm() < overlap {
+                overlaps.push(Pair::new_ordered(*a, *b));
+            }
+        }
+    }
+    overlaps
+}
+
+/// The result of `run` and its fastest time of `RUNS`.
+fn time<T>(mut run: impl FnMut() -> T) -> (T, Duration) {
+    let mut fastest = Duration::MAX;
+    let mut result = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        result = Some(run());
+        fastest = fastest.min(start.elapsed());
+    }
+    (result.expect("Run at least once"), fastest)
+}
+
+fn report(query: &str, unindexed: Duration, indexed: Duration) {
+    let speedup = unindexed.as_secs_f64() / indexed.as_secs_f64();
+    println!("{query:<16} {unindexed:>12.3?} {indexed:>12.3?} {speedup:>9.1}x");
+}
+
+fn main() {
+    let molecule = lattice();
+    println!("{} atoms, fastest of {RUNS} runs", molecule.atoms().len());
+    let (index, build) = time(|| SpatialIndex::new(&molecule));
+    println!("index built in {build:.3?}");
+    println!("{:<16} {:>12} {:>12} {:>10}", "query", "unindexed", "indexed", "speedup");
+
+    let tolerance = graph::DEFAULT_BOND_TOLERANCE;
+    let (expected, unindexed) = time(|| all_pairs_bonds(&molecule, tolerance));
+    let (bonds, indexed) = time(|| graph::perceive_bonds(&molecule, &index, tolerance));
+    assert_eq!(bonds, expected);
+    report("perceive bonds", unindexed, indexed);
+
+    // closer than the lattice spacing, so that there are overlaps to find
+    let overlap = 1.45;
+    let (expected, unindexed) = time(|| all_pairs_overlaps(&molecule, overlap));
+    let (validation, indexed) = time(|| molecule.validate(&index, overlap));
+    let found = validation.overlaps.iter().map(|overlap| overlap.atoms);
+    assert_eq!(found.collect::<Vec<_>>(), expected);
+    report("overlaps", unindexed, indexed);
+
+    let sphere = Region::Sphere {
+        center: Point3::new(10., 10., 10.),
+        radius: 5.,
+    };
+    let (expected, unindexed) = time(|| {
+        let atoms = molecule.atoms().into_iter();
+        let inside = atoms.filter(|(_, atom)| sphere.contains(&atom.position()));
+        inside.map(|(idx, _)| idx).collect()
+    });
+    let (inside, indexed) = time(|| index.atoms_in(&sphere));
+    assert_eq!(inside, expected);
+    report("sphere", unindexed, indexed);
+}
@@ -0,0 +1,120 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::entity::Stack;
+
+/// The last value computed from a workspace, keyed by the workspace
+/// revision and the request parameters.
+///
+/// A cache is not part of the workspace value: clones start empty and
+/// comparisons ignore it. It fills behind a shared reference, so readers of
+/// the workspace can use it.
+pub(crate) struct RevisionCache<K, V> {
+    entry: Mutex<Option<(u64, K, Arc<V>)>>,
+}
+
+impl<K: PartialEq, V> RevisionCache<K, V> {
+    pub fn get(&self, revision: u64, key: &K) -> Option<Arc<V>> {
+        let entry = self.entry.lock().unwrap_or_else(PoisonError::into_inner);
+        entry
+            .as_ref()
+            .filter(|(cached_revision, cached_key, _)| {
+                *cached_revision == revision && cached_key == key
+            })
+            .map(|(_, _, value)| value.clone())
+    }
+
+    pub fn insert(&self, revision: u64, key: K, value: V) -> Arc<V> {
+        let value = Arc::new(value);
+        let mut entry = self.entry.lock().unwrap_or_else(PoisonError::into_inner);
+        *entry = Some((revision, key, value.clone()));
+        value
+    }
+}
+
+impl<K, V> Default for RevisionCache<K, V> {
+    fn default() -> Self {
+        Self {
+            entry: Mutex::default(),
+        }
+    }
+}
+
+impl<K, V> Clone for RevisionCache<K, V> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl<K, V> PartialEq for RevisionCache<K, V> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl<K, V> fmt::Debug for RevisionCache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RevisionCache")
+    }
+}
+
+/// Values computed from stacks, such as the molecules read from them,
+/// keyed by the stack itself. Stacks are never changed in place, an edit
+/// puts a new `Arc` in the workspace, so a value stays valid for as long as
+/// the same `Arc` is in use and the entry holding it keeps its address from
+/// being reused.
+///
+/// Like `RevisionCache`, it is not part of the workspace value.
+pub(crate) struct StackCache<V> {
+    entries: Mutex<Vec<(Arc<Stack>, Arc<V>)>>,
+}
+
+impl<V> StackCache<V> {
+    pub fn get(&self, stack: &Arc<Stack>) -> Option<Arc<V>> {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries
+            .iter()
+            .find(|(cached, _)| Arc::ptr_eq(cached, stack))
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Cache `value` as computed from `stack`, dropping the entries of
+    /// stacks no longer among `live`.
+    pub fn insert(&self, stack: &Arc<Stack>, value: V, live: &[Arc<Stack>]) -> Arc<V> {
+        let value = Arc::new(value);
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.retain(|(cached, _)| {
+            !Arc::ptr_eq(cached, stack) && live.iter().any(|live| Arc::ptr_eq(cached, live))
+        });
+        entries.push((stack.clone(), value.clone()));
+        value
+    }
+}
+
+impl<V> Default for StackCache<V> {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::default(),
+        }
+    }
+}
+
+impl<V> Clone for StackCache<V> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl<V> PartialEq for StackCache<V> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl<V> fmt::Debug for StackCache<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StackCache")
+    }
+}
@@ -0,0 +1,189 @@
+use nalgebra::{Matrix3, Point3, Transform3, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::error::LMECoreError;
+
+/// Periodic unit cell given by its lattice vectors a, b and c (Cartesian,
+/// same units as the atom positions), and whether the structure repeats
+/// along each of them, as a slab does not along its normal.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(from = "CellForm")]
+pub struct Cell {
+    #[cfg_attr(feature = "openapi", schema(value_type = [[f64; 3]; 3]))]
+    vectors: [Vector3<f64>; 3],
+    periodic: [bool; 3],
+}
+
+/// A cell as it may be read: its lattice vectors alone, periodic along all
+/// of them as cells used to be written, or with its periodic flags.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CellForm {
+    Vectors([Vector3<f64>; 3]),
+    Flagged {
+        vectors: [Vector3<f64>; 3],
+        #[serde(default = "periodic_everywhere")]
+        periodic: [bool; 3],
+    },
+}
+
+fn periodic_everywhere() -> [bool; 3] {
+    [true; 3]
+}
+
+impl From<CellForm> for Cell {
+    fn from(form: CellForm) -> Self {
+        match form {
+            CellForm::Vectors(vectors) => Self {
+                vectors,
+                periodic: periodic_everywhere(),
+            },
+            CellForm::Flagged { vectors, periodic } => Self { vectors, periodic },
+        }
+    }
+}
+
+impl Cell {
+    /// A cell periodic along all three lattice vectors.
+    pub fn new(a: Vector3<f64>, b: Vector3<f64>, c: Vector3<f64>) -> Self {
+        Self {
+            vectors: [a, b, c],
+            periodic: periodic_everywhere(),
+        }
+    }
+
+    /// The cell of lengths `[a, b, c]` and angles `[alpha, beta, gamma]`
+    /// (degrees), with a along x and b in the xy plane. Fails with
+    /// `SingularCell` if the angles leave no room for c.
+    pub fn from_parameters(
+        [a, b, c]: [f64; 3],
+        [alpha, beta, gamma]: [f64; 3],
+    ) -> Result<Self, LMECoreError> {
+        let [alpha, beta, gamma] = [alpha, beta, gamma].map(f64::to_radians);
+        let cx = beta.cos();
+        let cy = (alpha.cos() - beta.cos() * gamma.cos()) / gamma.sin();
+        let squared = 1. - cx * cx - cy * cy;
+        if !squared.is_finite() || squared <= 0. {
+            return Err(LMECoreError::SingularCell);
+        }
+        let cz = squared.sqrt();
+        Ok(Self::new(
+            Vector3::new(a, 0., 0.),
+            Vector3::new(b * gamma.cos(), b * gamma.sin(), 0.),
+            Vector3::new(cx, cy, cz) * c,
+        ))
+    }
+
+    pub fn with_periodic(self, periodic: [bool; 3]) -> Self {
+        Self { periodic, ..self }
+    }
+
+    pub fn vectors(&self) -> &[Vector3<f64>; 3] {
+        &self.vectors
+    }
+
+    /// Whether the structure repeats along a, b and c.
+    pub fn periodic(&self) -> [bool; 3] {
+        self.periodic
+    }
+
+    /// Lattice matrix with the lattice vectors as columns.
+    pub fn matrix(&self) -> Matrix3<f64> {
+        Matrix3::from_columns(&self.vectors)
+    }
+
+    /// Cell lengths `[a, b, c]` and angles `[alpha, beta, gamma]` (degrees).
+    pub fn parameters(&self) -> ([f64; 3], [f64; 3]) {
+        let [a, b, c] = &self.vectors;
+        (
+            [a.norm(), b.norm(), c.norm()],
+            [
+                b.angle(c).to_degrees(),
+                a.angle(c).to_degrees(),
+                a.angle(b).to_degrees(),
+            ],
+        )
+    }
+
+    /// The cell of a supercell repeating this one `repeats` times along
+    /// each lattice vector.
+    pub fn repeat(&self, repeats: [usize; 3]) -> Self {
+        let mut vectors = self.vectors;
+        for (vector, times) in vectors.iter_mut().zip(repeats) {
+            *vector *= times as f64;
+        }
+        Self { vectors, ..*self }
+    }
+
+    pub fn to_cartesian(&self, fractional: &Point3<f64>) -> Point3<f64> {
+        Point3::from(self.matrix() * fractional.coords)
+    }
+
+    pub fn to_fractional(&self, cartesian: &Point3<f64>) -> Result<Point3<f64>, LMECoreError> {
+        let inverse = self
+            .matrix()
+            .try_inverse()
+            .ok_or(LMECoreError::SingularCell)?;
+        Ok(Point3::from(inverse * cartesian.coords))
+    }
+
+    /// The cell with its lattice vectors moved by the linear part of `transform`.
+    pub fn transform(&self, transform: &Transform3<f64>) -> Self {
+        let origin = transform * Point3::origin();
+        let vectors = self
+            .vectors
+            .map(|vector| transform * Point3::from(vector) - origin);
+        Self { vectors, ..*self }
+    }
+}
+
+mod test {
+    #[test]
+    fn fractional_round_trip() {
+        use crate::{cell::Cell, error::LMECoreError};
+        use nalgebra::{Point3, Vector3};
+
+        let cell = Cell::new(
+            Vector3::new(5., 0., 0.),
+            Vector3::new(1., 4., 0.),
+            Vector3::new(0.5, 0.5, 6.),
+        );
+        let fractional = Point3::new(0.25, 0.5, 0.75);
+        let cartesian = cell.to_cartesian(&fractional);
+        assert!((cartesian - Point3::new(2.125, 2.375, 4.5)).norm() < 1e-12);
+        assert!((cell.to_fractional(&cartesian).unwrap() - fractional).norm() < 1e-12);
+
+        let flat = Cell::new(Vector3::x(), Vector3::y(), Vector3::x() + Vector3::y());
+        assert!(matches!(
+            flat.to_fractional(&cartesian),
+            Err(LMECoreError::SingularCell)
+        ));
+    }
+
+    #[test]
+    fn cells_are_built_from_their_parameters() {
+        use crate::{cell::Cell, error::LMECoreError};
+        use nalgebra::Vector3;
+
+        let cell = Cell::from_parameters([3., 4., 5.], [80., 95., 110.]).unwrap();
+        let (lengths, angles) = cell.parameters();
+        for (value, expected) in lengths.into_iter().zip([3., 4., 5.]) {
+            assert!((value - expected).abs() < 1e-12);
+        }
+        for (value, expected) in angles.into_iter().zip([80., 95., 110.]) {
+            assert!((value - expected).abs() < 1e-9);
+        }
+        assert!(matches!(
+            Cell::from_parameters([1., 1., 1.], [90., 90., 180.]),
+            Err(LMECoreError::SingularCell)
+        ));
+
+        let old: Cell = serde_json::from_str("[[2,0,0],[0,2,0],[0,0,2]]").unwrap();
+        assert_eq!(old.periodic(), [true; 3]);
+        let slab = old.repeat([2, 3, 1]).with_periodic([true, true, false]);
+        assert_eq!(slab.vectors()[1], Vector3::new(0., 6., 0.));
+        let written = serde_json::to_string(&slab).unwrap();
+        assert_eq!(serde_json::from_str::<Cell>(&written).unwrap(), slab);
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, HashMap};
+
+use pair::Pair;
+use serde::Serialize;
+
+use crate::entity::{Atom, Molecule};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AtomChange {
+    pub from: Atom,
+    pub to: Atom,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BondChange {
+    #[cfg_attr(feature = "openapi", schema(value_type = [usize; 2]))]
+    pub pair: Pair<usize>,
+    pub from: f64,
+    pub to: f64,
+}
+
+/// Differences between two molecules, keyed by atom index. Only existing
+/// atoms are compared, and only bonds between them. Bonds are sorted by pair.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MoleculeDiff {
+    pub added_atoms: BTreeMap<usize, Atom>,
+    pub removed_atoms: Vec<usize>,
+    /// Atoms present in both molecules with another element or residue, or
+    /// moved further than the tolerance
+    pub changed_atoms: BTreeMap<usize, AtomChange>,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<([usize; 2], f64)>))]
+    pub added_bonds: Vec<(Pair<usize>, f64)>,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<[usize; 2]>))]
+    pub removed_bonds: Vec<Pair<usize>>,
+    /// Bonds present in both molecules with another bond order
+    pub changed_bonds: Vec<BondChange>,
+}
+
+impl MoleculeDiff {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+fn live_bonds(molecule: &Molecule) -> HashMap<Pair<usize>, f64> {
+    molecule
+        .bonds()
+        .iter()
+        .filter(|(pair, _)| {
+            let (a, b) = (**pair).into();
+            molecule.atom(a).is_some() && molecule.atom(b).is_some()
+        })
+        .map(|(pair, order)| (*pair, *order))
+        .collect()
+}
+
+fn same_atom(a: &Atom, b: &Atom, tolerance: f64) -> bool {
+    a.element() == b.element()
+        && a.residue() == b.residue()
+        && nalgebra::distance(&a.position(), &b.position()) <= tolerance
+}
+
+impl Molecule {
+    /// What has to change to turn `self` into `other`. Atoms moved by no
+    /// more than `tolerance` count as unchanged.
+    pub fn diff(&self, other: &Molecule, tolerance: f64) -> MoleculeDiff {
+        let before = self.atoms().into_iter().collect::<BTreeMap<_, _>>();
+        let after = other.atoms().into_iter().collect::<BTreeMap<_, _>>();
+        let mut diff = MoleculeDiff::default();
+        for (idx, atom) in &after {
+            match before.get(idx) {
+                None => {
+                    diff.added_atoms.insert(*idx, *atom);
+                }
+                Some(previous) if !same_atom(previous, atom, tolerance) => {
+                    let change = AtomChange {
+                        from: *previous,
+                        to: *atom,
+                    };
+                    diff.changed_atoms.insert(*idx, change);
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed_atoms = before
+            .keys()
+            .filter(|idx| !after.contains_key(idx))
+            .copied()
+            .collect();
+
+        let (before, after) = (live_bonds(self), live_bonds(other));
+        for (pair, order) in &after {
+            match before.get(pair) {
+                None => diff.added_bonds.push((*pair, *order)),
+                Some(previous) if previous != order => diff.changed_bonds.push(BondChange {
+                    pair: *pair,
+                    from: *previous,
+                    to: *order,
+                }),
+                Some(_) => {}
+            }
+        }
+        diff.removed_bonds = before
+            .keys()
+            .filter(|pair| !after.contains_key(pair))
+            .copied()
+            .collect();
+        diff.added_bonds.sort_by_key(|(pair, _)| *pair);
+        diff.removed_bonds.sort();
+        diff.changed_bonds.sort_by_key(|change| change.pair);
+        diff
+    }
+}
+
+mod test {
+    #[test]
+    fn diff_lists_every_kind_of_change() {
+        use crate::entity::{Atom, Molecule};
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let atom = |element, x| Some(Atom::new(element, Point3::new(x, 0., 0.)));
+        let before = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, atom(6, 0.)),
+                (1, atom(8, 1.2)),
+                (2, atom(1, -1.)),
+            ]))
+            .set_bonds(HashMap::from([
+                (Pair::new_ordered(0, 1), 2.),
+                (Pair::new_ordered(0, 2), 1.),
+            ]));
+        assert!(before.diff(&before, 0.).is_empty());
+
+        let after = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, atom(6, 0.)),
+                (1, atom(16, 1.6)),
+                (3, atom(1, 1.)),
+            ]))
+            .set_bonds(HashMap::from([
+                (Pair::new_ordered(0, 1), 1.),
+                (Pair::new_ordered(0, 3), 1.),
+            ]));
+        let diff = before.diff(&after, 0.);
+        assert_eq!(diff.added_atoms.keys().collect::<Vec<_>>(), vec![&3]);
+        assert_eq!(diff.removed_atoms, vec![2]);
+        assert_eq!(diff.changed_atoms[&1].to.element(), 16);
+        assert_eq!(diff.added_bonds, vec![(Pair::new_ordered(0, 3), 1.)]);
+        assert_eq!(diff.removed_bonds, vec![Pair::new_ordered(0, 2)]);
+        assert_eq!(diff.changed_bonds.len(), 1);
+        assert_eq!(
+            (diff.changed_bonds[0].from, diff.changed_bonds[0].to),
+            (2., 1.)
+        );
+
+        let nudged = before.clone().set_atoms(HashMap::from([
+            (0, atom(6, 0.)),
+            (1, atom(8, 1.25)),
+            (2, atom(1, -1.)),
+        ]));
+        assert_eq!(before.diff(&nudged, 0.1), Default::default());
+        assert_eq!(before.diff(&nudged, 0.01).changed_atoms.len(), 1);
+    }
+}
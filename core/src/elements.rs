@@ -0,0 +1,145 @@
+/// Element symbols indexed by atomic number - 1.
+const SYMBOLS: [&str; 118] = [
+    "H", "He", "Li", "Be", "B", "C", "N", "O", "F", "Ne",
+    "Na", "Mg", "Al", "Si", "P", "S", "Cl", "Ar", "K", "Ca",
+    "Sc", "Ti", "V", "Cr", "Mn", "Fe", "Co", "Ni", "Cu", "Zn",
+    "Ga", "Ge", "As", "Se", "Br", "Kr", "Rb", "Sr", "Y", "Zr",
+    "Nb", "Mo", "Tc", "Ru", "Rh", "Pd", "Ag", "Cd", "In", "Sn",
+    "Sb", "Te", "I", "Xe", "Cs", "Ba", "La", "Ce", "Pr", "Nd",
+    "Pm", "Sm", "Eu", "Gd", "Tb", "Dy", "Ho", "Er", "Tm", "Yb",
+    "Lu", "Hf", "Ta", "W", "Re", "Os", "Ir", "Pt", "Au", "Hg",
+    "Tl", "Pb", "Bi", "Po", "At", "Rn", "Fr", "Ra", "Ac", "Th",
+    "Pa", "U", "Np", "Pu", "Am", "Cm", "Bk", "Cf", "Es", "Fm",
+    "Md", "No", "Lr", "Rf", "Db", "Sg", "Bh", "Hs", "Mt", "Ds",
+    "Rg", "Cn", "Nh", "Fl", "Mc", "Lv", "Ts", "Og",
+];
+
+/// Single-bond covalent radii in Å (Cordero et al., 2008) indexed by atomic number - 1.
+const COVALENT_RADII: [f64; 96] = [
+    0.31, 0.28, 1.28, 0.96, 0.84, 0.76, 0.71, 0.66, 0.57, 0.58,
+    1.66, 1.41, 1.21, 1.11, 1.07, 1.05, 1.02, 1.06, 2.03, 1.76,
+    1.70, 1.60, 1.53, 1.39, 1.39, 1.32, 1.26, 1.24, 1.32, 1.22,
+    1.22, 1.20, 1.19, 1.20, 1.20, 1.16, 2.20, 1.95, 1.90, 1.75,
+    1.64, 1.54, 1.47, 1.46, 1.42, 1.39, 1.45, 1.44, 1.42, 1.39,
+    1.39, 1.38, 1.39, 1.40, 2.44, 2.15, 2.07, 2.04, 2.03, 2.01,
+    1.99, 1.98, 1.98, 1.96, 1.94, 1.92, 1.92, 1.89, 1.90, 1.87,
+    1.87, 1.75, 1.70, 1.62, 1.51, 1.44, 1.41, 1.36, 1.36, 1.32,
+    1.45, 1.46, 1.48, 1.40, 1.50, 1.50, 2.60, 2.21, 2.15, 2.06,
+    2.00, 1.96, 1.90, 1.87, 1.80, 1.69,
+];
+
+/// Standard atomic weights in g/mol indexed by atomic number - 1; elements
+/// without stable isotopes use the mass number of their longest-lived isotope.
+const ATOMIC_MASSES: [f64; 118] = [
+    1.008, 4.0026, 6.94, 9.0122, 10.81, 12.011, 14.007, 15.999, 18.998, 20.180,
+    22.990, 24.305, 26.982, 28.085, 30.974, 32.06, 35.45, 39.948, 39.098, 40.078,
+    44.956, 47.867, 50.942, 51.996, 54.938, 55.845, 58.933, 58.693, 63.546, 65.38,
+    69.723, 72.630, 74.922, 78.971, 79.904, 83.798, 85.468, 87.62, 88.906, 91.224,
+    92.906, 95.95, 98., 101.07, 102.91, 106.42, 107.87, 112.41, 114.82, 118.71,
+    121.76, 127.60, 126.90, 131.29, 132.91, 137.33, 138.91, 140.12, 140.91, 144.24,
+    145., 150.36, 151.96, 157.25, 158.93, 162.50, 164.93, 167.26, 168.93, 173.05,
+    174.97, 178.49, 180.95, 183.84, 186.21, 190.23, 192.22, 195.08, 196.97, 200.59,
+    204.38, 207.2, 208.98, 209., 210., 222., 223., 226., 227., 232.04,
+    231.04, 238.03, 237., 244., 243., 247., 247., 251., 252., 257.,
+    258., 259., 262., 267., 268., 269., 270., 269., 278., 281.,
+    282., 285., 286., 289., 290., 293., 294., 294.,
+];
+
+/// Masses in u of the most abundant isotope of hydrogen to krypton,
+/// indexed by atomic number - 1.
+const MONOISOTOPIC_MASSES: [f64; 36] = [
+    1.007825, 4.002603, 7.016003, 9.012183, 11.009305, 12., 14.003074, 15.994915, 18.998403,
+    19.992440, 22.989769, 23.985042, 26.981538, 27.976927, 30.973762, 31.972071, 34.968853,
+    39.962383, 38.963706, 39.962591, 44.955908, 47.947942, 50.943957, 51.940506, 54.938044,
+    55.934936, 58.933194, 57.935342, 62.929598, 63.929142, 68.925574, 73.921178, 74.921595,
+    79.916522, 78.918338, 83.911498,
+];
+
+/// Masses in u of the most abundant isotope of heavier elements common in
+/// molecules, by atomic number.
+const HEAVY_MONOISOTOPIC_MASSES: [(usize, f64); 17] = [
+    (42, 97.905405), (44, 101.904344), (45, 102.905498), (46, 105.903480), (47, 106.905092),
+    (48, 113.903365), (50, 119.902202), (53, 126.904473), (54, 131.904155), (74, 183.950933),
+    (76, 191.961477), (77, 192.962924), (78, 194.964792), (79, 196.966569), (80, 201.970643),
+    (82, 207.976653), (83, 208.980399),
+];
+
+/// Radius used for elements without a tabulated covalent radius.
+const DEFAULT_COVALENT_RADIUS: f64 = 1.5;
+
+pub fn symbol(element: usize) -> Option<&'static str> {
+    element.checked_sub(1).and_then(|idx| SYMBOLS.get(idx)).copied()
+}
+
+/// Atomic number of an element symbol, ignoring case.
+pub fn number(symbol: &str) -> Option<usize> {
+    SYMBOLS
+        .iter()
+        .position(|candidate| candidate.eq_ignore_ascii_case(symbol))
+        .map(|idx| idx + 1)
+}
+
+pub fn covalent_radius(element: usize) -> f64 {
+    element
+        .checked_sub(1)
+        .and_then(|idx| COVALENT_RADII.get(idx))
+        .copied()
+        .unwrap_or(DEFAULT_COVALENT_RADIUS)
+}
+
+pub fn atomic_mass(element: usize) -> Option<f64> {
+    element
+        .checked_sub(1)
+        .and_then(|idx| ATOMIC_MASSES.get(idx))
+        .copied()
+}
+
+/// Mass in u of the most abundant isotope, `None` for elements not
+/// tabulated: hydrogen to krypton and common heavier elements are.
+pub fn monoisotopic_mass(element: usize) -> Option<f64> {
+    element
+        .checked_sub(1)
+        .and_then(|idx| MONOISOTOPIC_MASSES.get(idx))
+        .or_else(|| {
+            HEAVY_MONOISOTOPIC_MASSES
+                .iter()
+                .find(|(number, _)| *number == element)
+                .map(|(_, mass)| mass)
+        })
+        .copied()
+}
+
+/// Standard valence of common main-group elements.
+pub fn valence(element: usize) -> Option<usize> {
+    match element {
+        1 | 9 | 17 | 35 | 53 => Some(1),
+        8 | 16 | 34 => Some(2),
+        5 | 7 | 15 => Some(3),
+        6 | 14 => Some(4),
+        _ => None,
+    }
+}
+
+/// Highest bond order sum an atom of the element can reasonably have,
+/// allowing for charged and hypervalent forms, `None` if not tabulated.
+pub fn max_valence(element: usize) -> Option<usize> {
+    match element {
+        1 | 9 => Some(1),
+        8 => Some(3),
+        5 | 6 | 7 | 14 => Some(4),
+        15 | 33 => Some(5),
+        16 | 34 => Some(6),
+        17 | 35 | 53 => Some(7),
+        _ => None,
+    }
+}
+
+/// Lone pairs left on an atom bonded at its standard valence.
+pub fn lone_pairs(element: usize) -> usize {
+    match element {
+        7 | 15 => 1,
+        8 | 16 | 34 => 2,
+        9 | 17 | 35 | 53 => 3,
+        _ => 0,
+    }
+}
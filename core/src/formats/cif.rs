@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+
+use nalgebra::{Matrix3, Point3, Vector3};
+
+use crate::{
+    cell::Cell,
+    elements,
+    entity::{Atom, Molecule},
+    error::LMECoreError,
+};
+
+/// Images of a site closer than this (Å) are the same atom
+const SAME_SITE: f64 = 1e-2;
+
+enum Token {
+    Data,
+    Loop,
+    Tag(String),
+    Value(String),
+}
+
+/// The tokens of a CIF file with their 1-based line numbers. Text fields
+/// between lines starting with `;` are one value.
+fn tokens(text: &str) -> Result<Vec<(usize, Token)>, LMECoreError> {
+    let mut tokens = vec![];
+    let mut lines = text.lines().enumerate().map(|(idx, line)| (idx + 1, line));
+    while let Some((line_no, line)) = lines.next() {
+        if let Some(first) = line.strip_prefix(';') {
+            let mut field = vec![first];
+            loop {
+                let Some((_, line)) = lines.next() else {
+                    let message = "Text field is not closed".to_string();
+                    return Err(LMECoreError::ParseError(line_no, message));
+                };
+                if line.starts_with(';') {
+                    break;
+                }
+                field.push(line);
+            }
+            tokens.push((line_no, Token::Value(field.join("\n"))));
+            continue;
+        }
+        let mut rest = line.trim_start();
+        while !rest.is_empty() && !rest.starts_with('#') {
+            let quote = rest.chars().next().filter(|c| *c == '\'' || *c == '"');
+            if let Some(quote) = quote {
+                // a quote ends a quoted value only where whitespace follows
+                let ends = |at: &usize| rest[at + 1..].starts_with(char::is_whitespace);
+                let closing = rest[1..]
+                    .match_indices(quote)
+                    .map(|(at, _)| at + 1)
+                    .find(|at| ends(at) || at + 1 == rest.len())
+                    .ok_or_else(|| {
+                        let message = format!("Quoted value {rest:?} is not closed");
+                        LMECoreError::ParseError(line_no, message)
+                    })?;
+                tokens.push((line_no, Token::Value(rest[1..closing].to_string())));
+                rest = rest[closing + 1..].trim_start();
+                continue;
+            }
+            let (word, after) = rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+            let token = if word.eq_ignore_ascii_case("loop_") {
+                Token::Loop
+            } else if word.get(..5).is_some_and(|start| start.eq_ignore_ascii_case("data_")) {
+                Token::Data
+            } else if word.starts_with('_') {
+                Token::Tag(word.to_lowercase())
+            } else {
+                Token::Value(word.to_string())
+            };
+            tokens.push((line_no, token));
+            rest = after.trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+/// Values of a row of a loop, with the line the row starts on
+type Row = (usize, Vec<String>);
+
+/// The items and loops of a data block, tags in lower case.
+#[derive(Default)]
+struct Block {
+    items: HashMap<String, (usize, String)>,
+    /// Tags of each loop, with its rows
+    loops: Vec<(Vec<String>, Vec<Row>)>,
+}
+
+impl Block {
+    /// The first data block of a CIF file.
+    fn read(text: &str) -> Result<Self, LMECoreError> {
+        let mut block = Self::default();
+        let mut tokens = tokens(text)?.into_iter().peekable();
+        let mut started = false;
+        while let Some((line_no, token)) = tokens.next() {
+            match token {
+                Token::Data if started => break,
+                Token::Data => started = true,
+                Token::Tag(tag) => match tokens.next() {
+                    Some((_, Token::Value(value))) => {
+                        block.items.insert(tag, (line_no, value));
+                    }
+                    _ => {
+                        let message = format!("Expected a value of {tag}");
+                        return Err(LMECoreError::ParseError(line_no, message));
+                    }
+                },
+                Token::Loop => {
+                    let mut tags = vec![];
+                    while let Some((_, Token::Tag(_))) = tokens.peek() {
+                        if let Some((_, Token::Tag(tag))) = tokens.next() {
+                            tags.push(tag);
+                        }
+                    }
+                    let mut values = vec![];
+                    while let Some((_, Token::Value(_))) = tokens.peek() {
+                        if let Some((line_no, Token::Value(value))) = tokens.next() {
+                            values.push((line_no, value));
+                        }
+                    }
+                    if tags.is_empty() || values.len() % tags.len() != 0 {
+                        let message = format!(
+                            "Loop of {} tags has {} values",
+                            tags.len(),
+                            values.len()
+                        );
+                        return Err(LMECoreError::ParseError(line_no, message));
+                    }
+                    let rows = values
+                        .chunks(tags.len())
+                        .map(|row| (row[0].0, row.iter().map(|(_, value)| value.clone()).collect()))
+                        .collect();
+                    block.loops.push((tags, rows));
+                }
+                Token::Value(value) => {
+                    let message = format!("Value {value:?} belongs to no tag");
+                    return Err(LMECoreError::ParseError(line_no, message));
+                }
+            }
+        }
+        Ok(block)
+    }
+
+    /// The loop listing `tag`, with the column of each of its tags.
+    fn table(&self, tag: &str) -> Option<(HashMap<&str, usize>, &[Row])> {
+        let (tags, rows) = self.loops.iter().find(|(tags, _)| tags.iter().any(|t| t == tag))?;
+        let columns = tags
+            .iter()
+            .enumerate()
+            .map(|(column, tag)| (tag.as_str(), column))
+            .collect();
+        Some((columns, rows))
+    }
+}
+
+/// A CIF number, without the standard uncertainty in parentheses.
+fn number(value: &str) -> Option<f64> {
+    value.split('(').next()?.parse().ok()
+}
+
+/// The element of a site, by its type symbol or label: `Fe3+` and `Fe2`
+/// are iron, `C12` carbon.
+fn site_element(value: &str) -> Option<usize> {
+    let letters = value
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .map_or(value, |end| &value[..end]);
+    letters
+        .get(..2)
+        .and_then(elements::number)
+        .or_else(|| letters.get(..1).and_then(elements::number))
+}
+
+/// A symmetry operation such as `-x+1/2, y, z-1/2`, as the matrix and
+/// translation acting on fractional coordinates.
+fn symmetry_operation(operation: &str) -> Option<(Matrix3<f64>, Vector3<f64>)> {
+    let parts = operation.split(',').collect::<Vec<_>>();
+    let [_, _, _] = parts[..] else {
+        return None;
+    };
+    let mut matrix = Matrix3::zeros();
+    let mut translation = Vector3::zeros();
+    for (row, part) in parts.into_iter().enumerate() {
+        let part = part.replace(char::is_whitespace, "").to_lowercase();
+        let mut rest = part.as_str();
+        while !rest.is_empty() {
+            let (sign, term) = match rest.strip_prefix('-') {
+                Some(term) => (-1., term),
+                None => (1., rest.strip_prefix('+').unwrap_or(rest)),
+            };
+            let (term, after) = term.split_at(term.find(['+', '-']).unwrap_or(term.len()));
+            match term {
+                "x" => matrix[(row, 0)] += sign,
+                "y" => matrix[(row, 1)] += sign,
+                "z" => matrix[(row, 2)] += sign,
+                term => {
+                    let value = match term.split_once('/') {
+                        Some((numerator, denominator)) => {
+                            numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?
+                        }
+                        None => term.parse().ok()?,
+                    };
+                    translation[row] += sign * value;
+                }
+            }
+            rest = after;
+        }
+    }
+    Some((matrix, translation))
+}
+
+impl Molecule {
+    /// Read the first data block of a CIF file: its cell and the atom
+    /// sites with every image the listed symmetry operations make, wrapped
+    /// into the cell, numbered from 0 site by site. Images of a site that
+    /// coincide are one atom. Sites may be given in fractional or Cartesian
+    /// coordinates; no bonds are read. Errors carry the 1-based line number.
+    pub fn from_cif(text: &str) -> Result<Molecule, LMECoreError> {
+        let block = Block::read(text)?;
+        let parameter = |tag: &str, default: Option<f64>| match block.items.get(tag) {
+            Some((line_no, value)) => number(value).ok_or_else(|| {
+                let message = format!("Invalid {tag} {value:?}");
+                LMECoreError::ParseError(*line_no, message)
+            }),
+            None => default.ok_or_else(|| LMECoreError::ParseError(1, format!("No {tag}"))),
+        };
+        let [a, b, c] =
+            ["a", "b", "c"].map(|axis| parameter(&format!("_cell_length_{axis}"), None));
+        let [alpha, beta, gamma] = ["alpha", "beta", "gamma"]
+            .map(|angle| parameter(&format!("_cell_angle_{angle}"), Some(90.)));
+        let cell = Cell::from_parameters([a?, b?, c?], [alpha?, beta?, gamma?])?;
+
+        let operation_tags = ["_space_group_symop_operation_xyz", "_symmetry_equiv_pos_as_xyz"];
+        let mut operations = vec![];
+        for tag in operation_tags {
+            let listed = match block.table(tag) {
+                Some((columns, rows)) => rows
+                    .iter()
+                    .map(|(line_no, row)| (*line_no, row[columns[tag]].clone()))
+                    .collect(),
+                None => block.items.get(tag).cloned().into_iter().collect::<Vec<_>>(),
+            };
+            for (line_no, operation) in listed {
+                let parsed = symmetry_operation(&operation).ok_or_else(|| {
+                    let message = format!("Invalid symmetry operation {operation:?}");
+                    LMECoreError::ParseError(line_no, message)
+                })?;
+                operations.push(parsed);
+            }
+            if !operations.is_empty() {
+                break;
+            }
+        }
+        if operations.is_empty() {
+            operations.push((Matrix3::identity(), Vector3::zeros()));
+        }
+
+        let (columns, rows) = block
+            .table("_atom_site_fract_x")
+            .or_else(|| block.table("_atom_site_cartn_x"))
+            .ok_or_else(|| LMECoreError::ParseError(1, "No atom sites".to_string()))?;
+        let fractional = columns.contains_key("_atom_site_fract_x");
+        let element_column = columns
+            .get("_atom_site_type_symbol")
+            .or_else(|| columns.get("_atom_site_label"));
+        let coordinate_columns = match fractional {
+            true => ["_atom_site_fract_x", "_atom_site_fract_y", "_atom_site_fract_z"],
+            false => ["_atom_site_cartn_x", "_atom_site_cartn_y", "_atom_site_cartn_z"],
+        }
+        .map(|tag| columns.get(tag).copied());
+        let mut atoms = HashMap::new();
+        for (line_no, row) in rows {
+            let error = |message: String| LMECoreError::ParseError(*line_no, message);
+            let symbol = element_column.map(|column| row[*column].as_str());
+            let element = symbol
+                .and_then(site_element)
+                .ok_or_else(|| error(format!("Unknown element of site {symbol:?}")))?;
+            let mut position = Point3::origin();
+            for (axis, column) in coordinate_columns.iter().enumerate() {
+                let value = column.map(|column| row[column].as_str());
+                position[axis] = value
+                    .and_then(number)
+                    .ok_or_else(|| error(format!("Invalid coordinate {value:?}")))?;
+            }
+            if !fractional {
+                position = cell.to_fractional(&position)?;
+            }
+            let mut images = Vec::<Point3<f64>>::new();
+            for (matrix, translation) in &operations {
+                let image = (matrix * position + translation).map(|value| value - value.floor());
+                let same = |other: &Point3<f64>| {
+                    let offset = (image - other).map(|value| value - value.round());
+                    (cell.matrix() * offset).norm() < SAME_SITE
+                };
+                if !images.iter().any(same) {
+                    images.push(image);
+                }
+            }
+            for image in images {
+                let atom = Atom::new(element, cell.to_cartesian(&image));
+                atoms.insert(atoms.len(), Some(atom));
+            }
+        }
+        Ok(Molecule::default().set_atoms(atoms).set_cell(Some(cell)))
+    }
+
+
+    /// Minimal CIF of the molecule in data block `block`: cell parameters,
+    /// space group P1 and the atom sites in fractional coordinates, labelled
+    /// by element and a per-element counter. Fails with `NoCell` without a
+    /// unit cell.
+    pub fn to_cif(&self, block: &str) -> Result<String, LMECoreError> {
+        let cell = self.cell().ok_or(LMECoreError::NoCell)?;
+        let ([a, b, c], [alpha, beta, gamma]) = cell.parameters();
+        let mut lines = vec![
+            format!("data_{block}"),
+            format!("_cell_length_a {a:.6}"),
+            format!("_cell_length_b {b:.6}"),
+            format!("_cell_length_c {c:.6}"),
+            format!("_cell_angle_alpha {alpha:.6}"),
+            format!("_cell_angle_beta {beta:.6}"),
+            format!("_cell_angle_gamma {gamma:.6}"),
+            "_symmetry_space_group_name_H-M 'P 1'".to_string(),
+            "_symmetry_Int_Tables_number 1".to_string(),
+            "loop_".to_string(),
+            "_symmetry_equiv_pos_as_xyz".to_string(),
+            "'x, y, z'".to_string(),
+            "loop_".to_string(),
+            "_atom_site_label".to_string(),
+            "_atom_site_type_symbol".to_string(),
+            "_atom_site_fract_x".to_string(),
+            "_atom_site_fract_y".to_string(),
+            "_atom_site_fract_z".to_string(),
+        ];
+        let mut counters = HashMap::<&str, usize>::new();
+        for (_, atom) in self.atoms() {
+            let symbol = elements::symbol(atom.element()).unwrap_or("X");
+            let counter = counters.entry(symbol).or_default();
+            *counter += 1;
+            let position = cell.to_fractional(&atom.position())?;
+            lines.push(format!(
+                "{symbol}{counter} {symbol} {:.6} {:.6} {:.6}",
+                position.x, position.y, position.z
+            ));
+        }
+        Ok(lines.join("\n") + "\n")
+    }
+}
+
+mod test {
+    #[test]
+    fn cif_lists_fractional_sites() {
+        use crate::{
+            cell::Cell,
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+        };
+        use nalgebra::{Point3, Vector3};
+        use std::collections::HashMap;
+
+        let molecule = Molecule::default().set_atoms(HashMap::from([
+            (0, Some(Atom::new(11, Point3::new(0., 0., 0.)))),
+            (3, Some(Atom::new(17, Point3::new(1., 1.7320508075688772, 2.)))),
+        ]));
+        assert!(matches!(molecule.to_cif("nacl"), Err(LMECoreError::NoCell)));
+
+        let hexagonal = Cell::new(
+            Vector3::new(2., 0., 0.),
+            Vector3::new(-1., 1.7320508075688772, 0.),
+            Vector3::new(0., 0., 4.),
+        );
+        let cif = molecule.set_cell(Some(hexagonal)).to_cif("nacl").unwrap();
+        let lines = cif.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "data_nacl");
+        assert!(lines.contains(&"_cell_angle_gamma 120.000000"));
+        assert_eq!(lines[lines.len() - 2], "Na1 Na 0.000000 0.000000 0.000000");
+        assert_eq!(lines[lines.len() - 1], "Cl1 Cl 1.000000 1.000000 0.500000");
+    }
+
+    #[test]
+    fn cif_sites_are_expanded_by_symmetry() {
+        use crate::{entity::Molecule, error::LMECoreError};
+        use nalgebra::Point3;
+
+        let text = "\
+# made by hand
+data_salt
+_cell_length_a 5.6402(3)
+_cell_length_b 5.6402(3)
+_cell_length_c 4.0
+_cell_angle_gamma 90
+_publ_section_title
+;
+A salt, centred
+;
+loop_
+_symmetry_equiv_pos_as_xyz
+'x, y, z'
+'-x, -y, -z'
+'x+1/2, y+1/2, z'
+loop_
+_atom_site_label
+_atom_site_type_symbol
+_atom_site_fract_x
+_atom_site_fract_y
+_atom_site_fract_z
+Na1 Na+ 0 0 0
+Cl1 Cl- 0.25 0.25 0.5
+data_other
+_cell_length_a 1
+";
+        let salt = Molecule::from_cif(text).unwrap();
+        let ([a, _, c], [_, _, gamma]) = salt.cell().unwrap().parameters();
+        assert_eq!((a, c), (5.6402, 4.));
+        assert!((gamma - 90.).abs() < 1e-9);
+        let sites = salt
+            .atoms()
+            .into_iter()
+            .map(|(idx, atom)| (idx, atom.element()))
+            .collect::<Vec<_>>();
+        assert_eq!(sites, vec![(0, 11), (1, 11), (2, 17), (3, 17)]);
+        let position = |idx| salt.atom(idx).unwrap().position();
+        assert!((position(1) - Point3::new(2.8201, 2.8201, 0.)).norm() < 1e-9);
+        assert!((position(3) - Point3::new(4.23015, 4.23015, 2.)).norm() < 1e-9);
+
+        let written = salt.to_cif("salt").unwrap();
+        assert_eq!(Molecule::from_cif(&written).unwrap().atoms().len(), 4);
+        assert!(matches!(
+            Molecule::from_cif("data_x\n_cell_length_a 1\n"),
+            Err(LMECoreError::ParseError(1, _))
+        ));
+        let loose = text.replace("Cl1 Cl- 0.25 0.25 0.5", "Cl1 Cl- 0.25 0.25");
+        assert!(matches!(
+            Molecule::from_cif(&loose),
+            Err(LMECoreError::ParseError(16, _))
+        ));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{
+    elements,
+    entity::{Layer, Mirror},
+    error::LMECoreError,
+    Workspace,
+};
+
+/// `text` as a quoted DOT string.
+fn quoted(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
+
+fn element(element: usize) -> String {
+    elements::symbol(element).map_or_else(|| element.to_string(), str::to_string)
+}
+
+/// Variant name of a layer, looking through annotations, and a few words
+/// on what it does.
+fn describe(layer: &Layer) -> (&'static str, String) {
+    let atoms = |count: usize| match count {
+        1 => "1 atom".to_string(),
+        count => format!("{count} atoms"),
+    };
+    match layer {
+        Layer::Annotated(_, layer) => describe(layer),
+        Layer::Fill(molecule) => (
+            "Fill",
+            format!("{}, {} bonds", atoms(molecule.atoms().len()), molecule.bonds().len()),
+        ),
+        Layer::Transform(transform) => {
+            let shift = transform.matrix().fixed_view::<3, 1>(0, 3);
+            let shift = format!("({:.3}, {:.3}, {:.3})", shift[0], shift[1], shift[2]);
+            ("Transform", format!("moves by {shift}"))
+        }
+        Layer::MoveAtoms(_, moved) => ("MoveAtoms", atoms(moved.len())),
+        Layer::Mirror(mirror, mirrored) => {
+            let by = match mirror {
+                Mirror::Plane { .. } => "plane",
+                Mirror::Inversion(_) => "inversion",
+            };
+            ("Mirror", format!("{by}, {}", atoms(mirrored.len())))
+        }
+        Layer::Replicate(copies) => ("Replicate", format!("{} copies", copies.len())),
+        Layer::IgnoreBonds => ("IgnoreBonds", "drops all bonds".to_string()),
+        Layer::ReplaceElement(from, to) => (
+            "ReplaceElement",
+            format!("{} to {}", element(*from), element(*to)),
+        ),
+        Layer::RemoveElement(removed) => ("RemoveElement", element(*removed)),
+        Layer::Remap(mapping) => ("Remap", atoms(mapping.len())),
+        Layer::PluginFilter(plugin, args) => {
+            let command = std::iter::once(plugin).chain(args).cloned();
+            ("PluginFilter", command.collect::<Vec<_>>().join(" "))
+        }
+    }
+}
+
+impl Workspace {
+    /// The layer tree of all stacks as a Graphviz digraph: the base on top,
+    /// one node per layer shared by the stacks below it, and a box for each
+    /// stack under its top layer. Fails with `EmptyStack` like `tree`.
+    pub fn to_dot(&self) -> Result<String, LMECoreError> {
+        let mut lines = vec![
+            "digraph layers {".to_string(),
+            "    base [label=\"base\", shape=doubleoctagon];".to_string(),
+        ];
+        for node in self.tree()? {
+            let (kind, summary) = describe(&node.layer);
+            let mut label = vec![];
+            if let Some(meta) = node.layer.meta() {
+                label.extend(meta.name.iter().chain(&meta.comment).map(String::as_str));
+            }
+            label.extend([kind, &summary]);
+            let id = node.id;
+            lines.push(format!("    layer{id} [label={}];", quoted(&label.join("\n"))));
+            match node.parent {
+                Some(parent) => lines.push(format!("    layer{parent} -> layer{id};")),
+                None => lines.push(format!("    base -> layer{id};")),
+            }
+            for idx in &node.indexes {
+                let label = quoted(&format!("{idx}: {}", self.stack_names()[*idx]));
+                lines.push(format!("    stack{idx} [label={label}, shape=box];"));
+                lines.push(format!("    layer{id} -> stack{idx} [style=dashed];"));
+            }
+        }
+        lines.push("}".to_string());
+        Ok(lines.join("\n") + "\n")
+    }
+}
+
+mod test {
+    #[test]
+    fn branches_are_drawn_below_their_shared_layers() {
+        use crate::{
+            entity::{Atom, Layer, LayerMeta, Molecule, Stack},
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::{collections::HashMap, sync::Arc};
+
+        let water = Molecule::default().set_atoms(HashMap::from([
+            (0, Some(Atom::new(8, Point3::origin()))),
+            (1, Some(Atom::new(1, Point3::new(0.96, 0., 0.)))),
+        ]));
+        let fill = Arc::new(Layer::Annotated(
+            LayerMeta {
+                name: Some("water \"A\"".to_string()),
+                comment: None,
+            },
+            Box::new(Layer::Fill(Box::new(water))),
+        ));
+        let mut workspace = Workspace::new(Molecule::default());
+        workspace.create_stack(Arc::new(Stack::new(vec![fill.clone()])), 0);
+        for layer in [Layer::ReplaceElement(8, 16), Layer::IgnoreBonds] {
+            let stack = Stack::new(vec![fill.clone(), Arc::new(layer)]);
+            workspace.create_stack(Arc::new(stack), 0);
+        }
+
+        let dot = workspace.to_dot().unwrap();
+        assert!(dot.starts_with("digraph layers {\n"));
+        assert!(dot.contains("layer0 [label=\"water \\\"A\\\"\\nFill\\n2 atoms, 0 bonds\"];"));
+        assert!(dot.contains("base -> layer0;"));
+        assert!(dot.contains("layer1 [label=\"ReplaceElement\\nO to S\"];"));
+        assert!(dot.contains("layer0 -> layer1;\n"));
+        assert!(dot.contains("layer0 -> layer2;\n"));
+        assert!(dot.contains("layer0 -> stack0 [style=dashed];"));
+        assert!(dot.contains("layer2 -> stack2 [style=dashed];"));
+        assert_eq!(dot.matches("shape=box").count(), 3);
+        assert!(dot.ends_with("}\n"));
+
+        workspace.create_stack(Arc::new(Stack::new(vec![])), 0);
+        assert!(workspace.to_dot().is_err());
+    }
+}
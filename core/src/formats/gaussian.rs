@@ -0,0 +1,305 @@
+use std::collections::{BTreeMap, HashMap};
+
+use n_to_n::NtoN;
+use serde::{Deserialize, Serialize};
+
+use crate::{elements, entity::Molecule, error::LMECoreError, ElectronicState};
+
+/// ONIOM layers of a Gaussian job, each given by a group. Atoms in neither
+/// group are in the low layer, and an atom in both is in the high layer.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Oniom {
+    pub high: String,
+    #[serde(default)]
+    pub medium: Option<String>,
+    /// Charge and multiplicity of each system in the order Gaussian reads
+    /// them: 3 for two layers, 6 for three. When empty, the state of the
+    /// whole molecule is used for every system.
+    #[serde(default)]
+    pub states: Vec<ElectronicState>,
+}
+
+/// The parts of a Gaussian input besides the molecule.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GaussianJob {
+    /// Link 0 commands such as `%chk=job.chk`
+    #[serde(default)]
+    pub link0: Vec<String>,
+    /// Route section, starting with `#`. Asking for `geom=connectivity`
+    /// adds the bonds after the molecule specification.
+    pub route: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Charge and multiplicity, in place of the state of the stack
+    #[serde(default)]
+    pub state: Option<ElectronicState>,
+    #[serde(default)]
+    pub oniom: Option<Oniom>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Layer {
+    Low,
+    Medium,
+    High,
+}
+
+impl Layer {
+    fn letter(&self) -> &'static str {
+        match self {
+            Self::Low => "L",
+            Self::Medium => "M",
+            Self::High => "H",
+        }
+    }
+}
+
+fn invalid(message: impl Into<String>) -> LMECoreError {
+    LMECoreError::InvalidGaussianJob(message.into())
+}
+
+impl Molecule {
+    /// Gaussian input for `job`, atoms in index order as Cartesian
+    /// coordinates. `state` is used unless the job gives its own, the
+    /// title defaults to `title`, and ONIOM layers are looked up in
+    /// `groups`. An atom with an `mm_type` property is written with that
+    /// type and its partial charge, if any, and an atom with an isotope
+    /// with `Iso=`. With ONIOM, every atom bonded to an atom of a higher
+    /// layer gets a hydrogen link atom on that atom.
+    ///
+    /// Fails with `InvalidGaussianJob` for a route not starting with `#`,
+    /// Link 0 commands not starting with `%` and a wrong number of ONIOM
+    /// states, and with `NoSuchGroup` for a layer group with no atom here.
+    pub fn to_gaussian(
+        &self,
+        job: &GaussianJob,
+        state: ElectronicState,
+        title: &str,
+        groups: &NtoN<String, usize>,
+    ) -> Result<String, LMECoreError> {
+        let route = job.route.trim();
+        if !route.starts_with('#') {
+            return Err(invalid("Route section must start with #"));
+        }
+        if let Some(command) = job.link0.iter().find(|command| !command.starts_with('%')) {
+            return Err(invalid(format!(
+                "Link 0 command {command:?} must start with %"
+            )));
+        }
+        let state = job.state.unwrap_or(state);
+        let atoms = self.atoms();
+        let serials = atoms
+            .iter()
+            .enumerate()
+            .map(|(serial, (idx, _))| (*idx, serial + 1))
+            .collect::<HashMap<_, _>>();
+
+        let mut layers = HashMap::new();
+        let mut states = vec![state];
+        if let Some(oniom) = &job.oniom {
+            let named = [
+                (Layer::Medium, oniom.medium.as_ref()),
+                (Layer::High, Some(&oniom.high)),
+            ];
+            for (layer, group) in named {
+                let Some(group) = group else {
+                    continue;
+                };
+                let members = groups
+                    .get_left(group)
+                    .into_iter()
+                    .filter(|idx| serials.contains_key(idx))
+                    .collect::<Vec<_>>();
+                if members.is_empty() {
+                    return Err(LMECoreError::NoSuchGroup(group.clone()));
+                }
+                layers.extend(members.into_iter().map(|idx| (idx, layer)));
+            }
+            let systems = if oniom.medium.is_some() { 6 } else { 3 };
+            states = match oniom.states.len() {
+                0 => vec![state; systems],
+                count if count == systems => oniom.states.clone(),
+                count => {
+                    return Err(invalid(format!(
+                        "Expected {systems} ONIOM states, got {count}"
+                    )))
+                }
+            };
+        }
+        let layer = |idx: &usize| layers.get(idx).copied().unwrap_or(Layer::Low);
+
+        // bonded atoms by serial, for link atoms and the connectivity block
+        let mut bonded = BTreeMap::<usize, Vec<(usize, f64)>>::new();
+        for (pair, order) in self.bonds() {
+            let (a, b) = (*pair).into();
+            if let (Some(serial_a), Some(serial_b)) = (serials.get(&a), serials.get(&b)) {
+                bonded.entry(*serial_a).or_default().push((b, *order));
+                bonded.entry(*serial_b).or_default().push((a, *order));
+            }
+        }
+
+        let mut lines = job.link0.clone();
+        lines.push(route.to_string());
+        lines.push(String::new());
+        let title = job
+            .title
+            .as_deref()
+            .unwrap_or(title)
+            .replace(['\r', '\n'], " ");
+        lines.push(if title.trim().is_empty() {
+            "lme2".to_string()
+        } else {
+            title
+        });
+        lines.push(String::new());
+        lines.push(
+            states
+                .iter()
+                .map(|state| format!("{} {}", state.charge, state.multiplicity))
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+        for (serial, (idx, atom)) in atoms.iter().enumerate() {
+            let mut label = elements::symbol(atom.element()).unwrap_or("X").to_string();
+            let metadata = self.metadata(*idx).cloned().unwrap_or_default();
+            if let Some(mm_type) = metadata.properties.get("mm_type") {
+                label.push_str(&format!("-{mm_type}"));
+                if let Some(charge) = metadata.charge {
+                    label.push_str(&format!("-{charge}"));
+                }
+            }
+            if let Some(isotope) = metadata.isotope {
+                label.push_str(&format!("(Iso={isotope})"));
+            }
+            let position = atom.position();
+            let mut line = format!(
+                "{label:<16}{:>14.8}{:>14.8}{:>14.8}",
+                position.x, position.y, position.z
+            );
+            if job.oniom.is_some() {
+                let own = layer(idx);
+                line.push_str(&format!(" {}", own.letter()));
+                let link = bonded
+                    .get(&(serial + 1))
+                    .into_iter()
+                    .flatten()
+                    .filter(|(other, _)| layer(other) > own)
+                    .map(|(other, _)| (std::cmp::Reverse(layer(other)), serials[other]))
+                    .min();
+                if let Some((_, host)) = link {
+                    line.push_str(&format!(" H {host}"));
+                }
+            }
+            lines.push(line);
+        }
+        lines.push(String::new());
+        if route.to_lowercase().contains("connectivity") {
+            for serial in 1..=atoms.len() {
+                let mut neighbours = bonded
+                    .get(&serial)
+                    .into_iter()
+                    .flatten()
+                    .map(|(other, order)| (serials[other], *order))
+                    .filter(|(other, _)| *other > serial)
+                    .collect::<Vec<_>>();
+                neighbours.sort_by_key(|(other, _)| *other);
+                let neighbours = neighbours
+                    .iter()
+                    .map(|(other, order)| format!(" {other} {order:.1}"))
+                    .collect::<String>();
+                lines.push(format!("{serial}{neighbours}"));
+            }
+            lines.push(String::new());
+        }
+        Ok(lines.join("\n") + "\n")
+    }
+}
+
+mod test {
+    #[test]
+    fn oniom_input_has_layers_and_link_atoms() {
+        use super::{GaussianJob, Oniom};
+        use crate::{
+            entity::{Atom, AtomMetadata, Molecule},
+            error::LMECoreError,
+            ElectronicState,
+        };
+        use n_to_n::NtoN;
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::{BTreeMap, HashMap, HashSet};
+
+        let atom = |element, x| Some(Atom::new(element, Point3::new(x, 0., 0.)));
+        // methanol's C-O with the carbon in the low layer
+        let molecule = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, atom(8, 0.)),
+                (1, atom(6, 1.43)),
+                (2, atom(1, -0.96)),
+            ]))
+            .set_bonds(HashMap::from([
+                (Pair::new_ordered(0, 1), 1.),
+                (Pair::new_ordered(0, 2), 1.),
+            ]))
+            .set_metadata(HashMap::from([(
+                1,
+                AtomMetadata {
+                    charge: Some(0.12),
+                    isotope: Some(13),
+                    properties: BTreeMap::from([("mm_type".to_string(), "CT".to_string())]),
+                },
+            )]));
+        let groups = NtoN::from(HashSet::from([
+            ("qm".to_string(), 0),
+            ("qm".to_string(), 2),
+        ]));
+        let mut job = GaussianJob {
+            link0: vec!["%chk=methanol.chk".to_string()],
+            route: "#p oniom(b3lyp/6-31g(d):amber) geom=connectivity".to_string(),
+            title: None,
+            state: None,
+            oniom: Some(Oniom {
+                high: "qm".to_string(),
+                medium: None,
+                states: vec![],
+            }),
+        };
+        let text = molecule
+            .to_gaussian(&job, ElectronicState::default(), "methanol", &groups)
+            .unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[..6],
+            [
+                "%chk=methanol.chk",
+                job.route.as_str(),
+                "",
+                "methanol",
+                "",
+                "0 1 0 1 0 1"
+            ]
+        );
+        assert!(
+            lines[6].starts_with("O ") && lines[6].ends_with(" H"),
+            "{text}"
+        );
+        assert!(lines[7].starts_with("C-CT-0.12(Iso=13) "), "{text}");
+        assert!(lines[7].ends_with(" L H 1"), "{text}");
+        assert_eq!(lines[10..], ["1 2 1.0 3 1.0", "2", "3", ""]);
+        assert!(text.ends_with("\n\n"));
+
+        job.oniom.as_mut().unwrap().states = vec![ElectronicState::default()];
+        assert!(matches!(
+            molecule.to_gaussian(&job, ElectronicState::default(), "", &groups),
+            Err(LMECoreError::InvalidGaussianJob(_))
+        ));
+        job.oniom = None;
+        job.route = "opt".to_string();
+        assert!(matches!(
+            molecule.to_gaussian(&job, ElectronicState::default(), "", &groups),
+            Err(LMECoreError::InvalidGaussianJob(_))
+        ));
+    }
+}
@@ -0,0 +1,17 @@
+pub mod cif;
+pub mod dot;
+pub mod gaussian;
+pub mod mol2;
+pub mod pdb;
+pub mod sdf;
+pub mod smiles;
+pub mod zmat;
+pub mod xyz;
+
+/// Fixed width field `range` of `line`, trimmed; short lines give "".
+fn column(line: &str, range: std::ops::Range<usize>) -> &str {
+    let end = range.end.min(line.len());
+    line.get(range.start.min(end)..end)
+        .unwrap_or_default()
+        .trim()
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use nalgebra::Point3;
+use pair::Pair;
+
+use crate::{
+    elements,
+    entity::{Atom, Molecule},
+    error::LMECoreError,
+};
+
+fn bond_order(bond_type: &str) -> Option<f64> {
+    match bond_type {
+        "1" | "am" => Some(1.),
+        "2" => Some(2.),
+        "3" => Some(3.),
+        "ar" => Some(1.5),
+        _ => None,
+    }
+}
+
+fn bond_type(order: f64) -> String {
+    if order == 1.5 {
+        "ar".to_string()
+    } else {
+        (order.round().clamp(1., 3.) as usize).to_string()
+    }
+}
+
+/// Element of a SYBYL atom type such as `C.ar` or `Cl`.
+fn element(atom_type: &str) -> Option<usize> {
+    elements::number(atom_type.split('.').next().unwrap_or_default())
+}
+
+impl Molecule {
+    /// Read the first molecule of a Tripos MOL2 file, numbering atoms from
+    /// 0 in atom record order. Elements are taken from the SYBYL atom
+    /// types; the types themselves, atom names, substructures and partial
+    /// charges have no place in a molecule and are dropped. Amide bonds
+    /// become single bonds. Errors carry the 1-based line number.
+    pub fn from_mol2(text: &str) -> Result<Molecule, LMECoreError> {
+        let mut section = "";
+        let mut molecules = 0;
+        let mut numbers = HashMap::new();
+        let mut atoms = HashMap::new();
+        let mut bonds = HashMap::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let error = |message: String| LMECoreError::ParseError(line_no + 1, message);
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix("@<TRIPOS>") {
+                section = name;
+                if section == "MOLECULE" {
+                    molecules += 1;
+                }
+                continue;
+            }
+            if molecules > 1 {
+                break;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match section {
+                "ATOM" => {
+                    let [id, _, x, y, z, atom_type, ..] = fields[..] else {
+                        return Err(error(format!("Incomplete atom record {line:?}")));
+                    };
+                    let coordinate = |value: &str| {
+                        value
+                            .parse::<f64>()
+                            .map_err(|_| error(format!("Invalid coordinate {value}")))
+                    };
+                    let position = Point3::new(coordinate(x)?, coordinate(y)?, coordinate(z)?);
+                    let element = element(atom_type)
+                        .ok_or_else(|| error(format!("Unknown atom type {atom_type}")))?;
+                    let idx = atoms.len();
+                    if numbers.insert(id, idx).is_some() {
+                        return Err(error(format!("Atom {id} is defined twice")));
+                    }
+                    atoms.insert(idx, Some(Atom::new(element, position)));
+                }
+                "BOND" => {
+                    let [_, a, b, bond_type, ..] = fields[..] else {
+                        return Err(error(format!("Incomplete bond record {line:?}")));
+                    };
+                    let atom = |id: &str| {
+                        numbers
+                            .get(id)
+                            .copied()
+                            .ok_or_else(|| error(format!("Bond to a missing atom {id}")))
+                    };
+                    let order = bond_order(bond_type)
+                        .ok_or_else(|| error(format!("Unsupported bond type {bond_type}")))?;
+                    bonds.insert(Pair::new_ordered(atom(a)?, atom(b)?), order);
+                }
+                _ => {}
+            }
+        }
+        Ok(Molecule::default().set_atoms(atoms).set_bonds(bonds))
+    }
+
+    /// Tripos MOL2 file of the molecule named `name`, atoms in index order.
+    /// Atom types are plain element symbols and there are no partial
+    /// charges. Bond order 1.5 is written as aromatic, others are rounded to
+    /// single, double or triple bonds.
+    pub fn to_mol2(&self, name: &str) -> String {
+        let atoms = self.atoms();
+        let numbers = atoms
+            .iter()
+            .enumerate()
+            .map(|(number, (idx, _))| (*idx, number + 1))
+            .collect::<HashMap<_, _>>();
+        let mut bonds = self
+            .bonds()
+            .iter()
+            .filter_map(|(pair, order)| {
+                let (a, b) = (*pair).into();
+                let (a, b) = (*numbers.get(&a)?, *numbers.get(&b)?);
+                Some((a.min(b), a.max(b), bond_type(*order)))
+            })
+            .collect::<Vec<_>>();
+        bonds.sort();
+
+        let mut lines = vec![
+            "@<TRIPOS>MOLECULE".to_string(),
+            name.replace(['\r', '\n'], " "),
+            format!("{} {} 0 0 0", atoms.len(), bonds.len()),
+            "SMALL".to_string(),
+            "NO_CHARGES".to_string(),
+            String::new(),
+            "@<TRIPOS>ATOM".to_string(),
+        ];
+        for (number, (_, atom)) in atoms.iter().enumerate() {
+            let symbol = elements::symbol(atom.element()).unwrap_or("Du");
+            let position = atom.position();
+            lines.push(format!(
+                "{:>7} {:<8} {:>10.4} {:>10.4} {:>10.4} {:<5} 1 UNL1 0.0000",
+                number + 1,
+                format!("{symbol}{}", number + 1),
+                position.x,
+                position.y,
+                position.z,
+                symbol
+            ));
+        }
+        lines.push("@<TRIPOS>BOND".to_string());
+        for (number, (a, b, bond_type)) in bonds.iter().enumerate() {
+            lines.push(format!("{:>6} {a:>5} {b:>5} {bond_type}", number + 1));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+mod test {
+    #[test]
+    fn mol2_round_trip() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let text = "\
+@<TRIPOS>MOLECULE
+acetamide
+ 4 3 0 0 0
+SMALL
+USER_CHARGES
+
+@<TRIPOS>ATOM
+      1 C1      0.0000  0.0000  0.0000 C.3   1 ACM  -0.1200
+      2 C2      1.5000  0.0000  0.0000 C.2   1 ACM   0.5000
+      5 O       2.1000  1.0000  0.0000 O.2   1 ACM  -0.5000
+      7 N       2.2000 -1.1000  0.0000 N.am  1 ACM  -0.4000
+@<TRIPOS>BOND
+     1     1     2    1
+     2     2     5    2
+     3     2     7   am
+";
+        let molecule = Molecule::from_mol2(text).unwrap();
+        let elements = molecule
+            .atoms()
+            .into_iter()
+            .map(|(idx, atom)| (idx, atom.element()))
+            .collect::<Vec<_>>();
+        assert_eq!(elements, vec![(0, 6), (1, 6), (2, 8), (3, 7)]);
+        assert_eq!(molecule.bonds()[&Pair::new_ordered(1, 2)], 2.);
+        assert_eq!(molecule.bonds()[&Pair::new_ordered(1, 3)], 1.);
+        assert_eq!(
+            Molecule::from_mol2(&molecule.to_mol2("acetamide")).unwrap(),
+            molecule
+        );
+
+        let benzene_bond = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, Some(Atom::new(6, Point3::origin()))),
+                (4, Some(Atom::new(6, Point3::new(1.4, 0., 0.)))),
+            ]))
+            .set_bonds(HashMap::from([(Pair::new_ordered(0, 4), 1.5)]));
+        let written = benzene_bond.to_mol2("fragment");
+        assert!(written.ends_with("     1     1     2 ar\n"), "{written}");
+
+        let broken = text.replace("N.am", "Xx.am");
+        assert!(matches!(
+            Molecule::from_mol2(&broken),
+            Err(LMECoreError::ParseError(11, _))
+        ));
+    }
+}
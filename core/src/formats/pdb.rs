@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashMap};
+
+use nalgebra::Point3;
+use pair::Pair;
+
+use super::column;
+use crate::{
+    elements,
+    entity::{Atom, Molecule, Residue},
+    error::LMECoreError,
+};
+
+/// Element of an atom record: the element columns if they are filled,
+/// otherwise the leading letters of the atom name, which start in column
+/// 14 for one letter elements.
+fn element(line: &str) -> Option<usize> {
+    let symbol = column(line, 76..78);
+    if !symbol.is_empty() {
+        return elements::number(symbol);
+    }
+    let name = line.get(12..16).unwrap_or_default();
+    let letters = name
+        .trim_start()
+        .chars()
+        .take_while(char::is_ascii_alphabetic)
+        .collect::<String>();
+    let symbol = if name.starts_with(' ') {
+        letters.get(..1)
+    } else {
+        letters.get(..2).or(letters.get(..1))
+    };
+    symbol.and_then(elements::number)
+}
+
+fn parse_field<T: std::str::FromStr>(
+    line: &str,
+    range: std::ops::Range<usize>,
+    what: &str,
+) -> Result<T, String> {
+    let field = column(line, range);
+    field
+        .parse()
+        .map_err(|_| format!("Invalid {what} {field:?}"))
+}
+
+fn parse_atom(line: &str) -> Result<(i32, Atom), String> {
+    let serial = parse_field(line, 6..11, "serial")?;
+    let coordinate = |range| parse_field(line, range, "coordinate");
+    let position = Point3::new(
+        coordinate(30..38)?,
+        coordinate(38..46)?,
+        coordinate(46..54)?,
+    );
+    let element = element(line).ok_or(format!("No element in {line:?}"))?;
+    let residue_name = column(line, 17..20);
+    let residue = Residue::new(
+        residue_name,
+        parse_field(line, 22..26, "residue number")?,
+        line.chars().nth(21).unwrap_or(' '),
+    )
+    .ok_or(format!("Invalid residue name {residue_name:?}"))?;
+    Ok((
+        serial,
+        Atom::new(element, position).set_residue(Some(residue)),
+    ))
+}
+
+impl Molecule {
+    /// Read the first model of a PDB file, numbering the `ATOM` and
+    /// `HETATM` records from 0 in file order. Every atom gets the residue
+    /// of its record. `CONECT` records become single bonds, as PDB files
+    /// carry no bond orders. Errors carry the 1-based line number.
+    pub fn from_pdb(text: &str) -> Result<Molecule, LMECoreError> {
+        let mut serials = HashMap::new();
+        let mut atoms = HashMap::new();
+        let mut connections = vec![];
+        for (line_no, line) in text.lines().enumerate() {
+            let error = |message: String| LMECoreError::ParseError(line_no + 1, message);
+            match line.get(..6).unwrap_or(line).trim_end() {
+                "ATOM" | "HETATM" => {
+                    let (serial, atom) = parse_atom(line).map_err(error)?;
+                    let idx = atoms.len();
+                    serials.insert(serial, idx);
+                    atoms.insert(idx, Some(atom));
+                }
+                "CONECT" => {
+                    let serial = |range| parse_field::<i32>(line, range, "serial").map_err(error);
+                    let from = serial(6..11)?;
+                    for start in [11, 16, 21, 26] {
+                        if !column(line, start..start + 5).is_empty() {
+                            connections.push((line_no, from, serial(start..start + 5)?));
+                        }
+                    }
+                }
+                "ENDMDL" | "END" => break,
+                _ => {}
+            }
+        }
+        let mut bonds = HashMap::new();
+        for (line_no, a, b) in connections {
+            let atom = |serial| {
+                serials.get(&serial).copied().ok_or_else(|| {
+                    LMECoreError::ParseError(
+                        line_no + 1,
+                        format!("Bond to a missing atom {serial}"),
+                    )
+                })
+            };
+            bonds.insert(Pair::new_ordered(atom(a)?, atom(b)?), 1.);
+        }
+        Ok(Molecule::default().set_atoms(atoms).set_bonds(bonds))
+    }
+
+    /// PDB file of the molecule, atoms in index order. Atoms with a residue
+    /// are written as `ATOM` records, the others as `HETATM` records of
+    /// residue `UNL` 1. Bonds are written as `CONECT` records without
+    /// their order.
+    pub fn to_pdb(&self) -> String {
+        let atoms = self.atoms();
+        let serials = atoms
+            .iter()
+            .enumerate()
+            .map(|(serial, (idx, _))| (*idx, serial + 1))
+            .collect::<HashMap<_, _>>();
+        let mut lines = vec![];
+        let mut counters = HashMap::<&str, usize>::new();
+        for (serial, (_, atom)) in atoms.iter().enumerate() {
+            let symbol = elements::symbol(atom.element()).unwrap_or("X");
+            let counter = counters.entry(symbol).or_default();
+            *counter += 1;
+            let mut name = format!("{symbol}{counter}");
+            if name.len() > 4 {
+                name = symbol.to_string();
+            }
+            if symbol.len() == 1 && name.len() < 4 {
+                name.insert(0, ' ');
+            }
+            let (record, residue) = match atom.residue() {
+                Some(residue) => ("ATOM", residue),
+                None => (
+                    "HETATM",
+                    Residue::new("UNL", 1, ' ').expect("Valid residue"),
+                ),
+            };
+            let position = atom.position();
+            lines.push(format!(
+                "{record:<6}{:>5} {name:<4} {:>3} {}{:>4}    {:>8.3}{:>8.3}{:>8.3}{:>6.2}{:>6.2}          {symbol:>2}",
+                serial + 1,
+                residue.name(),
+                residue.chain(),
+                residue.number(),
+                position.x,
+                position.y,
+                position.z,
+                1.,
+                0.,
+            ));
+        }
+        let mut bonded = BTreeMap::<usize, Vec<usize>>::new();
+        for pair in self.bonds().keys() {
+            let (a, b) = (*pair).into();
+            if let (Some(a), Some(b)) = (serials.get(&a), serials.get(&b)) {
+                bonded.entry(*a).or_default().push(*b);
+                bonded.entry(*b).or_default().push(*a);
+            }
+        }
+        for (serial, mut neighbours) in bonded {
+            neighbours.sort();
+            for chunk in neighbours.chunks(4) {
+                let neighbours = chunk
+                    .iter()
+                    .map(|neighbour| format!("{neighbour:>5}"))
+                    .collect::<String>();
+                lines.push(format!("CONECT{serial:>5}{neighbours}"));
+            }
+        }
+        lines.push("END".to_string());
+        lines.join("\n") + "\n"
+    }
+}
+
+mod test {
+    #[test]
+    fn pdb_round_trip() {
+        use crate::{entity::Molecule, error::LMECoreError};
+        use pair::Pair;
+
+        let text = "\
+HEADER    GLYCINE FRAGMENT
+ATOM      1  N   GLY A   7      -1.195   0.210   0.000  1.00  0.00           N
+ATOM      2  CA  GLY A   7       0.000   1.040   0.000  1.00  0.00           C
+ATOM      3  C   GLY A   7       1.250   0.180   0.000  1.00  0.00
+HETATM    9 CL   CL  B 101       4.000   0.000   0.000  1.00  0.00
+CONECT    1    2
+CONECT    2    1    3
+CONECT    3    2
+END
+ATOM     10  O   GLY A   8       9.000   9.000   9.000  1.00  0.00           O
+";
+        let molecule = Molecule::from_pdb(text).unwrap();
+        let atoms = molecule.atoms();
+        let elements = atoms
+            .iter()
+            .map(|(idx, atom)| (*idx, atom.element()))
+            .collect::<Vec<_>>();
+        assert_eq!(elements, vec![(0, 7), (1, 6), (2, 6), (3, 17)]);
+        let residue = atoms[1].1.residue().unwrap();
+        assert_eq!(
+            (residue.name(), residue.chain(), residue.number()),
+            ("GLY", 'A', 7)
+        );
+        assert_eq!(atoms[3].1.residue().unwrap().name(), "CL");
+        assert_eq!(molecule.bonds().len(), 2);
+        assert!(molecule.bonds().contains_key(&Pair::new_ordered(1, 2)));
+
+        let written = molecule.to_pdb();
+        assert_eq!(Molecule::from_pdb(&written).unwrap(), molecule);
+        assert!(written.contains("CONECT    2    1    3\n"), "{written}");
+
+        let broken = text.replace("   0.210", "   0.2x0");
+        assert!(matches!(
+            Molecule::from_pdb(&broken),
+            Err(LMECoreError::ParseError(2, _))
+        ));
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use nalgebra::Point3;
+use pair::Pair;
+
+use super::column;
+use crate::{
+    elements,
+    entity::{Atom, Molecule},
+    error::LMECoreError,
+};
+
+/// Bond type of a molfile bond block for a bond order; 4 is aromatic.
+fn bond_type(order: f64) -> usize {
+    if order == 1.5 {
+        4
+    } else {
+        order.round().clamp(1., 3.) as usize
+    }
+}
+
+fn bond_order(bond_type: usize) -> Option<f64> {
+    match bond_type {
+        1..=3 => Some(bond_type as f64),
+        4 => Some(1.5),
+        _ => None,
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(
+    line: &str,
+    range: std::ops::Range<usize>,
+) -> Result<T, String> {
+    let field = column(line, range);
+    field
+        .parse()
+        .map_err(|_| format!("Invalid number {field:?}"))
+}
+
+impl Molecule {
+    /// Read a V2000 molfile, numbering atoms from 0 in atom block order.
+    /// Bond types 1 to 3 keep their order and aromatic bonds (type 4) get
+    /// order 1.5. Property lines and anything after `M  END` are ignored.
+    /// Errors carry the 1-based line number.
+    pub fn from_molfile(text: &str) -> Result<Molecule, LMECoreError> {
+        let lines = text.lines().collect::<Vec<_>>();
+        let line = |line_no: usize| {
+            lines.get(line_no - 1).copied().ok_or_else(|| {
+                LMECoreError::ParseError(line_no, "Unexpected end of molfile".to_string())
+            })
+        };
+        let counts = line(4)?;
+        let error = |line_no: usize| move |message| LMECoreError::ParseError(line_no, message);
+        if counts.contains("V3000") {
+            return Err(error(4)("V3000 molfiles are not supported".to_string()));
+        }
+        let atom_count: usize = parse_number(counts, 0..3).map_err(error(4))?;
+        let bond_count: usize = parse_number(counts, 3..6).map_err(error(4))?;
+
+        let mut atoms = HashMap::with_capacity(atom_count);
+        for idx in 0..atom_count {
+            let line_no = idx + 5;
+            let text = line(line_no)?;
+            let coordinate = |range| parse_number::<f64>(text, range).map_err(error(line_no));
+            let position =
+                Point3::new(coordinate(0..10)?, coordinate(10..20)?, coordinate(20..30)?);
+            let symbol = column(text, 31..34);
+            let element = elements::number(symbol)
+                .ok_or_else(|| error(line_no)(format!("Unknown element {symbol}")))?;
+            atoms.insert(idx, Some(Atom::new(element, position)));
+        }
+        let mut bonds = HashMap::with_capacity(bond_count);
+        for bond in 0..bond_count {
+            let line_no = atom_count + bond + 5;
+            let text = line(line_no)?;
+            let atom = |range| {
+                parse_number::<usize>(text, range)
+                    .map_err(error(line_no))?
+                    .checked_sub(1)
+                    .filter(|idx| *idx < atom_count)
+                    .ok_or_else(|| error(line_no)("Bond to a missing atom".to_string()))
+            };
+            let (a, b) = (atom(0..3)?, atom(3..6)?);
+            let bond_type = parse_number(text, 6..9).map_err(error(line_no))?;
+            let order = bond_order(bond_type)
+                .ok_or_else(|| error(line_no)(format!("Unsupported bond type {bond_type}")))?;
+            bonds.insert(Pair::new_ordered(a, b), order);
+        }
+        Ok(Molecule::default().set_atoms(atoms).set_bonds(bonds))
+    }
+
+    /// Every record of an SDF file, read with `from_molfile`. Data items
+    /// are ignored.
+    pub fn from_sdf(text: &str) -> Result<Vec<Molecule>, LMECoreError> {
+        let mut molecules = vec![];
+        let mut record = vec![];
+        let mut first_line = 1;
+        for (line_no, line) in text.lines().enumerate() {
+            if line.trim_end() == "$$$$" {
+                let molecule =
+                    Molecule::from_molfile(&record.join("\n")).map_err(|err| match err {
+                        LMECoreError::ParseError(line, message) => {
+                            LMECoreError::ParseError(line + first_line - 1, message)
+                        }
+                        err => err,
+                    })?;
+                molecules.push(molecule);
+                record.clear();
+                first_line = line_no + 2;
+            } else {
+                record.push(line);
+            }
+        }
+        if record.iter().any(|line| !line.trim().is_empty()) {
+            return Err(LMECoreError::ParseError(
+                first_line,
+                "Record is not terminated by $$$$".to_string(),
+            ));
+        }
+        Ok(molecules)
+    }
+
+    /// V2000 molfile of the molecule with `title` as header line, atoms in
+    /// index order. Bond orders other than 1.5 are rounded to single,
+    /// double or triple bonds.
+    pub fn to_molfile(&self, title: &str) -> String {
+        let atoms = self.atoms();
+        let numbers = atoms
+            .iter()
+            .enumerate()
+            .map(|(number, (idx, _))| (*idx, number + 1))
+            .collect::<HashMap<_, _>>();
+        let mut bonds = self
+            .bonds()
+            .iter()
+            .filter_map(|(pair, order)| {
+                let (a, b) = (*pair).into();
+                let (a, b) = (*numbers.get(&a)?, *numbers.get(&b)?);
+                Some((a.min(b), a.max(b), bond_type(*order)))
+            })
+            .collect::<Vec<_>>();
+        bonds.sort();
+
+        let mut lines = vec![
+            title.replace(['\r', '\n'], " "),
+            "  lme2".to_string(),
+            String::new(),
+            format!(
+                "{:3}{:3}  0  0  0  0  0  0  0  0999 V2000",
+                atoms.len(),
+                bonds.len()
+            ),
+        ];
+        for (_, atom) in &atoms {
+            let position = atom.position();
+            lines.push(format!(
+                "{:10.4}{:10.4}{:10.4} {:<3} 0  0  0  0  0  0  0  0  0  0  0  0",
+                position.x,
+                position.y,
+                position.z,
+                elements::symbol(atom.element()).unwrap_or("X"),
+            ));
+        }
+        for (a, b, bond_type) in bonds {
+            lines.push(format!("{a:3}{b:3}{bond_type:3}  0"));
+        }
+        lines.push("M  END".to_string());
+        lines.join("\n") + "\n"
+    }
+
+    /// SDF file holding a record for each `(title, molecule)` pair.
+    pub fn to_sdf<'a, I>(records: I) -> String
+    where
+        I: IntoIterator<Item = (&'a str, &'a Molecule)>,
+    {
+        records
+            .into_iter()
+            .map(|(title, molecule)| molecule.to_molfile(title) + "$$$$\n")
+            .collect()
+    }
+}
+
+mod test {
+    #[test]
+    fn molfile_round_trip() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let atom = |element, x, y| Some(Atom::new(element, Point3::new(x, y, 0.)));
+        let formaldehyde = Molecule::default()
+            .set_atoms(HashMap::from([
+                (2, atom(6, 0., 0.)),
+                (5, atom(8, 1.2, 0.)),
+                (7, atom(1, -0.5, 0.9)),
+                (8, atom(1, -0.5, -0.9)),
+                (9, None),
+            ]))
+            .set_bonds(HashMap::from([
+                (Pair::new_ordered(2, 5), 2.),
+                (Pair::new_ordered(2, 7), 1.),
+                (Pair::new_ordered(2, 8), 1.),
+            ]));
+        let text = formaldehyde.to_molfile("formaldehyde");
+        assert_eq!(
+            text.lines().nth(3),
+            Some("  4  3  0  0  0  0  0  0  0  0999 V2000")
+        );
+        let read = Molecule::from_molfile(&text).unwrap();
+        assert_eq!(read.atom(1).unwrap().element(), 8);
+        assert_eq!(read.bonds()[&Pair::new_ordered(0, 1)], 2.);
+        assert_eq!(read.bonds().len(), 3);
+
+        let sdf = Molecule::to_sdf([("formaldehyde", &formaldehyde), ("copy", &read)]);
+        let records = Molecule::from_sdf(&sdf).unwrap();
+        assert_eq!(records, vec![read.clone(), read]);
+
+        let broken = sdf.replacen(" O ", " Q ", 1);
+        assert!(matches!(
+            Molecule::from_sdf(&broken),
+            Err(LMECoreError::ParseError(6, _))
+        ));
+    }
+}
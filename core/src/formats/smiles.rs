@@ -0,0 +1,344 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use pair::Pair;
+
+use crate::{elements, entity::Molecule, graph};
+
+/// Elements that may be written without brackets, with their normal
+/// valences in increasing order.
+fn normal_valences(element: usize) -> &'static [usize] {
+    match element {
+        5 => &[3],
+        6 => &[4],
+        7 | 15 => &[3, 5],
+        8 => &[2],
+        16 => &[2, 4, 6],
+        9 | 17 | 35 | 53 => &[1],
+        _ => &[],
+    }
+}
+
+fn is_aromatic(order: f64) -> bool {
+    order == 1.5
+}
+
+/// Part of the SMILES of a component, see `SmilesGraph::component`.
+enum Token {
+    /// An atom, preceded by the symbol of the bond leading to it
+    Atom(usize, &'static str),
+    Open,
+    Close,
+}
+
+fn ring_label(digit: usize) -> String {
+    if digit < 10 {
+        digit.to_string()
+    } else {
+        format!("%{digit}")
+    }
+}
+
+struct Walk {
+    /// Atoms in the order they are reached
+    visited: Vec<usize>,
+    /// The spanning tree, as the children of each atom
+    children: HashMap<usize, Vec<usize>>,
+    /// Bonds left out of the spanning tree, written as ring closures
+    closures: BTreeSet<Pair<usize>>,
+}
+
+struct SmilesGraph {
+    elements: HashMap<usize, usize>,
+    hydrogens: HashMap<usize, usize>,
+    aromatic: HashSet<usize>,
+    /// Neighbours of each atom in canonical order, with the bond order
+    neighbours: HashMap<usize, Vec<(usize, f64)>>,
+    rank: HashMap<usize, usize>,
+}
+
+impl SmilesGraph {
+    /// Hydrogens bonded once, to an atom other than hydrogen, are folded
+    /// into that atom's hydrogen count; all other atoms are kept.
+    fn new(molecule: &Molecule) -> Self {
+        let adjacency = graph::adjacency(molecule);
+        let element = |idx: &usize| molecule.atom(*idx).expect("Atom in bond graph").element();
+        let folded = adjacency
+            .iter()
+            .filter(|(idx, neighbours)| {
+                element(idx) == 1
+                    && neighbours.len() == 1
+                    && element(&neighbours[0].0) != 1
+                    && neighbours[0].1 == 1.
+            })
+            .map(|(idx, neighbours)| (*idx, neighbours[0].0))
+            .collect::<HashMap<_, _>>();
+        let mut hydrogens = HashMap::<usize, usize>::new();
+        folded
+            .values()
+            .for_each(|parent| *hydrogens.entry(*parent).or_default() += 1);
+        let rank = graph::canonical_order(molecule)
+            .into_iter()
+            .filter(|idx| !folded.contains_key(idx))
+            .enumerate()
+            .map(|(rank, idx)| (idx, rank))
+            .collect::<HashMap<_, _>>();
+        let neighbours = rank
+            .keys()
+            .map(|idx| {
+                let mut neighbours = adjacency[idx]
+                    .iter()
+                    .filter(|(neighbour, _)| rank.contains_key(neighbour))
+                    .copied()
+                    .collect::<Vec<_>>();
+                neighbours.sort_by_key(|(neighbour, _)| rank[neighbour]);
+                (*idx, neighbours)
+            })
+            .collect::<HashMap<_, _>>();
+        let aromatic = neighbours
+            .iter()
+            .filter(|(_, neighbours)| neighbours.iter().any(|(_, order)| is_aromatic(*order)))
+            .map(|(idx, _)| *idx)
+            .collect();
+        Self {
+            elements: rank.keys().map(|idx| (*idx, element(idx))).collect(),
+            hydrogens,
+            aromatic,
+            neighbours,
+            rank,
+        }
+    }
+
+    fn bond_symbol(&self, a: usize, b: usize, order: f64) -> &'static str {
+        if is_aromatic(order) {
+            return "";
+        }
+        match order.round() as usize {
+            2 => "=",
+            3 => "#",
+            4 => "$",
+            _ if self.aromatic.contains(&a) && self.aromatic.contains(&b) => "-",
+            _ => "",
+        }
+    }
+
+    fn atom_symbol(&self, idx: usize) -> String {
+        let element = self.elements[&idx];
+        let hydrogens = self.hydrogens.get(&idx).copied().unwrap_or_default();
+        let aromatic = self.aromatic.contains(&idx);
+        let symbol = elements::symbol(element).unwrap_or("*");
+        let symbol = if aromatic {
+            symbol.to_lowercase()
+        } else {
+            symbol.to_string()
+        };
+        let valence = self.neighbours[&idx]
+            .iter()
+            .map(|(_, order)| *order)
+            .sum::<f64>()
+            .floor() as usize;
+        let implicit = normal_valences(element)
+            .iter()
+            .find(|normal| **normal >= valence)
+            .map(|normal| normal - valence);
+        let bare = implicit == Some(hydrogens)
+            && (!aromatic || matches!(element, 5 | 6 | 7 | 8 | 15 | 16));
+        match hydrogens {
+            _ if bare => symbol,
+            0 => format!("[{symbol}]"),
+            1 => format!("[{symbol}H]"),
+            count => format!("[{symbol}H{count}]"),
+        }
+    }
+
+    /// Depth-first walk from `root` in canonical order.
+    fn walk(&self, root: usize) -> Walk {
+        let mut visited = vec![root];
+        let mut seen = HashSet::from([root]);
+        let mut children = HashMap::<usize, Vec<usize>>::new();
+        let mut closures = BTreeSet::new();
+        let mut stack = vec![(root, None, 0)];
+        while let Some((idx, parent, next)) = stack.pop() {
+            let Some((neighbour, _)) = self.neighbours[&idx].get(next).copied() else {
+                continue;
+            };
+            stack.push((idx, parent, next + 1));
+            if Some(neighbour) == parent {
+                continue;
+            }
+            if seen.contains(&neighbour) {
+                closures.insert(Pair::new_ordered(idx, neighbour));
+            } else {
+                seen.insert(neighbour);
+                visited.push(neighbour);
+                children.entry(idx).or_default().push(neighbour);
+                stack.push((neighbour, Some(idx), 0));
+            }
+        }
+        Walk {
+            visited,
+            children,
+            closures,
+        }
+    }
+
+    fn component(&self, root: usize) -> (Vec<usize>, String) {
+        let Walk {
+            visited,
+            children,
+            closures,
+        } = self.walk(root);
+        let position = visited
+            .iter()
+            .enumerate()
+            .map(|(position, idx)| (*idx, position))
+            .collect::<HashMap<_, _>>();
+        let mut rings = HashMap::<usize, Vec<(usize, f64)>>::new();
+        for pair in &closures {
+            let (a, b) = (*pair).into();
+            let order = self.neighbours[&a]
+                .iter()
+                .find(|(neighbour, _)| *neighbour == b)
+                .expect("Closure is a bond")
+                .1;
+            rings.entry(a).or_default().push((b, order));
+            rings.entry(b).or_default().push((a, order));
+        }
+        rings
+            .values_mut()
+            .for_each(|ring| ring.sort_by_key(|(other, _)| self.rank[other]));
+
+        let mut open = BTreeSet::<usize>::new();
+        let mut labels = HashMap::<Pair<usize>, usize>::new();
+        let mut text = String::new();
+        let mut stack = vec![Token::Atom(root, "")];
+        while let Some(token) = stack.pop() {
+            let (idx, bond) = match token {
+                Token::Open => {
+                    text.push('(');
+                    continue;
+                }
+                Token::Close => {
+                    text.push(')');
+                    continue;
+                }
+                Token::Atom(idx, bond) => (idx, bond),
+            };
+            text.push_str(bond);
+            text.push_str(&self.atom_symbol(idx));
+            for (other, order) in rings.get(&idx).into_iter().flatten() {
+                let pair = Pair::new_ordered(idx, *other);
+                if position[other] < position[&idx] {
+                    let digit = labels.remove(&pair).expect("Ring was opened");
+                    open.remove(&digit);
+                    text.push_str(&ring_label(digit));
+                } else {
+                    let digit = (1..).find(|digit| !open.contains(digit)).unwrap();
+                    open.insert(digit);
+                    labels.insert(pair, digit);
+                    text.push_str(self.bond_symbol(idx, *other, *order));
+                    text.push_str(&ring_label(digit));
+                }
+            }
+            let children = children.get(&idx).map(Vec::as_slice).unwrap_or_default();
+            for (nth, child) in children.iter().enumerate().rev() {
+                let order = self.neighbours[&idx]
+                    .iter()
+                    .find(|(neighbour, _)| neighbour == child)
+                    .expect("Child is a neighbour")
+                    .1;
+                let branch = nth + 1 < children.len();
+                if branch {
+                    stack.push(Token::Close);
+                }
+                stack.push(Token::Atom(*child, self.bond_symbol(idx, *child, order)));
+                if branch {
+                    stack.push(Token::Open);
+                }
+            }
+        }
+        (visited, text)
+    }
+}
+
+impl Molecule {
+    /// Canonical SMILES of the molecule, atoms ordered with
+    /// `graph::canonical_order`. Bond orders are taken as stored: 1.5 is
+    /// aromatic, other orders are rounded. Hydrogens bonded to one heavy
+    /// atom are written as hydrogen counts; the others are kept as atoms.
+    /// Disconnected fragments are sorted and joined with `.`.
+    pub fn to_smiles(&self) -> String {
+        let graph = SmilesGraph::new(self);
+        let mut roots = graph.rank.iter().collect::<Vec<_>>();
+        roots.sort_by_key(|(_, rank)| **rank);
+        let mut written = HashSet::new();
+        let mut fragments = vec![];
+        for (root, _) in roots {
+            if written.contains(root) {
+                continue;
+            }
+            let (visited, text) = graph.component(*root);
+            written.extend(visited);
+            fragments.push(text);
+        }
+        fragments.sort();
+        fragments.join(".")
+    }
+}
+
+mod test {
+    #[test]
+    fn smiles_ignores_input_numbering() {
+        use crate::entity::{Atom, Molecule};
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let build = |elements: &[usize], bonds: &[(usize, usize, f64)], permutation: &[usize]| {
+            let atoms = elements
+                .iter()
+                .enumerate()
+                .map(|(idx, element)| {
+                    (
+                        permutation[idx],
+                        Some(Atom::new(*element, Point3::origin())),
+                    )
+                })
+                .collect::<HashMap<_, _>>();
+            let bonds = bonds
+                .iter()
+                .map(|(a, b, order)| (Pair::new_ordered(permutation[*a], permutation[*b]), *order))
+                .collect::<HashMap<_, _>>();
+            Molecule::default().set_atoms(atoms).set_bonds(bonds)
+        };
+
+        // acetic acid with explicit hydrogens
+        let elements = [6, 6, 8, 8, 1, 1, 1, 1];
+        let bonds = [
+            (0, 1, 1.),
+            (1, 2, 2.),
+            (1, 3, 1.),
+            (0, 4, 1.),
+            (0, 5, 1.),
+            (0, 6, 1.),
+            (3, 7, 1.),
+        ];
+        let smiles = build(&elements, &bonds, &[0, 1, 2, 3, 4, 5, 6, 7]).to_smiles();
+        assert_eq!(
+            smiles,
+            build(&elements, &bonds, &[7, 3, 5, 1, 0, 6, 2, 4]).to_smiles()
+        );
+        assert_eq!(smiles.len(), "CC(=O)O".len());
+
+        // pyrrole and a bare oxygen atom
+        let elements = [7, 6, 6, 6, 6, 1, 1, 1, 1, 1, 8];
+        let bonds = (0..5)
+            .map(|idx| (idx, (idx + 1) % 5, 1.5))
+            .chain((0..5).map(|idx| (idx, idx + 5, 1.)))
+            .collect::<Vec<_>>();
+        let identity = (0..11).collect::<Vec<_>>();
+        let smiles = build(&elements, &bonds, &identity).to_smiles();
+        let shuffled = [4, 2, 6, 0, 5, 3, 1, 10, 8, 9, 7];
+        assert_eq!(smiles, build(&elements, &bonds, &shuffled).to_smiles());
+        assert_eq!(smiles, "[O].[nH]1cccc1");
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use nalgebra::{Point3, Vector3};
+
+use crate::{
+    cell::Cell,
+    elements,
+    entity::{Atom, Molecule},
+    error::LMECoreError,
+};
+
+fn parse_atom(line: &str) -> Result<Atom, String> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let [element, x, y, z, ..] = fields[..] else {
+        return Err(format!(
+            "Expected an element and 3 coordinates, got {line:?}"
+        ));
+    };
+    let element = elements::number(element)
+        .or_else(|| {
+            element
+                .parse()
+                .ok()
+                .filter(|number| elements::symbol(*number).is_some())
+        })
+        .ok_or(format!("Unknown element {element}"))?;
+    let coordinate = |value: &str| {
+        value
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid coordinate {value}"))
+    };
+    let position = Point3::new(coordinate(x)?, coordinate(y)?, coordinate(z)?);
+    Ok(Atom::new(element, position))
+}
+
+/// The `key=value` pairs of an extended XYZ comment line, keys in lower
+/// case and values without their double quotes. Words without a value
+/// are left out.
+fn comment_fields(comment: &str) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+    let mut rest = comment.trim_start();
+    while !rest.is_empty() {
+        let word_end = rest.find(|c: char| c == '=' || c.is_whitespace());
+        let (key, after) = rest.split_at(word_end.unwrap_or(rest.len()));
+        rest = match after.strip_prefix('=') {
+            Some(value) => {
+                let (value, after) = match value.strip_prefix('"') {
+                    Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+                    None => {
+                        let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                        value.split_at(end)
+                    }
+                };
+                fields.insert(key.to_lowercase(), value.to_string());
+                after
+            }
+            None => after,
+        };
+        rest = rest.trim_start();
+    }
+    fields
+}
+
+/// The cell of an extended XYZ comment line, from its `Lattice` and `pbc`
+/// fields. Without `pbc` the cell is periodic along every vector.
+fn comment_cell(comment: &str) -> Result<Option<Cell>, String> {
+    let fields = comment_fields(comment);
+    let Some(lattice) = fields.get("lattice") else {
+        return Ok(None);
+    };
+    let values = lattice
+        .split_whitespace()
+        .map(|value| value.parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|values| values.len() == 9)
+        .ok_or(format!("Expected 9 numbers as Lattice, got {lattice:?}"))?;
+    let vector = |at: usize| Vector3::new(values[at], values[at + 1], values[at + 2]);
+    let cell = Cell::new(vector(0), vector(3), vector(6));
+    let Some(pbc) = fields.get("pbc") else {
+        return Ok(Some(cell));
+    };
+    let flags = pbc
+        .split_whitespace()
+        .map(|flag| match flag.to_lowercase().as_str() {
+            "t" | "true" | "1" => Some(true),
+            "f" | "false" | "0" => Some(false),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .and_then(|flags| <[bool; 3]>::try_from(flags).ok())
+        .ok_or(format!("Expected 3 flags as pbc, got {pbc:?}"))?;
+    Ok(Some(cell.with_periodic(flags)))
+}
+
+impl Molecule {
+    /// Read the first frame of an XYZ file, numbering atoms from 0 in line
+    /// order. Elements may be given by symbol or atomic number, and columns
+    /// after the coordinates are ignored. No bonds are guessed. The cell of
+    /// an extended XYZ file is read from the `Lattice` and `pbc` fields of
+    /// its comment line. Errors carry the 1-based line number.
+    pub fn from_xyz(text: &str) -> Result<Molecule, LMECoreError> {
+        let mut lines = text.lines();
+        let count = lines.next().unwrap_or_default().trim();
+        let count = count
+            .parse::<usize>()
+            .map_err(|_| LMECoreError::ParseError(1, format!("Invalid atom count {count:?}")))?;
+        let comment = lines.next().unwrap_or_default();
+        let cell = comment_cell(comment).map_err(|message| LMECoreError::ParseError(2, message))?;
+        let mut atoms = HashMap::with_capacity(count);
+        for idx in 0..count {
+            let line_no = idx + 3;
+            let line = lines.next().ok_or_else(|| {
+                LMECoreError::ParseError(line_no, format!("Expected {count} atoms, got {idx}"))
+            })?;
+            let atom =
+                parse_atom(line).map_err(|message| LMECoreError::ParseError(line_no, message))?;
+            atoms.insert(idx, Some(atom));
+        }
+        Ok(Molecule::default().set_atoms(atoms).set_cell(cell))
+    }
+
+    /// XYZ text of the molecule: the atom count, `comment` (kept on one
+    /// line), then one `symbol x y z` line per atom in index order. With a
+    /// cell it is extended XYZ, the comment starting with the `Lattice` and
+    /// `pbc` fields.
+    pub fn to_xyz(&self, comment: &str) -> String {
+        let atoms = self.atoms();
+        let mut comment = comment.replace(['\r', '\n'], " ");
+        if let Some(cell) = self.cell() {
+            let lattice = cell
+                .vectors()
+                .iter()
+                .flat_map(|vector| vector.iter())
+                .map(|value| format!("{value:.6}"))
+                .collect::<Vec<_>>();
+            let pbc = cell.periodic().map(|periodic| if periodic { "T" } else { "F" });
+            comment = format!(
+                "Lattice=\"{}\" pbc=\"{}\" {comment}",
+                lattice.join(" "),
+                pbc.join(" ")
+            )
+            .trim_end()
+            .to_string();
+        }
+        let mut lines = vec![atoms.len().to_string(), comment];
+        for (_, atom) in atoms {
+            let position = atom.position();
+            lines.push(format!(
+                "{} {:.6} {:.6} {:.6}",
+                elements::symbol(atom.element()).unwrap_or("X"),
+                position.x,
+                position.y,
+                position.z
+            ));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+mod test {
+    #[test]
+    fn xyz_lists_atoms_in_index_order() {
+        use crate::entity::{Atom, Molecule};
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let molecule = Molecule::default().set_atoms(HashMap::from([
+            (3, Some(Atom::new(1, Point3::new(0., 0., 0.74)))),
+            (1, Some(Atom::new(1, Point3::new(0., 0., 0.)))),
+            (2, None),
+        ]));
+        assert_eq!(
+            molecule.to_xyz("hydrogen\nmolecule"),
+            "2\nhydrogen molecule\nH 0.000000 0.000000 0.000000\nH 0.000000 0.000000 0.740000\n"
+        );
+        assert_eq!(Molecule::default().to_xyz(""), "0\n\n");
+    }
+
+    #[test]
+    fn xyz_reads_back_what_it_writes() {
+        use crate::{entity::Molecule, error::LMECoreError};
+
+        let text = "3\nwater\nO 0.0 0.0 0.117\n1 0.0 0.757 -0.467\nh 0.0 -0.757 -0.467 0.41\n";
+        let molecule = Molecule::from_xyz(text).unwrap();
+        let elements = molecule
+            .atoms()
+            .into_iter()
+            .map(|(idx, atom)| (idx, atom.element()))
+            .collect::<Vec<_>>();
+        assert_eq!(elements, vec![(0, 8), (1, 1), (2, 1)]);
+        let written = molecule.to_xyz("water");
+        assert_eq!(Molecule::from_xyz(&written).unwrap(), molecule);
+
+        assert!(matches!(
+            Molecule::from_xyz("2\n\nC 0 0 0\n"),
+            Err(LMECoreError::ParseError(4, _))
+        ));
+        assert!(matches!(
+            Molecule::from_xyz("1\n\nQ 0 0 0\n"),
+            Err(LMECoreError::ParseError(3, _))
+        ));
+    }
+
+    #[test]
+    fn extended_xyz_keeps_the_cell() {
+        use crate::{cell::Cell, entity::Molecule, error::LMECoreError};
+        use nalgebra::Vector3;
+
+        let text = concat!(
+            "2\n",
+            "Lattice=\"4.0 0.0 0.0 0.0 4.0 0.0 0.0 0.0 10.0\" Properties=species:S:1:pos:R:3 ",
+            "pbc=\"T T F\" slab\n",
+            "Na 0 0 0\nCl 2 2 0\n",
+        );
+        let molecule = Molecule::from_xyz(text).unwrap();
+        let cell = Cell::new(Vector3::x() * 4., Vector3::y() * 4., Vector3::z() * 10.);
+        let slab = cell.with_periodic([true, true, false]);
+        assert_eq!(molecule.cell(), Some(&slab));
+        let written = molecule.to_xyz("slab");
+        assert!(written.contains("pbc=\"T T F\" slab\n"));
+        assert_eq!(Molecule::from_xyz(&written).unwrap(), molecule);
+
+        let bulk = Molecule::from_xyz("0\nLattice=\"4 0 0 0 4 0 0 0 10\"\n").unwrap();
+        assert_eq!(bulk.cell(), Some(&cell));
+        assert!(matches!(
+            Molecule::from_xyz("0\nLattice=\"4 0 0\"\n"),
+            Err(LMECoreError::ParseError(2, _))
+        ));
+    }
+}
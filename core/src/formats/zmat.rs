@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use nalgebra::{Matrix3, Point3, Vector3};
+use pair::Pair;
+
+use crate::{
+    elements,
+    entity::{Atom, Molecule},
+    error::LMECoreError,
+    geometry, graph,
+};
+
+/// Placed atoms usable as a reference, most preferred first: bonded to
+/// `anchor` (if any) before the rest, in placement order.
+fn candidates<'a>(
+    placed: &'a [usize],
+    adjacency: &'a BTreeMap<usize, Vec<(usize, f64)>>,
+    anchor: usize,
+) -> impl Iterator<Item = usize> + 'a {
+    let bonded = move |idx: &usize| adjacency[&anchor].iter().any(|(n, _)| n == idx);
+    placed
+        .iter()
+        .copied()
+        .filter(move |idx| bonded(idx))
+        .chain(placed.iter().copied().filter(move |idx| !bonded(idx)))
+}
+
+fn collinear(a: &Point3<f64>, b: &Point3<f64>, c: &Point3<f64>) -> bool {
+    let angle = geometry::angle(a, b, c);
+    !(1. ..=179.).contains(&angle)
+}
+
+/// Position at `length` from `bond`, making `angle` with `angle_ref` and
+/// `dihedral` with `dihedral_ref` (degrees), by the natural extension
+/// reference frame construction. `None` if the references are collinear.
+fn place(
+    bond: &Point3<f64>,
+    angle_ref: &Point3<f64>,
+    dihedral_ref: &Point3<f64>,
+    length: f64,
+    angle: f64,
+    dihedral: f64,
+) -> Option<Point3<f64>> {
+    let bc = (bond - angle_ref).normalize();
+    let n = (angle_ref - dihedral_ref).cross(&bc);
+    if n.norm() < 1e-8 {
+        return None;
+    }
+    let n = n.normalize();
+    let frame = Matrix3::from_columns(&[bc, n.cross(&bc), n]);
+    let (angle, dihedral) = (angle.to_radians(), dihedral.to_radians());
+    let local = Vector3::new(
+        -length * angle.cos(),
+        length * angle.sin() * dihedral.cos(),
+        length * angle.sin() * dihedral.sin(),
+    );
+    Some(bond + frame * local)
+}
+
+/// Parse one Z-matrix line into the element and the `(reference, value)`
+/// pairs, checking references against the `placed` atoms before it.
+fn parse_line(line: &str, placed: usize) -> Result<(usize, Vec<(usize, f64)>), String> {
+    let mut fields = line
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|field| !field.is_empty());
+    let symbol = fields.next().ok_or("Missing element symbol")?;
+    let element = elements::number(symbol).ok_or(format!("Unknown element {symbol}"))?;
+    let fields = fields.collect::<Vec<_>>();
+    let expected = placed.min(3) * 2;
+    if fields.len() != expected {
+        return Err(format!(
+            "Expected {expected} fields after the element, found {}",
+            fields.len()
+        ));
+    }
+    let mut references = vec![];
+    for pair in fields.chunks(2) {
+        let reference = pair[0]
+            .parse::<usize>()
+            .ok()
+            .filter(|reference| (1..=placed).contains(reference))
+            .ok_or(format!("Invalid reference atom {}", pair[0]))?;
+        if references.iter().any(|(other, _)| *other == reference - 1) {
+            return Err(format!("Reference atom {reference} used twice"));
+        }
+        let value = pair[1]
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or(format!("Invalid value {}", pair[1]))?;
+        references.push((reference - 1, value));
+    }
+    if let Some((_, length)) = references.first() {
+        if *length <= 0. {
+            return Err(format!("Bond length {length} is not positive"));
+        }
+    }
+    Ok((element, references))
+}
+
+impl Molecule {
+    /// Build a molecule from a Z-matrix in the format written by
+    /// `to_zmatrix`, numbering atoms from 0 in line order and bonding every
+    /// atom to its distance reference. Blank lines are skipped; fields may be
+    /// separated by whitespace or commas. Errors carry the 1-based line number.
+    pub fn from_zmatrix(text: &str) -> Result<Molecule, LMECoreError> {
+        let mut positions: Vec<Point3<f64>> = vec![];
+        let mut atoms = HashMap::new();
+        let mut bonds = HashMap::new();
+        for (line_no, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let error = |message: String| LMECoreError::ParseError(line_no + 1, message);
+            let (element, references) = parse_line(line, positions.len()).map_err(error)?;
+            let position = match references[..] {
+                [] => Point3::origin(),
+                [(bond, length)] => positions[bond] + Vector3::z() * length,
+                [(bond, length), (angle_ref, angle)] => {
+                    let axis = positions[bond] - positions[angle_ref];
+                    let helper = if axis.x.abs() < 0.9 * axis.norm() {
+                        Vector3::x()
+                    } else {
+                        Vector3::y()
+                    };
+                    place(
+                        &positions[bond],
+                        &positions[angle_ref],
+                        &(positions[angle_ref] + helper),
+                        length,
+                        angle,
+                        0.,
+                    )
+                    .expect("Helper direction is not collinear")
+                }
+                [(bond, length), (angle_ref, angle), (dihedral_ref, dihedral)] => place(
+                    &positions[bond],
+                    &positions[angle_ref],
+                    &positions[dihedral_ref],
+                    length,
+                    angle,
+                    dihedral,
+                )
+                .ok_or_else(|| error("Reference atoms are collinear".to_string()))?,
+                _ => unreachable!("At most three references are parsed"),
+            };
+            let idx = positions.len();
+            if let Some((bond, _)) = references.first() {
+                bonds.insert(Pair::new_ordered(*bond, idx), 1.);
+            }
+            atoms.insert(idx, Some(Atom::new(element, position)));
+            positions.push(position);
+        }
+        Ok(Molecule::default().set_atoms(atoms).set_bonds(bonds))
+    }
+
+    /// Z-matrix of the molecule, one line per atom: element symbol, then
+    /// the bonded reference atom and bond length, an angle reference and
+    /// angle, and a dihedral reference and dihedral (degrees). References
+    /// are 1-based line numbers.
+    ///
+    /// Atoms are ordered by a breadth-first walk of the bond graph from the
+    /// lowest atom index, so each atom is placed relative to the atom it
+    /// is bonded to. Molecules with more than one fragment are rejected.
+    pub fn to_zmatrix(&self) -> Result<String, LMECoreError> {
+        let adjacency = graph::adjacency(self);
+        let Some(start) = adjacency.keys().next().copied() else {
+            return Ok(String::new());
+        };
+        let mut parents = HashMap::from([(start, None)]);
+        let mut order = vec![];
+        let mut queue = VecDeque::from([start]);
+        while let Some(idx) = queue.pop_front() {
+            order.push(idx);
+            for (neighbour, _) in &adjacency[&idx] {
+                if !parents.contains_key(neighbour) {
+                    parents.insert(*neighbour, Some(idx));
+                    queue.push_back(*neighbour);
+                }
+            }
+        }
+        if order.len() != adjacency.len() {
+            return Err(LMECoreError::DisconnectedFragments);
+        }
+
+        let line = order
+            .iter()
+            .enumerate()
+            .map(|(line, idx)| (*idx, line + 1))
+            .collect::<HashMap<_, _>>();
+        let position = |idx: usize| self.atom(idx).expect("Atom in bond graph").position();
+        let mut lines = vec![];
+        for (placed_count, idx) in order.iter().enumerate() {
+            let atom = self.atom(*idx).expect("Atom in bond graph");
+            let symbol = elements::symbol(atom.element()).unwrap_or("X");
+            let placed = &order[..placed_count];
+            let mut fields = vec![symbol.to_string()];
+            if let Some(bond) = parents[idx] {
+                let p = position(*idx);
+                let b = position(bond);
+                fields.push(format!("{} {:.6}", line[&bond], geometry::distance(&p, &b)));
+                let angle_ref = candidates(placed, &adjacency, bond).find(|other| *other != bond);
+                if let Some(angle_ref) = angle_ref {
+                    let a = position(angle_ref);
+                    fields.push(format!(
+                        "{} {:.6}",
+                        line[&angle_ref],
+                        geometry::angle(&p, &b, &a)
+                    ));
+                    let dihedral_ref = candidates(placed, &adjacency, angle_ref)
+                        .filter(|other| *other != bond && *other != angle_ref)
+                        .find(|other| !collinear(&b, &a, &position(*other)))
+                        .or_else(|| {
+                            candidates(placed, &adjacency, angle_ref)
+                                .find(|other| *other != bond && *other != angle_ref)
+                        });
+                    if let Some(dihedral_ref) = dihedral_ref {
+                        let d = position(dihedral_ref);
+                        fields.push(format!(
+                            "{} {:.6}",
+                            line[&dihedral_ref],
+                            geometry::dihedral(&p, &b, &a, &d)
+                        ));
+                    }
+                }
+            }
+            lines.push(fields.join(" "));
+        }
+        Ok(lines.join("\n") + "\n")
+    }
+}
+
+mod test {
+    #[test]
+    fn zmatrix_round_trip() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        // hydrogen peroxide, numbered so that the walk differs from index order
+        let atoms = [
+            (0, 1, Point3::new(-0.9, 0.9, 0.)),
+            (1, 8, Point3::new(0., 0.7, 0.)),
+            (2, 8, Point3::new(0., -0.7, 0.)),
+            (3, 1, Point3::new(0.9, -0.9, 0.3)),
+        ];
+        let molecule = Molecule::default()
+            .set_atoms(
+                atoms
+                    .iter()
+                    .map(|(idx, element, position)| (*idx, Some(Atom::new(*element, *position))))
+                    .collect(),
+            )
+            .set_bonds(HashMap::from([
+                (Pair::new_ordered(0, 1), 1.),
+                (Pair::new_ordered(1, 2), 1.),
+                (Pair::new_ordered(2, 3), 1.),
+            ]));
+        let zmatrix = molecule.to_zmatrix().unwrap();
+        let lines = zmatrix.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "H");
+        assert!(lines[1].starts_with("O 1 "));
+        assert!(lines[2].starts_with("O 2 1.400000 1 "));
+        let fields = lines[3].split(' ').collect::<Vec<_>>();
+        assert_eq!(fields[..2], ["H", "3"]);
+        let dihedral = fields[6].parse::<f64>().unwrap();
+        assert!(dihedral.abs() > 90. && dihedral.abs() < 180.);
+
+        let rebuilt = Molecule::from_zmatrix(&zmatrix).unwrap();
+        assert_eq!(rebuilt.to_zmatrix().unwrap(), zmatrix);
+        assert!(matches!(
+            Molecule::from_zmatrix("O\nH 1 0.96\n\nH 3 0.96 1 104.5"),
+            Err(LMECoreError::ParseError(4, _))
+        ));
+
+        let fragments = molecule.set_bonds(HashMap::from([(Pair::new_ordered(0, 1), 1.)]));
+        assert!(matches!(
+            fragments.to_zmatrix(),
+            Err(LMECoreError::DisconnectedFragments)
+        ));
+    }
+}
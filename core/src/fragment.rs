@@ -0,0 +1,189 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    f64::consts::PI,
+};
+
+use nalgebra::{Unit, UnitQuaternion};
+use pair::Pair;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entity::{Atom, Molecule},
+    error::LMECoreError,
+    graph,
+    hydrogens::perpendicular,
+};
+
+/// A substituent to put in place of a terminal atom.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Fragment {
+    pub molecule: Molecule,
+    /// Atom of the fragment bonded to the rest of the molecule
+    pub attachment: usize,
+    /// Atom of the fragment standing in for the atom it is attached to. It
+    /// fixes the direction and length of the new bond and is not added.
+    pub dummy: usize,
+}
+
+/// A fragment given in full or by its name in the workspace library.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum FragmentRef {
+    Named(String),
+    Inline(Box<Fragment>),
+}
+
+impl Fragment {
+    /// The attachment and dummy atoms, failing with `InvalidFragment` if
+    /// either is missing or they are the same atom.
+    pub fn check(&self) -> Result<(Atom, Atom), LMECoreError> {
+        let invalid = |message: &str| LMECoreError::InvalidFragment(message.to_string());
+        let attachment = self
+            .molecule
+            .atom(self.attachment)
+            .ok_or_else(|| invalid("Attachment atom is missing"))?;
+        let dummy = self
+            .molecule
+            .atom(self.dummy)
+            .ok_or_else(|| invalid("Dummy atom is missing"))?;
+        if self.attachment == self.dummy {
+            return Err(invalid("Attachment and dummy are the same atom"));
+        }
+        Ok((attachment, dummy))
+    }
+
+    /// The patch replacing the terminal atom `replaced` of `molecule` with
+    /// the fragment, and the index of each added fragment atom. The
+    /// attachment atom takes the index of `replaced`, the other atoms get
+    /// new indices in fragment index order. The fragment is turned so its
+    /// dummy-to-attachment vector follows the broken bond, keeping the
+    /// length of the fragment's own bond; the rotation about the bond is
+    /// arbitrary. The new bond has the order of the broken one.
+    ///
+    /// Fails with `MissingAtoms` if `replaced` is not in `molecule`, with
+    /// `NotTerminal` if it has other than one bond and with
+    /// `InvalidFragment` if the attachment or dummy atom is missing.
+    pub fn substitute(
+        &self,
+        molecule: &Molecule,
+        replaced: usize,
+    ) -> Result<(Molecule, BTreeMap<usize, usize>), LMECoreError> {
+        let invalid = |message: &str| LMECoreError::InvalidFragment(message.to_string());
+        let target = molecule
+            .atom(replaced)
+            .ok_or(LMECoreError::MissingAtoms(vec![replaced]))?;
+        let [(parent, order)] = graph::adjacency(molecule)[&replaced][..] else {
+            return Err(LMECoreError::NotTerminal(replaced));
+        };
+        let (attachment, dummy) = self.check()?;
+
+        let parent_position = molecule.atom(parent).expect("Bonded atom").position();
+        let bond = Unit::try_new(target.position() - parent_position, f64::EPSILON)
+            .ok_or_else(|| invalid("Replaced atom sits on the atom it is bonded to"))?;
+        let own_bond = attachment.position() - dummy.position();
+        let length = own_bond.norm();
+        let own_bond = Unit::try_new(own_bond, f64::EPSILON)
+            .ok_or_else(|| invalid("Attachment sits on the dummy atom"))?;
+        let rotation =
+            UnitQuaternion::rotation_between_axis(&own_bond, &bond).unwrap_or_else(|| {
+                UnitQuaternion::from_axis_angle(&Unit::new_unchecked(perpendicular(&own_bond)), PI)
+            });
+        let anchor = parent_position + bond.as_ref() * length;
+
+        let mut next = molecule.next_index();
+        let indices = self
+            .molecule
+            .atoms()
+            .into_iter()
+            .filter(|(idx, _)| *idx != self.dummy)
+            .map(|(idx, _)| {
+                if idx == self.attachment {
+                    (idx, replaced)
+                } else {
+                    next += 1;
+                    (idx, next - 1)
+                }
+            })
+            .collect::<BTreeMap<_, _>>();
+        let atoms = indices
+            .iter()
+            .map(|(from, to)| {
+                let atom = self.molecule.atom(*from).expect("Fragment atom");
+                let position = anchor + rotation * (atom.position() - attachment.position());
+                (*to, Some(atom.set_position(position)))
+            })
+            .collect::<HashMap<_, _>>();
+        let mut bonds = self
+            .molecule
+            .bonds()
+            .iter()
+            .filter_map(|(pair, order)| {
+                let (a, b) = (*pair).into();
+                let (a, b) = (indices.get(&a)?, indices.get(&b)?);
+                Some((Pair::new_ordered(*a, *b), *order))
+            })
+            .collect::<HashMap<_, _>>();
+        bonds.insert(Pair::new_ordered(parent, replaced), order);
+        let patch = Molecule::default().set_atoms(atoms).set_bonds(bonds);
+        Ok((patch, indices))
+    }
+}
+
+mod test {
+    #[test]
+    fn substitute_aligns_the_fragment_with_the_bond() {
+        use super::Fragment;
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::{BTreeMap, HashMap};
+
+        let atom = |element, x, y, z| Some(Atom::new(element, Point3::new(x, y, z)));
+        // hydroxyl hydrogen of methanol pointing along z
+        let molecule = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, atom(6, 0., 0., -1.4)),
+                (1, atom(8, 0., 0., 0.)),
+                (2, atom(1, 0., 0., 0.96)),
+            ]))
+            .set_bonds(HashMap::from([
+                (Pair::new_ordered(0, 1), 1.),
+                (Pair::new_ordered(1, 2), 1.),
+            ]));
+        // a methyl group bonded along -x to its dummy
+        let methyl = Fragment {
+            molecule: Molecule::default()
+                .set_atoms(HashMap::from([
+                    (0, atom(6, 0., 0., 0.)),
+                    (1, atom(0, -1.43, 0., 0.)),
+                    (2, atom(1, 0.36, 1.03, 0.)),
+                ]))
+                .set_bonds(HashMap::from([
+                    (Pair::new_ordered(0, 1), 1.),
+                    (Pair::new_ordered(0, 2), 1.),
+                ])),
+            attachment: 0,
+            dummy: 1,
+        };
+        let (patch, indices) = methyl.substitute(&molecule, 2).unwrap();
+        assert_eq!(indices, BTreeMap::from([(0, 2), (2, 3)]));
+        let merged = Molecule::merge(molecule.clone(), patch);
+        let carbon = merged.atom(2).unwrap();
+        assert_eq!(carbon.element(), 6);
+        assert!((carbon.position() - Point3::new(0., 0., 1.43)).norm() < 1e-9);
+        let hydrogen = merged.atom(3).unwrap().position();
+        assert!((hydrogen.z - 1.79).abs() < 1e-9);
+        assert_eq!(merged.bonds().len(), 3);
+        assert!(merged.bonds().contains_key(&Pair::new_ordered(2, 3)));
+        assert!(merged.bonds().contains_key(&Pair::new_ordered(1, 2)));
+
+        assert!(matches!(
+            methyl.substitute(&molecule, 1),
+            Err(LMECoreError::NotTerminal(1))
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+use nalgebra::{Isometry3, Matrix3, Point3, Rotation3, Translation3, UnitQuaternion, Vector3};
+
+pub fn centroid(points: &[Point3<f64>]) -> Point3<f64> {
+    if points.is_empty() {
+        return Point3::origin();
+    }
+    let sum = points
+        .iter()
+        .fold(Vector3::zeros(), |sum, point| sum + point.coords);
+    Point3::from(sum / points.len() as f64)
+}
+
+/// Mean of `points` weighted by `weights`, `None` if the weights sum to zero.
+pub fn weighted_centroid(points: &[Point3<f64>], weights: &[f64]) -> Option<Point3<f64>> {
+    let total = weights.iter().sum::<f64>();
+    if total == 0. {
+        return None;
+    }
+    let sum = points
+        .iter()
+        .zip(weights)
+        .fold(Vector3::zeros(), |sum, (point, weight)| {
+            sum + point.coords * *weight
+        });
+    Some(Point3::from(sum / total))
+}
+
+/// Proper rotation taking the principal axes of inertia of `points`, with
+/// masses `weights`, about `center` onto x, y and z in increasing order of
+/// moment. The direction of each axis is arbitrary.
+pub fn principal_axes(
+    points: &[Point3<f64>],
+    weights: &[f64],
+    center: &Point3<f64>,
+) -> Rotation3<f64> {
+    let inertia = points
+        .iter()
+        .zip(weights)
+        .fold(Matrix3::zeros(), |inertia, (point, weight)| {
+            let r = point - center;
+            inertia + (Matrix3::identity() * r.norm_squared() - r * r.transpose()) * *weight
+        });
+    let eigen = inertia.symmetric_eigen();
+    let mut order = [0, 1, 2];
+    order.sort_by(|a, b| eigen.eigenvalues[*a].total_cmp(&eigen.eigenvalues[*b]));
+    let mut axes = Matrix3::from_rows(&order.map(|idx| eigen.eigenvectors.column(idx).transpose()));
+    if axes.determinant() < 0. {
+        axes.row_mut(2).neg_mut();
+    }
+    Rotation3::from_matrix_unchecked(axes)
+}
+
+pub fn distance(a: &Point3<f64>, b: &Point3<f64>) -> f64 {
+    (a - b).norm()
+}
+
+/// Angle a-b-c at `b`, in degrees.
+pub fn angle(a: &Point3<f64>, b: &Point3<f64>, c: &Point3<f64>) -> f64 {
+    (a - b).angle(&(c - b)).to_degrees()
+}
+
+/// Dihedral angle a-b-c-d in degrees, in (-180, 180] with the IUPAC sign convention.
+pub fn dihedral(a: &Point3<f64>, b: &Point3<f64>, c: &Point3<f64>, d: &Point3<f64>) -> f64 {
+    let (b1, b2, b3) = (b - a, c - b, d - c);
+    let (n1, n2) = (b1.cross(&b2), b2.cross(&b3));
+    (b2.norm() * b1.dot(&n2)).atan2(n1.dot(&n2)).to_degrees()
+}
+
+pub fn rmsd(p: &[Point3<f64>], q: &[Point3<f64>]) -> f64 {
+    assert_eq!(p.len(), q.len(), "RMSD requires point sets of equal size");
+    if p.is_empty() {
+        return 0.;
+    }
+    let sum = p
+        .iter()
+        .zip(q)
+        .map(|(a, b)| (a - b).norm_squared())
+        .sum::<f64>();
+    (sum / p.len() as f64).sqrt()
+}
+
+/// Optimal rotation (Kabsch algorithm) superposing the centered `p` onto the centered `q`.
+pub fn kabsch(p: &[Point3<f64>], q: &[Point3<f64>]) -> Matrix3<f64> {
+    assert_eq!(p.len(), q.len(), "Kabsch requires point sets of equal size");
+    let (p_center, q_center) = (centroid(p), centroid(q));
+    let covariance = p.iter().zip(q).fold(Matrix3::zeros(), |h, (a, b)| {
+        h + (a - p_center) * (b - q_center).transpose()
+    });
+    let svd = covariance.svd(true, true);
+    let (u, v_t) = (
+        svd.u.expect("SVD computed with U"),
+        svd.v_t.expect("SVD computed with V^T"),
+    );
+    let d = (v_t.transpose() * u.transpose()).determinant().signum();
+    v_t.transpose() * Matrix3::from_diagonal(&Vector3::new(1., 1., d)) * u.transpose()
+}
+
+/// RMSD between `p` and `q` after translating both to their centroids and
+/// rotating `p` onto `q`.
+pub fn aligned_rmsd(p: &[Point3<f64>], q: &[Point3<f64>]) -> f64 {
+    let motion = superpose(p, q);
+    let aligned = p.iter().map(|point| motion * point).collect::<Vec<_>>();
+    rmsd(&aligned, q)
+}
+
+/// Rigid motion superposing `p` onto `q`: `p` moved to the centroid of `q`
+/// and rotated with `kabsch` about it.
+pub fn superpose(p: &[Point3<f64>], q: &[Point3<f64>]) -> Isometry3<f64> {
+    let rotation = Rotation3::from_matrix_unchecked(kabsch(p, q));
+    let (p_center, q_center) = (centroid(p), centroid(q));
+    Translation3::from(q_center.coords)
+        * UnitQuaternion::from_rotation_matrix(&rotation)
+        * Translation3::from(-p_center.coords)
+}
+
+/// Greedy complete-linkage clustering over the pairwise distances given by `distance`.
+///
+/// Items are visited in index order; each joins the first existing cluster
+/// whose members are all closer than `threshold`, otherwise it starts a new
+/// cluster. The result only depends on the input order, so it is deterministic.
+pub fn greedy_cluster<F>(items: usize, threshold: f64, distance: F) -> Vec<Vec<usize>>
+where
+    F: Fn(usize, usize) -> f64,
+{
+    let mut clusters: Vec<Vec<usize>> = vec![];
+    for item in 0..items {
+        let cluster = clusters.iter_mut().find(|cluster| {
+            cluster
+                .iter()
+                .all(|member| distance(*member, item) < threshold)
+        });
+        if let Some(cluster) = cluster {
+            cluster.push(item)
+        } else {
+            clusters.push(vec![item])
+        }
+    }
+    clusters
+}
+
+mod test {
+    #[test]
+    fn aligned_rmsd_ignores_rigid_motion() {
+        use crate::geometry::aligned_rmsd;
+        use nalgebra::{Point3, Rotation3, Vector3};
+
+        let p = vec![
+            Point3::new(0., 0., 0.),
+            Point3::new(1.5, 0., 0.),
+            Point3::new(0., 1.2, 0.3),
+            Point3::new(-0.4, 0.2, 1.1),
+        ];
+        let rotation = Rotation3::from_axis_angle(&Vector3::z_axis(), 1.1);
+        let q = p
+            .iter()
+            .map(|point| rotation * point + Vector3::new(3., -2., 5.))
+            .collect::<Vec<_>>();
+        assert!(aligned_rmsd(&p, &q) < 1e-8);
+    }
+
+    #[test]
+    fn principal_axes_follow_the_moments() {
+        use crate::geometry::{principal_axes, weighted_centroid};
+        use nalgebra::{Point3, Vector3};
+
+        // a rod along (1, 1, 0) with a light atom off its axis along z
+        let points = [
+            Point3::new(-1., -1., 0.),
+            Point3::new(1., 1., 0.),
+            Point3::new(0., 0., 0.5),
+        ];
+        let weights = [12., 12., 1.];
+        let center = weighted_centroid(&points, &weights).unwrap();
+        assert!((center - Point3::new(0., 0., 0.02)).norm() < 1e-12);
+        let rotation = principal_axes(&points, &weights, &center);
+        assert!((rotation.matrix().determinant() - 1.).abs() < 1e-12);
+        let rod = rotation * Vector3::new(1., 1., 0.).normalize();
+        assert!((rod.x.abs() - 1.).abs() < 1e-9, "{rod}");
+        let off_axis = rotation * Vector3::z();
+        assert!((off_axis.y.abs() - 1.).abs() < 1e-9, "{off_axis}");
+        assert_eq!(weighted_centroid(&points, &[0.; 3]), None);
+    }
+
+    #[test]
+    fn greedy_cluster_keeps_input_order() {
+        use crate::geometry::greedy_cluster;
+
+        let values: [f64; 5] = [0., 0.5, 10., 0.8, 10.2];
+        let clusters = greedy_cluster(values.len(), 1., |a, b| (values[a] - values[b]).abs());
+        assert_eq!(clusters, vec![vec![0, 1, 3], vec![2, 4]]);
+    }
+}
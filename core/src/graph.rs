@@ -0,0 +1,717 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    hash::Hash,
+};
+
+use pair::Pair;
+
+use crate::{elements, entity::Molecule, error::LMECoreError, spatial::SpatialIndex};
+
+/// Default slack, in Å, added to the sum of covalent radii by `perceive_bonds`.
+pub const DEFAULT_BOND_TOLERANCE: f64 = 0.4;
+
+/// Bond graph over the existing atoms of a molecule: atom index to
+/// `(neighbour, bond order)` pairs. Bonds touching removed atoms are skipped.
+pub fn adjacency(molecule: &Molecule) -> BTreeMap<usize, Vec<(usize, f64)>> {
+    let mut adjacency = molecule
+        .atoms()
+        .into_iter()
+        .map(|(idx, _)| (idx, vec![]))
+        .collect::<BTreeMap<_, _>>();
+    for (pair, order) in molecule.bonds() {
+        let (a, b) = (*pair).into();
+        if adjacency.contains_key(&a) && adjacency.contains_key(&b) && a != b {
+            adjacency.entry(a).or_default().push((b, *order));
+            adjacency.entry(b).or_default().push((a, *order));
+        }
+    }
+    adjacency
+        .values_mut()
+        .for_each(|neighbours| neighbours.sort_by_key(|(idx, _)| *idx));
+    adjacency
+}
+
+fn dense_ranks<K: Ord + Clone + Hash>(keys: &BTreeMap<usize, K>) -> BTreeMap<usize, usize> {
+    let mut distinct = keys.values().cloned().collect::<Vec<_>>();
+    distinct.sort();
+    distinct.dedup();
+    let ranks = distinct
+        .into_iter()
+        .enumerate()
+        .map(|(rank, key)| (key, rank))
+        .collect::<HashMap<_, _>>();
+    keys.iter().map(|(idx, key)| (*idx, ranks[key])).collect()
+}
+
+fn distinct_count(ranks: &BTreeMap<usize, usize>) -> usize {
+    ranks.values().max().map_or(0, |max| max + 1)
+}
+
+/// Canonical atom order of a molecule, heaviest atoms first.
+///
+/// Atoms are ranked by element and connectivity, then the ranks are refined
+/// with the neighbours' ranks (Morgan-like) until stable. Remaining ties
+/// (symmetry-equivalent atoms) are broken in favour of the lower original
+/// index and refined again, so the result only depends on the bond graph.
+pub fn canonical_order(molecule: &Molecule) -> Vec<usize> {
+    let adjacency = adjacency(molecule);
+    let bond_key = |order: f64| (order * 1000.).round() as i64;
+    let initial = molecule
+        .atoms()
+        .into_iter()
+        .map(|(idx, atom)| {
+            let neighbours = &adjacency[&idx];
+            let valence = neighbours
+                .iter()
+                .map(|(_, order)| bond_key(*order))
+                .sum::<i64>();
+            (idx, (Reverse(atom.element()), neighbours.len(), valence))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let mut ranks = dense_ranks(&initial);
+
+    loop {
+        loop {
+            let keys = ranks
+                .iter()
+                .map(|(idx, rank)| {
+                    let mut neighbours = adjacency[idx]
+                        .iter()
+                        .map(|(neighbour, order)| (ranks[neighbour], bond_key(*order)))
+                        .collect::<Vec<_>>();
+                    neighbours.sort();
+                    (*idx, (*rank, neighbours))
+                })
+                .collect::<BTreeMap<_, _>>();
+            let refined = dense_ranks(&keys);
+            let stable = distinct_count(&refined) == distinct_count(&ranks);
+            ranks = refined;
+            if stable {
+                break;
+            }
+        }
+        if distinct_count(&ranks) == ranks.len() {
+            break;
+        }
+        let mut members = HashMap::<usize, Vec<usize>>::new();
+        ranks
+            .iter()
+            .for_each(|(idx, rank)| members.entry(*rank).or_default().push(*idx));
+        let tied = members
+            .into_iter()
+            .filter(|(_, members)| members.len() > 1)
+            .min_by_key(|(rank, _)| *rank)
+            .map(|(_, members)| members.into_iter().min().expect("Tie has members"))
+            .expect("Ranks are not distinct");
+        let keys = ranks
+            .iter()
+            .map(|(idx, rank)| (*idx, (*rank, *idx != tied)))
+            .collect::<BTreeMap<_, _>>();
+        ranks = dense_ranks(&keys);
+    }
+
+    let mut order = ranks.into_iter().collect::<Vec<_>>();
+    order.sort_by_key(|(_, rank)| *rank);
+    order.into_iter().map(|(idx, _)| idx).collect()
+}
+
+/// Pairs of existing atoms that are not bonded yet but closer than the
+/// sum of their covalent radii plus `tolerance`, sorted, with `index` the
+/// spatial index of `molecule`. Meant for geometries read without
+/// connectivity; bond orders are not guessed.
+pub fn perceive_bonds(
+    molecule: &Molecule,
+    index: &SpatialIndex,
+    tolerance: f64,
+) -> Vec<Pair<usize>> {
+    let radii = molecule
+        .atoms()
+        .into_iter()
+        .map(|(idx, atom)| (idx, elements::covalent_radius(atom.element())))
+        .collect::<HashMap<_, _>>();
+    let reach = radii.values().copied().fold(0., f64::max);
+    index
+        .pairs_within(2. * reach + tolerance)
+        .into_iter()
+        .filter(|(a, b, distance)| *distance < radii[a] + radii[b] + tolerance)
+        .map(|(a, b, _)| Pair::new_ordered(a, b))
+        .filter(|pair| !molecule.bonds().contains_key(pair))
+        .collect()
+}
+
+/// Connected components of the bond graph, each sorted, ordered by their
+/// lowest atom index. Atoms without bonds are components of their own.
+pub fn components(molecule: &Molecule) -> Vec<Vec<usize>> {
+    let adjacency = adjacency(molecule);
+    let mut seen = BTreeSet::new();
+    let mut components = vec![];
+    for start in adjacency.keys() {
+        if !seen.insert(*start) {
+            continue;
+        }
+        let mut component = vec![*start];
+        let mut queue = vec![*start];
+        while let Some(idx) = queue.pop() {
+            for (neighbour, _) in &adjacency[&idx] {
+                if seen.insert(*neighbour) {
+                    component.push(*neighbour);
+                    queue.push(*neighbour);
+                }
+            }
+        }
+        component.sort();
+        components.push(component);
+    }
+    components
+}
+
+/// Bonds that are in some ring, with each atom's ring neighbours: the bond
+/// graph with its bridges removed (Tarjan's algorithm), keeping only atoms
+/// left with a neighbour.
+fn ring_bonds(adjacency: &BTreeMap<usize, Vec<(usize, f64)>>) -> BTreeMap<usize, Vec<usize>> {
+    let mut order = HashMap::new();
+    let mut low = HashMap::new();
+    let mut bridges = BTreeSet::new();
+    for root in adjacency.keys() {
+        if order.contains_key(root) {
+            continue;
+        }
+        order.insert(*root, order.len());
+        low.insert(*root, order[root]);
+        // atom, the atom it was reached from, next neighbour to visit
+        let mut stack = vec![(*root, None, 0)];
+        while let Some((idx, parent, next)) = stack.pop() {
+            if let Some((neighbour, _)) = adjacency[&idx].get(next) {
+                stack.push((idx, parent, next + 1));
+                if Some(*neighbour) == parent {
+                    continue;
+                }
+                if let Some(seen) = order.get(neighbour) {
+                    let seen = *seen;
+                    low.entry(idx).and_modify(|low| *low = seen.min(*low));
+                } else {
+                    order.insert(*neighbour, order.len());
+                    low.insert(*neighbour, order[neighbour]);
+                    stack.push((*neighbour, Some(idx), 0));
+                }
+            } else if let Some(parent) = parent {
+                let reached = low[&idx];
+                low.entry(parent).and_modify(|low| *low = reached.min(*low));
+                if reached > order[&parent] {
+                    bridges.insert(Pair::new_ordered(parent, idx));
+                }
+            }
+        }
+    }
+    adjacency
+        .iter()
+        .map(|(idx, neighbours)| {
+            let neighbours = neighbours
+                .iter()
+                .map(|(neighbour, _)| *neighbour)
+                .filter(|neighbour| !bridges.contains(&Pair::new_ordered(*idx, *neighbour)))
+                .collect::<Vec<_>>();
+            (*idx, neighbours)
+        })
+        .filter(|(_, neighbours)| !neighbours.is_empty())
+        .collect()
+}
+
+/// A ring as its atoms in ring order, starting from the lowest index and
+/// continuing towards its lower neighbour.
+fn normalize_ring(mut ring: Vec<usize>) -> Vec<usize> {
+    let start = (0..ring.len()).min_by_key(|idx| ring[*idx]).unwrap_or(0);
+    ring.rotate_left(start);
+    if ring.len() > 2 && ring[ring.len() - 1] < ring[1] {
+        ring[1..].reverse();
+    }
+    ring
+}
+
+/// Smallest set of smallest rings of the bond graph, each as its atoms in
+/// ring order (see `normalize_ring`), sorted by size and then by atoms.
+///
+/// Each ring system is handled on its own: candidate rings are built
+/// Horton-style from the shortest paths between every atom and the two ends
+/// of every ring bond, then taken in increasing size as long as they are
+/// independent of those taken before, until the system has as many rings as
+/// its cyclomatic number.
+pub fn rings(molecule: &Molecule) -> Vec<Vec<usize>> {
+    let ring_bonds = ring_bonds(&adjacency(molecule));
+    let mut rings = vec![];
+    let mut assigned = BTreeSet::new();
+    for start in ring_bonds.keys() {
+        if !assigned.insert(*start) {
+            continue;
+        }
+        let mut system = vec![*start];
+        let mut queue = vec![*start];
+        while let Some(idx) = queue.pop() {
+            for neighbour in &ring_bonds[&idx] {
+                if assigned.insert(*neighbour) {
+                    system.push(*neighbour);
+                    queue.push(*neighbour);
+                }
+            }
+        }
+        system.sort();
+        rings.extend(system_rings(&ring_bonds, &system));
+    }
+    rings.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
+    rings
+}
+
+fn system_rings(ring_bonds: &BTreeMap<usize, Vec<usize>>, atoms: &[usize]) -> Vec<Vec<usize>> {
+    let bonds = atoms
+        .iter()
+        .flat_map(|idx| {
+            ring_bonds[idx]
+                .iter()
+                .map(|other| Pair::new_ordered(*idx, *other))
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .enumerate()
+        .map(|(nth, pair)| (pair, nth))
+        .collect::<BTreeMap<_, _>>();
+    let wanted = bonds.len() + 1 - atoms.len();
+
+    let mut candidates = BTreeSet::new();
+    for root in atoms {
+        let mut parent = HashMap::from([(*root, *root)]);
+        let mut queue = VecDeque::from([*root]);
+        while let Some(idx) = queue.pop_front() {
+            for neighbour in &ring_bonds[&idx] {
+                if !parent.contains_key(neighbour) {
+                    parent.insert(*neighbour, idx);
+                    queue.push_back(*neighbour);
+                }
+            }
+        }
+        let path = |mut idx: usize| {
+            let mut path = vec![idx];
+            while idx != *root {
+                idx = parent[&idx];
+                path.push(idx);
+            }
+            path
+        };
+        for pair in bonds.keys() {
+            let (a, b) = (*pair).into();
+            if parent[&a] == b || parent[&b] == a {
+                continue;
+            }
+            let (to_a, to_b) = (path(a), path(b));
+            if to_a.iter().filter(|idx| to_b.contains(idx)).count() != 1 {
+                continue;
+            }
+            // root to a, then b back to just before root
+            let mut ring = to_a;
+            ring.reverse();
+            ring.extend(&to_b[..to_b.len() - 1]);
+            let ring = normalize_ring(ring);
+            candidates.insert((ring.len(), ring));
+        }
+    }
+
+    // the rings taken so far as bond sets, by their lowest bond
+    let mut basis = BTreeMap::<usize, Vec<u64>>::new();
+    let mut rings = vec![];
+    for (_, ring) in candidates {
+        if rings.len() == wanted {
+            break;
+        }
+        let mut bits = vec![0u64; bonds.len().div_ceil(64)];
+        for (nth, idx) in ring.iter().enumerate() {
+            let bond = bonds[&Pair::new_ordered(*idx, ring[(nth + 1) % ring.len()])];
+            bits[bond / 64] ^= 1 << (bond % 64);
+        }
+        while let Some(lowest) = bits
+            .iter()
+            .enumerate()
+            .find(|(_, word)| **word != 0)
+            .map(|(nth, word)| nth * 64 + word.trailing_zeros() as usize)
+        {
+            match basis.get(&lowest) {
+                Some(reducer) => bits
+                    .iter_mut()
+                    .zip(reducer)
+                    .for_each(|(word, reducer)| *word ^= reducer),
+                None => {
+                    basis.insert(lowest, bits);
+                    rings.push(ring);
+                    break;
+                }
+            }
+        }
+    }
+    rings
+}
+
+/// Pattern atom to molecule atom, see `substructures`.
+pub type Mapping = BTreeMap<usize, usize>;
+
+/// Complete `mapping`, which maps the pattern atoms before `depth` in
+/// `order`, in every possible way, calling `found` with each complete one.
+fn extend_match(
+    depth: usize,
+    order: &[usize],
+    mapping: &mut Mapping,
+    candidates: &dyn Fn(&Mapping, usize) -> Vec<usize>,
+    found: &mut dyn FnMut(&Mapping),
+) {
+    let Some(query_idx) = order.get(depth) else {
+        found(mapping);
+        return;
+    };
+    for idx in candidates(mapping, *query_idx) {
+        mapping.insert(*query_idx, idx);
+        extend_match(depth + 1, order, mapping, candidates, found);
+        mapping.remove(query_idx);
+    }
+}
+
+/// Every occurrence of `pattern` in the bond graph of `molecule`, as a
+/// mapping from pattern atom to molecule atom. Pattern atoms of element 0
+/// match any element and pattern bonds of order 0 match any bond order;
+/// other bonds must have the same order. Atoms not bonded in the pattern
+/// may be bonded in the molecule. Matches covering the same atoms, such as
+/// the symmetric ones of a carboxylate, are reported once.
+pub fn substructures(pattern: &Molecule, molecule: &Molecule) -> Vec<Mapping> {
+    let (query, target) = (adjacency(pattern), adjacency(molecule));
+    // pattern atoms in breadth-first order, so each one but the first of a
+    // component is bonded to an earlier one
+    let mut order = vec![];
+    let mut seen = BTreeSet::new();
+    for start in query.keys() {
+        if !seen.insert(*start) {
+            continue;
+        }
+        let mut queue = VecDeque::from([*start]);
+        while let Some(idx) = queue.pop_front() {
+            order.push(idx);
+            for (neighbour, _) in &query[&idx] {
+                if seen.insert(*neighbour) {
+                    queue.push_back(*neighbour);
+                }
+            }
+        }
+    }
+    let element = |molecule: &Molecule, idx: usize| molecule.atom(idx).expect("Atom").element();
+    let bond = |idx: usize, other: usize| {
+        target[&idx]
+            .iter()
+            .find(|(neighbour, _)| *neighbour == other)
+            .map(|(_, order)| *order)
+    };
+    let fits = |mapping: &Mapping, query_idx: usize, idx: usize| {
+        let wanted = element(pattern, query_idx);
+        (wanted == 0 || wanted == element(molecule, idx))
+            && target[&idx].len() >= query[&query_idx].len()
+            && !mapping.values().any(|used| *used == idx)
+            && query[&query_idx].iter().all(|(neighbour, order)| {
+                let Some(mapped) = mapping.get(neighbour) else {
+                    return true;
+                };
+                bond(idx, *mapped).is_some_and(|found| *order == 0. || found == *order)
+            })
+    };
+
+    let candidates = |mapping: &Mapping, query_idx: usize| {
+        let anchor = query[&query_idx]
+            .iter()
+            .find_map(|(neighbour, _)| mapping.get(neighbour));
+        let candidates = match anchor {
+            Some(anchor) => target[anchor].iter().map(|(idx, _)| *idx).collect(),
+            None => target.keys().copied().collect::<Vec<_>>(),
+        };
+        candidates
+            .into_iter()
+            .filter(|idx| fits(mapping, query_idx, *idx))
+            .collect()
+    };
+    let mut matches = vec![];
+    let mut covered = BTreeSet::new();
+    if !order.is_empty() {
+        extend_match(
+            0,
+            &order,
+            &mut Mapping::new(),
+            &candidates,
+            &mut |mapping| {
+                if covered.insert(mapping.values().copied().collect::<BTreeSet<_>>()) {
+                    matches.push(mapping.clone());
+                }
+            },
+        );
+    }
+    matches
+}
+
+/// The atoms still connected to `moving` once its bond to `fixed` is cut,
+/// `moving` included. Fails with `MissingAtoms` if either atom is missing,
+/// with `NotBonded` if they are not bonded and with `RingBond` if the bond
+/// is in a ring, leaving one connected piece.
+pub fn side(
+    molecule: &Molecule,
+    fixed: usize,
+    moving: usize,
+) -> Result<BTreeSet<usize>, LMECoreError> {
+    let adjacency = adjacency(molecule);
+    let missing = [fixed, moving]
+        .into_iter()
+        .filter(|idx| !adjacency.contains_key(idx))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(LMECoreError::MissingAtoms(missing));
+    }
+    if !adjacency[&moving].iter().any(|(idx, _)| *idx == fixed) {
+        return Err(LMECoreError::NotBonded(fixed, moving));
+    }
+    let mut side = BTreeSet::from([moving]);
+    let mut queue = vec![moving];
+    while let Some(idx) = queue.pop() {
+        for (neighbour, _) in &adjacency[&idx] {
+            if idx == moving && *neighbour == fixed {
+                continue;
+            }
+            if *neighbour == fixed {
+                return Err(LMECoreError::RingBond(fixed, moving));
+            }
+            if side.insert(*neighbour) {
+                queue.push(*neighbour);
+            }
+        }
+    }
+    Ok(side)
+}
+
+mod test {
+    #[test]
+    fn canonical_order_ignores_input_numbering() {
+        use crate::{
+            entity::{Atom, Molecule},
+            graph::canonical_order,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::{HashMap, HashSet};
+
+        // acetaldehyde: C0-C1=O2, three H on C0, one H on C1
+        let elements = [6, 6, 8, 1, 1, 1, 1];
+        let bonds = [
+            (0, 1, 1.),
+            (1, 2, 2.),
+            (0, 3, 1.),
+            (0, 4, 1.),
+            (0, 5, 1.),
+            (1, 6, 1.),
+        ];
+        let build = |permutation: &[usize]| {
+            let atoms = elements
+                .iter()
+                .enumerate()
+                .map(|(idx, element)| {
+                    (
+                        permutation[idx],
+                        Some(Atom::new(*element, Point3::origin())),
+                    )
+                })
+                .collect::<HashMap<_, _>>();
+            let bonds = bonds
+                .iter()
+                .map(|(a, b, order)| (Pair::new_ordered(permutation[*a], permutation[*b]), *order))
+                .collect::<HashMap<_, _>>();
+            Molecule::default().set_atoms(atoms).set_bonds(bonds)
+        };
+        let canonical = |molecule: Molecule| {
+            let mapping = canonical_order(&molecule)
+                .into_iter()
+                .enumerate()
+                .map(|(new, old)| (old, new))
+                .collect::<HashMap<_, _>>();
+            let molecule = molecule.remap(&mapping);
+            let elements = molecule
+                .atoms()
+                .into_iter()
+                .map(|(idx, atom)| (idx, atom.element()))
+                .collect::<Vec<_>>();
+            let bonds = molecule.bonds().keys().copied().collect::<HashSet<_>>();
+            (elements, bonds)
+        };
+
+        let (elements, bonds) = canonical(build(&[0, 1, 2, 3, 4, 5, 6]));
+        assert_eq!(elements[0], (0, 8));
+        assert_eq!((elements, bonds), canonical(build(&[6, 4, 0, 2, 5, 1, 3])));
+    }
+
+    #[test]
+    fn bonds_are_perceived_from_distances() {
+        use super::perceive_bonds;
+        use crate::{
+            entity::{Atom, Molecule},
+            spatial::SpatialIndex,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let atom = |element, x| Some(Atom::new(element, Point3::new(x, 0., 0.)));
+        // H-O-H in a line with a distant chloride
+        let molecule = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, atom(1, -0.96)),
+                (1, atom(8, 0.)),
+                (2, atom(1, 0.96)),
+                (5, atom(17, 4.)),
+            ]))
+            .set_bonds(HashMap::from([(Pair::new_ordered(0, 1), 1.)]));
+        let index = SpatialIndex::new(&molecule);
+        assert_eq!(
+            perceive_bonds(&molecule, &index, 0.4),
+            vec![Pair::new_ordered(1, 2)]
+        );
+        assert_eq!(
+            perceive_bonds(&molecule, &index, 1.5),
+            vec![Pair::new_ordered(0, 2), Pair::new_ordered(1, 2)]
+        );
+    }
+
+    #[test]
+    fn components_follow_bonds() {
+        use crate::{
+            entity::{Atom, Molecule},
+            graph::components,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let molecule = Molecule::default()
+            .set_atoms(
+                (0..6)
+                    .map(|idx| (idx, Some(Atom::new(6, Point3::origin()))))
+                    .collect::<HashMap<_, _>>(),
+            )
+            .set_bonds(
+                [(0, 4), (4, 2), (1, 5)]
+                    .into_iter()
+                    .map(|(a, b)| (Pair::new_ordered(a, b), 1.))
+                    .collect(),
+            );
+        assert_eq!(
+            components(&molecule),
+            vec![vec![0, 2, 4], vec![1, 5], vec![3]]
+        );
+    }
+
+    #[test]
+    fn rings_are_the_smallest_set() {
+        use crate::{
+            entity::{Atom, Molecule},
+            graph::rings,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let build = |atoms: usize, bonds: &[(usize, usize)]| {
+            Molecule::default()
+                .set_atoms(
+                    (0..atoms)
+                        .map(|idx| (idx, Some(Atom::new(6, Point3::origin()))))
+                        .collect::<HashMap<_, _>>(),
+                )
+                .set_bonds(
+                    bonds
+                        .iter()
+                        .map(|(a, b)| (Pair::new_ordered(*a, *b), 1.))
+                        .collect(),
+                )
+        };
+        // naphthalene, with a methyl 10 on atom 2 and a cyclopropyl 11-13
+        // bonded to it
+        let mut bonds = (0..5).map(|idx| (idx, idx + 1)).collect::<Vec<_>>();
+        bonds.extend([(5, 0), (5, 6), (6, 7), (7, 8), (8, 9), (9, 0)]);
+        bonds.extend([(2, 10), (10, 11), (11, 12), (12, 13), (13, 11)]);
+        assert_eq!(
+            rings(&build(14, &bonds)),
+            vec![
+                vec![11, 12, 13],
+                vec![0, 1, 2, 3, 4, 5],
+                vec![0, 5, 6, 7, 8, 9],
+            ]
+        );
+
+        // cubane has five rings in its smallest set, all four-membered
+        let cube = (0..4)
+            .flat_map(|idx| {
+                [
+                    (idx, (idx + 1) % 4),
+                    (idx + 4, (idx + 1) % 4 + 4),
+                    (idx, idx + 4),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let rings = rings(&build(8, &cube));
+        assert_eq!(rings.len(), 5);
+        assert!(rings.iter().all(|ring| ring.len() == 4));
+        assert!(super::rings(&build(3, &[(0, 1), (1, 2)])).is_empty());
+    }
+
+    #[test]
+    fn substructures_match_once_per_atom_set() {
+        use crate::{
+            entity::{Atom, Molecule},
+            graph::substructures,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::{BTreeMap, HashMap};
+
+        let build = |elements: &[usize], bonds: &[(usize, usize, f64)]| {
+            Molecule::default()
+                .set_atoms(
+                    elements
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, element)| (idx, Some(Atom::new(*element, Point3::origin()))))
+                        .collect::<HashMap<_, _>>(),
+                )
+                .set_bonds(
+                    bonds
+                        .iter()
+                        .map(|(a, b, order)| (Pair::new_ordered(*a, *b), *order))
+                        .collect(),
+                )
+        };
+        // malonic acid: HOOC-CH2-COOH, hydrogens left out
+        let acid = build(
+            &[6, 6, 8, 8, 6, 8, 8],
+            &[
+                (0, 1, 1.),
+                (1, 2, 2.),
+                (1, 3, 1.),
+                (0, 4, 1.),
+                (4, 5, 2.),
+                (4, 6, 1.),
+            ],
+        );
+        // any atom bonded to a carbon with two oxygens, the bond orders of
+        // the carbon-oxygen bonds left open
+        let carboxyl = build(&[0, 6, 8, 8], &[(0, 1, 1.), (1, 2, 0.), (1, 3, 0.)]);
+        let matches = substructures(&carboxyl, &acid);
+        assert_eq!(
+            matches,
+            vec![
+                BTreeMap::from([(0, 0), (1, 1), (2, 2), (3, 3)]),
+                BTreeMap::from([(0, 0), (1, 4), (2, 5), (3, 6)]),
+            ]
+        );
+        let ketone = build(&[6, 8], &[(0, 1, 2.)]);
+        assert_eq!(substructures(&ketone, &acid).len(), 2);
+        let peroxide = build(&[8, 8], &[(0, 1, 1.)]);
+        assert!(substructures(&peroxide, &acid).is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use crate::entity::Stack;
+
+/// Number of earlier versions kept per stack unless configured otherwise.
+pub const DEFAULT_HISTORY_DEPTH: usize = 64;
+
+/// Earlier and undone versions of one stack. Stacks are immutable behind
+/// their `Arc`, so a version is just the `Arc` that was replaced.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct StackHistory {
+    undo: VecDeque<Arc<Stack>>,
+    redo: Vec<Arc<Stack>>,
+}
+
+impl StackHistory {
+    /// Remember `previous` before it is replaced by a new edit, dropping
+    /// the oldest version beyond `depth` and the whole redo tail.
+    pub fn record(&mut self, previous: Arc<Stack>, depth: usize) {
+        self.redo.clear();
+        self.undo.push_back(previous);
+        while self.undo.len() > depth {
+            self.undo.pop_front();
+        }
+    }
+
+    /// The version before `current`, which becomes redoable.
+    pub fn undo(&mut self, current: Arc<Stack>) -> Option<Arc<Stack>> {
+        let previous = self.undo.pop_back()?;
+        self.redo.push(current);
+        Some(previous)
+    }
+
+    /// The version undone last, with `current` becoming undoable again.
+    pub fn redo(&mut self, current: Arc<Stack>) -> Option<Arc<Stack>> {
+        let next = self.redo.pop()?;
+        self.undo.push_back(current);
+        Some(next)
+    }
+}
+
+mod test {
+    #[test]
+    fn edits_after_undo_drop_the_redo_tail() {
+        use crate::{entity::Stack, history::StackHistory};
+        use std::sync::Arc;
+
+        let versions = (0..4)
+            .map(|_| Arc::new(Stack::default()))
+            .collect::<Vec<_>>();
+        let mut history = StackHistory::default();
+        history.record(versions[0].clone(), 2);
+        history.record(versions[1].clone(), 2);
+        history.record(versions[2].clone(), 2);
+
+        let undone = history.undo(versions[3].clone()).unwrap();
+        assert!(Arc::ptr_eq(&undone, &versions[2]));
+        let undone = history.undo(undone).unwrap();
+        assert!(Arc::ptr_eq(&undone, &versions[1]));
+        assert!(history.undo(undone.clone()).is_none());
+
+        let redone = history.redo(undone).unwrap();
+        assert!(Arc::ptr_eq(&redone, &versions[2]));
+        history.record(redone, 2);
+        assert!(history.redo(versions[3].clone()).is_none());
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use nalgebra::{Unit, UnitQuaternion, Vector3};
+use pair::Pair;
+
+use crate::{
+    elements,
+    entity::{Atom, Molecule},
+    graph,
+};
+
+const HYDROGEN: usize = 1;
+
+/// Any unit vector perpendicular to `axis`.
+pub(crate) fn perpendicular(axis: &Vector3<f64>) -> Vector3<f64> {
+    let helper = if axis.x.abs() < 0.9 {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    axis.cross(&helper).normalize()
+}
+
+/// Directions of the free positions around an atom with steric number
+/// `steric` whose occupied bond directions are `bonded` (unit vectors).
+/// With a single fixed direction, the first free position is placed
+/// anti-periplanar to `reference` (staggered).
+fn free_directions(
+    steric: usize,
+    bonded: &[Vector3<f64>],
+    reference: Option<Vector3<f64>>,
+) -> Vec<Vector3<f64>> {
+    let tetrahedral = (-1f64 / 3.).acos();
+    match (steric, bonded) {
+        (2, []) => vec![Vector3::x(), -Vector3::x()],
+        (3, []) => (0..3)
+            .map(|k| {
+                let angle = k as f64 * 120f64.to_radians();
+                Vector3::new(angle.cos(), angle.sin(), 0.)
+            })
+            .collect(),
+        (4, []) => vec![
+            Vector3::new(1., 1., 1.).normalize(),
+            Vector3::new(1., -1., -1.).normalize(),
+            Vector3::new(-1., 1., -1.).normalize(),
+            Vector3::new(-1., -1., 1.).normalize(),
+        ],
+        (2, [u]) => vec![-u],
+        (3, [u]) | (4, [u]) => {
+            let p = reference
+                .map(|reference| u * reference.dot(u) - reference)
+                .filter(|p| p.norm() > 1e-6)
+                .map(|p| p.normalize())
+                .unwrap_or_else(|| perpendicular(u));
+            let (angle, count) = if steric == 3 {
+                (120f64.to_radians(), 2)
+            } else {
+                (tetrahedral, 3)
+            };
+            let axis = Unit::new_normalize(*u);
+            (0..count)
+                .map(|k| {
+                    let spin = UnitQuaternion::from_axis_angle(
+                        &axis,
+                        k as f64 * 360f64.to_radians() / count as f64,
+                    );
+                    u * angle.cos() + spin * p * angle.sin()
+                })
+                .collect()
+        }
+        (3, [u1, u2]) => vec![-(u1 + u2).normalize()],
+        (4, [u1, u2]) => {
+            let bisector = -(u1 + u2).normalize();
+            let normal = u1.cross(u2).normalize();
+            let half = tetrahedral / 2.;
+            vec![
+                bisector * half.cos() + normal * half.sin(),
+                bisector * half.cos() - normal * half.sin(),
+            ]
+        }
+        (4, [u1, u2, u3]) => vec![-(u1 + u2 + u3).normalize()],
+        _ => vec![],
+    }
+}
+
+/// Hydrogens completing the standard valence of every heavy atom, as a
+/// patch to write on top of `molecule`. New atoms get indices from
+/// `molecule.next_index()` upward, in atom index order.
+///
+/// Only elements with a tabulated valence (`elements::valence`) are
+/// protonated. Geometry follows the steric number (bonds plus lone pairs):
+/// linear, trigonal planar or tetrahedral, with bond lengths from covalent
+/// radii. Atoms with more than three existing neighbours, charged or
+/// hypervalent centres, and aromatic bond orders are not handled and are
+/// left untouched.
+pub fn add_hydrogens(molecule: &Molecule) -> Molecule {
+    let adjacency = graph::adjacency(molecule);
+    let mut next = molecule.next_index();
+    let mut atoms = HashMap::new();
+    let mut bonds = HashMap::new();
+    for (idx, atom) in molecule.atoms() {
+        if atom.element() == HYDROGEN {
+            continue;
+        }
+        let Some(valence) = elements::valence(atom.element()) else {
+            continue;
+        };
+        let neighbours = &adjacency[&idx];
+        let used = neighbours
+            .iter()
+            .map(|(_, order)| *order)
+            .sum::<f64>()
+            .round() as usize;
+        let missing = valence.saturating_sub(used);
+        if missing == 0 {
+            continue;
+        }
+        let steric = neighbours.len() + missing + elements::lone_pairs(atom.element());
+        let position = atom.position();
+        let bonded = neighbours
+            .iter()
+            .filter_map(|(neighbour, _)| molecule.atom(*neighbour))
+            .map(|neighbour| (neighbour.position() - position).normalize())
+            .collect::<Vec<_>>();
+        let reference = neighbours.first().and_then(|(neighbour, _)| {
+            adjacency[neighbour]
+                .iter()
+                .find(|(second, _)| *second != idx)
+                .and_then(|(second, _)| molecule.atom(*second))
+                .map(|second| second.position() - position)
+        });
+        let length = elements::covalent_radius(atom.element())
+            + elements::covalent_radius(HYDROGEN);
+        for direction in free_directions(steric.min(4), &bonded, reference)
+            .into_iter()
+            .take(missing)
+        {
+            let hydrogen = Atom::new(HYDROGEN, position + direction * length);
+            atoms.insert(next, Some(hydrogen));
+            bonds.insert(Pair::new_ordered(idx, next), 1.);
+            next += 1;
+        }
+    }
+    Molecule::default().set_atoms(atoms).set_bonds(bonds)
+}
+
+mod test {
+    #[test]
+    fn protonate_ethane_and_water() {
+        use crate::{
+            entity::{Atom, Molecule},
+            hydrogens::add_hydrogens,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let ethane = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, Some(Atom::new(6, Point3::new(0., 0., 0.)))),
+                (1, Some(Atom::new(6, Point3::new(1.52, 0., 0.)))),
+            ]))
+            .set_bonds(HashMap::from([(Pair::new_ordered(0, 1), 1.)]));
+        let patch = add_hydrogens(&ethane);
+        assert_eq!(
+            patch.atoms().iter().map(|(idx, _)| *idx).collect::<Vec<_>>(),
+            vec![2, 3, 4, 5, 6, 7]
+        );
+        let carbon = Point3::new(0., 0., 0.);
+        let other = Point3::new(1.52, 0., 0.);
+        for (_, hydrogen) in &patch.atoms()[..3] {
+            let (a, b) = (other - carbon, hydrogen.position() - carbon);
+            assert!((b.norm() - 1.07).abs() < 1e-9);
+            assert!((a.angle(&b).to_degrees() - 109.47).abs() < 0.01);
+        }
+
+        let water = Molecule::default()
+            .set_atoms(HashMap::from([(0, Some(Atom::new(8, Point3::origin())))]));
+        let hydrogens = add_hydrogens(&water).positions();
+        assert_eq!(hydrogens.len(), 2);
+        let angle = hydrogens[0].coords.angle(&hydrogens[1].coords).to_degrees();
+        assert!((angle - 109.47).abs() < 0.01);
+    }
+}
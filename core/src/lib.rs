@@ -1,40 +1,191 @@
-use std::{collections::HashMap, sync::Arc};
+//! The layered molecule model of LME, without the HTTP server.
+//!
+//! A [`Workspace`] holds a base [`Molecule`] and any number of stacks.
+//! Each stack is a list of [`Layer`]s overlaid on the base, from patches of
+//! atoms and bonds to rules such as transforms and renumberings; reading a
+//! stack overlays its layers in order. Stacks sharing their lower layers
+//! share them in memory, and an export stores them once as a tree
+//! ([`WorkspaceExport`]).
+//!
+//! Every edit is an [`Operation`] passed to [`Workspace::apply`], which
+//! keeps an undo history per stack and a log the workspace can be rebuilt
+//! from. Molecules read and write the file
+//! formats of [`formats`], and the analyses in [`geometry`], [`graph`],
+//! [`properties`] and [`validation`] work on any molecule.
+//!
+//! ```
+//! use lme_core::{entity::{Atom, Layer, Molecule}, operation::Operation, Workspace};
+//! use nalgebra::{Point3, Transform3, Translation3};
+//! use std::collections::HashMap;
+//!
+//! let water = Molecule::default().set_atoms(HashMap::from([
+//!     (0, Some(Atom::new(8, Point3::origin()))),
+//!     (1, Some(Atom::new(1, Point3::new(0.96, 0., 0.)))),
+//! ]));
+//! let mut workspace = Workspace::new(water);
+//! workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+//! let up = Translation3::new(0., 0., 1.).to_homogeneous();
+//! let shift = Layer::Transform(Transform3::from_matrix_unchecked(up));
+//! workspace
+//!     .apply(Operation::AddLayers { stack_idx: 0, layers: vec![shift] })
+//!     .unwrap();
+//! let moved = workspace.read(0).unwrap();
+//! assert_eq!(moved.atom(1).unwrap().position(), Point3::new(0.96, 0., 1.));
+//! ```
 
-use entity::{Layer, Molecule, Stack};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    num::NonZeroUsize,
+    sync::Arc,
+};
+
+use cache::{RevisionCache, StackCache};
+use entity::{AtomMetadata, Layer, LayerMeta, Mirror, Molecule, Stack};
+use fragment::{Fragment, FragmentRef};
+use history::{StackHistory, DEFAULT_HISTORY_DEPTH};
 use error::LMECoreError;
 use n_to_n::NtoN;
+use hydrogens::perpendicular;
+use nalgebra::{Isometry3, Point3, Transform3, Translation3, Unit, UnitQuaternion, Vector3};
+use operation::{AuditEntry, BatchEdit, BatchFailure, OperationLog, RigidMotion};
+use pair::Pair;
+use spatial::SpatialIndex;
+use symmetry::{Symmetry, SymmetryCopy};
+use template::Template;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use unique_value_map::{InsertResult, UniqueValueMap};
+
+mod cache;
+pub mod cell;
+pub mod diff;
+pub mod elements;
+pub mod fragment;
+pub mod formats;
+pub mod geometry;
+pub mod graph;
+pub mod history;
+pub mod hydrogens;
+pub mod operation;
+pub mod optimizer;
+pub mod properties;
+pub mod selection;
+pub mod spatial;
+pub mod symmetry;
+pub mod template;
+pub mod validation;
 
 pub mod error {
     use serde::Serialize;
 
-    #[derive(Serialize)]
+    #[derive(Debug, Serialize)]
     pub enum LMECoreError {
         // IdMapUniqueError,
-        // NoSuchAtom,
-        // NoSuchId,
+        NoSuchAtom,
+        UnknownAtom(usize),
+        /// No atom holds this name
+        NoSuchName(String),
         // RootLayerError,
         // NotFillLayer,
-        PluginLayerError(isize, String),
+        LayerError(LayerError),
+        ReplayFailed(usize, Box<LMECoreError>),
         NoSuchStack,
+        AtomCountMismatch(usize),
+        IncompatibleStacks(Vec<usize>),
+        NoCommonPrefix,
+        MalformedTree,
+        /// A stack order that does not hold each index of this many stacks
+        /// exactly once
+        InvalidPermutation(usize),
+        NothingToUndo,
+        NothingToRedo,
+        DuplicatedName(String),
+        DisconnectedFragments,
+        NoCell,
+        SingularCell,
+        ParseError(usize, String),
+        UnknownProperty(String),
+        EmptyStack(usize),
+        MissingAtoms(Vec<usize>),
+        LayerRejected(usize, Box<LMECoreError>),
+        NoSuchGroup(String),
+        /// A stack has no layer at this position, counted from the bottom
+        NoSuchLayer(usize),
+        /// A mirror plane was given with a zero normal vector
+        ZeroNormal,
+        /// Symmetry operations that do not form a usable point group
+        InvalidSymmetry(String),
+        /// The atom to replace has other than one bond
+        NotTerminal(usize),
+        /// A fragment's attachment or dummy atom is missing or misplaced
+        InvalidFragment(String),
+        NoSuchFragment(String),
+        /// A measurement was asked of this many atoms instead of 2, 3 or 4
+        MeasureArity(usize),
+        NotBonded(usize, usize),
+        /// The bond is in a ring, so the molecule has no side to move
+        RingBond(usize, usize),
+        /// A bond length or angle target outside the values it can take
+        InvalidTarget(f64),
+        /// No element has this symbol
+        UnknownElement(String),
+        /// A spin multiplicity below 1
+        InvalidMultiplicity(usize),
+        /// A Gaussian job that can't be written as an input file
+        InvalidGaussianJob(String),
+        /// A template that can't be parsed, or rendered against its context
+        InvalidTemplate(String),
+        NoSuchTemplate(String),
+        /// The stack changed since the request read it
+        StackChanged(usize),
+        /// Edits of a batch that failed, in batch order
+        BatchFailed(Vec<crate::operation::BatchFailure>),
+        /// Named or grouped atoms a renumbering would move in one stack
+        /// while other stacks still have them
+        SharedAtoms(Vec<usize>),
+        /// Only this many atoms are matched between two stacks, too few to
+        /// superpose them
+        NotEnoughMatchedAtoms(usize),
         // WorkspaceNameConflict,
         // WorkspaceNotFound,
     }
+
+    /// Why a layer could not be applied to the molecule below it.
+    #[derive(Debug, Serialize)]
+    pub enum LayerError {
+        /// No executable of this name in the plugin directory
+        UnknownPlugin(String),
+        /// A plugin name that is a path, or leads out of the plugin directory
+        InvalidPluginName(String),
+        /// The plugin ran but exited unsuccessfully, with its exit code
+        PluginRejected(Option<i32>),
+        /// The plugin's output is not a molecule
+        InvalidPluginOutput(String),
+        /// Talking to the plugin process failed
+        PluginIo(String),
+        /// A `Remap` layer sends two atoms to this index
+        RemapCollision(usize),
+    }
+
+    impl From<LayerError> for LMECoreError {
+        fn from(value: LayerError) -> Self {
+            Self::LayerError(value)
+        }
+    }
 }
 
 pub mod entity {
     use std::{
-        collections::{HashMap, HashSet},
-        io::Write,
-        path::PathBuf,
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+        io::{ErrorKind, Write},
+        path::{Path, PathBuf},
         process::{Command, Stdio},
-        sync::Arc,
+        sync::{Arc, OnceLock},
     };
 
-    use lazy_static::lazy_static;
     use n_to_n::NtoN;
-    use nalgebra::{Point3, Transform3};
+    use nalgebra::{Isometry3, Point3, Transform3, Vector3};
     use pair::Pair;
     use rayon::iter::{
         IndexedParallelIterator, IntoParallelIterator, ParallelBridge, ParallelIterator,
@@ -42,7 +193,12 @@ pub mod entity {
     use serde::{Deserialize, Serialize};
     use std::env;
 
-    use crate::error::LMECoreError;
+    use crate::{
+        cell::Cell,
+        error::{LMECoreError, LayerError},
+        symmetry::SymmetryCopy,
+        AtomRef,
+    };
 
     fn get_plugin_directory() -> PathBuf {
         let env_var = env::var("LME_PLUGIN_DIRECTORY");
@@ -55,17 +211,169 @@ pub mod entity {
         }
     }
 
-    lazy_static! {
-        static ref PLUGIN_DIRECTORY: PathBuf = get_plugin_directory();
+    static PLUGIN_DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+
+    /// Run plugins from `directory` instead of `LME_PLUGIN_DIRECTORY`, or
+    /// `plugins` of the working directory. Only takes effect before the
+    /// first plugin runs, and returns whether it did.
+    pub fn set_plugin_directory(directory: PathBuf) -> bool {
+        PLUGIN_DIRECTORY.set(directory).is_ok()
+    }
+
+    fn plugin_directory() -> &'static Path {
+        PLUGIN_DIRECTORY.get_or_init(get_plugin_directory)
+    }
+
+    /// The executable `plugin` of the plugin directory. Fails with
+    /// `InvalidPluginName` for names holding a path separator or `..`, and
+    /// for links leading out of the directory.
+    pub(crate) fn plugin_path(plugin: &str) -> Result<PathBuf, LayerError> {
+        let invalid = || LayerError::InvalidPluginName(plugin.to_string());
+        if plugin.is_empty() || plugin.contains(['/', '\\']) || plugin.contains("..") {
+            return Err(invalid());
+        }
+        let unknown = |_| LayerError::UnknownPlugin(plugin.to_string());
+        let directory = plugin_directory().canonicalize().map_err(unknown)?;
+        let path = directory.join(plugin).canonicalize().map_err(unknown)?;
+        match path.starts_with(&directory) {
+            true => Ok(path),
+            false => Err(invalid()),
+        }
+    }
+
+    /// Run the executable `plugin` of the plugin directory with `args`,
+    /// feeding it `input` and returning what it writes to stdout.
+    pub(crate) fn run_plugin(
+        plugin: &str,
+        args: &[String],
+        input: &str,
+    ) -> Result<String, LayerError> {
+        let io_error = |err: std::io::Error| LayerError::PluginIo(err.to_string());
+        let mut child = Command::new(plugin_path(plugin)?)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| match err.kind() {
+                ErrorKind::NotFound => LayerError::UnknownPlugin(plugin.to_string()),
+                _ => io_error(err),
+            })?;
+        let Some(ref mut stdin) = child.stdin else {
+            return Err(LayerError::PluginIo(
+                "Unable to get stdin of child process".to_string(),
+            ));
+        };
+        stdin.write_all(input.as_bytes()).map_err(io_error)?;
+        let output = child.wait_with_output().map_err(io_error)?;
+        if !output.status.success() {
+            return Err(LayerError::PluginRejected(output.status.code()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Residue an atom belongs to in a biomolecule, as found in PDB files.
+    /// The name is kept in place so atoms stay `Copy`.
+    #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, PartialOrd)]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[serde(try_from = "ResidueRecord", into = "ResidueRecord")]
+    pub struct Residue {
+        #[cfg_attr(feature = "openapi", schema(value_type = String))]
+        name: [u8; 3],
+        number: i32,
+        chain: char,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct ResidueRecord {
+        name: String,
+        number: i32,
+        chain: char,
+    }
+
+    impl Residue {
+        /// `None` unless `name` has at most 3 ASCII characters.
+        pub fn new(name: &str, number: i32, chain: char) -> Option<Self> {
+            let mut bytes = [b' '; 3];
+            if !name.is_ascii() || name.len() > bytes.len() {
+                return None;
+            }
+            bytes[..name.len()].copy_from_slice(name.as_bytes());
+            Some(Self {
+                name: bytes,
+                number,
+                chain,
+            })
+        }
+
+        pub fn name(&self) -> &str {
+            std::str::from_utf8(&self.name)
+                .expect("Residue names are ASCII")
+                .trim_end()
+        }
+
+        pub fn number(&self) -> i32 {
+            self.number
+        }
+
+        pub fn chain(&self) -> char {
+            self.chain
+        }
+    }
+
+    impl TryFrom<ResidueRecord> for Residue {
+        type Error = String;
+
+        fn try_from(value: ResidueRecord) -> Result<Self, Self::Error> {
+            Self::new(&value.name, value.number, value.chain)
+                .ok_or(format!("Invalid residue name {:?}", value.name))
+        }
+    }
+
+    impl From<Residue> for ResidueRecord {
+        fn from(value: Residue) -> Self {
+            Self {
+                name: value.name().to_string(),
+                number: value.number,
+                chain: value.chain,
+            }
+        }
     }
 
     #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, PartialOrd)]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     pub struct Atom {
         element: usize,
+        #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
         position: Point3<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        residue: Option<Residue>,
     }
 
     impl Atom {
+        pub fn new(element: usize, position: Point3<f64>) -> Self {
+            Self {
+                element,
+                position,
+                residue: None,
+            }
+        }
+
+        pub fn residue(&self) -> Option<Residue> {
+            self.residue
+        }
+
+        pub fn set_residue(self, residue: Option<Residue>) -> Self {
+            Self { residue, ..self }
+        }
+
+        pub fn element(&self) -> usize {
+            self.element
+        }
+
+        pub fn position(&self) -> Point3<f64> {
+            self.position
+        }
+
         pub fn set_element(self, element: usize) -> Self {
             Self { element, ..self }
         }
@@ -79,20 +387,332 @@ pub mod entity {
         }
     }
 
+    /// Optional data attached to an atom besides its element and position.
+    /// Kept beside the atoms in `Molecule`, so a layer can set it for an
+    /// atom without writing the atom itself.
+    #[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    pub struct AtomMetadata {
+        /// Partial charge in units of the elementary charge
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub charge: Option<f64>,
+        /// Mass number, the most abundant isotope when not set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub isotope: Option<u16>,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub properties: BTreeMap<String, String>,
+    }
+
+    impl AtomMetadata {
+        /// Overlay `high` on `self`: fields set in `high` replace those of
+        /// `self` and its properties are added to those of `self`.
+        pub fn overlay(self, high: Self) -> Self {
+            let mut properties = self.properties;
+            properties.extend(high.properties);
+            Self {
+                charge: high.charge.or(self.charge),
+                isotope: high.isotope.or(self.isotope),
+                properties,
+            }
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self == &Self::default()
+        }
+    }
+
+    /// Bonds are (de)serialized as a list of `[pair, order]` entries, since
+    /// a `Pair` can't be a key of a JSON object.
+    mod bond_list {
+        use std::{collections::HashMap, fmt};
+
+        use pair::Pair;
+        use serde::{
+            de::{MapAccess, SeqAccess, Visitor},
+            Deserializer, Serializer,
+        };
+
+        pub fn serialize<S: Serializer>(
+            bonds: &HashMap<Pair<usize>, f64>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(bonds)
+        }
+
+        struct BondsVisitor;
+
+        impl<'de> Visitor<'de> for BondsVisitor {
+            type Value = HashMap<Pair<usize>, f64>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a list of [pair, bond order] entries")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut bonds = HashMap::new();
+                while let Some((pair, order)) = seq.next_element::<(Pair<usize>, f64)>()? {
+                    bonds.insert(pair, order);
+                }
+                Ok(bonds)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut bonds = HashMap::new();
+                while let Some((pair, order)) = map.next_entry::<Pair<usize>, f64>()? {
+                    bonds.insert(pair, order);
+                }
+                Ok(bonds)
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<HashMap<Pair<usize>, f64>, D::Error> {
+            deserializer.deserialize_any(BondsVisitor)
+        }
+    }
+
     #[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     pub struct Molecule {
+        /// Atoms by index; null removes the atom in a patch or layer
+        #[cfg_attr(feature = "openapi", schema(value_type = HashMap<usize, Atom>))]
         atoms: HashMap<usize, Option<Atom>>,
+        #[serde(with = "bond_list")]
+        #[cfg_attr(feature = "openapi", schema(value_type = Vec<([usize; 2], f64)>))]
         bonds: HashMap<Pair<usize>, f64>,
+        #[cfg_attr(feature = "openapi", schema(value_type = Vec<(usize, String)>))]
         groups: NtoN<usize, String>,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        metadata: HashMap<usize, AtomMetadata>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cell: Option<Cell>,
     }
 
     impl Molecule {
+        /// Overlay `high` on `low`. Atoms removed in `high` take their bonds,
+        /// group memberships and metadata in `low` with them. The metadata
+        /// of an atom is overlaid field by field, see `AtomMetadata::overlay`.
         pub fn merge(mut low: Self, high: Self) -> Self {
+            let removed = high
+                .atoms
+                .iter()
+                .filter(|(_, atom)| atom.is_none())
+                .map(|(idx, _)| *idx)
+                .collect::<HashSet<_>>();
+            if !removed.is_empty() {
+                low = low.remove_atoms(&removed);
+            }
             low.atoms.extend(high.atoms);
             low.bonds.extend(high.bonds);
             low.groups.extend(high.groups);
+            for (idx, metadata) in high.metadata {
+                let low_metadata = low.metadata.remove(&idx).unwrap_or_default();
+                low.metadata.insert(idx, low_metadata.overlay(metadata));
+            }
+            low.cell = high.cell.or(low.cell);
             low
         }
+
+        pub fn cell(&self) -> Option<&Cell> {
+            self.cell.as_ref()
+        }
+
+        pub fn set_cell(self, cell: Option<Cell>) -> Self {
+            Self { cell, ..self }
+        }
+
+        /// The molecule with atom positions in fractional coordinates of its cell.
+        pub fn to_fractional(mut self) -> Result<Self, LMECoreError> {
+            let cell = self.cell.ok_or(LMECoreError::NoCell)?;
+            for atom in self.atoms.values_mut().flatten() {
+                *atom = atom.set_position(cell.to_fractional(&atom.position)?);
+            }
+            Ok(self)
+        }
+
+        /// Read atom positions as fractional coordinates of `cell` and convert
+        /// them to Cartesian.
+        pub fn from_fractional(mut self, cell: &Cell) -> Self {
+            for atom in self.atoms.values_mut().flatten() {
+                *atom = atom.set_position(cell.to_cartesian(&atom.position));
+            }
+            self
+        }
+
+        pub fn set_atoms(self, atoms: HashMap<usize, Option<Atom>>) -> Self {
+            Self { atoms, ..self }
+        }
+
+        pub fn set_bonds(self, bonds: HashMap<Pair<usize>, f64>) -> Self {
+            Self { bonds, ..self }
+        }
+
+        pub fn metadata(&self, idx: usize) -> Option<&AtomMetadata> {
+            self.metadata.get(&idx)
+        }
+
+        /// Metadata of every atom that has some.
+        pub fn all_metadata(&self) -> &HashMap<usize, AtomMetadata> {
+            &self.metadata
+        }
+
+        pub fn set_metadata(self, metadata: HashMap<usize, AtomMetadata>) -> Self {
+            Self { metadata, ..self }
+        }
+
+        /// Existing atoms (removed ones skipped) ordered by atom index.
+        pub fn atoms(&self) -> Vec<(usize, Atom)> {
+            let mut atoms = self
+                .atoms
+                .iter()
+                .filter_map(|(idx, atom)| atom.map(|atom| (*idx, atom)))
+                .collect::<Vec<_>>();
+            atoms.sort_by_key(|(idx, _)| *idx);
+            atoms
+        }
+
+        pub fn atom(&self, idx: usize) -> Option<Atom> {
+            self.atoms.get(&idx).copied().flatten()
+        }
+
+        /// Smallest index above every index used in this molecule, removed atoms included.
+        pub fn next_index(&self) -> usize {
+            self.atoms.keys().max().map_or(0, |max| max + 1)
+        }
+
+        pub fn positions(&self) -> Vec<Point3<f64>> {
+            self.atoms()
+                .into_iter()
+                .map(|(_, atom)| atom.position)
+                .collect()
+        }
+
+        pub fn bonds(&self) -> &HashMap<Pair<usize>, f64> {
+            &self.bonds
+        }
+
+        /// Move every atom, bond, group membership and metadata entry from its
+        /// old index to `mapping[old]`. Entries whose index is not in `mapping` are dropped.
+        pub fn remap(self, mapping: &HashMap<usize, usize>) -> Self {
+            let atoms = self
+                .atoms
+                .into_iter()
+                .filter_map(|(idx, atom)| mapping.get(&idx).map(|idx| (*idx, atom)))
+                .collect();
+            let bonds = self
+                .bonds
+                .into_iter()
+                .filter_map(|(pair, order)| {
+                    let (a, b) = pair.into();
+                    Some((Pair::new_ordered(*mapping.get(&a)?, *mapping.get(&b)?), order))
+                })
+                .collect();
+            let groups = self
+                .groups
+                .into_iter()
+                .filter_map(|(idx, group)| mapping.get(&idx).map(|idx| (*idx, group)))
+                .collect::<HashSet<_>>();
+            let metadata = self
+                .metadata
+                .into_iter()
+                .filter_map(|(idx, metadata)| mapping.get(&idx).map(|idx| (*idx, metadata)))
+                .collect();
+            Self {
+                atoms,
+                bonds,
+                groups: NtoN::from(groups),
+                metadata,
+                cell: self.cell,
+            }
+        }
+
+        /// Mark the given atoms as removed and drop their bonds, group
+        /// memberships and metadata.
+        pub fn remove_atoms(mut self, indices: &HashSet<usize>) -> Self {
+            indices.iter().for_each(|idx| {
+                self.atoms.insert(*idx, None);
+            });
+            self.bonds.retain(|pair, _| !indices.iter().any(|idx| pair.contains(idx)));
+            for idx in indices {
+                self.groups.remove_left(idx);
+                self.metadata.remove(idx);
+            }
+            self
+        }
+
+        /// Drop every atom (tombstones included) outside `kept`, with the
+        /// bonds, group memberships and metadata that involve them.
+        pub fn restrict(mut self, kept: &HashSet<usize>) -> Self {
+            self.atoms.retain(|idx, _| kept.contains(idx));
+            self.metadata.retain(|idx, _| kept.contains(idx));
+            self.bonds.retain(|pair, _| {
+                let (a, b) = (*pair).into();
+                kept.contains(&a) && kept.contains(&b)
+            });
+            self.groups = self
+                .groups
+                .into_iter()
+                .filter(|(idx, _)| kept.contains(idx))
+                .collect::<HashSet<_>>()
+                .into();
+            self
+        }
+    }
+
+    /// A molecule patch as a client may write it, with atoms keyed, bonded
+    /// and grouped by index or by name, see `AtomRef`.
+    #[derive(Debug, Default, Deserialize, Clone, PartialEq)]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    pub struct MoleculePatch {
+        /// Atoms by index or name; null removes the atom
+        #[cfg_attr(feature = "openapi", schema(value_type = HashMap<String, Atom>))]
+        atoms: HashMap<AtomRef, Option<Atom>>,
+        #[cfg_attr(feature = "openapi", schema(value_type = Vec<([AtomRef; 2], f64)>))]
+        bonds: Vec<([AtomRef; 2], f64)>,
+        groups: Vec<(AtomRef, String)>,
+        #[serde(default)]
+        #[cfg_attr(feature = "openapi", schema(value_type = HashMap<String, AtomMetadata>))]
+        metadata: HashMap<AtomRef, AtomMetadata>,
+        #[serde(default)]
+        cell: Option<Cell>,
+    }
+
+    impl MoleculePatch {
+        /// The patch as a molecule, with every atom given to `resolve` for
+        /// its index. Fails with the first error of `resolve`.
+        pub fn resolve<F>(self, resolve: F) -> Result<Molecule, LMECoreError>
+        where
+            F: Fn(&AtomRef) -> Result<usize, LMECoreError>,
+        {
+            let atoms = self
+                .atoms
+                .into_iter()
+                .map(|(atom, value)| Ok((resolve(&atom)?, value)))
+                .collect::<Result<_, LMECoreError>>()?;
+            let bonds = self
+                .bonds
+                .iter()
+                .map(|([a, b], order)| Ok((Pair::new_ordered(resolve(a)?, resolve(b)?), *order)))
+                .collect::<Result<_, LMECoreError>>()?;
+            let groups = self
+                .groups
+                .into_iter()
+                .map(|(atom, group)| Ok((resolve(&atom)?, group)))
+                .collect::<Result<HashSet<_>, LMECoreError>>()?;
+            let metadata = self
+                .metadata
+                .into_iter()
+                .map(|(atom, metadata)| Ok((resolve(&atom)?, metadata)))
+                .collect::<Result<_, LMECoreError>>()?;
+            Ok(Molecule {
+                atoms,
+                bonds,
+                groups: NtoN::from(groups),
+                metadata,
+                cell: self.cell,
+            })
+        }
     }
 
     pub struct CompactedMolecule {
@@ -124,30 +744,187 @@ pub mod entity {
                 atoms,
                 bonds,
                 groups: NtoN::from(groups),
+                metadata: HashMap::new(),
+                cell: None,
+            }
+        }
+    }
+
+    /// A reflection through a plane or a point.
+    #[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    pub enum Mirror {
+        /// The plane through `point` perpendicular to `normal`
+        Plane {
+            #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+            point: Point3<f64>,
+            #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+            normal: Vector3<f64>,
+        },
+        /// Inversion through a point
+        #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+        Inversion(Point3<f64>),
+    }
+
+    impl Mirror {
+        pub fn reflect(&self, position: Point3<f64>) -> Point3<f64> {
+            match self {
+                Self::Plane { point, normal } => {
+                    let normal = normal.normalize();
+                    position - 2. * (position - point).dot(&normal) * normal
+                }
+                Self::Inversion(center) => center + (center - position),
+            }
+        }
+    }
+
+    /// Human readable description of a layer, with no effect on its result.
+    #[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    pub struct LayerMeta {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub comment: Option<String>,
+    }
+
+    impl LayerMeta {
+        /// `self` with the fields set in `patch` replaced.
+        pub fn patch(self, patch: LayerMeta) -> Self {
+            Self {
+                name: patch.name.or(self.name),
+                comment: patch.comment.or(self.comment),
             }
         }
     }
 
     #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     pub enum Layer {
-        Fill(Molecule),
+        Fill(Box<Molecule>),
+        /// A 4x4 homogeneous matrix, column-major
+        #[cfg_attr(feature = "openapi", schema(value_type = [f64; 16]))]
         Transform(Transform3<f64>),
+        /// A rigid motion of the listed atoms only, leaving the cell and
+        /// every other atom in place
+        MoveAtoms(Isometry3<f64>, BTreeSet<usize>),
+        /// The listed atoms reflected by a mirror, the others left in place
+        Mirror(Mirror, BTreeSet<usize>),
+        /// Symmetric copies of atoms, added with the bonds between them
+        Replicate(Vec<SymmetryCopy>),
         IgnoreBonds,
         ReplaceElement(usize, usize),
         RemoveElement(usize),
+        Remap(HashMap<usize, usize>),
         PluginFilter(String, Vec<String>),
+        /// A layer with a name or comment attached, acting as the inner layer
+        Annotated(LayerMeta, Box<Layer>),
     }
 
     impl Layer {
+        /// The layer with its annotation, if any, removed.
+        pub fn unannotated(&self) -> &Layer {
+            match self {
+                Self::Annotated(_, layer) => layer.unannotated(),
+                layer => layer,
+            }
+        }
+
+        pub fn meta(&self) -> Option<&LayerMeta> {
+            match self {
+                Self::Annotated(meta, _) => Some(meta),
+                _ => None,
+            }
+        }
+
+        /// Indices of the atoms below the layer that become those in `kept`
+        /// above it: the ones remapped into `kept` for a remapping, `kept`
+        /// itself for any other layer.
+        pub fn kept_below(&self, kept: &HashSet<usize>) -> HashSet<usize> {
+            match self {
+                Self::Annotated(_, layer) => layer.kept_below(kept),
+                Self::Remap(mapping) => mapping
+                    .iter()
+                    .filter(|(_, to)| kept.contains(to))
+                    .map(|(from, _)| *from)
+                    .collect(),
+                _ => kept.clone(),
+            }
+        }
+
+        /// The layer with its references to atoms outside `kept`, indices
+        /// above the layer, dropped; see `Molecule::restrict`. A remapping
+        /// is kept only where it maps into `kept`. Plugins are left as they
+        /// are.
+        pub fn restrict(&self, kept: &HashSet<usize>) -> Self {
+            match self {
+                Self::Annotated(meta, layer) => {
+                    Self::Annotated(meta.clone(), Box::new(layer.restrict(kept)))
+                }
+                Self::Fill(molecule) => {
+                    Self::Fill(Box::new(molecule.as_ref().clone().restrict(kept)))
+                }
+                Self::Remap(mapping) => Self::Remap(
+                    mapping
+                        .iter()
+                        .filter(|(_, to)| kept.contains(to))
+                        .map(|(from, to)| (*from, *to))
+                        .collect(),
+                ),
+                Self::MoveAtoms(motion, atoms) => Self::MoveAtoms(
+                    *motion,
+                    atoms.iter().filter(|idx| kept.contains(idx)).copied().collect(),
+                ),
+                Self::Mirror(mirror, atoms) => Self::Mirror(
+                    *mirror,
+                    atoms.iter().filter(|idx| kept.contains(idx)).copied().collect(),
+                ),
+                Self::Replicate(copies) => Self::Replicate(
+                    copies
+                        .iter()
+                        .map(|copy| SymmetryCopy {
+                            transform: copy.transform,
+                            atoms: copy
+                                .atoms
+                                .iter()
+                                .filter(|(from, to)| kept.contains(from) && kept.contains(to))
+                                .map(|(from, to)| (*from, *to))
+                                .collect(),
+                        })
+                        .collect(),
+                ),
+                layer => layer.clone(),
+            }
+        }
+
         pub fn filter(&self, mut low: Molecule) -> Result<Molecule, LMECoreError> {
             match self {
-                Self::Fill(high) => Ok(Molecule::merge(low, high.clone())),
+                Self::Annotated(_, layer) => layer.filter(low),
+                Self::Fill(high) => Ok(Molecule::merge(low, high.as_ref().clone())),
                 Self::Transform(transform) => {
                     low.atoms.iter_mut().for_each(|(_, atom)| {
                         *atom = atom.map(|atom| atom.transform_position(transform))
                     });
+                    low.cell = low.cell.map(|cell| cell.transform(transform));
+                    Ok(low)
+                }
+                Self::MoveAtoms(motion, atoms) => {
+                    for idx in atoms {
+                        if let Some(Some(atom)) = low.atoms.get_mut(idx) {
+                            *atom = atom.set_position(motion * atom.position);
+                        }
+                    }
                     Ok(low)
                 }
+                Self::Mirror(mirror, atoms) => {
+                    for idx in atoms {
+                        if let Some(Some(atom)) = low.atoms.get_mut(idx) {
+                            *atom = atom.set_position(mirror.reflect(atom.position));
+                        }
+                    }
+                    Ok(low)
+                }
+                Self::Replicate(copies) => Ok(SymmetryCopy::replicate(copies, low)),
                 Self::IgnoreBonds => {
                     low.bonds = HashMap::new();
                     Ok(low)
@@ -165,44 +942,32 @@ pub mod entity {
                     Ok(low)
                 }
                 Self::RemoveElement(element) => {
-                    low.atoms.iter_mut().for_each(|(_, atom)| {
-                        *atom = atom.and_then(|atom| {
-                            if &atom.element == element {
-                                None
-                            } else {
-                                Some(atom)
+                    let removed = low
+                        .atoms()
+                        .into_iter()
+                        .filter(|(_, atom)| &atom.element == element)
+                        .map(|(idx, _)| idx)
+                        .collect();
+                    Ok(low.remove_atoms(&removed))
+                }
+                Self::Remap(mapping) => {
+                    let mut targets = HashSet::new();
+                    for idx in low.atoms.keys() {
+                        if let Some(target) = mapping.get(idx) {
+                            if !targets.insert(*target) {
+                                return Err(LayerError::RemapCollision(*target).into());
                             }
-                        })
-                    });
-                    Ok(low)
+                        }
+                    }
+                    Ok(low.remap(mapping))
                 }
                 Self::PluginFilter(plugin, args) => {
-                    let mut command = PLUGIN_DIRECTORY.clone();
-                    command.push(plugin);
-                    let mut child = Command::new(command)
-                        .args(args)
-                        .stdin(Stdio::piped())
-                        .spawn()
-                        .map_err(|err| LMECoreError::PluginLayerError(-1, err.to_string()))?;
                     let data_to_send = serde_json::to_string(&low)
-                        .map_err(|err| LMECoreError::PluginLayerError(-2, err.to_string()))?;
-                    if let Some(ref mut stdin) = child.stdin {
-                        stdin
-                            .write_all(&data_to_send.as_bytes())
-                            .map_err(|err| LMECoreError::PluginLayerError(-3, err.to_string()))?;
-                        let output = child
-                            .wait_with_output()
-                            .map_err(|err| LMECoreError::PluginLayerError(-4, err.to_string()))?;
-                        let data = String::from_utf8_lossy(&output.stdout);
-                        let high: Molecule = serde_json::from_str(&data)
-                            .map_err(|err| LMECoreError::PluginLayerError(-5, err.to_string()))?;
-                        Ok(Molecule::merge(low, high))
-                    } else {
-                        Err(LMECoreError::PluginLayerError(
-                            -6,
-                            "Unable to get stdin of child process".to_string(),
-                        ))
-                    }
+                        .map_err(|err| LayerError::PluginIo(err.to_string()))?;
+                    let data = run_plugin(plugin, args, &data_to_send)?;
+                    let high: Molecule = serde_json::from_str(&data)
+                        .map_err(|err| LayerError::InvalidPluginOutput(err.to_string()))?;
+                    Ok(Molecule::merge(low, high))
                 }
             }
         }
@@ -232,17 +997,34 @@ pub mod entity {
             self.0.push(layer)
         }
 
+        /// Merge `w` into the top layer if it is a `Fill` (keeping its
+        /// annotation), otherwise push `w` as a new `Fill` layer.
         pub fn write(&mut self, w: Molecule) {
             let top = self.0.last().map(|top| top.as_ref());
-            if let Some(Layer::Fill(current)) = top {
-                let updated = Molecule::merge(current.clone(), w);
-                *self.0.last_mut().expect("Should never hint this condition") =
-                    Arc::new(Layer::Fill(updated))
+            if let Some(Layer::Fill(current)) = top.map(Layer::unannotated) {
+                let merged = Molecule::merge(current.as_ref().clone(), w);
+                let mut updated = Layer::Fill(Box::new(merged));
+                if let Some(meta) = top.and_then(Layer::meta) {
+                    updated = Layer::Annotated(meta.clone(), Box::new(updated));
+                }
+                *self.0.last_mut().expect("Should never hint this condition") = Arc::new(updated)
             } else {
-                self.add_layer(Arc::new(Layer::Fill(w)))
+                self.add_layer(Arc::new(Layer::Fill(Box::new(w))))
             }
         }
 
+        /// Patch the annotation of the top layer, see `LayerMeta::patch`.
+        /// Returns false for a stack without layers.
+        pub fn annotate(&mut self, patch: LayerMeta) -> bool {
+            let Some(top) = self.0.last_mut() else {
+                return false;
+            };
+            let meta = top.meta().cloned().unwrap_or_default().patch(patch);
+            let layer = top.unannotated().clone();
+            *top = Arc::new(Layer::Annotated(meta, Box::new(layer)));
+            true
+        }
+
         pub fn read(&self, mut container: Molecule) -> Result<Molecule, LMECoreError> {
             for layer in &self.0 {
                 container = layer.filter(container)?
@@ -256,16 +1038,143 @@ pub mod entity {
 pub struct Workspace {
     base: Molecule,
     stacks: Vec<Arc<Stack>>,
-    pub atom_names: HashMap<String, usize>,
+    /// Names and groups are checked against live atoms when assigned, but
+    /// kept when the atom is later removed by a layer. Only the workspace
+    /// operations that remove atoms themselves (`remove_hydrogens`,
+    /// `remove_atoms`) drop them.
+    pub atom_names: UniqueValueMap<usize, String>,
     pub groups: NtoN<String, usize>,
+    revision: u64,
+    rmsd_cache: RevisionCache<(Vec<usize>, bool), Vec<Vec<f64>>>,
+    /// Molecules read from the stacks, see `resolve`
+    resolved: StackCache<Molecule>,
+    /// Spatial indices of the molecules read from the stacks, built when
+    /// first asked for
+    indices: StackCache<SpatialIndex>,
+    log: OperationLog,
+    /// Undo history of each stack, in the same order as `stacks`
+    histories: Vec<StackHistory>,
+    history_depth: usize,
+    /// Unique name of each stack, in the same order as `stacks`. Unlike
+    /// the index, a name keeps referring to the same stack when earlier
+    /// stacks are removed.
+    stack_names: Vec<String>,
+    /// Number of stack names generated so far
+    stack_serial: usize,
+    /// Charge and multiplicity of each stack, in the same order as `stacks`
+    electronic_states: Vec<ElectronicState>,
+    /// Version of each stack, in the same order as `stacks`, see
+    /// `stack_version`
+    stack_versions: Vec<u64>,
+    /// Named fragments `substitute` can refer to
+    fragments: BTreeMap<String, Fragment>,
+    /// Named templates stacks can be rendered with
+    templates: BTreeMap<String, Template>,
+    /// Who changed what and when, see `record`
+    audit: Vec<AuditEntry>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct WorkspaceExport {
     base: Molecule,
     stacks: Vec<StackTree>,
-    atom_names: HashMap<String, usize>,
+    #[cfg_attr(feature = "openapi", schema(value_type = HashMap<usize, String>))]
+    atom_names: UniqueValueMap<usize, String>,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<(String, usize)>))]
     groups: NtoN<String, usize>,
+    /// Stack names by stack index; exports without them get generated names
+    #[serde(default)]
+    stack_names: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    fragments: BTreeMap<String, Fragment>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[cfg_attr(feature = "openapi", schema(value_type = BTreeMap<String, String>))]
+    templates: BTreeMap<String, Template>,
+    /// Electronic states by stack index; exports without them get the
+    /// default state for every stack
+    #[serde(default)]
+    electronic_states: Vec<ElectronicState>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    audit: Vec<AuditEntry>,
+    /// Indexes of the stacks without layers, which only exports made with
+    /// `WorkspaceSnapshot::into_export` hold
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    empty_stacks: Vec<usize>,
+}
+
+/// Total charge and spin multiplicity of the molecule a stack holds, as
+/// quantum chemistry inputs ask for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ElectronicState {
+    pub charge: i32,
+    pub multiplicity: usize,
+}
+
+impl Default for ElectronicState {
+    /// A neutral singlet
+    fn default() -> Self {
+        Self {
+            charge: 0,
+            multiplicity: 1,
+        }
+    }
+}
+
+/// What a stack holds, without its molecule.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct StackSummary {
+    pub index: usize,
+    pub name: String,
+    /// Atoms left in the molecule read from the stack
+    pub atoms: usize,
+    pub layers: usize,
+    pub version: u64,
+}
+
+/// A layer of a stack, with how much it changes the molecule below it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LayerSummary {
+    pub layer: Layer,
+    /// Atoms the layer adds, removes or changes
+    pub atoms: usize,
+}
+
+/// An atom as a client may give it: by index, or by its name, which keeps
+/// referring to the same atom as indices shift.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(untagged)]
+pub enum AtomRef {
+    Index(usize),
+    Name(String),
+}
+
+impl AtomRef {
+    pub fn resolve(&self, workspace: &Workspace) -> Result<usize, LMECoreError> {
+        match self {
+            Self::Index(idx) => Ok(*idx),
+            Self::Name(name) => workspace.resolve_atom(name),
+        }
+    }
+}
+
+impl From<usize> for AtomRef {
+    fn from(idx: usize) -> Self {
+        Self::Index(idx)
+    }
+}
+
+fn selection_centroid(molecule: &Molecule, atoms: &BTreeSet<usize>) -> Point3<f64> {
+    let positions = atoms
+        .iter()
+        .filter_map(|idx| molecule.atom(*idx))
+        .map(|atom| atom.position())
+        .collect::<Vec<_>>();
+    geometry::centroid(&positions)
 }
 
 impl Workspace {
@@ -273,40 +1182,487 @@ impl Workspace {
         Self {
             base,
             stacks: vec![],
-            atom_names: HashMap::new(),
+            atom_names: UniqueValueMap::new(),
             groups: NtoN::new(),
+            revision: 0,
+            rmsd_cache: RevisionCache::default(),
+            resolved: StackCache::default(),
+            indices: StackCache::default(),
+            log: OperationLog::default(),
+            histories: vec![],
+            history_depth: DEFAULT_HISTORY_DEPTH,
+            stack_names: vec![],
+            stack_serial: 0,
+            fragments: BTreeMap::new(),
+            templates: BTreeMap::new(),
+            electronic_states: vec![],
+            stack_versions: vec![],
+            audit: vec![],
         }
     }
 
-    pub fn read(&self, index: usize) -> Result<Molecule, LMECoreError> {
-        self.stacks
-            .get(index)
-            .map_or(Err(LMECoreError::NoSuchStack), |stack| {
-                stack.read(self.base.clone())
-            })
+    /// Keep up to `depth` earlier versions of every stack for `undo`.
+    pub fn with_history_depth(self, history_depth: usize) -> Self {
+        Self {
+            history_depth,
+            ..self
+        }
     }
 
-    pub fn stacks(&self) -> usize {
-        self.stacks.len()
+    pub fn history_depth(&self) -> usize {
+        self.history_depth
     }
 
-    pub fn create_stack(&mut self, stack: Arc<Stack>, copies: usize) -> usize {
-        let index = self.stacks.len();
-        for _ in 0..=copies {
-            self.stacks.push(stack.clone());
-        }
-        index
+    /// Counter incremented by every change to the stacks.
+    pub fn revision(&self) -> u64 {
+        self.revision
     }
 
-    pub fn create_stack_from_layer(&mut self, layer: Arc<Layer>, copies: usize) -> usize {
-        let stack = Stack::new(vec![layer]);
-        self.create_stack(Arc::new(stack), copies)
+    pub fn read(&self, index: usize) -> Result<Molecule, LMECoreError> {
+        self.read_shared(index).map(Arc::unwrap_or_clone)
     }
 
-    pub fn clone_stack(&mut self, stack_idx: usize, copies: usize) -> Option<usize> {
-        let stack = self.stacks.get(stack_idx).cloned()?;
+    /// The molecule of a stack like `read`, shared with the cache of
+    /// molecules read before, so reading a stack again before it changes
+    /// costs no more than cloning the `Arc`.
+    pub fn read_shared(&self, index: usize) -> Result<Arc<Molecule>, LMECoreError> {
+        let stack = self.stacks.get(index).ok_or(LMECoreError::NoSuchStack)?;
+        self.resolve(stack)
+    }
+
+    /// Overlay the layers of `stack` on the base, unless the molecule it
+    /// holds is cached. Failed reads are not.
+    fn resolve(&self, stack: &Arc<Stack>) -> Result<Arc<Molecule>, LMECoreError> {
+        if let Some(molecule) = self.resolved.get(stack) {
+            return Ok(molecule);
+        }
+        let molecule = stack.read(self.base.clone())?;
+        Ok(self.resolved.insert(stack, molecule, &self.stacks))
+    }
+
+    /// The spatial index of the molecule read from a stack, shared by the
+    /// distance queries on it until the stack changes.
+    pub fn spatial_index(&self, index: usize) -> Result<Arc<SpatialIndex>, LMECoreError> {
+        let stack = self.stacks.get(index).ok_or(LMECoreError::NoSuchStack)?;
+        if let Some(index) = self.indices.get(stack) {
+            return Ok(index);
+        }
+        let spatial = SpatialIndex::new(self.resolve(stack)?.as_ref());
+        Ok(self.indices.insert(stack, spatial, &self.stacks))
+    }
+
+    /// Fail with `UnknownAtom` unless the base or some stack has a live atom
+    /// at `atom_idx`, the requirement for naming or grouping it.
+    pub fn check_atom(&self, atom_idx: usize) -> Result<(), LMECoreError> {
+        if self.base.atom(atom_idx).is_some() {
+            return Ok(());
+        }
+        for index in 0..self.stacks.len() {
+            if self.read(index)?.atom(atom_idx).is_some() {
+                return Ok(());
+            }
+        }
+        Err(LMECoreError::UnknownAtom(atom_idx))
+    }
+
+    /// The atom a client refers to, either by index or by name. Fails with
+    /// `NoSuchName` for a name no atom has.
+    pub fn resolve_atom(&self, reference: &str) -> Result<usize, LMECoreError> {
+        reference.parse().or_else(|_| {
+            self.atom_names
+                .get_by_value(&reference.to_string())
+                .copied()
+                .ok_or_else(|| LMECoreError::NoSuchName(reference.to_string()))
+        })
+    }
+
+    /// Indices of atoms given by index or name, see `resolve_atom`.
+    pub fn resolve_refs(&self, references: &[AtomRef]) -> Result<Vec<usize>, LMECoreError> {
+        references.iter().map(|reference| reference.resolve(self)).collect()
+    }
+
+    /// Comma separated atom references resolved with `resolve_atom`, empty
+    /// references skipped.
+    pub fn resolve_atoms(&self, references: &str) -> Result<Vec<usize>, LMECoreError> {
+        references
+            .split(',')
+            .map(str::trim)
+            .filter(|reference| !reference.is_empty())
+            .map(|reference| self.resolve_atom(reference))
+            .collect()
+    }
+
+    pub fn stacks(&self) -> usize {
+        self.stacks.len()
+    }
+
+    /// The layer tree of all stacks as a flat list of linked nodes.
+    pub fn tree(&self) -> Result<Vec<TreeNode>, LMECoreError> {
+        Ok(StackTree::nodes(&StackTree::dehydration(&self.stacks)?))
+    }
+
+    /// Make the selected stacks share the `Arc`s of their longest common
+    /// layer prefix, i.e. the leading layers that are equal (by value) in
+    /// every selected stack. Returns the prefix length; fails with
+    /// `NoCommonPrefix` for fewer than two stacks or an empty prefix.
+    pub fn promote_prefix(&mut self, stacks: &[usize]) -> Result<usize, LMECoreError> {
+        let selected = stacks
+            .iter()
+            .map(|idx| self.stacks.get(*idx).cloned())
+            .collect::<Option<Vec<_>>>()
+            .ok_or(LMECoreError::NoSuchStack)?;
+        let (first, rest) = selected.split_first().ok_or(LMECoreError::NoCommonPrefix)?;
+        let length = first
+            .get_layers()
+            .iter()
+            .enumerate()
+            .take_while(|(depth, layer)| {
+                rest.iter()
+                    .all(|stack| stack.get_layers().get(*depth) == Some(*layer))
+            })
+            .count();
+        if rest.is_empty() || length == 0 {
+            return Err(LMECoreError::NoCommonPrefix);
+        }
+        let prefix = &first.get_layers()[..length];
+        for idx in stacks {
+            let mut layers = prefix.to_vec();
+            layers.extend_from_slice(&self.stacks[*idx].get_layers()[length..]);
+            self.stacks[*idx] = Arc::new(Stack::new(layers));
+        }
+        self.revision += 1;
+        Ok(length)
+    }
+
+    /// Group all stacks into clusters whose members are pairwise closer than
+    /// `threshold` (aligned RMSD), see `geometry::greedy_cluster`. Atoms are
+    /// paired by index order, so every stack must have the same atom count.
+    pub fn cluster(&self, threshold: f64) -> Result<Vec<Vec<usize>>, LMECoreError> {
+        let conformers = (0..self.stacks.len())
+            .into_par_iter()
+            .map(|index| self.read(index).map(|molecule| molecule.positions()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(first) = conformers.first() {
+            if let Some(index) = conformers
+                .iter()
+                .position(|conformer| conformer.len() != first.len())
+            {
+                return Err(LMECoreError::AtomCountMismatch(index));
+            }
+        }
+        Ok(geometry::greedy_cluster(
+            conformers.len(),
+            threshold,
+            |a, b| geometry::aligned_rmsd(&conformers[a], &conformers[b]),
+        ))
+    }
+
+    /// Pairwise RMSD matrix of the selected stacks, after superposition if
+    /// `align` is set. Atoms are paired by index order; stacks whose atom
+    /// count differs from the first selected one are reported with
+    /// `IncompatibleStacks`. The last result is cached until the next change
+    /// to the stacks.
+    pub fn rmsd_matrix(
+        &self,
+        stacks: &[usize],
+        align: bool,
+    ) -> Result<Arc<Vec<Vec<f64>>>, LMECoreError> {
+        let key = (stacks.to_vec(), align);
+        if let Some(matrix) = self.rmsd_cache.get(self.revision, &key) {
+            return Ok(matrix);
+        }
+        let conformers = stacks
+            .par_iter()
+            .map(|index| self.read(*index).map(|molecule| molecule.positions()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let incompatible = stacks
+            .iter()
+            .zip(&conformers)
+            .filter(|(_, conformer)| conformer.len() != conformers[0].len())
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>();
+        if !incompatible.is_empty() {
+            return Err(LMECoreError::IncompatibleStacks(incompatible));
+        }
+        let distance = |a: usize, b: usize| {
+            if align {
+                geometry::aligned_rmsd(&conformers[a], &conformers[b])
+            } else {
+                geometry::rmsd(&conformers[a], &conformers[b])
+            }
+        };
+        let upper = (0..conformers.len())
+            .into_par_iter()
+            .map(|a| (a + 1..conformers.len()).map(|b| distance(a, b)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let matrix = (0..conformers.len())
+            .map(|a| {
+                (0..conformers.len())
+                    .map(|b| match a.cmp(&b) {
+                        Ordering::Less => upper[a][b - a - 1],
+                        Ordering::Equal => 0.,
+                        Ordering::Greater => upper[b][a - b - 1],
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(self.rmsd_cache.insert(self.revision, key, matrix))
+    }
+
+    pub fn create_stack(&mut self, stack: Arc<Stack>, copies: usize) -> usize {
+        let index = self.stacks.len();
+        for _ in 0..=copies {
+            let name = self.generate_stack_name();
+            self.stacks.push(stack.clone());
+            self.histories.push(StackHistory::default());
+            self.stack_names.push(name);
+            self.electronic_states.push(ElectronicState::default());
+            self.stack_versions.push(self.pending_version());
+        }
+        self.revision += 1;
+        index
+    }
+
+    /// Create one empty stack called `name`, or a generated name if none is
+    /// given, and return its name. Fails with `DuplicatedName` if another
+    /// stack already has this name.
+    pub fn create_named_stack(&mut self, name: Option<String>) -> Result<String, LMECoreError> {
+        if let Some(name) = &name {
+            if self.stack_names.contains(name) {
+                return Err(LMECoreError::DuplicatedName(name.clone()));
+            }
+        }
+        let index = self.create_stack(Arc::new(Stack::new(vec![])), 0);
+        if let Some(name) = name {
+            self.stack_names[index] = name;
+        }
+        Ok(self.stack_names[index].clone())
+    }
+
+    /// Give a stack another unique name, returning its previous name.
+    pub fn rename_stack(
+        &mut self,
+        stack_idx: usize,
+        name: String,
+    ) -> Result<String, LMECoreError> {
+        let position = self.stack_names.iter().position(|held| held == &name);
+        match position {
+            _ if stack_idx >= self.stack_names.len() => Err(LMECoreError::NoSuchStack),
+            Some(holder) if holder != stack_idx => Err(LMECoreError::DuplicatedName(name)),
+            _ => Ok(std::mem::replace(&mut self.stack_names[stack_idx], name)),
+        }
+    }
+
+    fn generate_stack_name(&mut self) -> String {
+        loop {
+            self.stack_serial += 1;
+            let name = format!("stack-{}", self.stack_serial);
+            if !self.stack_names.contains(&name) {
+                return name;
+            }
+        }
+    }
+
+    pub fn stack_names(&self) -> &[String] {
+        &self.stack_names
+    }
+
+    pub fn electronic_state(&self, stack_idx: usize) -> Result<ElectronicState, LMECoreError> {
+        self.electronic_states
+            .get(stack_idx)
+            .copied()
+            .ok_or(LMECoreError::NoSuchStack)
+    }
+
+    /// Set the charge and multiplicity of a stack, failing with
+    /// `InvalidMultiplicity` below 1. Like atom names, the state is not part
+    /// of the stack history and is left alone by `undo`.
+    pub fn set_electronic_state(
+        &mut self,
+        stack_idx: usize,
+        state: ElectronicState,
+    ) -> Result<(), LMECoreError> {
+        if state.multiplicity == 0 {
+            return Err(LMECoreError::InvalidMultiplicity(state.multiplicity));
+        }
+        let held = self
+            .electronic_states
+            .get_mut(stack_idx)
+            .ok_or(LMECoreError::NoSuchStack)?;
+        *held = state;
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Version of a stack. It grows every time an operation applied to the
+    /// workspace edits the stack, or puts another stack at its index, so a
+    /// client can tell whether the stack it read is still the same. Stacks
+    /// of a rebuilt workspace start over from 0.
+    pub fn stack_version(&self, stack_idx: usize) -> Result<u64, LMECoreError> {
+        self.stack_versions
+            .get(stack_idx)
+            .copied()
+            .ok_or(LMECoreError::NoSuchStack)
+    }
+
+    /// The version given to stacks by the operation being applied: its
+    /// position in the log, counting from 1. Failed operations are not
+    /// logged, so replaying the log hands out the same versions.
+    fn pending_version(&self) -> u64 {
+        self.log.total() as u64 + 1
+    }
+
+    /// Give the stacks at `indices` the pending version, skipping indices
+    /// past the last stack.
+    pub(crate) fn bump_versions(&mut self, indices: impl IntoIterator<Item = usize>) {
+        let pending = self.pending_version();
+        for idx in indices {
+            if let Some(version) = self.stack_versions.get_mut(idx) {
+                *version = pending;
+            }
+        }
+    }
+
+    pub fn stack_summaries(&self) -> Result<Vec<StackSummary>, LMECoreError> {
+        self.stacks
+            .iter()
+            .zip(&self.stack_names)
+            .enumerate()
+            .map(|(index, (stack, name))| {
+                Ok(StackSummary {
+                    index,
+                    name: name.clone(),
+                    atoms: self.resolve(stack)?.atoms().len(),
+                    layers: stack.get_layers().len(),
+                    version: self.stack_versions[index],
+                })
+            })
+            .collect()
+    }
+
+    /// The layers of a stack from the bottom up, each compared with the
+    /// molecule read from the layers below it. Fails with `LayerRejected`
+    /// if a layer no longer applies.
+    pub fn layer_summaries(&self, stack_idx: usize) -> Result<Vec<LayerSummary>, LMECoreError> {
+        let stack = self.stacks.get(stack_idx).ok_or(LMECoreError::NoSuchStack)?;
+        let mut molecule = self.base.clone();
+        let mut summaries = vec![];
+        for (position, layer) in stack.get_layers().iter().enumerate() {
+            let next = layer
+                .filter(molecule.clone())
+                .map_err(|err| LMECoreError::LayerRejected(position, Box::new(err)))?;
+            let diff = molecule.diff(&next, 0.);
+            summaries.push(LayerSummary {
+                layer: layer.as_ref().clone(),
+                atoms: diff.added_atoms.len()
+                    + diff.removed_atoms.len()
+                    + diff.changed_atoms.len(),
+            });
+            molecule = next;
+        }
+        Ok(summaries)
+    }
+
+    /// Current index of the stack called `name`.
+    pub fn stack_index(&self, name: &str) -> Result<usize, LMECoreError> {
+        self.stack_names
+            .iter()
+            .position(|stack_name| stack_name == name)
+            .ok_or(LMECoreError::NoSuchStack)
+    }
 
-        Some(self.create_stack(stack, copies))
+    /// Swap in a new version of a stack, keeping the old one for `undo`.
+    fn replace_stack(&mut self, stack_idx: usize, stack: Arc<Stack>) {
+        let previous = std::mem::replace(&mut self.stacks[stack_idx], stack);
+        self.histories[stack_idx].record(previous, self.history_depth);
+    }
+
+    /// Restore the version of a stack before its last edit. Only the
+    /// stack's layers are restored, not workspace-wide atom names or groups.
+    pub fn undo(&mut self, stack_idx: usize) -> Result<(), LMECoreError> {
+        let current = self.stacks.get(stack_idx).ok_or(LMECoreError::NoSuchStack)?;
+        let previous = self.histories[stack_idx]
+            .undo(current.clone())
+            .ok_or(LMECoreError::NothingToUndo)?;
+        self.stacks[stack_idx] = previous;
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Reapply the edit of a stack undone last. A new edit after `undo`
+    /// discards what could be redone.
+    pub fn redo(&mut self, stack_idx: usize) -> Result<(), LMECoreError> {
+        let current = self.stacks.get(stack_idx).ok_or(LMECoreError::NoSuchStack)?;
+        let next = self.histories[stack_idx]
+            .redo(current.clone())
+            .ok_or(LMECoreError::NothingToRedo)?;
+        self.stacks[stack_idx] = next;
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Remove a stack; the stacks after it move down by one index.
+    pub fn remove_stack(&mut self, stack_idx: usize) -> Result<(), LMECoreError> {
+        if stack_idx >= self.stacks.len() {
+            return Err(LMECoreError::NoSuchStack);
+        }
+        self.stacks.remove(stack_idx);
+        self.histories.remove(stack_idx);
+        self.stack_names.remove(stack_idx);
+        self.electronic_states.remove(stack_idx);
+        self.stack_versions.remove(stack_idx);
+        self.bump_versions(stack_idx..self.stacks.len());
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Put the stacks in a new order, `order[i]` being the current index
+    /// of the stack that moves to index `i`. Histories, names and electronic
+    /// states move with their stacks, and stacks changing index get a new
+    /// version. Fails with `InvalidPermutation` unless `order` holds every
+    /// stack index exactly once.
+    pub fn reorder_stacks(&mut self, order: &[usize]) -> Result<(), LMECoreError> {
+        let invalid = LMECoreError::InvalidPermutation(self.stacks.len());
+        let mut seen = vec![false; self.stacks.len()];
+        for idx in order {
+            match seen.get_mut(*idx) {
+                Some(seen) if !*seen => *seen = true,
+                _ => return Err(invalid),
+            }
+        }
+        if order.len() != self.stacks.len() {
+            return Err(invalid);
+        }
+        fn permute<T>(items: Vec<T>, order: &[usize]) -> Vec<T> {
+            let mut items = items.into_iter().map(Some).collect::<Vec<_>>();
+            order
+                .iter()
+                .map(|idx| items[*idx].take().expect("Order is a permutation"))
+                .collect()
+        }
+        self.stacks = permute(std::mem::take(&mut self.stacks), order);
+        self.histories = permute(std::mem::take(&mut self.histories), order);
+        self.stack_names = permute(std::mem::take(&mut self.stack_names), order);
+        self.electronic_states = permute(std::mem::take(&mut self.electronic_states), order);
+        self.stack_versions = permute(std::mem::take(&mut self.stack_versions), order);
+        let moved = order.iter().enumerate().filter(|(to, from)| to != *from);
+        self.bump_versions(moved.map(|(to, _)| to).collect::<Vec<_>>());
+        self.revision += 1;
+        Ok(())
+    }
+
+    pub fn create_stack_from_layer(&mut self, layer: Arc<Layer>, copies: usize) -> usize {
+        let stack = Stack::new(vec![layer]);
+        self.create_stack(Arc::new(stack), copies)
+    }
+
+    /// Copies of a stack, which keep its electronic state.
+    pub fn clone_stack(&mut self, stack_idx: usize, copies: usize) -> Option<usize> {
+        let stack = self.stacks.get(stack_idx).cloned()?;
+        let state = self.electronic_states[stack_idx];
+        let index = self.create_stack(stack, copies);
+        self.electronic_states[index..].fill(state);
+        Some(index)
     }
 
     pub fn clone_base(&mut self, stack_idx: usize, copies: usize) -> Option<usize> {
@@ -315,13 +1671,15 @@ impl Workspace {
         Some(self.create_stack(Arc::new(base), copies))
     }
 
+    /// Write `data` onto the stacks `start_idx..start_idx + range`. Returns
+    /// false, writing nothing, unless that is a non-empty range of stacks.
     pub fn write_to_stack(&mut self, start_idx: usize, range: usize, data: Molecule) -> bool {
-        let max_idx = start_idx + range - 1;
-        if max_idx >= self.stacks.len() {
+        let end = start_idx.checked_add(range);
+        if range == 0 || end.is_none_or(|end| end > self.stacks.len()) {
             false
         } else {
             let stacks = (start_idx..start_idx + range)
-                .par_bridge()
+                .into_par_iter()
                 .map(|i| {
                     let mut stack = self.stacks[i].as_ref().clone();
                     stack.write(data.clone());
@@ -329,24 +1687,737 @@ impl Workspace {
                 })
                 .collect::<Vec<_>>();
             for (i, stack) in stacks.into_iter().enumerate() {
-                self.stacks[i + start_idx] = Arc::new(stack)
+                self.replace_stack(i + start_idx, Arc::new(stack))
             }
+            self.revision += 1;
             true
         }
     }
 
+    /// Renumber the atoms of one stack through `mapping` (old index to new
+    /// index) by pushing a `Layer::Remap` onto it. The workspace-wide atom
+    /// names and groups are rewritten with the same mapping; entries for
+    /// indices outside the mapping are left as they are. Fails with
+    /// `SharedAtoms` if an index moved from or to has a name or group and
+    /// another stack has an atom there, as the name or group would change
+    /// atoms in that stack too.
+    pub fn remap_indices(
+        &mut self,
+        stack_idx: usize,
+        mapping: HashMap<usize, usize>,
+    ) -> Result<(), LMECoreError> {
+        self.stacks.get(stack_idx).ok_or(LMECoreError::NoSuchStack)?;
+        let moved = mapping
+            .iter()
+            .filter(|(from, to)| from != to)
+            .flat_map(|(from, to)| [*from, *to])
+            .filter(|idx| {
+                self.atom_names.get(idx).is_some() || !self.groups.get_right(idx).is_empty()
+            })
+            .collect::<BTreeSet<_>>();
+        let mut shared = BTreeSet::new();
+        for index in (0..self.stacks.len()).filter(|index| *index != stack_idx) {
+            let molecule = self.read(index)?;
+            shared.extend(moved.iter().filter(|idx| molecule.atom(**idx).is_some()));
+        }
+        if !shared.is_empty() {
+            return Err(LMECoreError::SharedAtoms(shared.into_iter().collect()));
+        }
+        let atom_names = self
+            .atom_names
+            .clone()
+            .map_keys(|idx| mapping.get(idx).copied().unwrap_or(*idx))
+            .map_err(LMECoreError::DuplicatedName)?;
+        let groups = self
+            .groups
+            .clone()
+            .into_iter()
+            .map(|(group, idx)| (group, mapping.get(&idx).copied().unwrap_or(idx)))
+            .collect::<HashSet<_>>()
+            .into();
+        if !self.add_layer_to_stack(stack_idx, 1, Arc::new(Layer::Remap(mapping))) {
+            return Err(LMECoreError::NoSuchStack);
+        }
+        self.atom_names = atom_names;
+        self.groups = groups;
+        Ok(())
+    }
+
+    /// Drop workspace-wide atom names and group memberships of the given
+    /// atoms that no stack has any more. Stacks that can't be read are taken
+    /// to still have them.
+    pub fn forget_atoms(&mut self, indices: &HashSet<usize>) {
+        let mut gone = indices.clone();
+        for index in 0..self.stacks.len() {
+            match self.read(index) {
+                Ok(molecule) => gone.retain(|idx| molecule.atom(*idx).is_none()),
+                Err(_) => return,
+            }
+        }
+        self.atom_names.retain(|idx, _| !gone.contains(idx));
+        for idx in &gone {
+            self.groups.remove_right(idx);
+        }
+    }
+
+    /// Remove every hydrogen of a stack (with its bonds) by pushing a
+    /// `Layer::RemoveElement(1)`. Returns, for each heavy atom that lost
+    /// hydrogens, how many were attached to it.
+    pub fn remove_hydrogens(
+        &mut self,
+        stack_idx: usize,
+    ) -> Result<HashMap<usize, usize>, LMECoreError> {
+        let molecule = self.read(stack_idx)?;
+        let hydrogens = molecule
+            .atoms()
+            .into_iter()
+            .filter(|(_, atom)| atom.element() == 1)
+            .map(|(idx, _)| idx)
+            .collect::<HashSet<_>>();
+        let mut implicit = HashMap::new();
+        for (idx, neighbours) in graph::adjacency(&molecule) {
+            let count = neighbours
+                .iter()
+                .filter(|(neighbour, _)| hydrogens.contains(neighbour))
+                .count();
+            if count > 0 && !hydrogens.contains(&idx) {
+                implicit.insert(idx, count);
+            }
+        }
+        self.add_layer_to_stack(stack_idx, 1, Arc::new(Layer::RemoveElement(1)));
+        self.forget_atoms(&hydrogens);
+        Ok(implicit)
+    }
+
+    /// Remove atoms from a stack by writing them as removed into its top
+    /// layer, and drop their workspace-wide names and group memberships
+    /// unless other stacks still have them.
+    /// Fails with `MissingAtoms` listing every index without a live atom in
+    /// the stack, in which case nothing is removed.
+    pub fn remove_atoms(
+        &mut self,
+        stack_idx: usize,
+        atoms: &[usize],
+    ) -> Result<(), LMECoreError> {
+        let molecule = self.read(stack_idx)?;
+        let mut missing = atoms
+            .iter()
+            .filter(|idx| molecule.atom(**idx).is_none())
+            .copied()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            missing.sort();
+            missing.dedup();
+            return Err(LMECoreError::MissingAtoms(missing));
+        }
+        let atoms = atoms.iter().copied().collect::<HashSet<_>>();
+        self.write_to_stack(stack_idx, 1, Molecule::default().remove_atoms(&atoms));
+        self.forget_atoms(&atoms);
+        Ok(())
+    }
+
+    /// `atoms` and the members of `group`, failing with `NoSuchGroup` for a
+    /// group without members and with `MissingAtoms` if one of them is not
+    /// in `molecule`.
+    fn select_atoms(
+        &self,
+        molecule: &Molecule,
+        atoms: Vec<usize>,
+        group: Option<String>,
+    ) -> Result<BTreeSet<usize>, LMECoreError> {
+        let mut atoms = atoms.into_iter().collect::<BTreeSet<_>>();
+        if let Some(group) = group {
+            let members = self.groups.get_left(&group);
+            if members.is_empty() {
+                return Err(LMECoreError::NoSuchGroup(group));
+            }
+            atoms.extend(members);
+        }
+        let missing = atoms
+            .iter()
+            .filter(|idx| molecule.atom(**idx).is_none())
+            .copied()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(LMECoreError::MissingAtoms(missing));
+        }
+        Ok(atoms)
+    }
+
+    /// Push a `Layer::MoveAtoms` onto a stack, moving the atoms of `motion`
+    /// and the members of its group, and return how many atoms it moves.
+    /// See `select_atoms` for how the selection can fail.
+    pub fn move_atoms(
+        &mut self,
+        stack_idx: usize,
+        motion: RigidMotion,
+    ) -> Result<usize, LMECoreError> {
+        let molecule = self.read(stack_idx)?;
+        let atoms = self.select_atoms(&molecule, motion.atoms, motion.group)?;
+        let center = motion
+            .center
+            .unwrap_or_else(|| selection_centroid(&molecule, &atoms));
+        let rotation = Isometry3::rotation_wrt_point(
+            UnitQuaternion::from_scaled_axis(motion.rotation),
+            center,
+        );
+        let motion = Translation3::from(motion.translation) * rotation;
+        let count = atoms.len();
+        self.add_layers(stack_idx, vec![Layer::MoveAtoms(motion, atoms)])?;
+        Ok(count)
+    }
+
+    /// Push a `Layer::MoveAtoms` onto a stack turning the side of `moving`
+    /// about its bond to `fixed` by `angle` degrees, counterclockwise when
+    /// looking from `moving` down to `fixed`. Returns how many atoms it
+    /// moves; see `graph::side` for how finding that side can fail.
+    pub fn rotate_torsion(
+        &mut self,
+        stack_idx: usize,
+        [fixed, moving]: [usize; 2],
+        angle: f64,
+    ) -> Result<usize, LMECoreError> {
+        let molecule = self.read(stack_idx)?;
+        let atoms = graph::side(&molecule, fixed, moving)?;
+        let position = |idx| molecule.atom(idx).expect("Bonded atom").position();
+        let (fixed, moving) = (position(fixed), position(moving));
+        let axis = (moving - fixed).normalize() * angle.to_radians();
+        let motion = Isometry3::rotation_wrt_point(UnitQuaternion::from_scaled_axis(axis), moving);
+        let count = atoms.len();
+        self.add_layers(stack_idx, vec![Layer::MoveAtoms(motion, atoms)])?;
+        Ok(count)
+    }
+
+    /// Whichever of the sides of bond a-b holds fewer atoms, as the atom it
+    /// starts from and its atoms, the side of `b` on a tie.
+    fn smaller_side(
+        molecule: &Molecule,
+        a: usize,
+        b: usize,
+    ) -> Result<(usize, BTreeSet<usize>), LMECoreError> {
+        let (side_a, side_b) = (graph::side(molecule, b, a)?, graph::side(molecule, a, b)?);
+        if side_a.len() < side_b.len() {
+            Ok((a, side_a))
+        } else {
+            Ok((b, side_b))
+        }
+    }
+
+    /// Push a `Layer::MoveAtoms` onto a stack shifting the smaller side of
+    /// bond a-b along it until the bond is `length` Å long, and return how
+    /// many atoms it moves. Fails with `InvalidTarget` unless `length` is
+    /// positive and otherwise like `graph::side`.
+    pub fn set_bond_length(
+        &mut self,
+        stack_idx: usize,
+        [a, b]: [usize; 2],
+        length: f64,
+    ) -> Result<usize, LMECoreError> {
+        if length <= 0. || !length.is_finite() {
+            return Err(LMECoreError::InvalidTarget(length));
+        }
+        let molecule = self.read(stack_idx)?;
+        let (moving, atoms) = Self::smaller_side(&molecule, a, b)?;
+        let fixed = if moving == a { b } else { a };
+        let position = |idx| molecule.atom(idx).expect("Bonded atom").position();
+        let bond = position(moving) - position(fixed);
+        let direction = Unit::try_new(bond, f64::EPSILON).unwrap_or(Vector3::x_axis());
+        let motion = Isometry3::from(Translation3::from(
+            direction.as_ref() * (length - bond.norm()),
+        ));
+        let count = atoms.len();
+        self.add_layers(stack_idx, vec![Layer::MoveAtoms(motion, atoms)])?;
+        Ok(count)
+    }
+
+    /// Push a `Layer::MoveAtoms` onto a stack turning the smaller of the
+    /// sides of `a` and `c` about `b`, in the a-b-c plane, until the angle
+    /// a-b-c is `angle` degrees. Returns how many atoms it moves. Fails
+    /// with `InvalidTarget` for angles outside [0, 180] and otherwise like
+    /// `graph::side`, with `RingBond` if a-b-c is part of a ring.
+    pub fn set_bond_angle(
+        &mut self,
+        stack_idx: usize,
+        [a, b, c]: [usize; 3],
+        angle: f64,
+    ) -> Result<usize, LMECoreError> {
+        if !(0. ..=180.).contains(&angle) {
+            return Err(LMECoreError::InvalidTarget(angle));
+        }
+        let molecule = self.read(stack_idx)?;
+        let (side_a, side_c) = (graph::side(&molecule, b, a)?, graph::side(&molecule, b, c)?);
+        if side_a.contains(&c) {
+            return Err(LMECoreError::RingBond(b, a));
+        }
+        let position = |idx| molecule.atom(idx).expect("Bonded atom").position();
+        let (arm_a, arm_c) = (position(a) - position(b), position(c) - position(b));
+        let axis = Unit::try_new(arm_a.cross(&arm_c), f64::EPSILON)
+            .unwrap_or_else(|| Unit::new_normalize(perpendicular(&arm_a)));
+        let change = (angle - geometry::angle(&position(a), &position(b), &position(c)))
+            .to_radians();
+        let (change, atoms) = if side_a.len() < side_c.len() {
+            (-change, side_a)
+        } else {
+            (change, side_c)
+        };
+        let rotation = UnitQuaternion::from_axis_angle(&axis, change);
+        let motion = Isometry3::rotation_wrt_point(rotation, position(b));
+        let count = atoms.len();
+        self.add_layers(stack_idx, vec![Layer::MoveAtoms(motion, atoms)])?;
+        Ok(count)
+    }
+
+    /// Push a `Layer::Mirror` onto a stack, reflecting `atoms` and the
+    /// members of `group`, or every atom if neither is given, and return
+    /// how many atoms it reflects. Fails with `ZeroNormal` for a plane
+    /// without a direction.
+    pub fn mirror_atoms(
+        &mut self,
+        stack_idx: usize,
+        atoms: Vec<usize>,
+        group: Option<String>,
+        mirror: Mirror,
+    ) -> Result<usize, LMECoreError> {
+        if matches!(mirror, Mirror::Plane { normal, .. } if normal.norm() == 0.) {
+            return Err(LMECoreError::ZeroNormal);
+        }
+        let molecule = self.read(stack_idx)?;
+        let atoms = if atoms.is_empty() && group.is_none() {
+            molecule.atoms().into_iter().map(|(idx, _)| idx).collect()
+        } else {
+            self.select_atoms(&molecule, atoms, group)?
+        };
+        let count = atoms.len();
+        self.add_layers(stack_idx, vec![Layer::Mirror(mirror, atoms)])?;
+        Ok(count)
+    }
+
+    /// Push a `Layer::Replicate` onto a stack with a copy of `atoms` and the
+    /// members of `group`, or of every atom if neither is given, for each
+    /// element of `symmetry` about `center` (the centroid of the copied
+    /// atoms if not given). Returns the indices of the new atoms; copies
+    /// landing on an existing atom are merged into it.
+    pub fn replicate_atoms(
+        &mut self,
+        stack_idx: usize,
+        atoms: Vec<usize>,
+        group: Option<String>,
+        symmetry: &Symmetry,
+        center: Option<Point3<f64>>,
+    ) -> Result<Vec<usize>, LMECoreError> {
+        let molecule = self.read(stack_idx)?;
+        let atoms = if atoms.is_empty() && group.is_none() {
+            molecule.atoms().into_iter().map(|(idx, _)| idx).collect()
+        } else {
+            self.select_atoms(&molecule, atoms, group)?
+        };
+        let center = center.unwrap_or_else(|| selection_centroid(&molecule, &atoms));
+        let start = molecule.next_index();
+        let copies = SymmetryCopy::expand(symmetry, center, &atoms, start)?;
+        self.add_layers(stack_idx, vec![Layer::Replicate(copies)])?;
+        let replicated = self.read(stack_idx)?;
+        Ok(replicated
+            .atoms()
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .filter(|idx| *idx >= start)
+            .collect())
+    }
+
+    /// Grow a stack into a supercell of its cell repeated `repeats` times
+    /// along a, b and c: a `Layer::Replicate` with a translated copy of
+    /// every atom for each further cell, numbered from the stack's
+    /// `next_index()` cell by cell with c varying fastest, then a `Fill`
+    /// setting the supercell as the cell. Returns the indices of the new
+    /// atoms, fewer if copies landed on atoms, and fails with `NoCell`
+    /// without a unit cell. Only bonds within a cell are copied.
+    pub fn supercell(
+        &mut self,
+        stack_idx: usize,
+        repeats: [NonZeroUsize; 3],
+    ) -> Result<Vec<usize>, LMECoreError> {
+        let molecule = self.read(stack_idx)?;
+        let cell = *molecule.cell().ok_or(LMECoreError::NoCell)?;
+        let atoms = molecule.atoms().into_iter().map(|(idx, _)| idx).collect::<Vec<_>>();
+        let repeats = repeats.map(NonZeroUsize::get);
+        let [a, b, c] = repeats;
+        let start = molecule.next_index();
+        let mut next = start;
+        let copies = (0..a)
+            .flat_map(|i| (0..b).flat_map(move |j| (0..c).map(move |k| [i, j, k])))
+            .skip(1)
+            .map(|image| {
+                let shift = cell.to_cartesian(&Point3::from(image.map(|n| n as f64)));
+                let transform = Translation3::from(shift.coords).to_homogeneous();
+                let atoms = atoms
+                    .iter()
+                    .map(|idx| {
+                        next += 1;
+                        (*idx, next - 1)
+                    })
+                    .collect();
+                SymmetryCopy {
+                    transform: Transform3::from_matrix_unchecked(transform),
+                    atoms,
+                }
+            })
+            .collect();
+        let patch = Molecule::default().set_cell(Some(cell.repeat(repeats)));
+        let layers = vec![Layer::Replicate(copies), Layer::Fill(Box::new(patch))];
+        self.add_layers(stack_idx, layers)?;
+        let grown = self.read(stack_idx)?;
+        Ok(grown
+            .atoms()
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .filter(|idx| *idx >= start)
+            .collect())
+    }
+
+    pub fn fragments(&self) -> &BTreeMap<String, Fragment> {
+        &self.fragments
+    }
+
+    pub fn fragment(&self, name: &str) -> Result<&Fragment, LMECoreError> {
+        self.fragments
+            .get(name)
+            .ok_or_else(|| LMECoreError::NoSuchFragment(name.to_string()))
+    }
+
+    /// Store a fragment under `name`, replacing any fragment of that name.
+    /// Fails with `InvalidFragment` if its attachment or dummy atom is
+    /// missing.
+    pub fn set_fragment(&mut self, name: String, fragment: Fragment) -> Result<(), LMECoreError> {
+        fragment.check()?;
+        self.fragments.insert(name, fragment);
+        Ok(())
+    }
+
+    pub fn remove_fragment(&mut self, name: &str) -> Result<Fragment, LMECoreError> {
+        self.fragments
+            .remove(name)
+            .ok_or_else(|| LMECoreError::NoSuchFragment(name.to_string()))
+    }
+
+    pub fn templates(&self) -> &BTreeMap<String, Template> {
+        &self.templates
+    }
+
+    pub fn template(&self, name: &str) -> Result<&Template, LMECoreError> {
+        self.templates
+            .get(name)
+            .ok_or_else(|| LMECoreError::NoSuchTemplate(name.to_string()))
+    }
+
+    /// Store a template under `name`, replacing any template of that name.
+    pub fn set_template(&mut self, name: String, template: Template) {
+        self.templates.insert(name, template);
+    }
+
+    pub fn remove_template(&mut self, name: &str) -> Result<Template, LMECoreError> {
+        self.templates
+            .remove(name)
+            .ok_or_else(|| LMECoreError::NoSuchTemplate(name.to_string()))
+    }
+
+    /// Replace the terminal atom `atom_idx` of a stack with `fragment` by
+    /// writing it into the top layer (see `Fragment::substitute`), and drop
+    /// the workspace-wide names and group memberships of the replaced atom
+    /// unless other stacks still have it.
+    /// Returns the new index of each fragment atom.
+    pub fn substitute(
+        &mut self,
+        stack_idx: usize,
+        atom_idx: usize,
+        fragment: &FragmentRef,
+    ) -> Result<BTreeMap<usize, usize>, LMECoreError> {
+        let fragment = match fragment {
+            FragmentRef::Named(name) => self.fragment(name)?,
+            FragmentRef::Inline(fragment) => fragment.as_ref(),
+        };
+        let (patch, indices) = fragment.substitute(&self.read(stack_idx)?, atom_idx)?;
+        self.write_to_stack(stack_idx, 1, patch);
+        self.forget_atoms(&HashSet::from([atom_idx]));
+        Ok(indices)
+    }
+
+    /// A stack restricted to the members of `group`, the whole stack if no
+    /// group is given. Fails with `NoSuchGroup` if none of the members are
+    /// in the stack.
+    pub fn read_group(
+        &self,
+        stack_idx: usize,
+        group: Option<&str>,
+    ) -> Result<Molecule, LMECoreError> {
+        let molecule = self.read(stack_idx)?;
+        let Some(group) = group else {
+            return Ok(molecule);
+        };
+        let members = self.groups.get_left(&group.to_string());
+        let selection = molecule.restrict(&members);
+        if selection.atoms().is_empty() {
+            return Err(LMECoreError::NoSuchGroup(group.to_string()));
+        }
+        Ok(selection)
+    }
+
+    /// Overlay `metadata` on the metadata of atoms of a stack with a
+    /// `Layer::Fill`, see `AtomMetadata::overlay`. Returns the number of
+    /// atoms written; fails with `MissingAtoms` if any of them is not in
+    /// the stack.
+    pub fn set_metadata(
+        &mut self,
+        stack_idx: usize,
+        metadata: HashMap<usize, AtomMetadata>,
+    ) -> Result<usize, LMECoreError> {
+        let molecule = self.read(stack_idx)?;
+        let mut missing = metadata
+            .keys()
+            .filter(|idx| molecule.atom(**idx).is_none())
+            .copied()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            missing.sort();
+            return Err(LMECoreError::MissingAtoms(missing));
+        }
+        let count = metadata.len();
+        let patch = Molecule::default().set_metadata(metadata);
+        self.add_layers(stack_idx, vec![Layer::Fill(Box::new(patch))])?;
+        Ok(count)
+    }
+
+    /// Push a `Layer::Transform` onto a stack moving the members of `group`,
+    /// or every atom if no group is given, to the standard orientation of
+    /// `Molecule::standard_orientation`. The whole stack moves along.
+    pub fn orient(&mut self, stack_idx: usize, group: Option<&str>) -> Result<(), LMECoreError> {
+        let frame = self.read_group(stack_idx, group)?.standard_orientation();
+        let transform = Transform3::from_matrix_unchecked(frame.to_homogeneous());
+        self.add_layers(stack_idx, vec![Layer::Transform(transform)])
+    }
+
+    /// The rigid motion superposing stack `a` onto stack `b` (see
+    /// `geometry::superpose`) and the RMSD left after it. Atoms are matched
+    /// by index: `atoms` and the members of `group`, or every atom both
+    /// stacks have if neither is given. See `select_atoms` for how the
+    /// selection can fail; if no atom is matched, it fails with
+    /// `NotEnoughMatchedAtoms`.
+    pub fn superposition(
+        &self,
+        a: usize,
+        b: usize,
+        atoms: Vec<usize>,
+        group: Option<String>,
+    ) -> Result<(Isometry3<f64>, f64), LMECoreError> {
+        self.superpose_matched(a, b, atoms, group, 1)
+    }
+
+    /// `superposition`, failing with `NotEnoughMatchedAtoms` when fewer than
+    /// `needed` atoms are matched.
+    fn superpose_matched(
+        &self,
+        a: usize,
+        b: usize,
+        atoms: Vec<usize>,
+        group: Option<String>,
+        needed: usize,
+    ) -> Result<(Isometry3<f64>, f64), LMECoreError> {
+        let (molecule_a, molecule_b) = (self.read(a)?, self.read(b)?);
+        let matched = if atoms.is_empty() && group.is_none() {
+            molecule_a
+                .atoms()
+                .into_iter()
+                .map(|(idx, _)| idx)
+                .filter(|idx| molecule_b.atom(*idx).is_some())
+                .collect()
+        } else {
+            self.select_atoms(&molecule_a, atoms.clone(), group.clone())?;
+            self.select_atoms(&molecule_b, atoms, group)?
+        };
+        if matched.len() < needed {
+            return Err(LMECoreError::NotEnoughMatchedAtoms(matched.len()));
+        }
+        let positions = |molecule: &Molecule| {
+            matched
+                .iter()
+                .map(|idx| molecule.atom(*idx).expect("Matched atom").position())
+                .collect::<Vec<_>>()
+        };
+        let (p, q) = (positions(&molecule_a), positions(&molecule_b));
+        let motion = geometry::superpose(&p, &q);
+        let moved = p.iter().map(|point| motion * point).collect::<Vec<_>>();
+        Ok((motion, geometry::rmsd(&moved, &q)))
+    }
+
+    /// Push a `Layer::Transform` onto stack `a` superposing it onto stack
+    /// `b`, matching atoms as `superposition` does, and return the RMSD
+    /// left. Fewer than three matched atoms leave the rotation undetermined
+    /// and fail with `NotEnoughMatchedAtoms`.
+    pub fn align_to(
+        &mut self,
+        a: usize,
+        b: usize,
+        atoms: Vec<usize>,
+        group: Option<String>,
+    ) -> Result<f64, LMECoreError> {
+        let (motion, rmsd) = self.superpose_matched(a, b, atoms, group, 3)?;
+        let transform = Transform3::from_matrix_unchecked(motion.to_homogeneous());
+        self.add_layers(a, vec![Layer::Transform(transform)])?;
+        Ok(rmsd)
+    }
+
+    /// Push a layer with the bonds `graph::perceive_bonds` finds in a
+    /// stack, all single bonds, and return them. No layer is pushed if no
+    /// bond is found.
+    pub fn perceive_bonds(
+        &mut self,
+        stack_idx: usize,
+        tolerance: f64,
+    ) -> Result<Vec<Pair<usize>>, LMECoreError> {
+        let (molecule, index) = (self.read_shared(stack_idx)?, self.spatial_index(stack_idx)?);
+        let bonds = graph::perceive_bonds(&molecule, &index, tolerance);
+        if !bonds.is_empty() {
+            let bonds = bonds.iter().map(|pair| (*pair, 1.)).collect();
+            let patch = Molecule::default().set_bonds(bonds);
+            self.add_layers(stack_idx, vec![Layer::Fill(Box::new(patch))])?;
+        }
+        Ok(bonds)
+    }
+
+    /// Complete the valence of the heavy atoms of a stack with hydrogens
+    /// (see `hydrogens::add_hydrogens`) in a new layer, returning the indices
+    /// of the new atoms. Nothing is pushed if no hydrogen is missing.
+    pub fn add_hydrogens(&mut self, stack_idx: usize) -> Result<Vec<usize>, LMECoreError> {
+        let patch = hydrogens::add_hydrogens(&self.read(stack_idx)?);
+        let added = patch
+            .atoms()
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        if !added.is_empty() {
+            self.add_layers(stack_idx, vec![Layer::Fill(Box::new(patch))])?;
+        }
+        Ok(added)
+    }
+
+    /// `write_to_stack` for a patch whose atom positions are fractional
+    /// coordinates. Each stack converts with the patch's cell if it has one,
+    /// otherwise with its own, and fails with `NoCell` if neither is set.
+    pub fn write_fractional(
+        &mut self,
+        start_idx: usize,
+        range: usize,
+        data: Molecule,
+    ) -> Result<(), LMECoreError> {
+        let end = start_idx.checked_add(range);
+        if range == 0 || end.is_none_or(|end| end > self.stacks.len()) {
+            return Err(LMECoreError::NoSuchStack);
+        }
+        let patches = (start_idx..start_idx + range)
+            .map(|idx| {
+                let cell = match data.cell() {
+                    Some(cell) => *cell,
+                    None => *self.read(idx)?.cell().ok_or(LMECoreError::NoCell)?,
+                };
+                Ok(data.clone().from_fractional(&cell))
+            })
+            .collect::<Result<Vec<_>, LMECoreError>>()?;
+        for (offset, patch) in patches.into_iter().enumerate() {
+            self.write_to_stack(start_idx + offset, 1, patch);
+        }
+        Ok(())
+    }
+
+    /// Add `molecule` to a stack as new atoms numbered from the stack's
+    /// `next_index()`, returning the indices of the new atoms in the order
+    /// of their original indices.
+    pub fn import(
+        &mut self,
+        stack_idx: usize,
+        molecule: Molecule,
+    ) -> Result<Vec<usize>, LMECoreError> {
+        let next = self.read(stack_idx)?.next_index();
+        let mapping = molecule
+            .atoms()
+            .into_iter()
+            .enumerate()
+            .map(|(offset, (idx, _))| (idx, next + offset))
+            .collect::<HashMap<_, _>>();
+        let mut added = mapping.values().copied().collect::<Vec<_>>();
+        added.sort();
+        self.write_to_stack(stack_idx, 1, molecule.remap(&mapping));
+        Ok(added)
+    }
+
+    /// Copy the atoms of `motion` and the members of its group from stack
+    /// `source` into stack `target` with the bonds among them, every atom
+    /// if none are selected. The copies are moved by `motion` and numbered
+    /// as by `import`; they take no atom names or workspace groups. Returns
+    /// the source to target index mapping.
+    pub fn copy_atoms(
+        &mut self,
+        target: usize,
+        source: usize,
+        motion: RigidMotion,
+    ) -> Result<HashMap<usize, usize>, LMECoreError> {
+        let molecule = self.read(source)?;
+        let atoms = if motion.atoms.is_empty() && motion.group.is_none() {
+            molecule.atoms().into_iter().map(|(idx, _)| idx).collect()
+        } else {
+            self.select_atoms(&molecule, motion.atoms, motion.group)?
+        };
+        let center = motion
+            .center
+            .unwrap_or_else(|| selection_centroid(&molecule, &atoms));
+        let rotation = Isometry3::rotation_wrt_point(
+            UnitQuaternion::from_scaled_axis(motion.rotation),
+            center,
+        );
+        let motion = Translation3::from(motion.translation) * rotation;
+        let kept = atoms.iter().copied().collect::<HashSet<_>>();
+        let copy = Layer::MoveAtoms(motion, atoms.clone()).filter(molecule.restrict(&kept))?;
+        let added = self.import(target, copy)?;
+        Ok(atoms.into_iter().zip(added).collect())
+    }
+
+    /// Close the gaps left by removed atoms in a stack, renumbering its atoms
+    /// to `0..n` in their current order. Returns the old to new index mapping.
+    pub fn compact(&mut self, stack_idx: usize) -> Result<HashMap<usize, usize>, LMECoreError> {
+        let mapping = self
+            .read(stack_idx)?
+            .atoms()
+            .into_iter()
+            .enumerate()
+            .map(|(new, (old, _))| (old, new))
+            .collect::<HashMap<_, _>>();
+        self.remap_indices(stack_idx, mapping.clone())?;
+        Ok(mapping)
+    }
+
+    /// Permanently renumber a stack into its canonical atom order
+    /// (`graph::canonical_order`), returning the old to new index mapping.
+    pub fn renumber(&mut self, stack_idx: usize) -> Result<HashMap<usize, usize>, LMECoreError> {
+        let molecule = self.read(stack_idx)?;
+        let mapping = graph::canonical_order(&molecule)
+            .into_iter()
+            .enumerate()
+            .map(|(new, old)| (old, new))
+            .collect::<HashMap<_, _>>();
+        self.remap_indices(stack_idx, mapping.clone())?;
+        Ok(mapping)
+    }
+
     pub fn add_layer_to_stack(
         &mut self,
         start_idx: usize,
         range: usize,
         layer: Arc<Layer>,
     ) -> bool {
-        let max_idx = start_idx + range - 1;
-        if max_idx >= self.stacks.len() {
+        let end = start_idx.checked_add(range);
+        if range == 0 || end.is_none_or(|end| end > self.stacks.len()) {
             false
         } else {
             let stacks = (start_idx..start_idx + range)
-                .par_bridge()
+                .into_par_iter()
                 .map(|i| {
                     let mut stack = self.stacks[i].as_ref().clone();
                     stack.add_layer(layer.clone());
@@ -354,54 +2425,456 @@ impl Workspace {
                 })
                 .collect::<Vec<_>>();
             for (i, stack) in stacks.into_iter().enumerate() {
-                self.stacks[i + start_idx] = Arc::new(stack);
+                self.replace_stack(i + start_idx, Arc::new(stack));
             }
+            self.revision += 1;
             true
         }
     }
-}
 
-impl From<&Workspace> for WorkspaceExport {
-    fn from(value: &Workspace) -> Self {
-        Self {
-            base: value.base.clone(),
-            stacks: StackTree::dehydration(&value.stacks),
-            atom_names: value.atom_names.clone(),
-            groups: value.groups.clone(),
+    /// Set the name or comment of the top layer of a stack. Fails with
+    /// `EmptyStack` if the stack has no layer to annotate.
+    pub fn annotate_layer(
+        &mut self,
+        stack_idx: usize,
+        meta: LayerMeta,
+    ) -> Result<(), LMECoreError> {
+        let mut stack = self
+            .stacks
+            .get(stack_idx)
+            .ok_or(LMECoreError::NoSuchStack)?
+            .as_ref()
+            .clone();
+        if !stack.annotate(meta) {
+            return Err(LMECoreError::EmptyStack(stack_idx));
+        }
+        self.replace_stack(stack_idx, Arc::new(stack));
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Push `layers` onto a stack as one change: each layer is applied on
+    /// top of the previous ones first, and the stack is only replaced if all
+    /// of them apply. Fails with `LayerRejected` carrying the position of
+    /// the first failing layer, leaving the stack untouched.
+    pub fn add_layers(
+        &mut self,
+        stack_idx: usize,
+        layers: Vec<Layer>,
+    ) -> Result<(), LMECoreError> {
+        let mut molecule = self.read(stack_idx)?;
+        let mut stack = self.stacks[stack_idx].as_ref().clone();
+        for (position, layer) in layers.into_iter().enumerate() {
+            molecule = layer
+                .filter(molecule)
+                .map_err(|err| LMECoreError::LayerRejected(position, Box::new(err)))?;
+            stack.add_layer(Arc::new(layer));
+        }
+        self.replace_stack(stack_idx, Arc::new(stack));
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Apply `edits` in order to one stack and the workspace-wide atom names
+    /// and groups, all or none. Every edit sees the edits before it, so a
+    /// name can be given to an atom written earlier in the batch. The writes
+    /// are merged into one fill layer, pushed before the next added layer
+    /// if any, so a batch without added layers leaves a single new layer on
+    /// the stack. The edits of the stack end up as a single step of its undo
+    /// history.
+    ///
+    /// Fails with `NoSuchStack`, or with `BatchFailed` listing every edit
+    /// that does not apply, leaving the workspace untouched.
+    pub fn batch(&mut self, stack_idx: usize, edits: Vec<BatchEdit>) -> Result<(), LMECoreError> {
+        let mut molecule = self.read(stack_idx)?;
+        let mut stack = self.stacks[stack_idx].as_ref().clone();
+        let mut atom_names = self.atom_names.clone();
+        let mut groups = self.groups.clone();
+        let mut written: Option<Molecule> = None;
+        // atoms of the stack as the batch left it, or of the rest of the workspace
+        let check_atom = |molecule: &Molecule, atom_idx: usize| {
+            let elsewhere = || {
+                self.base.atom(atom_idx).is_some()
+                    || (0..self.stacks.len())
+                        .filter(|index| *index != stack_idx)
+                        .any(|index| self.read(index).is_ok_and(|m| m.atom(atom_idx).is_some()))
+            };
+            match molecule.atom(atom_idx).is_some() || elsewhere() {
+                true => Ok(()),
+                false => Err(LMECoreError::UnknownAtom(atom_idx)),
+            }
+        };
+        let mut failures = vec![];
+        for (edit, change) in edits.into_iter().enumerate() {
+            let result = match change {
+                BatchEdit::Write(patch) => {
+                    molecule = Molecule::merge(molecule, patch.as_ref().clone());
+                    written = Some(match written.take() {
+                        Some(written) => Molecule::merge(written, *patch),
+                        None => *patch,
+                    });
+                    Ok(())
+                }
+                BatchEdit::AddLayer(layer) => match layer.filter(molecule.clone()) {
+                    Ok(filtered) => {
+                        molecule = filtered;
+                        if let Some(written) = written.take() {
+                            stack.add_layer(Arc::new(Layer::Fill(Box::new(written))));
+                        }
+                        stack.add_layer(Arc::new(layer));
+                        Ok(())
+                    }
+                    Err(err) => Err(LMECoreError::LayerRejected(edit, Box::new(err))),
+                },
+                BatchEdit::Name { atom_idx, name } => {
+                    check_atom(&molecule, atom_idx).and_then(|_| {
+                        match atom_names.insert(atom_idx, name.clone()) {
+                            InsertResult::Duplicated(_) => Err(LMECoreError::DuplicatedName(name)),
+                            _ => Ok(()),
+                        }
+                    })
+                }
+                BatchEdit::Group { atom_idx, group } => {
+                    check_atom(&molecule, atom_idx).map(|_| {
+                        groups.insert(group, atom_idx);
+                    })
+                }
+            };
+            if let Err(error) = result {
+                failures.push(BatchFailure { edit, error });
+            }
+        }
+        if !failures.is_empty() {
+            return Err(LMECoreError::BatchFailed(failures));
+        }
+        if let Some(written) = written {
+            stack.add_layer(Arc::new(Layer::Fill(Box::new(written))));
+        }
+        if stack.get_layers().len() != self.stacks[stack_idx].get_layers().len() {
+            self.replace_stack(stack_idx, Arc::new(stack));
         }
+        self.atom_names = atom_names;
+        self.groups = groups;
+        self.revision += 1;
+        Ok(())
     }
+
+    /// Push the layer at `position` of stack `source` onto stack `target`,
+    /// sharing it rather than copying. As with `add_layers`, the target is
+    /// left untouched with `LayerRejected` carrying `position` if the layer
+    /// does not apply on top of it.
+    pub fn cherry_pick(
+        &mut self,
+        target: usize,
+        source: usize,
+        position: usize,
+    ) -> Result<(), LMECoreError> {
+        let layer = self
+            .stacks
+            .get(source)
+            .ok_or(LMECoreError::NoSuchStack)?
+            .get_layers()
+            .get(position)
+            .ok_or(LMECoreError::NoSuchLayer(position))?
+            .clone();
+        let molecule = self.read(target)?;
+        layer
+            .filter(molecule)
+            .map_err(|err| LMECoreError::LayerRejected(position, Box::new(err)))?;
+        let mut stack = self.stacks[target].as_ref().clone();
+        stack.add_layer(layer);
+        self.replace_stack(target, Arc::new(stack));
+        self.revision += 1;
+        Ok(())
+    }
+}
+
+/// The parts of a workspace an export is built from. Stacks are shared
+/// with the workspace rather than copied, so taking a snapshot is cheap and
+/// the stack trees can be built after the workspace lock is released.
+pub struct WorkspaceSnapshot {
+    base: Molecule,
+    stacks: Vec<Arc<Stack>>,
+    atom_names: UniqueValueMap<usize, String>,
+    groups: NtoN<String, usize>,
+    stack_names: Vec<String>,
+    fragments: BTreeMap<String, Fragment>,
+    templates: BTreeMap<String, Template>,
+    electronic_states: Vec<ElectronicState>,
+    audit: Vec<AuditEntry>,
 }
 
-impl Into<Workspace> for &WorkspaceExport {
-    fn into(self) -> Workspace {
-        let stacks = StackTree::hydration(&self.stacks);
-        Workspace {
+impl Workspace {
+    pub fn snapshot(&self) -> WorkspaceSnapshot {
+        WorkspaceSnapshot {
             base: self.base.clone(),
-            stacks,
+            stacks: self.stacks.clone(),
             atom_names: self.atom_names.clone(),
             groups: self.groups.clone(),
+            stack_names: self.stack_names.clone(),
+            fragments: self.fragments.clone(),
+            templates: self.templates.clone(),
+            electronic_states: self.electronic_states.clone(),
+            audit: self.audit.clone(),
+        }
+    }
+}
+
+impl WorkspaceSnapshot {
+    /// Keep only the atoms in at least one of `groups`, in the base, every
+    /// layer, and the atom names and groups. Groups name atoms of the
+    /// resolved stacks, so each stack is walked top down, following its
+    /// remappings back to the indices below them, and the base keeps what
+    /// any stack keeps of it. The audit trail is left out, as its
+    /// operations may name any atom.
+    pub fn restrict(self, groups: &[String]) -> Self {
+        let kept = groups
+            .iter()
+            .flat_map(|group| self.groups.get_left(group))
+            .collect::<HashSet<_>>();
+        let restricted = self
+            .stacks
+            .iter()
+            .map(|stack| {
+                let mut kept_above = kept.clone();
+                let mut layers = stack
+                    .get_layers()
+                    .iter()
+                    .rev()
+                    .map(|layer| {
+                        let restricted = Arc::new(layer.restrict(&kept_above));
+                        kept_above = layer.kept_below(&kept_above);
+                        restricted
+                    })
+                    .collect::<Vec<_>>();
+                layers.reverse();
+                (layers, kept_above)
+            })
+            .collect::<Vec<_>>();
+        let kept_in_base = match restricted.is_empty() {
+            true => kept.clone(),
+            false => restricted.iter().flat_map(|(_, kept)| kept).copied().collect(),
+        };
+        let stacks = restricted
+            .into_iter()
+            .map(|(mut layers, kept)| {
+                // the base keeps atoms for other stacks this one must not see
+                if kept != kept_in_base {
+                    let identity = kept.iter().map(|idx| (*idx, *idx)).collect();
+                    layers.insert(0, Arc::new(Layer::Remap(identity)));
+                }
+                Arc::new(Stack::new(layers))
+            })
+            .collect();
+        let mut atom_names = self.atom_names;
+        atom_names.retain(|idx, _| kept.contains(idx));
+        Self {
+            base: self.base.restrict(&kept_in_base),
+            stacks,
+            atom_names,
+            groups: self
+                .groups
+                .into_iter()
+                .filter(|(_, idx)| kept.contains(idx))
+                .collect::<HashSet<_>>()
+                .into(),
+            stack_names: self.stack_names,
+            fragments: self.fragments,
+            templates: self.templates,
+            electronic_states: self.electronic_states,
+            audit: vec![],
+        }
+    }
+}
+
+impl TryFrom<WorkspaceSnapshot> for WorkspaceExport {
+    type Error = LMECoreError;
+
+    /// Fails with `EmptyStack` if a stack has no layer, as such a stack has
+    /// no node to hang from in the stack trees.
+    fn try_from(value: WorkspaceSnapshot) -> Result<Self, Self::Error> {
+        StackTree::dehydration(&value.stacks)?;
+        Ok(value.into_export())
+    }
+}
+
+impl WorkspaceSnapshot {
+    /// An export of every stack, listing those without layers by index
+    /// in `empty_stacks`. Readers of exports that don't know of the field
+    /// take such an export for malformed.
+    pub fn into_export(self) -> WorkspaceExport {
+        let (empty, layered): (Vec<_>, Vec<_>) = self
+            .stacks
+            .iter()
+            .enumerate()
+            .partition(|(_, stack)| stack.get_layers().is_empty());
+        WorkspaceExport {
+            stacks: StackTree::merge_stacks(layered),
+            base: self.base,
+            atom_names: self.atom_names,
+            groups: self.groups,
+            stack_names: self.stack_names,
+            fragments: self.fragments,
+            templates: self.templates,
+            electronic_states: self.electronic_states,
+            audit: self.audit,
+            empty_stacks: empty.into_iter().map(|(idx, _)| idx).collect(),
         }
     }
 }
 
+impl TryFrom<&Workspace> for WorkspaceExport {
+    type Error = LMECoreError;
+
+    fn try_from(value: &Workspace) -> Result<Self, Self::Error> {
+        Self::try_from(value.snapshot())
+    }
+}
+
+impl TryFrom<&WorkspaceExport> for Workspace {
+    type Error = LMECoreError;
+
+    /// Rebuild a workspace from its export, failing with `MalformedTree`
+    /// unless the stack trees hold every stack index `0..n` exactly once
+    /// and names or electronic states are given for other than `n` stacks,
+    /// and with `DuplicatedName` if two stacks share a name.
+    fn try_from(value: &WorkspaceExport) -> Result<Self, Self::Error> {
+        let mut indexes = StackTree::nodes(&value.stacks)
+            .into_iter()
+            .flat_map(|node| node.indexes)
+            .chain(value.empty_stacks.iter().copied())
+            .collect::<Vec<_>>();
+        indexes.sort();
+        if indexes.iter().enumerate().any(|(expected, idx)| expected != *idx) {
+            return Err(LMECoreError::MalformedTree);
+        }
+        let mut layered = StackTree::hydration(&value.stacks).into_iter();
+        let stacks = (0..indexes.len())
+            .map(|idx| match value.empty_stacks.contains(&idx) {
+                true => Arc::new(Stack::new(vec![])),
+                false => layered.next().expect("Every other index is in a tree"),
+            })
+            .collect::<Vec<_>>();
+        let stacks_count = stacks.len();
+        let mut workspace = Self {
+            base: value.base.clone(),
+            stacks,
+            atom_names: value.atom_names.clone(),
+            groups: value.groups.clone(),
+            revision: 0,
+            rmsd_cache: RevisionCache::default(),
+            resolved: StackCache::default(),
+            indices: StackCache::default(),
+            log: OperationLog::default(),
+            histories: vec![StackHistory::default(); stacks_count],
+            history_depth: DEFAULT_HISTORY_DEPTH,
+            stack_names: value.stack_names.clone(),
+            stack_serial: 0,
+            fragments: value.fragments.clone(),
+            templates: value.templates.clone(),
+            electronic_states: value.electronic_states.clone(),
+            stack_versions: vec![0; stacks_count],
+            audit: value.audit.clone(),
+        };
+        if workspace.stack_names.is_empty() {
+            workspace.stack_names = (0..stacks_count)
+                .map(|_| workspace.generate_stack_name())
+                .collect();
+        }
+        if workspace.electronic_states.is_empty() {
+            workspace.electronic_states = vec![ElectronicState::default(); stacks_count];
+        }
+        if workspace.stack_names.len() != stacks_count
+            || workspace.electronic_states.len() != stacks_count
+        {
+            return Err(LMECoreError::MalformedTree);
+        }
+        let mut names = HashSet::new();
+        for name in &workspace.stack_names {
+            if !names.insert(name) {
+                return Err(LMECoreError::DuplicatedName(name.clone()));
+            }
+        }
+        Ok(workspace)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct StackTree {
-    layer: Layer,
+    /// Shared with the stacks the tree is built from, so exporting copies
+    /// no layer
+    #[cfg_attr(feature = "openapi", schema(value_type = Layer))]
+    layer: Arc<Layer>,
     indexes: Vec<usize>,
     children: Vec<StackTree>,
 }
 
+/// A `StackTree` node flattened out of the nesting, for clients rendering
+/// the branching structure. `id` is the node position in a pre-order walk
+/// of the trees, so it is stable for a given list of trees.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TreeNode {
+    id: usize,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    layer: Layer,
+    indexes: Vec<usize>,
+}
+
+impl TreeNode {
+    pub fn layer(&self) -> &Layer {
+        &self.layer
+    }
+}
+
 impl StackTree {
-    pub fn dehydration<'a, I>(stacks: I) -> Vec<StackTree>
+    pub fn nodes(trees: &[StackTree]) -> Vec<TreeNode> {
+        let mut nodes = vec![];
+        for tree in trees {
+            tree.push_nodes(None, &mut nodes);
+        }
+        nodes
+    }
+
+    fn push_nodes(&self, parent: Option<usize>, nodes: &mut Vec<TreeNode>) -> usize {
+        let id = nodes.len();
+        nodes.push(TreeNode {
+            id,
+            parent,
+            children: vec![],
+            layer: self.layer.as_ref().clone(),
+            indexes: self.indexes.clone(),
+        });
+        for child in &self.children {
+            let child = child.push_nodes(Some(id), nodes);
+            nodes[id].children.push(child);
+        }
+        id
+    }
+
+    pub fn dehydration<'a, I>(stacks: I) -> Result<Vec<StackTree>, LMECoreError>
     where
         I: IntoIterator<Item = &'a Arc<Stack>>,
+    {
+        let stacks = stacks.into_iter().enumerate().collect::<Vec<_>>();
+        if let Some((idx, _)) = stacks.iter().find(|(_, stack)| stack.get_layers().is_empty()) {
+            return Err(LMECoreError::EmptyStack(*idx));
+        }
+        Ok(Self::merge_stacks(stacks))
+    }
+
+    /// Trees of stacks given with their index, none of them empty.
+    fn merge_stacks<'a, I>(stacks: I) -> Vec<StackTree>
+    where
+        I: IntoIterator<Item = (usize, &'a Arc<Stack>)>,
     {
         let mut trees = vec![];
-        for (idx, stack) in stacks.into_iter().enumerate() {
+        for (idx, stack) in stacks {
             let matched = trees
                 .iter_mut()
-                .map(|tree: &mut StackTree| tree.merge(idx, stack.get_layers()))
-                .any(|result| result);
+                .any(|tree: &mut StackTree| tree.merge(idx, stack.get_layers()));
             if !matched {
                 trees.push(StackTree::from((stack.get_layers().as_slice(), idx)))
             }
@@ -416,18 +2889,36 @@ impl StackTree {
         let mut stacks: HashMap<usize, Arc<Stack>> = HashMap::new();
 
         for tree in trees.into_iter() {
-            stacks.extend(tree.to_stacks(&vec![]));
+            stacks.extend(tree.to_stacks(&[]));
         }
 
         let mut stacks = stacks.into_iter().collect::<Vec<_>>();
-        stacks.sort_by(|(a, _), (b, _)| a.cmp(b));
+        stacks.sort_by_key(|(idx, _)| *idx);
         stacks.into_iter().map(|(_, stack)| stack).collect()
     }
 
-    fn to_stacks(&self, base: &Vec<Arc<Layer>>) -> HashMap<usize, Arc<Stack>> {
+    /// Layer chain of every stack in this tree, from the bottom layer up,
+    /// ordered by stack index. The inverse of merging the stacks with
+    /// `dehydration`.
+    pub fn flatten(&self) -> Vec<Vec<Layer>> {
+        let mut stacks = self.to_stacks(&[]).into_iter().collect::<Vec<_>>();
+        stacks.sort_by_key(|(idx, _)| *idx);
+        stacks
+            .into_iter()
+            .map(|(_, stack)| {
+                stack
+                    .get_layers()
+                    .iter()
+                    .map(|layer| layer.as_ref().clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn to_stacks(&self, base: &[Arc<Layer>]) -> HashMap<usize, Arc<Stack>> {
         let mut map = HashMap::new();
-        let mut base = base.clone();
-        base.push(Arc::new(self.layer.clone()));
+        let mut base = base.to_vec();
+        base.push(self.layer.clone());
         for index in &self.indexes {
             map.insert(*index, Arc::new(Stack::new(base.clone())));
         }
@@ -441,15 +2932,14 @@ impl StackTree {
         let (current, elements) = layers
             .split_first()
             .expect("Should never hint this condition");
-        if current.as_ref() == &self.layer {
-            if elements.len() == 0 {
+        if Arc::ptr_eq(current, &self.layer) || current == &self.layer {
+            if elements.is_empty() {
                 self.indexes.push(idx);
             } else {
                 let matched = self
                     .children
                     .iter_mut()
-                    .map(|item| item.merge(idx, elements))
-                    .any(|result| result);
+                    .any(|item| item.merge(idx, elements));
                 if !matched {
                     self.children.push(StackTree::from((elements, idx)))
                 }
@@ -464,18 +2954,1288 @@ impl StackTree {
 impl From<(&[Arc<Layer>], usize)> for StackTree {
     fn from((stack, idx): (&[Arc<Layer>], usize)) -> Self {
         let (bottom, highers) = stack.split_first().expect("Don't create with empty stack");
-        if highers.len() == 0 {
+        if highers.is_empty() {
             Self {
-                layer: bottom.as_ref().clone(),
+                layer: bottom.clone(),
                 indexes: vec![idx],
                 children: vec![],
             }
         } else {
             Self {
-                layer: bottom.as_ref().clone(),
+                layer: bottom.clone(),
                 indexes: vec![],
                 children: vec![StackTree::from((highers, idx))],
             }
         }
     }
 }
+
+mod test {
+    #[test]
+    fn flattened_tree_gives_back_the_stacks() {
+        use crate::{
+            entity::{Layer, Stack},
+            StackTree,
+        };
+        use std::sync::Arc;
+
+        let shared = vec![Layer::IgnoreBonds, Layer::ReplaceElement(8, 16)];
+        let chains = vec![
+            [shared.clone(), vec![Layer::RemoveElement(1)]].concat(),
+            [shared, vec![Layer::RemoveElement(6), Layer::IgnoreBonds]].concat(),
+        ];
+        let stacks = chains
+            .iter()
+            .map(|chain| Arc::new(Stack::new(chain.iter().cloned().map(Arc::new).collect())))
+            .collect::<Vec<_>>();
+        let trees = StackTree::dehydration(&stacks).unwrap();
+        assert_eq!(trees.len(), 1);
+        assert_eq!(trees[0].flatten(), chains);
+    }
+
+    #[test]
+    fn snapshot_exports_the_workspace_it_was_taken_from() {
+        use crate::{
+            entity::{Atom, Molecule},
+            operation::Operation,
+            Workspace, WorkspaceExport,
+        };
+        use nalgebra::Point3;
+        use std::{collections::HashMap, sync::Arc};
+
+        let mut workspace = Workspace::new(Molecule::default());
+        workspace.apply(Operation::CreateStack { copies: 1 }).unwrap();
+        let patch = Molecule::default()
+            .set_atoms(HashMap::from([(0, Some(Atom::new(6, Point3::origin())))]));
+        for stack_idx in 0..2 {
+            let write = Operation::Write {
+                start: stack_idx,
+                range: 1,
+                data: patch.clone(),
+            };
+            workspace.apply(write).unwrap();
+        }
+        let expected = WorkspaceExport::try_from(&workspace).unwrap();
+
+        let snapshot = workspace.snapshot();
+        assert!(snapshot
+            .stacks
+            .iter()
+            .zip(&workspace.stacks)
+            .all(|(taken, live)| Arc::ptr_eq(taken, live)));
+        workspace.apply(Operation::RemoveStack { stack_idx: 0 }).unwrap();
+        assert_eq!(WorkspaceExport::try_from(snapshot).unwrap(), expected);
+    }
+
+    #[test]
+    fn full_exports_keep_empty_stacks() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            operation::Operation,
+            Workspace, WorkspaceExport,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let mut workspace = Workspace::new(Molecule::default());
+        workspace.apply(Operation::CreateStack { copies: 2 }).unwrap();
+        let write = Operation::Write {
+            start: 1,
+            range: 1,
+            data: Molecule::default()
+                .set_atoms(HashMap::from([(0, Some(Atom::new(6, Point3::origin())))])),
+        };
+        workspace.apply(write).unwrap();
+        assert!(matches!(
+            WorkspaceExport::try_from(&workspace),
+            Err(LMECoreError::EmptyStack(0))
+        ));
+
+        let export = workspace.snapshot().into_export();
+        assert_eq!(export.empty_stacks, vec![0, 2]);
+        let restored = Workspace::try_from(&export).unwrap();
+        assert_eq!(restored.stack_names, workspace.stack_names);
+        let layers = restored
+            .stacks
+            .iter()
+            .map(|stack| stack.get_layers().len())
+            .collect::<Vec<_>>();
+        assert_eq!(layers, vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn reordered_stacks_export_consistently() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            operation::Operation,
+            Workspace, WorkspaceExport,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let mut workspace = Workspace::new(Molecule::default());
+        workspace.apply(Operation::CreateStack { copies: 2 }).unwrap();
+        for (stack_idx, element) in [(0, 6), (2, 8)] {
+            let patch = Molecule::default()
+                .set_atoms(HashMap::from([(0, Some(Atom::new(element, Point3::origin())))]));
+            let write = Operation::Write {
+                start: stack_idx,
+                range: 1,
+                data: patch,
+            };
+            workspace.apply(write).unwrap();
+        }
+        workspace
+            .apply(Operation::Write {
+                start: 1,
+                range: 1,
+                data: Molecule::default(),
+            })
+            .unwrap();
+        let names = workspace.stack_names().to_vec();
+        let before = (0..3)
+            .map(|idx| workspace.read(idx).unwrap())
+            .collect::<Vec<_>>();
+
+        for order in [vec![0, 1], vec![0, 1, 1], vec![0, 1, 3]] {
+            assert!(matches!(
+                workspace.reorder_stacks(&order),
+                Err(LMECoreError::InvalidPermutation(3))
+            ));
+        }
+        let order = [2, 0, 1];
+        workspace
+            .apply(Operation::ReorderStacks {
+                order: order.to_vec(),
+            })
+            .unwrap();
+        for (idx, previous) in order.iter().enumerate() {
+            assert_eq!(workspace.read(idx).unwrap(), before[*previous]);
+            assert_eq!(workspace.stack_names()[idx], names[*previous]);
+        }
+
+        let export = WorkspaceExport::try_from(&workspace).unwrap();
+        let restored = Workspace::try_from(&export).unwrap();
+        assert_eq!(restored.stack_names(), workspace.stack_names());
+        for idx in 0..3 {
+            assert_eq!(restored.read(idx).unwrap(), workspace.read(idx).unwrap());
+        }
+    }
+
+    #[test]
+    fn layer_summaries_count_touched_atoms() {
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let mut workspace = Workspace::new(Molecule::default());
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let atom = |element, x| Some(Atom::new(element, Point3::new(x, 0., 0.)));
+        let fill = Molecule::default().set_atoms(HashMap::from([
+            (0, atom(6, 0.)),
+            (1, atom(8, 1.2)),
+            (2, atom(8, -1.2)),
+        ]));
+        let layers = vec![
+            Layer::Fill(Box::new(fill)),
+            Layer::ReplaceElement(8, 16),
+            Layer::RemoveElement(7),
+        ];
+        workspace
+            .apply(Operation::AddLayers {
+                stack_idx: 0,
+                layers: layers.clone(),
+            })
+            .unwrap();
+        let summaries = workspace.layer_summaries(0).unwrap();
+        assert_eq!(
+            summaries.iter().map(|summary| summary.atoms).collect::<Vec<_>>(),
+            vec![3, 2, 0]
+        );
+        assert_eq!(summaries[1].layer, layers[1]);
+    }
+
+    #[test]
+    fn writes_land_on_the_stacks_in_range() {
+        use crate::{
+            entity::{Atom, Molecule},
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let atom = |element| Some(Atom::new(element, Point3::origin()));
+        let mut workspace = Workspace::new(Molecule::default());
+        workspace.apply(Operation::CreateStack { copies: 63 }).unwrap();
+        for idx in 0..64 {
+            let own = Molecule::default().set_atoms(HashMap::from([(0, atom(idx + 1))]));
+            assert!(workspace.write_to_stack(idx, 1, own));
+        }
+        let shared = Molecule::default().set_atoms(HashMap::from([(1, atom(1))]));
+        assert!(workspace.write_to_stack(8, 48, shared.clone()));
+        for idx in 0..64 {
+            let molecule = workspace.read(idx).unwrap();
+            assert_eq!(molecule.atom(0).unwrap().element(), idx + 1);
+            assert_eq!(molecule.atom(1).is_some(), (8..56).contains(&idx));
+        }
+
+        assert!(!workspace.write_to_stack(0, 0, shared.clone()));
+        assert!(!workspace.write_to_stack(60, 5, shared.clone()));
+        assert!(!workspace.write_to_stack(usize::MAX, 2, shared));
+        let empty = Operation::Write {
+            start: 0,
+            range: 0,
+            data: Molecule::default(),
+        };
+        assert!(workspace.apply(empty).is_err());
+    }
+
+    #[test]
+    fn cherry_pick_shares_the_layer() {
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            error::LMECoreError,
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::{collections::HashMap, sync::Arc};
+
+        let mut workspace = Workspace::new(Molecule::default());
+        workspace.apply(Operation::CreateStack { copies: 1 }).unwrap();
+        let fill = Molecule::default()
+            .set_atoms(HashMap::from([(0, Some(Atom::new(8, Point3::origin())))]));
+        let layers = vec![Layer::Fill(Box::new(fill)), Layer::ReplaceElement(8, 16)];
+        workspace
+            .apply(Operation::AddLayers { stack_idx: 0, layers })
+            .unwrap();
+        let pick = |position| Operation::CherryPick {
+            target: 1,
+            source: 0,
+            position,
+        };
+        assert!(matches!(
+            workspace.apply(pick(2)),
+            Err(LMECoreError::NoSuchLayer(2))
+        ));
+        workspace.apply(pick(0)).unwrap();
+        workspace.apply(pick(1)).unwrap();
+        assert_eq!(workspace.read(1).unwrap(), workspace.read(0).unwrap());
+        let (source, target) = (&workspace.stacks[0], &workspace.stacks[1]);
+        assert!(Arc::ptr_eq(&source.get_layers()[1], &target.get_layers()[1]));
+    }
+
+    #[test]
+    fn copied_atoms_keep_their_internal_bonds() {
+        use crate::{
+            entity::{Atom, Molecule},
+            operation::{Operation, OperationOutput, RigidMotion},
+            Workspace,
+        };
+        use nalgebra::{Point3, Vector3};
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let atom = |x| Some(Atom::new(6, Point3::new(x, 0., 0.)));
+        let bond = |a, b| (Pair::new_ordered(a, b), 1.);
+        let mut workspace = Workspace::new(
+            Molecule::default()
+                .set_atoms(HashMap::from([(0, atom(0.)), (1, atom(1.)), (2, atom(2.))]))
+                .set_bonds(HashMap::from([bond(0, 1), bond(1, 2)])),
+        );
+        workspace.apply(Operation::CreateStack { copies: 1 }).unwrap();
+        let copy = Operation::CopyAtoms {
+            target: 1,
+            source: 0,
+            motion: RigidMotion {
+                atoms: vec![1, 2],
+                group: None,
+                rotation: Vector3::zeros(),
+                center: None,
+                translation: Vector3::new(0., 5., 0.),
+            },
+        };
+        let output = workspace.apply(copy).unwrap();
+        assert_eq!(output, OperationOutput::Mapping(HashMap::from([(1, 3), (2, 4)])));
+        let target = workspace.read(1).unwrap();
+        assert_eq!(target.atoms().len(), 5);
+        assert_eq!(target.atom(3).unwrap().position(), Point3::new(1., 5., 0.));
+        assert_eq!(target.bonds().get(&Pair::new_ordered(3, 4)), Some(&1.));
+        assert_eq!(target.bonds().len(), 3);
+        assert_eq!(workspace.read(0).unwrap().atoms().len(), 3);
+    }
+
+    #[test]
+    fn supercells_repeat_the_cell() {
+        use crate::{
+            cell::Cell,
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            operation::{Operation, OperationOutput},
+            Workspace,
+        };
+        use nalgebra::{Point3, Vector3};
+        use pair::Pair;
+        use std::{collections::HashMap, num::NonZeroUsize};
+
+        let dimer = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, Some(Atom::new(1, Point3::new(0., 0., 0.)))),
+                (1, Some(Atom::new(1, Point3::new(0.7, 0., 0.)))),
+            ]))
+            .set_bonds(HashMap::from([(Pair::new_ordered(0, 1), 1.)]));
+        let mut workspace = Workspace::new(dimer.clone());
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let repeats = [2, 1, 3].map(|n| NonZeroUsize::new(n).unwrap());
+        let supercell = Operation::Supercell {
+            stack_idx: 0,
+            repeats,
+        };
+        assert!(matches!(
+            workspace.apply(supercell.clone()),
+            Err(LMECoreError::NoCell)
+        ));
+
+        let cell = Cell::new(Vector3::x() * 2., Vector3::y() * 2., Vector3::z() * 3.);
+        let mut workspace = Workspace::new(dimer.set_cell(Some(cell)));
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let output = workspace.apply(supercell).unwrap();
+        assert_eq!(output, OperationOutput::Atoms((2..12).collect()));
+        let grown = workspace.read(0).unwrap();
+        assert_eq!(grown.cell(), Some(&cell.repeat([2, 1, 3])));
+        // the copies of the second cell along c come first
+        assert_eq!(grown.atom(3).unwrap().position(), Point3::new(0.7, 0., 3.));
+        assert_eq!(grown.atom(11).unwrap().position(), Point3::new(2.7, 0., 6.));
+        assert_eq!(grown.bonds().len(), 6);
+    }
+
+    #[test]
+    fn moved_atoms_turn_about_their_centroid() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            operation::{Operation, OperationOutput, RigidMotion},
+            Workspace,
+        };
+        use nalgebra::{Point3, Vector3};
+        use std::{collections::HashMap, f64::consts::PI};
+
+        let atom = |x, y| Some(Atom::new(6, Point3::new(x, y, 0.)));
+        let mut workspace = Workspace::new(Molecule::default().set_atoms(HashMap::from([
+            (0, atom(0., 0.)),
+            (1, atom(1., 0.)),
+            (2, atom(3., 0.)),
+        ])));
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let tip = Operation::AddToGroup {
+            atom_idx: 2,
+            group: "tip".to_string(),
+        };
+        workspace.apply(tip).unwrap();
+        let motion = RigidMotion {
+            atoms: vec![1],
+            group: Some("tip".to_string()),
+            rotation: Vector3::z() * PI,
+            center: None,
+            translation: Vector3::new(0., 0., 1.),
+        };
+        let output = workspace
+            .apply(Operation::MoveAtoms {
+                stack_idx: 0,
+                motion: motion.clone(),
+            })
+            .unwrap();
+        assert_eq!(output, OperationOutput::Affected(2));
+        let moved = workspace.read(0).unwrap();
+        let position = |idx| moved.atom(idx).unwrap().position();
+        assert_eq!(position(0), Point3::origin());
+        assert!((position(1) - Point3::new(3., 0., 1.)).norm() < 1e-12);
+        assert!((position(2) - Point3::new(1., 0., 1.)).norm() < 1e-12);
+
+        let missing = RigidMotion {
+            atoms: vec![7],
+            ..motion
+        };
+        assert!(matches!(
+            workspace.apply(Operation::MoveAtoms {
+                stack_idx: 0,
+                motion: missing,
+            }),
+            Err(LMECoreError::MissingAtoms(atoms)) if atoms == vec![7]
+        ));
+    }
+
+    #[test]
+    fn mirror_reflects_selected_atoms() {
+        use crate::{
+            entity::{Atom, Mirror, Molecule},
+            error::LMECoreError,
+            operation::{Operation, OperationOutput},
+            Workspace,
+        };
+        use nalgebra::{Point3, Vector3};
+        use std::collections::HashMap;
+
+        let atom = |x, y, z| Some(Atom::new(6, Point3::new(x, y, z)));
+        let mut workspace = Workspace::new(Molecule::default().set_atoms(HashMap::from([
+            (0, atom(1., 2., 3.)),
+            (1, atom(-1., 0., 1.)),
+        ])));
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let mirror = |atoms, mirror| Operation::MirrorAtoms {
+            stack_idx: 0,
+            atoms,
+            group: None,
+            mirror,
+        };
+        let plane = Mirror::Plane {
+            point: Point3::new(1., 0., 0.),
+            normal: Vector3::new(2., 0., 0.),
+        };
+        let output = workspace.apply(mirror(vec![], plane)).unwrap();
+        assert_eq!(output, OperationOutput::Affected(2));
+        let inversion = Mirror::Inversion(Point3::new(0., 1., 0.));
+        workspace.apply(mirror(vec![1], inversion)).unwrap();
+        let molecule = workspace.read(0).unwrap();
+        assert_eq!(molecule.atom(0).unwrap().position(), Point3::new(1., 2., 3.));
+        assert_eq!(molecule.atom(1).unwrap().position(), Point3::new(-3., 2., -1.));
+
+        let flat = Mirror::Plane {
+            point: Point3::origin(),
+            normal: Vector3::zeros(),
+        };
+        assert!(matches!(
+            workspace.apply(mirror(vec![], flat)),
+            Err(LMECoreError::ZeroNormal)
+        ));
+    }
+
+    #[test]
+    fn library_fragments_substitute_by_name_and_export() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            fragment::{Fragment, FragmentRef},
+            operation::Operation,
+            Workspace, WorkspaceExport,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let atom = |element, x| Some(Atom::new(element, Point3::new(x, 0., 0.)));
+        let mut workspace = Workspace::new(
+            Molecule::default()
+                .set_atoms(HashMap::from([(0, atom(6, 0.)), (1, atom(1, 1.09))]))
+                .set_bonds(HashMap::from([(Pair::new_ordered(0, 1), 1.)])),
+        );
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let fluoro = Fragment {
+            molecule: Molecule::default()
+                .set_atoms(HashMap::from([(0, atom(9, 1.35)), (1, atom(6, 0.))])),
+            attachment: 0,
+            dummy: 1,
+        };
+        let broken = Fragment {
+            dummy: 0,
+            ..fluoro.clone()
+        };
+        let set = |name: &str, fragment| Operation::SetFragment {
+            name: name.to_string(),
+            fragment,
+        };
+        assert!(matches!(
+            workspace.apply(set("F", broken)),
+            Err(LMECoreError::InvalidFragment(_))
+        ));
+        workspace.apply(set("F", fluoro.clone())).unwrap();
+        let substitute = |name: &str| Operation::Substitute {
+            stack_idx: 0,
+            atom_idx: 1,
+            fragment: FragmentRef::Named(name.to_string()),
+        };
+        assert!(matches!(
+            workspace.apply(substitute("Cl")),
+            Err(LMECoreError::NoSuchFragment(_))
+        ));
+        workspace.apply(substitute("F")).unwrap();
+        let fluorine = workspace.read(0).unwrap().atom(1).unwrap();
+        assert_eq!(fluorine.element(), 9);
+        assert!((fluorine.position() - Point3::new(1.35, 0., 0.)).norm() < 1e-9);
+
+        let export = WorkspaceExport::try_from(&workspace).unwrap();
+        let restored = Workspace::try_from(&export).unwrap();
+        assert_eq!(restored.fragment("F").unwrap(), &fluoro);
+    }
+
+    #[test]
+    fn torsion_turns_one_side_of_the_bond() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            geometry::dihedral,
+            operation::{Operation, OperationOutput},
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        // a chain 0-1-2-3 with a branch 4 on 2, and a ring bond 1-5-6-1
+        let atom = |x, y, z| Some(Atom::new(6, Point3::new(x, y, z)));
+        let molecule = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, atom(0., 1., 0.)),
+                (1, atom(0., 0., 0.)),
+                (2, atom(1.5, 0., 0.)),
+                (3, atom(1.5, 1., 0.)),
+                (4, atom(2.5, 0., 0.)),
+                (5, atom(-1., -1., 0.)),
+                (6, atom(0., -1.5, 0.)),
+            ]))
+            .set_bonds(
+                [(0, 1), (1, 2), (2, 3), (2, 4), (1, 5), (5, 6), (6, 1)]
+                    .into_iter()
+                    .map(|(a, b)| (Pair::new_ordered(a, b), 1.))
+                    .collect(),
+            );
+        let mut workspace = Workspace::new(molecule);
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let torsion = |bond, angle| Operation::RotateTorsion {
+            stack_idx: 0,
+            bond,
+            angle,
+        };
+        let output = workspace.apply(torsion([1, 2], 60.)).unwrap();
+        assert_eq!(output, OperationOutput::Affected(3));
+        let turned = workspace.read(0).unwrap();
+        let position = |idx| turned.atom(idx).unwrap().position();
+        let measured = dihedral(&position(0), &position(1), &position(2), &position(3));
+        assert!((measured - 60.).abs() < 1e-9, "{measured}");
+        assert_eq!(position(0), Point3::new(0., 1., 0.));
+        assert_eq!(position(4), Point3::new(2.5, 0., 0.));
+
+        assert!(matches!(
+            workspace.apply(torsion([1, 5], 10.)),
+            Err(LMECoreError::RingBond(1, 5))
+        ));
+        assert!(matches!(
+            workspace.apply(torsion([0, 3], 10.)),
+            Err(LMECoreError::NotBonded(0, 3))
+        ));
+    }
+
+    #[test]
+    fn internal_coordinate_edits_move_the_smaller_side() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            geometry::{angle, distance},
+            operation::{Operation, OperationOutput},
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        // a chain 0-1-2 with two atoms 3 and 4 hanging on 0
+        let atom = |x, y| Some(Atom::new(6, Point3::new(x, y, 0.)));
+        let molecule = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, atom(0., 0.)),
+                (1, atom(1.5, 0.)),
+                (2, atom(1.5, 1.5)),
+                (3, atom(-1., 1.)),
+                (4, atom(-1., -1.)),
+            ]))
+            .set_bonds(
+                [(0, 1), (1, 2), (0, 3), (0, 4)]
+                    .into_iter()
+                    .map(|(a, b)| (Pair::new_ordered(a, b), 1.))
+                    .collect(),
+            );
+        let mut workspace = Workspace::new(molecule);
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+
+        let stretch = |bond, length| Operation::SetBondLength {
+            stack_idx: 0,
+            bond,
+            length,
+        };
+        let output = workspace.apply(stretch([1, 0], 2.)).unwrap();
+        assert_eq!(output, OperationOutput::Affected(2));
+        let stretched = workspace.read(0).unwrap();
+        let position = |idx| stretched.atom(idx).unwrap().position();
+        assert!((distance(&position(0), &position(1)) - 2.).abs() < 1e-12);
+        assert_eq!(position(0), Point3::origin());
+        assert!((position(2) - Point3::new(2., 1.5, 0.)).norm() < 1e-12);
+
+        let bend = |atoms, angle| Operation::SetBondAngle {
+            stack_idx: 0,
+            atoms,
+            angle,
+        };
+        let output = workspace.apply(bend([2, 1, 0], 120.)).unwrap();
+        assert_eq!(output, OperationOutput::Affected(1));
+        let bent = workspace.read(0).unwrap();
+        let position = |idx| bent.atom(idx).unwrap().position();
+        assert!((angle(&position(2), &position(1), &position(0)) - 120.).abs() < 1e-9);
+        assert!((distance(&position(1), &position(2)) - 1.5).abs() < 1e-12);
+        assert_eq!(position(3), Point3::new(-1., 1., 0.));
+        workspace.apply(bend([0, 1, 2], 75.)).unwrap();
+        let bent = workspace.read(0).unwrap();
+        let position = |idx| bent.atom(idx).unwrap().position();
+        assert!((angle(&position(0), &position(1), &position(2)) - 75.).abs() < 1e-9);
+
+        assert!(matches!(
+            workspace.apply(stretch([0, 1], -1.)),
+            Err(LMECoreError::InvalidTarget(_))
+        ));
+        assert!(matches!(
+            workspace.apply(bend([3, 1, 2], 90.)),
+            Err(LMECoreError::NotBonded(1, 3))
+        ));
+    }
+
+    #[test]
+    fn stacks_align_on_matched_atoms() {
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            operation::{Operation, OperationOutput},
+            Workspace,
+        };
+        use nalgebra::{Isometry3, Point3, Transform3, Vector3};
+        use std::collections::HashMap;
+
+        let atom = |x, y, z| Some(Atom::new(6, Point3::new(x, y, z)));
+        let mut workspace = Workspace::new(Molecule::default().set_atoms(HashMap::from([
+            (0, atom(0., 0., 0.)),
+            (1, atom(1.5, 0., 0.)),
+            (2, atom(0., 1.5, 0.)),
+            (3, atom(0., 0., 1.5)),
+        ])));
+        workspace.apply(Operation::CreateStack { copies: 1 }).unwrap();
+        let motion = Isometry3::new(Vector3::new(2., -1., 3.), Vector3::new(0.3, 0.2, -0.5));
+        let moved = Layer::Transform(Transform3::from_matrix_unchecked(motion.to_homogeneous()));
+        workspace.add_layers(1, vec![moved]).unwrap();
+        // atom 3 of stack 1 is also out of place
+        let nudge = Molecule::default().set_atoms(HashMap::from([(3, atom(9., 9., 9.))]));
+        workspace.add_layers(1, vec![Layer::Fill(Box::new(nudge))]).unwrap();
+
+        let (_, rmsd) = workspace.superposition(1, 0, vec![0, 1, 2], None).unwrap();
+        assert!(rmsd < 1e-9);
+        let (_, rmsd) = workspace.superposition(1, 0, vec![], None).unwrap();
+        assert!(rmsd > 1.);
+
+        let align = Operation::AlignTo {
+            stack_idx: 1,
+            reference: 0,
+            atoms: vec![0, 1, 2],
+            group: None,
+        };
+        let OperationOutput::Rmsd(rmsd) = workspace.apply(align).unwrap() else {
+            panic!("AlignTo returns the RMSD");
+        };
+        assert!(rmsd < 1e-9);
+        let aligned = workspace.read(1).unwrap();
+        let position = aligned.atom(1).unwrap().position();
+        assert!((position - Point3::new(1.5, 0., 0.)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn alignment_needs_three_matched_atoms() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let atom = |x| Some(Atom::new(6, Point3::new(x, 0., 0.)));
+        let base = Molecule::default().set_atoms(HashMap::from([(0, atom(0.)), (1, atom(1.5))]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 1 }).unwrap();
+        assert!(workspace.superposition(1, 0, vec![], None).is_ok());
+        let align = Operation::AlignTo {
+            stack_idx: 1,
+            reference: 0,
+            atoms: vec![],
+            group: None,
+        };
+        assert!(matches!(
+            workspace.apply(align),
+            Err(LMECoreError::NotEnoughMatchedAtoms(2))
+        ));
+    }
+
+    #[test]
+    fn rmsd_needs_matched_atoms() {
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            error::LMECoreError,
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let base = Molecule::default()
+            .set_atoms(HashMap::from([(0, Some(Atom::new(6, Point3::origin())))]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 1 }).unwrap();
+        workspace.add_layers(1, vec![Layer::RemoveElement(6)]).unwrap();
+        assert!(matches!(
+            workspace.superposition(1, 0, vec![], None),
+            Err(LMECoreError::NotEnoughMatchedAtoms(0))
+        ));
+    }
+
+    #[test]
+    fn atom_metadata_overlays_through_layers() {
+        use crate::{
+            entity::{Atom, AtomMetadata, Layer, Molecule},
+            error::LMECoreError,
+            operation::{Operation, OperationOutput},
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::{BTreeMap, HashMap, HashSet};
+
+        let base = Molecule::default().set_atoms(HashMap::from([
+            (0, Some(Atom::new(6, Point3::origin()))),
+            (1, Some(Atom::new(8, Point3::new(1.2, 0., 0.)))),
+        ]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let charged = AtomMetadata {
+            charge: Some(-0.4),
+            properties: BTreeMap::from([("frozen".to_string(), "yes".to_string())]),
+            ..Default::default()
+        };
+        let output = workspace.apply(Operation::SetMetadata {
+            stack_idx: 0,
+            metadata: HashMap::from([(1, charged)]),
+        });
+        assert_eq!(output.unwrap(), OperationOutput::Affected(1));
+        let labelled = AtomMetadata {
+            isotope: Some(18),
+            properties: BTreeMap::from([("label".to_string(), "O1".to_string())]),
+            ..Default::default()
+        };
+        workspace
+            .set_metadata(0, HashMap::from([(1, labelled)]))
+            .unwrap();
+        let molecule = workspace.read(0).unwrap();
+        let metadata = molecule.metadata(1).unwrap();
+        assert_eq!(
+            (metadata.charge, metadata.isotope, metadata.properties.len()),
+            (Some(-0.4), Some(18), 2)
+        );
+        assert!(molecule.metadata(0).is_none());
+        assert!(matches!(
+            workspace.set_metadata(0, HashMap::from([(5, AtomMetadata::default())])),
+            Err(LMECoreError::MissingAtoms(missing)) if missing == vec![5]
+        ));
+
+        let json = serde_json::to_string(&molecule).unwrap();
+        assert_eq!(serde_json::from_str::<Molecule>(&json).unwrap(), molecule);
+        let remapped = Layer::Remap(HashMap::from([(0, 1), (1, 0)]))
+            .filter(molecule.clone())
+            .unwrap();
+        assert_eq!(remapped.metadata(0), molecule.metadata(1));
+        let removed = molecule.remove_atoms(&HashSet::from([1]));
+        assert!(removed.all_metadata().is_empty());
+    }
+
+    #[test]
+    fn electronic_states_follow_their_stacks() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            operation::Operation,
+            ElectronicState, Workspace, WorkspaceExport,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let base = Molecule::default()
+            .set_atoms(HashMap::from([(0, Some(Atom::new(8, Point3::origin())))]));
+        let mut workspace = Workspace::new(base.clone());
+        workspace.apply(Operation::CreateStack { copies: 1 }).unwrap();
+        // stacks need a layer to be exported
+        workspace.write_to_stack(0, 2, base);
+        let anion = ElectronicState {
+            charge: -1,
+            multiplicity: 2,
+        };
+        workspace
+            .apply(Operation::SetElectronicState {
+                stack_idx: 1,
+                state: anion,
+            })
+            .unwrap();
+        assert_eq!(workspace.electronic_state(0).unwrap(), ElectronicState::default());
+        assert_eq!(workspace.clone_stack(1, 0), Some(2));
+        assert_eq!(workspace.electronic_state(2).unwrap(), anion);
+        workspace.reorder_stacks(&[2, 0, 1]).unwrap();
+        workspace.remove_stack(2).unwrap();
+        assert_eq!(workspace.electronic_state(0).unwrap(), anion);
+        assert_eq!(workspace.electronic_state(1).unwrap(), ElectronicState::default());
+
+        let invalid = ElectronicState {
+            charge: 0,
+            multiplicity: 0,
+        };
+        assert!(matches!(
+            workspace.set_electronic_state(1, invalid),
+            Err(LMECoreError::InvalidMultiplicity(0))
+        ));
+        let export = WorkspaceExport::try_from(&workspace).unwrap();
+        let json = serde_json::to_value(&export).unwrap();
+        assert_eq!(json["electronic_states"][0]["charge"], -1);
+        let restored = Workspace::try_from(&export).unwrap();
+        assert_eq!(restored.electronic_state(0).unwrap(), anion);
+    }
+
+    #[test]
+    fn library_templates_render_stacks_and_export() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            operation::Operation,
+            template::Template,
+            Workspace, WorkspaceExport,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use serde_json::json;
+        use std::collections::HashMap;
+
+        let base = Molecule::default()
+            .set_atoms(HashMap::from([
+                (3, Some(Atom::new(8, Point3::origin()))),
+                (7, Some(Atom::new(1, Point3::new(0.96, 0., 0.)))),
+            ]))
+            .set_bonds(HashMap::from([(Pair::new_ordered(3, 7), 1.)]));
+        let mut workspace = Workspace::new(base.clone());
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        workspace.write_to_stack(0, 1, base);
+        workspace.atom_names.insert(7, "HO".to_string());
+        workspace.groups.insert("hydroxyl".to_string(), 7);
+        let source = "{{ params.method }} {{ charge }} {{ multiplicity }}
+{% for atom in atoms -%}
+{{ atom.serial }} {{ atom.symbol }}{% if atom.name %} {{ atom.name }}{% endif %}
+{% endfor -%}
+{% for bond in bonds %}{{ bond.a }}-{{ bond.b }}{% endfor %} {{ groups.hydroxyl.0 }}";
+        let template = Template::new(source.to_string()).unwrap();
+        workspace
+            .apply(Operation::SetTemplate {
+                name: "orca".to_string(),
+                template,
+            })
+            .unwrap();
+        let rendered = workspace
+            .render_template(0, "orca", json!({ "method": "!GFN2-xTB" }))
+            .unwrap();
+        assert_eq!(rendered, "!GFN2-xTB 0 1\n1 O\n2 H HO\n1-2 2");
+        assert!(matches!(
+            workspace.render_template(0, "nwchem", json!(null)),
+            Err(LMECoreError::NoSuchTemplate(_))
+        ));
+
+        let export = WorkspaceExport::try_from(&workspace).unwrap();
+        let restored = Workspace::try_from(&export).unwrap();
+        assert_eq!(restored.templates(), workspace.templates());
+    }
+
+    #[test]
+    fn batch_applies_all_edits_or_none() {
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            error::LMECoreError,
+            operation::{BatchEdit, Operation},
+            Workspace,
+        };
+        use nalgebra::{Point3, Transform3};
+        use std::collections::{HashMap, HashSet};
+
+        let atom = |element| Some(Atom::new(element, Point3::origin()));
+        let base = Molecule::default().set_atoms(HashMap::from([(0, atom(6))]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let oxygen = Molecule::default().set_atoms(HashMap::from([(1, atom(8))]));
+        let edits = vec![
+            BatchEdit::Write(Box::new(oxygen)),
+            BatchEdit::Name {
+                atom_idx: 1,
+                name: "O1".to_string(),
+            },
+            BatchEdit::Group {
+                atom_idx: 7,
+                group: "ring".to_string(),
+            },
+            BatchEdit::Name {
+                atom_idx: 0,
+                name: "O1".to_string(),
+            },
+        ];
+        let failed = workspace.apply(Operation::Batch {
+            stack_idx: 0,
+            edits: edits.clone(),
+        });
+        let Err(LMECoreError::BatchFailed(failures)) = failed else {
+            panic!("Batch with failing edits applied: {failed:?}");
+        };
+        let positions = failures
+            .iter()
+            .map(|failure| failure.edit)
+            .collect::<Vec<_>>();
+        assert_eq!(positions, vec![2, 3]);
+        assert!(matches!(failures[0].error, LMECoreError::UnknownAtom(7)));
+        assert!(matches!(failures[1].error, LMECoreError::DuplicatedName(_)));
+        assert!(workspace.read(0).unwrap().atom(1).is_none());
+        assert!(workspace.resolve_atom("O1").is_err());
+        assert_eq!(workspace.log().operations().len(), 1);
+
+        let edits = vec![
+            edits[0].clone(),
+            edits[1].clone(),
+            BatchEdit::Group {
+                atom_idx: 1,
+                group: "carbonyl".to_string(),
+            },
+            BatchEdit::AddLayer(Layer::Transform(Transform3::identity())),
+        ];
+        workspace
+            .apply(Operation::Batch {
+                stack_idx: 0,
+                edits,
+            })
+            .unwrap();
+        assert!(workspace.read(0).unwrap().atom(1).is_some());
+        assert_eq!(workspace.resolve_atom("O1").unwrap(), 1);
+        let carbonyl = workspace.groups.get_left(&"carbonyl".to_string());
+        assert_eq!(carbonyl, HashSet::from([1]));
+        assert_eq!(workspace.log().operations().len(), 2);
+        assert_eq!(workspace.stacks[0].get_layers().len(), 2);
+        workspace.apply(Operation::Undo { stack_idx: 0 }).unwrap();
+        assert!(workspace.read(0).unwrap().atom(1).is_none());
+
+        let oxygen = Molecule::default().set_atoms(HashMap::from([(1, atom(8))]));
+        let nitrogen = Molecule::default().set_atoms(HashMap::from([(2, atom(7))]));
+        let edits = vec![
+            BatchEdit::Write(Box::new(oxygen)),
+            BatchEdit::Write(Box::new(nitrogen)),
+            BatchEdit::Name {
+                atom_idx: 2,
+                name: "N1".to_string(),
+            },
+        ];
+        workspace
+            .apply(Operation::Batch {
+                stack_idx: 0,
+                edits,
+            })
+            .unwrap();
+        let layers = workspace.stacks[0].get_layers();
+        assert_eq!(layers.len(), 1);
+        let Layer::Fill(written) = layers[0].as_ref() else {
+            panic!("Batch left {layers:?}");
+        };
+        assert_eq!(written.atoms().len(), 2);
+        assert_eq!(workspace.resolve_atom("N1").unwrap(), 2);
+    }
+
+    #[test]
+    fn batch_reports_the_rejected_layer() {
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            error::{LMECoreError, LayerError},
+            operation::{BatchEdit, Operation},
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let base = Molecule::default().set_atoms(HashMap::from([
+            (0, Some(Atom::new(6, Point3::origin()))),
+            (1, Some(Atom::new(8, Point3::new(1.2, 0., 0.)))),
+        ]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let edits = vec![
+            BatchEdit::AddLayer(Layer::ReplaceElement(8, 16)),
+            BatchEdit::AddLayer(Layer::ReplaceElement(6, 7)),
+            BatchEdit::AddLayer(Layer::Remap(HashMap::from([(0, 5), (1, 5)]))),
+        ];
+        let failed = workspace.apply(Operation::Batch {
+            stack_idx: 0,
+            edits,
+        });
+        let Err(LMECoreError::BatchFailed(failures)) = failed else {
+            panic!("Batch with a failing layer applied: {failed:?}");
+        };
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].edit, 2);
+        assert!(matches!(
+            &failures[0].error,
+            LMECoreError::LayerRejected(2, err)
+                if matches!(**err, LMECoreError::LayerError(LayerError::RemapCollision(5)))
+        ));
+    }
+
+    #[test]
+    fn stacks_are_read_once_until_they_change() {
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::{Point3, Transform3, Translation3};
+        use std::{collections::HashMap, sync::Arc};
+
+        let carbon = Some(Atom::new(6, Point3::origin()));
+        let base = Molecule::default().set_atoms(HashMap::from([(0, carbon)]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 1 }).unwrap();
+        let shift = Translation3::new(1., 0., 0.).to_homogeneous();
+        let shift = Layer::Transform(Transform3::from_matrix_unchecked(shift));
+        let add = |workspace: &mut Workspace| {
+            let layers = vec![shift.clone()];
+            workspace
+                .apply(Operation::AddLayers { stack_idx: 0, layers })
+                .unwrap();
+        };
+        add(&mut workspace);
+        let first = workspace.read_shared(0).unwrap();
+        assert!(Arc::ptr_eq(&first, &workspace.read_shared(0).unwrap()));
+        assert!(!Arc::ptr_eq(&first, &workspace.read_shared(1).unwrap()));
+        let index = workspace.spatial_index(0).unwrap();
+        assert!(Arc::ptr_eq(&index, &workspace.spatial_index(0).unwrap()));
+
+        add(&mut workspace);
+        let second = workspace.read_shared(0).unwrap();
+        assert!(!Arc::ptr_eq(&index, &workspace.spatial_index(0).unwrap()));
+        assert_eq!(second.atom(0).unwrap().position(), Point3::new(2., 0., 0.));
+        workspace.apply(Operation::Undo { stack_idx: 0 }).unwrap();
+        assert_eq!(workspace.read(0).unwrap(), *first);
+        assert_eq!(workspace.clone().read(0).unwrap(), *first);
+    }
+
+    #[test]
+    fn stack_versions_grow_with_edits() {
+        use crate::{
+            entity::{Atom, Molecule},
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+
+        let mut workspace = Workspace::new(Molecule::default());
+        workspace.apply(Operation::CreateStack { copies: 2 }).unwrap();
+        let versions = |workspace: &Workspace| {
+            (0..workspace.stacks())
+                .map(|idx| workspace.stack_version(idx).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let created = versions(&workspace);
+        let atom = Atom::new(8, Point3::origin());
+        workspace
+            .apply(Operation::AppendAtom { stack_idx: 1, atom })
+            .unwrap();
+        let edited = versions(&workspace);
+        assert_eq!(edited[0], created[0]);
+        assert!(edited[1] > created[1]);
+        workspace
+            .apply(Operation::Undo { stack_idx: 2 })
+            .unwrap_err();
+        assert_eq!(versions(&workspace), edited);
+
+        // stack 2 moves to index 1, stack 0 stays put
+        workspace.apply(Operation::RemoveStack { stack_idx: 1 }).unwrap();
+        let removed = versions(&workspace);
+        assert_eq!(removed[0], edited[0]);
+        assert!(removed[1] > edited[1]);
+        workspace
+            .apply(Operation::ReorderStacks { order: vec![0, 1] })
+            .unwrap();
+        assert_eq!(versions(&workspace), removed);
+        assert!(workspace.stack_version(2).is_err());
+    }
+
+    #[test]
+    fn restricted_export_keeps_only_grouped_atoms() {
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            operation::Operation,
+            Workspace, WorkspaceExport,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::{HashMap, HashSet};
+
+        let atom = |element| Some(Atom::new(element, Point3::origin()));
+        let base = Molecule::default()
+            .set_atoms(HashMap::from([(0, atom(6)), (1, atom(8)), (2, atom(1))]))
+            .set_bonds(HashMap::from([
+                (Pair::new_ordered(0, 1), 2.),
+                (Pair::new_ordered(0, 2), 1.),
+            ]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let layers = vec![
+            Layer::Fill(Box::new(
+                Molecule::default().set_atoms(HashMap::from([(2, atom(9)), (3, atom(1))])),
+            )),
+            Layer::Remap(HashMap::from([(0, 1), (1, 0), (2, 3)])),
+        ];
+        workspace
+            .apply(Operation::AddLayers { stack_idx: 0, layers })
+            .unwrap();
+        let members = vec![
+            (0, "carbonyl".to_string()),
+            (1, "carbonyl".to_string()),
+            (1, "oxygen".to_string()),
+            (2, "halogen".to_string()),
+        ];
+        workspace.apply(Operation::AddToGroups { members }).unwrap();
+        workspace
+            .apply(Operation::SetAtomNames {
+                names: HashMap::from([(0, "C".to_string()), (2, "F".to_string())]),
+            })
+            .unwrap();
+
+        let groups = ["carbonyl".to_string(), "oxygen".to_string()];
+        let snapshot = workspace.snapshot().restrict(&groups);
+        let restricted = Workspace::try_from(&WorkspaceExport::try_from(snapshot).unwrap()).unwrap();
+        let molecule = restricted.read(0).unwrap();
+        let atoms = molecule
+            .atoms()
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        assert_eq!(atoms, vec![0, 1]);
+        assert_eq!(molecule.atom(0).unwrap().element(), 8);
+        assert_eq!(
+            molecule.bonds().keys().collect::<Vec<_>>(),
+            vec![&Pair::new_ordered(0, 1)]
+        );
+        assert_eq!(
+            restricted.groups.get_left(&"carbonyl".to_string()),
+            HashSet::from([0, 1])
+        );
+        assert!(restricted.groups.get_left(&"halogen".to_string()).is_empty());
+        assert_eq!(restricted.atom_names.iter().count(), 1);
+
+        let snapshot = workspace.snapshot().restrict(&[]);
+        let restricted = Workspace::try_from(&WorkspaceExport::try_from(snapshot).unwrap()).unwrap();
+        assert!(restricted.read(0).unwrap().atoms().is_empty());
+        assert!(restricted.groups.is_empty());
+    }
+
+    #[test]
+    fn restricted_export_follows_renumbered_stacks() {
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            operation::Operation,
+            Workspace, WorkspaceExport,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let atom = |element| Some(Atom::new(element, Point3::origin()));
+        let base = Molecule::default()
+            .set_atoms(HashMap::from([(0, atom(6)), (1, atom(8)), (2, atom(1))]))
+            .set_bonds(HashMap::from([
+                (Pair::new_ordered(0, 1), 2.),
+                (Pair::new_ordered(0, 2), 1.),
+            ]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 1 }).unwrap();
+        let renumbered = vec![Layer::Remap(HashMap::from([(0, 10), (1, 11), (2, 12)]))];
+        workspace
+            .apply(Operation::AddLayers { stack_idx: 0, layers: renumbered })
+            .unwrap();
+        workspace
+            .apply(Operation::AddLayers { stack_idx: 1, layers: vec![Layer::IgnoreBonds] })
+            .unwrap();
+        let members = [10, 12, 0].map(|idx| (idx, "kept".to_string())).to_vec();
+        workspace.apply(Operation::AddToGroups { members }).unwrap();
+
+        let snapshot = workspace.snapshot().restrict(&["kept".to_string()]);
+        let restricted = Workspace::try_from(&WorkspaceExport::try_from(snapshot).unwrap()).unwrap();
+        let indices = |molecule: &Molecule| {
+            let mut atoms = molecule
+                .atoms()
+                .into_iter()
+                .map(|(idx, atom)| (idx, atom.element()))
+                .collect::<Vec<_>>();
+            atoms.sort();
+            atoms
+        };
+        let molecule = restricted.read(0).unwrap();
+        assert_eq!(indices(&molecule), vec![(10, 6), (12, 1)]);
+        assert_eq!(
+            molecule.bonds().keys().collect::<Vec<_>>(),
+            vec![&Pair::new_ordered(10, 12)]
+        );
+        // the base keeps atom 2 for the first stack only
+        assert_eq!(indices(&restricted.read(1).unwrap()), vec![(0, 6)]);
+    }
+
+    #[test]
+    fn hydrogens_are_added_in_their_own_layer() {
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            error::LMECoreError,
+            operation::{Operation, OperationOutput},
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let mut workspace = Workspace::new(Molecule::default());
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let water = Molecule::default()
+            .set_atoms(HashMap::from([(0, Some(Atom::new(8, Point3::origin())))]));
+        let layers = vec![Layer::Fill(Box::new(water))];
+        workspace
+            .apply(Operation::AddLayers { stack_idx: 0, layers })
+            .unwrap();
+        let added = workspace.apply(Operation::AddHydrogens { stack_idx: 0 });
+        assert_eq!(added.unwrap(), OperationOutput::Atoms(vec![1, 2]));
+        assert_eq!(workspace.layer_summaries(0).unwrap().len(), 2);
+        assert_eq!(workspace.read(0).unwrap().atoms().len(), 3);
+
+        // a saturated stack gets no empty layer
+        let added = workspace.apply(Operation::AddHydrogens { stack_idx: 0 });
+        assert_eq!(added.unwrap(), OperationOutput::Atoms(vec![]));
+        assert_eq!(workspace.layer_summaries(0).unwrap().len(), 2);
+        workspace.apply(Operation::Undo { stack_idx: 0 }).unwrap();
+        assert_eq!(workspace.read(0).unwrap().atoms().len(), 1);
+        let missing = workspace.apply(Operation::AddHydrogens { stack_idx: 1 });
+        assert!(matches!(missing, Err(LMECoreError::NoSuchStack)));
+    }
+
+    #[test]
+    fn plugins_stay_in_the_plugin_directory() {
+        use crate::{entity::plugin_path, error::LayerError};
+
+        for name in ["/bin/sh", "../bin/sh", "..", "sub\\dir", ""] {
+            let err = plugin_path(name).unwrap_err();
+            assert!(matches!(err, LayerError::InvalidPluginName(_)), "{name}");
+        }
+        let missing = plugin_path("no-such-plugin").unwrap_err();
+        assert!(matches!(missing, LayerError::UnknownPlugin(_)));
+    }
+}
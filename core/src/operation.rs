@@ -0,0 +1,1028 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    num::NonZeroUsize,
+    sync::Arc,
+};
+
+use nalgebra::{Point3, Vector3};
+use pair::Pair;
+use serde::{Deserialize, Serialize};
+use n_to_n::NtoN;
+use unique_value_map::{InsertResult, UniqueValueMap};
+
+use crate::{
+    entity::{Atom, AtomMetadata, Layer, LayerMeta, Mirror, Molecule, Stack},
+    error::LMECoreError,
+    fragment::{Fragment, FragmentRef},
+    symmetry::Symmetry,
+    template::Template,
+    ElectronicState, Workspace,
+};
+
+/// A rotation followed by a translation of some atoms of a stack, see
+/// `Workspace::move_atoms`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RigidMotion {
+    #[serde(default)]
+    pub atoms: Vec<usize>,
+    /// A group whose members move along with `atoms`
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Rotation axis scaled by the angle in radians
+    #[serde(default)]
+    #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+    pub rotation: Vector3<f64>,
+    /// Point the rotation is about, the centroid of the moved atoms if unset
+    #[serde(default)]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<[f64; 3]>))]
+    pub center: Option<Point3<f64>>,
+    #[serde(default)]
+    #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+    pub translation: Vector3<f64>,
+}
+
+/// One edit of a batch, see `Workspace::batch`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum BatchEdit {
+    /// Write a molecule patch onto the stack
+    Write(Box<Molecule>),
+    AddLayer(Layer),
+    Name { atom_idx: usize, name: String },
+    Group { atom_idx: usize, group: String },
+}
+
+/// Why one edit of a batch failed.
+#[derive(Debug, Serialize)]
+pub struct BatchFailure {
+    /// Position of the edit in the batch
+    pub edit: usize,
+    pub error: LMECoreError,
+}
+
+/// A mutation of a workspace, as accepted by `Workspace::apply`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum Operation {
+    CreateStack { copies: usize },
+    /// Create one empty stack, named `name` or a generated name
+    CreateNamedStack { name: Option<String> },
+    RenameStack { stack_idx: usize, name: String },
+    CloneStack { stack_idx: usize, copies: usize },
+    RemoveStack { stack_idx: usize },
+    ReorderStacks { order: Vec<usize> },
+    Undo { stack_idx: usize },
+    Redo { stack_idx: usize },
+    CloneBase { stack_idx: usize, copies: usize },
+    Write { start: usize, range: usize, data: Molecule },
+    /// `Write` with atom positions in fractional coordinates
+    WriteFractional { start: usize, range: usize, data: Molecule },
+    AddLayer { start: usize, range: usize, layer: Layer },
+    /// Push several layers onto one stack, all or none
+    AddLayers { stack_idx: usize, layers: Vec<Layer> },
+    AnnotateLayer { stack_idx: usize, meta: LayerMeta },
+    /// Push a layer of another stack onto `target`, see `Workspace::cherry_pick`
+    CherryPick { target: usize, source: usize, position: usize },
+    AppendAtom { stack_idx: usize, atom: Atom },
+    MoveAtom {
+        stack_idx: usize,
+        atom_idx: usize,
+        #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+        position: Point3<f64>,
+    },
+    AddToGroup { atom_idx: usize, group: String },
+    RemoveFromGroup { atom_idx: usize, group: String },
+    SetAtomName { atom_idx: usize, name: String },
+    /// Name many atoms at once, see `Workspace::set_atom_names`
+    SetAtomNames { names: HashMap<usize, String> },
+    AddToGroups { members: Vec<(usize, String)> },
+    RemoveGroup { group: String },
+    RenameGroup { from: String, to: String },
+    RemoveFromAllGroups { atom_idx: usize },
+    Renumber { stack_idx: usize },
+    Compact { stack_idx: usize },
+    RemoveHydrogens { stack_idx: usize },
+    RemoveAtoms { stack_idx: usize, atoms: Vec<usize> },
+    MoveAtoms { stack_idx: usize, motion: RigidMotion },
+    /// Turn the side of `bond[1]` about the bond by `angle` degrees, see
+    /// `Workspace::rotate_torsion`
+    RotateTorsion {
+        stack_idx: usize,
+        bond: [usize; 2],
+        angle: f64,
+    },
+    /// Move the smaller side of a bond until it is `length` Å long, see
+    /// `Workspace::set_bond_length`
+    SetBondLength {
+        stack_idx: usize,
+        bond: [usize; 2],
+        length: f64,
+    },
+    /// Turn the smaller side of an angle until it is `angle` degrees, see
+    /// `Workspace::set_bond_angle`
+    SetBondAngle {
+        stack_idx: usize,
+        atoms: [usize; 3],
+        angle: f64,
+    },
+    /// Replicate atoms of a stack by symmetry, every atom if none are
+    /// selected, see `Workspace::replicate_atoms`
+    ReplicateAtoms {
+        stack_idx: usize,
+        #[serde(default)]
+        atoms: Vec<usize>,
+        #[serde(default)]
+        group: Option<String>,
+        symmetry: Symmetry,
+        #[serde(default)]
+        #[cfg_attr(feature = "openapi", schema(value_type = Option<[f64; 3]>))]
+        center: Option<Point3<f64>>,
+    },
+    /// Replace a terminal atom with a fragment, see `Workspace::substitute`
+    Substitute {
+        stack_idx: usize,
+        atom_idx: usize,
+        fragment: FragmentRef,
+    },
+    SetFragment { name: String, fragment: Fragment },
+    RemoveFragment { name: String },
+    SetTemplate {
+        name: String,
+        #[cfg_attr(feature = "openapi", schema(value_type = String))]
+        template: Template,
+    },
+    RemoveTemplate { name: String },
+    /// Reflect atoms of a stack, every atom if none are selected
+    MirrorAtoms {
+        stack_idx: usize,
+        #[serde(default)]
+        atoms: Vec<usize>,
+        #[serde(default)]
+        group: Option<String>,
+        mirror: Mirror,
+    },
+    /// Repeat the cell of a stack along a, b and c, see `Workspace::supercell`
+    Supercell {
+        stack_idx: usize,
+        #[cfg_attr(feature = "openapi", schema(value_type = [usize; 3]))]
+        repeats: [NonZeroUsize; 3],
+    },
+    AddHydrogens { stack_idx: usize },
+    /// Bond atoms close enough to each other, see `Workspace::perceive_bonds`
+    PerceiveBonds { stack_idx: usize, tolerance: f64 },
+    /// Superpose a stack onto `reference`, see `Workspace::align_to`
+    AlignTo {
+        stack_idx: usize,
+        reference: usize,
+        #[serde(default)]
+        atoms: Vec<usize>,
+        #[serde(default)]
+        group: Option<String>,
+    },
+    /// Move a stack to the standard orientation of a group, or of all its
+    /// atoms, see `Workspace::orient`
+    Orient {
+        stack_idx: usize,
+        #[serde(default)]
+        group: Option<String>,
+    },
+    /// Overlay metadata on atoms of a stack, see `Workspace::set_metadata`
+    SetMetadata {
+        stack_idx: usize,
+        metadata: HashMap<usize, AtomMetadata>,
+    },
+    SetElectronicState {
+        stack_idx: usize,
+        state: ElectronicState,
+    },
+    Import { stack_idx: usize, molecule: Molecule },
+    /// Copy atoms of stack `source` into `target`, see `Workspace::copy_atoms`
+    CopyAtoms {
+        target: usize,
+        source: usize,
+        motion: RigidMotion,
+    },
+    /// Apply several edits to one stack, all or none, see `Workspace::batch`
+    Batch {
+        stack_idx: usize,
+        edits: Vec<BatchEdit>,
+    },
+    PromotePrefix { stacks: Vec<usize> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OperationOutput {
+    /// Index of the first stack created by the operation
+    Stack(usize),
+    /// Index of the atom created by the operation
+    Atom(usize),
+    /// Indices of the atoms created by the operation
+    Atoms(Vec<usize>),
+    /// Bonds created by the operation, sorted
+    Bonds(Vec<Pair<usize>>),
+    /// Old to new atom index mapping
+    Mapping(HashMap<usize, usize>),
+    /// Number of removed hydrogens per heavy atom
+    Counts(HashMap<usize, usize>),
+    /// Number of atoms affected by the operation
+    Affected(usize),
+    /// Groups affected by the operation, sorted by name
+    Groups(Vec<String>),
+    /// Name of the stack created by the operation, or its previous name
+    StackName(String),
+    /// Number of layers shared by the selected stacks
+    PrefixLength(usize),
+    /// RMSD left after the operation superposed a stack
+    Rmsd(f64),
+    /// Whether the atom was new to the group
+    Membership(n_to_n::InsertResult),
+    /// Outcome of naming each atom
+    Names(BTreeMap<usize, InsertResult<usize, String>>),
+    Done,
+}
+
+/// What an applied operation changed, small enough to send to every client
+/// watching a workspace.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    /// Variant name of the operation, or of the whole-workspace replacement
+    pub kind: String,
+    /// Stacks edited in place; empty for workspace-wide edits and for
+    /// operations that create stacks
+    pub stacks: Vec<usize>,
+    /// Molecule each of `stacks` reads as once the request making the
+    /// change is done, so watchers need not fetch it
+    #[serde(default)]
+    pub molecules: BTreeMap<usize, Arc<Molecule>>,
+    /// Atoms named differently after the change, `None` if unnamed
+    #[serde(default)]
+    pub names: BTreeMap<usize, Option<String>>,
+    /// Groups with other members after the change, with all their members,
+    /// sorted; a group left empty has none
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<usize>>,
+}
+
+impl Change {
+    /// A change of `kind` to `stacks`, with no molecules, names or groups.
+    pub fn new(kind: String, stacks: Vec<usize>) -> Self {
+        Self {
+            kind,
+            stacks,
+            molecules: BTreeMap::new(),
+            names: BTreeMap::new(),
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// Add the molecules of `stacks` as `workspace` reads them.
+    pub fn with_molecules(self, workspace: &Workspace) -> Self {
+        let molecules = self
+            .stacks
+            .iter()
+            .filter_map(|&idx| Some((idx, workspace.read_shared(idx).ok()?)))
+            .collect();
+        Self { molecules, ..self }
+    }
+
+    /// Add the names and groups `workspace` has other than `names` and
+    /// `groups`, the ones it had before the change.
+    pub fn with_names(
+        self,
+        names: &UniqueValueMap<usize, String>,
+        groups: &NtoN<String, usize>,
+        workspace: &Workspace,
+    ) -> Self {
+        let atoms = names.data().keys().chain(workspace.atom_names.data().keys());
+        let names = atoms
+            .filter(|atom| names.get(*atom) != workspace.atom_names.get(*atom))
+            .map(|&atom| (atom, workspace.atom_names.get(&atom).cloned()))
+            .collect();
+        let altered = groups
+            .data()
+            .symmetric_difference(workspace.groups.data())
+            .map(|(group, _)| group)
+            .collect::<BTreeSet<_>>();
+        let groups = altered
+            .into_iter()
+            .map(|group| {
+                let mut members = workspace.groups.get_left(group).into_iter().collect::<Vec<_>>();
+                members.sort_unstable();
+                (group.clone(), members)
+            })
+            .collect();
+        Self {
+            names,
+            groups,
+            ..self
+        }
+    }
+}
+
+impl Operation {
+    pub fn change(&self) -> Change {
+        let kind = match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(variant)) => variant.keys().next().cloned(),
+            _ => None,
+        }
+        .expect("Operations serialize as externally tagged variants");
+        let stacks = match self {
+            Self::RemoveStack { stack_idx }
+            | Self::Undo { stack_idx }
+            | Self::Redo { stack_idx }
+            | Self::RenameStack { stack_idx, .. }
+            | Self::AddLayers { stack_idx, .. }
+            | Self::AnnotateLayer { stack_idx, .. }
+            | Self::AppendAtom { stack_idx, .. }
+            | Self::MoveAtom { stack_idx, .. }
+            | Self::Renumber { stack_idx }
+            | Self::Compact { stack_idx }
+            | Self::RemoveHydrogens { stack_idx }
+            | Self::RemoveAtoms { stack_idx, .. }
+            | Self::MoveAtoms { stack_idx, .. }
+            | Self::RotateTorsion { stack_idx, .. }
+            | Self::SetBondLength { stack_idx, .. }
+            | Self::SetBondAngle { stack_idx, .. }
+            | Self::MirrorAtoms { stack_idx, .. }
+            | Self::ReplicateAtoms { stack_idx, .. }
+            | Self::Substitute { stack_idx, .. }
+            | Self::Supercell { stack_idx, .. }
+            | Self::AddHydrogens { stack_idx }
+            | Self::PerceiveBonds { stack_idx, .. }
+            | Self::Orient { stack_idx, .. }
+            | Self::AlignTo { stack_idx, .. }
+            | Self::SetMetadata { stack_idx, .. }
+            | Self::SetElectronicState { stack_idx, .. }
+            | Self::Import { stack_idx, .. }
+            | Self::Batch { stack_idx, .. } => vec![*stack_idx],
+            Self::CherryPick { target, .. } | Self::CopyAtoms { target, .. } => vec![*target],
+            Self::Write { start, range, .. }
+            | Self::WriteFractional { start, range, .. }
+            | Self::AddLayer { start, range, .. } => (*start..start + range).collect(),
+            Self::PromotePrefix { stacks } => stacks.clone(),
+            _ => vec![],
+        };
+        Change::new(kind, stacks)
+    }
+}
+
+/// Maximum number of operations kept in a workspace log.
+const LOG_CAPACITY: usize = 1 << 16;
+
+/// The successful operations applied to a workspace, oldest first, in the
+/// order they acquired the workspace lock. Once `LOG_CAPACITY` is reached
+/// the oldest entries are dropped and counted in `dropped`; only a log with
+/// nothing dropped can rebuild the workspace with `Workspace::replay`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OperationLog {
+    dropped: usize,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<Operation>))]
+    operations: VecDeque<Operation>,
+}
+
+impl OperationLog {
+    fn push(&mut self, operation: Operation) {
+        if self.operations.len() == LOG_CAPACITY {
+            self.operations.pop_front();
+            self.dropped += 1;
+        }
+        self.operations.push_back(operation);
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Number of operations logged so far, dropped ones included.
+    pub fn total(&self) -> usize {
+        self.dropped + self.operations.len()
+    }
+
+    pub fn operations(&self) -> &VecDeque<Operation> {
+        &self.operations
+    }
+}
+
+/// A mutation as the audit trail of a workspace records it. Unlike the
+/// operation log, the trail is never truncated and is kept in exports.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch
+    pub time: u64,
+    /// Who asked for the mutation, if known
+    pub actor: Option<String>,
+    /// Stacks the mutation edited, by their index at the time, see
+    /// `Change::stacks`
+    pub stacks: Vec<usize>,
+    /// Variant name of the operation, or of the whole-workspace replacement
+    pub kind: String,
+    /// The operation, with its layers and patches; unset for replacements
+    pub operation: Option<Operation>,
+}
+
+impl Workspace {
+    /// Apply one operation and record it in the operation log if it
+    /// succeeds. The stacks it edits get a new version, as do stacks it
+    /// creates.
+    pub fn apply(&mut self, operation: Operation) -> Result<OperationOutput, LMECoreError> {
+        let output = self.perform(operation.clone())?;
+        self.bump_versions(operation.change().stacks);
+        self.log.push(operation);
+        Ok(output)
+    }
+
+    pub fn log(&self) -> &OperationLog {
+        &self.log
+    }
+
+    /// The audit trail, oldest entry first. Entries are only added with
+    /// `record`; `apply` leaves the trail alone as it doesn't know who is
+    /// asking.
+    pub fn audit(&self) -> &[AuditEntry] {
+        &self.audit
+    }
+
+    pub fn record(&mut self, entry: AuditEntry) {
+        self.audit.push(entry);
+    }
+
+    /// The same workspace with `audit` as its trail, so a trail survives
+    /// swapping in a replayed or imported workspace.
+    pub fn with_audit(self, audit: Vec<AuditEntry>) -> Self {
+        Self { audit, ..self }
+    }
+
+    pub fn take_audit(&mut self) -> Vec<AuditEntry> {
+        std::mem::take(&mut self.audit)
+    }
+
+    /// A fresh workspace on the same base and with the same history depth,
+    /// with `operations` applied in order.
+    /// Every operation only depends on the workspace state it is applied to
+    /// (never on hash map iteration order), so replaying the log of a
+    /// workspace rebuilds the same stacks, atom names and groups. Fails with
+    /// the position of the first operation that does not apply.
+    pub fn replay(&self, operations: Vec<Operation>) -> Result<Workspace, LMECoreError> {
+        let mut workspace =
+            Workspace::new(self.base.clone()).with_history_depth(self.history_depth());
+        for (position, operation) in operations.into_iter().enumerate() {
+            workspace
+                .apply(operation)
+                .map_err(|err| LMECoreError::ReplayFailed(position, Box::new(err)))?;
+        }
+        Ok(workspace)
+    }
+
+    fn perform(&mut self, operation: Operation) -> Result<OperationOutput, LMECoreError> {
+        match operation {
+            Operation::CreateStack { copies } => Ok(OperationOutput::Stack(
+                self.create_stack(Arc::new(Stack::new(vec![])), copies),
+            )),
+            Operation::CreateNamedStack { name } => {
+                self.create_named_stack(name).map(OperationOutput::StackName)
+            }
+            Operation::RenameStack { stack_idx, name } => self
+                .rename_stack(stack_idx, name)
+                .map(OperationOutput::StackName),
+            Operation::CloneStack { stack_idx, copies } => self
+                .clone_stack(stack_idx, copies)
+                .map(OperationOutput::Stack)
+                .ok_or(LMECoreError::NoSuchStack),
+            Operation::RemoveStack { stack_idx } => self
+                .remove_stack(stack_idx)
+                .map(|_| OperationOutput::Done),
+            Operation::ReorderStacks { order } => self
+                .reorder_stacks(&order)
+                .map(|_| OperationOutput::Done),
+            Operation::Undo { stack_idx } => self.undo(stack_idx).map(|_| OperationOutput::Done),
+            Operation::Redo { stack_idx } => self.redo(stack_idx).map(|_| OperationOutput::Done),
+            Operation::CloneBase { stack_idx, copies } => self
+                .clone_base(stack_idx, copies)
+                .map(OperationOutput::Stack)
+                .ok_or(LMECoreError::NoSuchStack),
+            Operation::Write { start, range, data } => self
+                .write_to_stack(start, range, data)
+                .then_some(OperationOutput::Done)
+                .ok_or(LMECoreError::NoSuchStack),
+            Operation::WriteFractional { start, range, data } => self
+                .write_fractional(start, range, data)
+                .map(|_| OperationOutput::Done),
+            Operation::AddLayers { stack_idx, layers } => self
+                .add_layers(stack_idx, layers)
+                .map(|_| OperationOutput::Done),
+            Operation::AnnotateLayer { stack_idx, meta } => self
+                .annotate_layer(stack_idx, meta)
+                .map(|_| OperationOutput::Done),
+            Operation::CherryPick {
+                target,
+                source,
+                position,
+            } => self
+                .cherry_pick(target, source, position)
+                .map(|_| OperationOutput::Done),
+            Operation::AddLayer {
+                start,
+                range,
+                layer,
+            } => self
+                .add_layer_to_stack(start, range, Arc::new(layer))
+                .then_some(OperationOutput::Done)
+                .ok_or(LMECoreError::NoSuchStack),
+            Operation::AppendAtom { stack_idx, atom } => {
+                let atom_idx = self.read(stack_idx)?.next_index();
+                self.write_atom(stack_idx, atom_idx, atom);
+                Ok(OperationOutput::Atom(atom_idx))
+            }
+            Operation::MoveAtom {
+                stack_idx,
+                atom_idx,
+                position,
+            } => {
+                let atom = self
+                    .read(stack_idx)?
+                    .atom(atom_idx)
+                    .ok_or(LMECoreError::NoSuchAtom)?;
+                self.write_atom(stack_idx, atom_idx, atom.set_position(position));
+                Ok(OperationOutput::Atom(atom_idx))
+            }
+            Operation::AddToGroup { atom_idx, group } => {
+                self.check_atom(atom_idx)?;
+                Ok(OperationOutput::Membership(
+                    self.groups.insert(group, atom_idx),
+                ))
+            }
+            Operation::RemoveFromGroup { atom_idx, group } => self
+                .groups
+                .remove(&group, &atom_idx)
+                .then_some(OperationOutput::Done)
+                .ok_or(LMECoreError::NoSuchAtom),
+            Operation::SetAtomName { atom_idx, name } => {
+                self.check_atom(atom_idx)?;
+                match self.atom_names.insert(atom_idx, name.clone()) {
+                    InsertResult::Duplicated(_) => Err(LMECoreError::DuplicatedName(name)),
+                    _ => Ok(OperationOutput::Done),
+                }
+            }
+            Operation::SetAtomNames { names } => {
+                self.set_atom_names(names).map(OperationOutput::Names)
+            }
+            Operation::AddToGroups { members } => {
+                for (atom_idx, _) in &members {
+                    self.check_atom(*atom_idx)?;
+                }
+                for (atom_idx, group) in members {
+                    self.groups.insert(group, atom_idx);
+                }
+                Ok(OperationOutput::Done)
+            }
+            Operation::RemoveGroup { group } => {
+                let mut members = self.groups.remove_left(&group);
+                members.sort();
+                Ok(OperationOutput::Atoms(members))
+            }
+            Operation::RenameGroup { from, to } => match self.groups.rename_left(&from, to) {
+                0 => Err(LMECoreError::NoSuchGroup(from)),
+                affected => Ok(OperationOutput::Affected(affected)),
+            },
+            Operation::RemoveFromAllGroups { atom_idx } => {
+                let mut groups = self.groups.remove_right(&atom_idx);
+                groups.sort();
+                Ok(OperationOutput::Groups(groups))
+            }
+            Operation::Renumber { stack_idx } => {
+                self.renumber(stack_idx).map(OperationOutput::Mapping)
+            }
+            Operation::Compact { stack_idx } => {
+                self.compact(stack_idx).map(OperationOutput::Mapping)
+            }
+            Operation::RemoveHydrogens { stack_idx } => self
+                .remove_hydrogens(stack_idx)
+                .map(OperationOutput::Counts),
+            Operation::RemoveAtoms { stack_idx, atoms } => self
+                .remove_atoms(stack_idx, &atoms)
+                .map(|_| OperationOutput::Done),
+            Operation::MoveAtoms { stack_idx, motion } => self
+                .move_atoms(stack_idx, motion)
+                .map(OperationOutput::Affected),
+            Operation::RotateTorsion {
+                stack_idx,
+                bond,
+                angle,
+            } => self
+                .rotate_torsion(stack_idx, bond, angle)
+                .map(OperationOutput::Affected),
+            Operation::SetBondLength {
+                stack_idx,
+                bond,
+                length,
+            } => self
+                .set_bond_length(stack_idx, bond, length)
+                .map(OperationOutput::Affected),
+            Operation::SetBondAngle {
+                stack_idx,
+                atoms,
+                angle,
+            } => self
+                .set_bond_angle(stack_idx, atoms, angle)
+                .map(OperationOutput::Affected),
+            Operation::ReplicateAtoms {
+                stack_idx,
+                atoms,
+                group,
+                symmetry,
+                center,
+            } => self
+                .replicate_atoms(stack_idx, atoms, group, &symmetry, center)
+                .map(OperationOutput::Atoms),
+            Operation::Substitute {
+                stack_idx,
+                atom_idx,
+                fragment,
+            } => self
+                .substitute(stack_idx, atom_idx, &fragment)
+                .map(|indices| OperationOutput::Mapping(indices.into_iter().collect())),
+            Operation::SetFragment { name, fragment } => self
+                .set_fragment(name, fragment)
+                .map(|_| OperationOutput::Done),
+            Operation::RemoveFragment { name } => self
+                .remove_fragment(&name)
+                .map(|_| OperationOutput::Done),
+            Operation::SetTemplate { name, template } => {
+                self.set_template(name, template);
+                Ok(OperationOutput::Done)
+            }
+            Operation::RemoveTemplate { name } => self
+                .remove_template(&name)
+                .map(|_| OperationOutput::Done),
+            Operation::MirrorAtoms {
+                stack_idx,
+                atoms,
+                group,
+                mirror,
+            } => self
+                .mirror_atoms(stack_idx, atoms, group, mirror)
+                .map(OperationOutput::Affected),
+            Operation::Supercell { stack_idx, repeats } => self
+                .supercell(stack_idx, repeats)
+                .map(OperationOutput::Atoms),
+            Operation::AddHydrogens { stack_idx } => {
+                self.add_hydrogens(stack_idx).map(OperationOutput::Atoms)
+            }
+            Operation::PerceiveBonds {
+                stack_idx,
+                tolerance,
+            } => self
+                .perceive_bonds(stack_idx, tolerance)
+                .map(OperationOutput::Bonds),
+            Operation::AlignTo {
+                stack_idx,
+                reference,
+                atoms,
+                group,
+            } => self
+                .align_to(stack_idx, reference, atoms, group)
+                .map(OperationOutput::Rmsd),
+            Operation::Orient { stack_idx, group } => self
+                .orient(stack_idx, group.as_deref())
+                .map(|_| OperationOutput::Done),
+            Operation::SetMetadata {
+                stack_idx,
+                metadata,
+            } => self
+                .set_metadata(stack_idx, metadata)
+                .map(OperationOutput::Affected),
+            Operation::SetElectronicState { stack_idx, state } => self
+                .set_electronic_state(stack_idx, state)
+                .map(|_| OperationOutput::Done),
+            Operation::Import {
+                stack_idx,
+                molecule,
+            } => self.import(stack_idx, molecule).map(OperationOutput::Atoms),
+            Operation::CopyAtoms {
+                target,
+                source,
+                motion,
+            } => self
+                .copy_atoms(target, source, motion)
+                .map(OperationOutput::Mapping),
+            Operation::Batch { stack_idx, edits } => self
+                .batch(stack_idx, edits)
+                .map(|_| OperationOutput::Done),
+            Operation::PromotePrefix { stacks } => self
+                .promote_prefix(&stacks)
+                .map(OperationOutput::PrefixLength),
+        }
+    }
+
+    /// Name the atoms in ascending index order. Every atom has to be live
+    /// (see `check_atom`) or nothing is named; a name already held by
+    /// another atom, including one named earlier in the same batch, is
+    /// reported as `Duplicated` for that entry and the others still apply.
+    pub fn set_atom_names(
+        &mut self,
+        names: HashMap<usize, String>,
+    ) -> Result<BTreeMap<usize, InsertResult<usize, String>>, LMECoreError> {
+        let names = names.into_iter().collect::<BTreeMap<_, _>>();
+        for atom_idx in names.keys() {
+            self.check_atom(*atom_idx)?;
+        }
+        Ok(names
+            .into_iter()
+            .map(|(atom_idx, name)| (atom_idx, self.atom_names.insert(atom_idx, name)))
+            .collect())
+    }
+
+    fn write_atom(&mut self, stack_idx: usize, atom_idx: usize, atom: Atom) {
+        let patch = Molecule::default().set_atoms(HashMap::from([(atom_idx, Some(atom))]));
+        self.write_to_stack(stack_idx, 1, patch);
+    }
+}
+
+mod test {
+    #[test]
+    fn replaying_the_log_rebuilds_the_workspace() {
+        use crate::{
+            entity::{Atom, Molecule},
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+
+        let mut workspace = Workspace::new(Molecule::default());
+        let operations = [
+            Operation::CreateStack { copies: 1 },
+            Operation::AppendAtom {
+                stack_idx: 1,
+                atom: Atom::new(8, Point3::origin()),
+            },
+            Operation::AddHydrogens { stack_idx: 1 },
+            Operation::CloneStack {
+                stack_idx: 5,
+                copies: 0,
+            },
+            Operation::Renumber { stack_idx: 1 },
+        ];
+        for operation in operations {
+            let _ = workspace.apply(operation);
+        }
+        assert_eq!(workspace.log().operations().len(), 4);
+        let log = workspace.log().operations().iter().cloned().collect();
+        assert_eq!(workspace.replay(log).unwrap(), workspace);
+    }
+
+    #[test]
+    fn batch_names_report_duplicates_per_entry() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+        use unique_value_map::InsertResult;
+
+        let base = Molecule::default().set_atoms(
+            (0..3)
+                .map(|idx| (idx, Some(Atom::new(6, Point3::origin()))))
+                .collect(),
+        );
+        let mut workspace = Workspace::new(base);
+        let names = |entries: &[(usize, &str)]| {
+            entries
+                .iter()
+                .map(|(idx, name)| (*idx, name.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        let results = workspace
+            .set_atom_names(names(&[(2, "C1"), (0, "C1"), (1, "C2")]))
+            .unwrap();
+        assert_eq!(
+            results.into_iter().collect::<Vec<_>>(),
+            vec![
+                (0, InsertResult::Inserted),
+                (1, InsertResult::Inserted),
+                (2, InsertResult::Duplicated(0)),
+            ]
+        );
+        assert!(matches!(
+            workspace.set_atom_names(names(&[(1, "C3"), (5, "C5")])),
+            Err(LMECoreError::UnknownAtom(5))
+        ));
+        assert_eq!(workspace.atom_names.get(&1), Some(&"C2".to_string()));
+    }
+
+    #[test]
+    fn removed_atoms_lose_bonds_and_names() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let base = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, Some(Atom::new(8, Point3::origin()))),
+                (1, Some(Atom::new(1, Point3::new(0.96, 0., 0.)))),
+            ]))
+            .set_bonds(HashMap::from([(Pair::new_ordered(0, 1), 1.)]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        workspace
+            .apply(Operation::SetAtomName {
+                atom_idx: 1,
+                name: "H1".to_string(),
+            })
+            .unwrap();
+        assert!(matches!(
+            workspace.apply(Operation::RemoveAtoms {
+                stack_idx: 0,
+                atoms: vec![1, 7, 5, 7],
+            }),
+            Err(LMECoreError::MissingAtoms(missing)) if missing == vec![5, 7]
+        ));
+        workspace
+            .apply(Operation::RemoveAtoms {
+                stack_idx: 0,
+                atoms: vec![1],
+            })
+            .unwrap();
+        let molecule = workspace.read(0).unwrap();
+        assert_eq!(molecule.atoms().len(), 1);
+        assert!(molecule.bonds().is_empty());
+        assert!(workspace.atom_names.is_empty());
+    }
+
+    #[test]
+    fn names_outlive_atoms_other_stacks_still_have() {
+        use crate::{
+            entity::{Atom, Molecule},
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let base = Molecule::default().set_atoms(HashMap::from([
+            (0, Some(Atom::new(8, Point3::origin()))),
+            (1, Some(Atom::new(1, Point3::new(0.96, 0., 0.)))),
+        ]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 1 }).unwrap();
+        workspace
+            .apply(Operation::SetAtomName {
+                atom_idx: 1,
+                name: "H1".to_string(),
+            })
+            .unwrap();
+        workspace
+            .apply(Operation::AddToGroup {
+                atom_idx: 1,
+                group: "water".to_string(),
+            })
+            .unwrap();
+        workspace.remove_hydrogens(0).unwrap();
+        assert_eq!(workspace.resolve_atom("H1").unwrap(), 1);
+        assert_eq!(workspace.groups.get_right(&1).len(), 1);
+        workspace
+            .apply(Operation::RemoveAtoms {
+                stack_idx: 1,
+                atoms: vec![1],
+            })
+            .unwrap();
+        assert!(workspace.atom_names.is_empty());
+        assert!(workspace.groups.is_empty());
+    }
+
+    #[test]
+    fn renumbering_keeps_names_other_stacks_have() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::{HashMap, HashSet};
+
+        let base = Molecule::default().set_atoms(HashMap::from([
+            (0, Some(Atom::new(8, Point3::origin()))),
+            (1, Some(Atom::new(1, Point3::new(0.96, 0., 0.)))),
+        ]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 1 }).unwrap();
+        workspace
+            .apply(Operation::SetAtomName {
+                atom_idx: 0,
+                name: "O1".to_string(),
+            })
+            .unwrap();
+        let swap = HashMap::from([(0, 1), (1, 0)]);
+        assert!(matches!(
+            workspace.remap_indices(0, swap.clone()),
+            Err(LMECoreError::SharedAtoms(atoms)) if atoms == vec![0]
+        ));
+        assert!(workspace.stacks[0].get_layers().is_empty());
+        assert_eq!(workspace.resolve_atom("O1").unwrap(), 0);
+
+        let removed = Molecule::default().remove_atoms(&HashSet::from([0]));
+        workspace
+            .apply(Operation::Write {
+                start: 1,
+                range: 1,
+                data: removed,
+            })
+            .unwrap();
+        workspace.remap_indices(0, swap).unwrap();
+        assert_eq!(workspace.resolve_atom("O1").unwrap(), 1);
+        assert_eq!(workspace.read(0).unwrap().atom(1).unwrap().element(), 8);
+    }
+
+    #[test]
+    fn failing_layer_leaves_the_stack_untouched() {
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            error::{LMECoreError, LayerError},
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let base = Molecule::default().set_atoms(HashMap::from([
+            (0, Some(Atom::new(6, Point3::origin()))),
+            (1, Some(Atom::new(8, Point3::new(1.2, 0., 0.)))),
+        ]));
+        let mut workspace = Workspace::new(base);
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let layers = vec![
+            Layer::ReplaceElement(8, 16),
+            Layer::Remap(HashMap::from([(0, 5), (1, 5)])),
+        ];
+        assert!(matches!(
+            workspace.apply(Operation::AddLayers { stack_idx: 0, layers }),
+            Err(LMECoreError::LayerRejected(1, err))
+                if matches!(*err, LMECoreError::LayerError(LayerError::RemapCollision(5)))
+        ));
+        assert_eq!(workspace.read(0).unwrap().atom(1).unwrap().element(), 8);
+
+        let layers = vec![Layer::ReplaceElement(8, 16), Layer::RemoveElement(6)];
+        workspace
+            .apply(Operation::AddLayers { stack_idx: 0, layers })
+            .unwrap();
+        let molecule = workspace.read(0).unwrap();
+        assert_eq!(molecule.atoms().len(), 1);
+        assert_eq!(molecule.atom(1).unwrap().element(), 16);
+    }
+
+    #[test]
+    fn layer_annotations_survive_export() {
+        use crate::{
+            entity::{Atom, LayerMeta, Molecule},
+            operation::Operation,
+            Workspace, WorkspaceExport,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let mut workspace = Workspace::new(Molecule::default());
+        workspace.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        let patch = Molecule::default()
+            .set_atoms(HashMap::from([(0, Some(Atom::new(6, Point3::origin())))]));
+        let write = Operation::Write {
+            start: 0,
+            range: 1,
+            data: patch.clone(),
+        };
+        workspace.apply(write.clone()).unwrap();
+        let meta = |name: Option<&str>, comment: Option<&str>| LayerMeta {
+            name: name.map(str::to_string),
+            comment: comment.map(str::to_string),
+        };
+        for meta in [meta(Some("scaffold"), None), meta(None, Some("from xyz"))] {
+            workspace
+                .apply(Operation::AnnotateLayer { stack_idx: 0, meta })
+                .unwrap();
+        }
+        // writing into an annotated fill layer keeps the annotation
+        workspace.apply(write).unwrap();
+
+        let export = WorkspaceExport::try_from(&workspace).unwrap();
+        let json = serde_json::to_string(&export).unwrap();
+        let export: WorkspaceExport = serde_json::from_str(&json).unwrap();
+        let imported = Workspace::try_from(&export).unwrap();
+        let tree = imported.tree().unwrap();
+        assert_eq!(tree.len(), 1);
+        assert_eq!(
+            tree[0].layer().meta(),
+            Some(&meta(Some("scaffold"), Some("from xyz")))
+        );
+        assert_eq!(imported.read(0).unwrap(), workspace.read(0).unwrap());
+    }
+}
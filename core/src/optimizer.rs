@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entity::{run_plugin, Layer, LayerMeta, Molecule},
+    error::{LMECoreError, LayerError},
+};
+
+/// An external program improving the geometry of a molecule, such as a
+/// wrapper around xtb: an executable of the plugin directory reading an
+/// XYZ file on stdin and writing the new geometry as an XYZ file to stdout,
+/// atoms in the same order.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Optimizer {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl Optimizer {
+    /// The program and its arguments, as they are named in the layer.
+    pub fn method(&self) -> String {
+        std::iter::once(&self.program)
+            .chain(&self.args)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Run the program on `molecule` and return a layer moving its atoms
+    /// to the new positions, see `read_output`. Fails with a `LayerError`
+    /// if the program can't be run or fails.
+    pub fn run(&self, molecule: &Molecule) -> Result<Layer, LMECoreError> {
+        let output = run_plugin(&self.program, &self.args, &molecule.to_xyz(&self.method()))?;
+        self.read_output(molecule, &output)
+    }
+
+    /// A layer named after the method moving the atoms of `molecule`, in
+    /// index order, to the positions of the XYZ file `output`. Fails with
+    /// `InvalidPluginOutput` unless it has one atom of the same element
+    /// for each atom.
+    pub fn read_output(&self, molecule: &Molecule, output: &str) -> Result<Layer, LMECoreError> {
+        let invalid = |message: String| LayerError::InvalidPluginOutput(message);
+        let optimized = Molecule::from_xyz(output).map_err(|err| match err {
+            LMECoreError::ParseError(line, message) => invalid(format!("Line {line}: {message}")),
+            err => invalid(format!("{err:?}")),
+        })?;
+        let atoms = molecule.atoms();
+        let positions = optimized.atoms();
+        if positions.len() != atoms.len() {
+            let message = format!("Expected {} atoms, got {}", atoms.len(), positions.len());
+            return Err(invalid(message).into());
+        }
+        let mut moved = HashMap::new();
+        for ((idx, atom), (_, new)) in atoms.into_iter().zip(positions) {
+            if new.element() != atom.element() {
+                return Err(invalid(format!("Atom {idx} changed its element")).into());
+            }
+            moved.insert(idx, Some(atom.set_position(new.position())));
+        }
+        let meta = LayerMeta {
+            name: Some(self.method()),
+            comment: None,
+        };
+        let patch = Molecule::default().set_atoms(moved);
+        Ok(Layer::Annotated(meta, Box::new(Layer::Fill(Box::new(patch)))))
+    }
+}
+
+mod test {
+    #[test]
+    fn optimized_geometry_becomes_a_named_layer() {
+        use super::Optimizer;
+        use crate::{
+            entity::{Atom, Layer, Molecule},
+            error::{LMECoreError, LayerError},
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let molecule = Molecule::default().set_atoms(HashMap::from([
+            (4, Some(Atom::new(8, Point3::origin()))),
+            (9, Some(Atom::new(1, Point3::new(1.2, 0., 0.)))),
+        ]));
+        let xtb = Optimizer {
+            program: "xtb-opt".to_string(),
+            args: vec!["--gfn".to_string(), "2".to_string()],
+        };
+        let layer = xtb
+            .read_output(&molecule, "2\nenergy -5.07\nO 0 0 0\nH 0.96 0 0\n")
+            .unwrap();
+        assert_eq!(layer.meta().unwrap().name.as_deref(), Some("xtb-opt --gfn 2"));
+        let optimized = layer.filter(molecule.clone()).unwrap();
+        assert_eq!(optimized.atom(9).unwrap().position(), Point3::new(0.96, 0., 0.));
+
+        let swapped = xtb.read_output(&molecule, "2\n\nH 0 0 0\nO 0.96 0 0\n");
+        assert!(matches!(
+            swapped,
+            Err(LMECoreError::LayerError(LayerError::InvalidPluginOutput(_)))
+        ));
+        let missing = Optimizer {
+            program: "no-such-optimizer".to_string(),
+            args: vec![],
+        };
+        assert!(matches!(
+            missing.run(&molecule),
+            Err(LMECoreError::LayerError(LayerError::UnknownPlugin(_)))
+        ));
+        assert!(matches!(layer.unannotated(), Layer::Fill(_)));
+    }
+}
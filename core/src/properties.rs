@@ -0,0 +1,276 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use nalgebra::{Isometry3, Point3, Translation3, UnitQuaternion};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{elements, entity::Molecule, error::LMECoreError, geometry};
+
+/// Derived properties of a molecule that can be requested by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Property {
+    Formula,
+    Weight,
+    #[serde(rename = "exact_mass")]
+    ExactMass,
+    Centroid,
+    Bbox,
+}
+
+impl FromStr for Property {
+    type Err = LMECoreError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "formula" => Ok(Self::Formula),
+            "weight" => Ok(Self::Weight),
+            "exact_mass" => Ok(Self::ExactMass),
+            "centroid" => Ok(Self::Centroid),
+            "bbox" => Ok(Self::Bbox),
+            _ => Err(LMECoreError::UnknownProperty(name.to_string())),
+        }
+    }
+}
+
+/// A distance in Å, or an angle or dihedral in degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Measurement {
+    Distance(f64),
+    Angle(f64),
+    Dihedral(f64),
+}
+
+impl Molecule {
+    /// Number of atoms of each element, by element symbol. Elements without
+    /// a symbol are counted as `X`.
+    pub fn composition(&self) -> BTreeMap<&'static str, usize> {
+        let mut counts = BTreeMap::new();
+        for (_, atom) in self.atoms() {
+            *counts
+                .entry(elements::symbol(atom.element()).unwrap_or("X"))
+                .or_default() += 1;
+        }
+        counts
+    }
+
+    /// Molecular formula in Hill order: carbon, then hydrogen, then the other
+    /// elements alphabetically; without carbon all elements are alphabetical.
+    pub fn formula(&self) -> String {
+        let mut counts = self.composition();
+        let mut leading = vec![];
+        if let Some(carbon) = counts.remove("C") {
+            leading.push(("C", carbon));
+            if let Some(hydrogen) = counts.remove("H") {
+                leading.push(("H", hydrogen));
+            }
+        }
+        leading
+            .into_iter()
+            .chain(counts)
+            .map(|(symbol, count)| match count {
+                1 => symbol.to_string(),
+                count => format!("{symbol}{count}"),
+            })
+            .collect()
+    }
+
+    /// Sum of the standard atomic weights, `None` if an element has no
+    /// tabulated weight.
+    pub fn molecular_weight(&self) -> Option<f64> {
+        self.atoms()
+            .into_iter()
+            .map(|(_, atom)| elements::atomic_mass(atom.element()))
+            .sum()
+    }
+
+    /// Monoisotopic mass, summing the mass of the most abundant isotope of
+    /// each atom. `None` if an element has no tabulated isotope mass.
+    pub fn exact_mass(&self) -> Option<f64> {
+        self.atoms()
+            .into_iter()
+            .map(|(_, atom)| elements::monoisotopic_mass(atom.element()))
+            .sum()
+    }
+
+    /// Center of the atom positions weighted by atomic weight, `None` if
+    /// the atoms weigh nothing. Atoms without a tabulated weight, such as
+    /// dummy atoms, weigh nothing.
+    pub fn center_of_mass(&self) -> Option<Point3<f64>> {
+        let (positions, masses) = self.positions_and_masses();
+        geometry::weighted_centroid(&positions, &masses)
+    }
+
+    /// The rigid motion taking the center of mass to the origin and the
+    /// principal axes of inertia onto x, y and z in increasing order of
+    /// moment. Atoms weigh as in `center_of_mass`; if all weigh nothing
+    /// they all count as equally heavy.
+    pub fn standard_orientation(&self) -> Isometry3<f64> {
+        let (positions, mut masses) = self.positions_and_masses();
+        if masses.iter().sum::<f64>() == 0. {
+            masses.fill(1.);
+        }
+        let Some(center) = geometry::weighted_centroid(&positions, &masses) else {
+            return Isometry3::identity();
+        };
+        let rotation = geometry::principal_axes(&positions, &masses, &center);
+        UnitQuaternion::from_rotation_matrix(&rotation) * Translation3::from(-center.coords)
+    }
+
+    fn positions_and_masses(&self) -> (Vec<Point3<f64>>, Vec<f64>) {
+        self.atoms()
+            .into_iter()
+            .map(|(_, atom)| {
+                let mass = elements::atomic_mass(atom.element()).unwrap_or_default();
+                (atom.position(), mass)
+            })
+            .unzip()
+    }
+
+    /// Axis-aligned bounding box of the atom positions, `None` without atoms.
+    pub fn bounding_box(&self) -> Option<(Point3<f64>, Point3<f64>)> {
+        let positions = self.positions();
+        let first = positions.first()?;
+        Some(
+            positions
+                .iter()
+                .fold((*first, *first), |(min, max), point| {
+                    (min.inf(point), max.sup(point))
+                }),
+        )
+    }
+
+    /// Distance between two atoms, angle a-b-c of three or dihedral a-b-c-d
+    /// of four. Fails with `MeasureArity` for other atom counts and with
+    /// `MissingAtoms` for atoms not in the molecule.
+    pub fn measure(&self, atoms: &[usize]) -> Result<Measurement, LMECoreError> {
+        let missing = atoms
+            .iter()
+            .copied()
+            .filter(|idx| self.atom(*idx).is_none())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(LMECoreError::MissingAtoms(missing));
+        }
+        let positions = atoms
+            .iter()
+            .map(|idx| self.atom(*idx).expect("Checked atom").position())
+            .collect::<Vec<_>>();
+        match positions[..] {
+            [a, b] => Ok(Measurement::Distance(geometry::distance(&a, &b))),
+            [a, b, c] => Ok(Measurement::Angle(geometry::angle(&a, &b, &c))),
+            [a, b, c, d] => Ok(Measurement::Dihedral(geometry::dihedral(&a, &b, &c, &d))),
+            _ => Err(LMECoreError::MeasureArity(atoms.len())),
+        }
+    }
+
+    pub fn property(&self, property: Property) -> Value {
+        match property {
+            Property::Formula => json!(self.formula()),
+            Property::Weight => json!(self.molecular_weight()),
+            Property::ExactMass => json!(self.exact_mass()),
+            Property::Centroid => json!(geometry::centroid(&self.positions())),
+            Property::Bbox => json!(self
+                .bounding_box()
+                .map(|(min, max)| json!({ "min": min, "max": max }))),
+        }
+    }
+}
+
+mod test {
+    #[test]
+    fn formula_uses_hill_order() {
+        use crate::entity::{Atom, Molecule};
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let molecule = |elements: &[usize]| {
+            Molecule::default().set_atoms(
+                elements
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, element)| (idx, Some(Atom::new(*element, Point3::origin()))))
+                    .collect::<HashMap<_, _>>(),
+            )
+        };
+        assert_eq!(molecule(&[8, 6, 1, 1, 6, 17, 1]).formula(), "C2H3ClO");
+        assert_eq!(molecule(&[1, 8, 1, 16, 8, 8, 8]).formula(), "H2O4S");
+        let benzene = molecule(&[6, 1, 6, 1, 6, 1, 6, 1, 6, 1, 6, 1]);
+        assert_eq!(benzene.composition(), [("C", 6), ("H", 6)].into());
+        assert_eq!(benzene.formula(), "C6H6");
+        let weight = molecule(&[8, 1, 1]).molecular_weight().unwrap();
+        assert!((weight - 18.015).abs() < 1e-9);
+        let exact = molecule(&[8, 1, 1]).exact_mass().unwrap();
+        assert!((exact - 18.010565).abs() < 1e-9);
+        assert_eq!(molecule(&[6, 92]).exact_mass(), None);
+    }
+
+    #[test]
+    fn standard_orientation_centers_the_mass() {
+        use crate::entity::{Atom, Molecule};
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        // carbon monoxide along z, away from the origin
+        let molecule = Molecule::default().set_atoms(HashMap::from([
+            (0, Some(Atom::new(6, Point3::new(1., 2., 3.)))),
+            (1, Some(Atom::new(8, Point3::new(1., 2., 4.128)))),
+            (2, Some(Atom::new(0, Point3::new(5., 5., 5.)))),
+        ]));
+        let center = molecule.center_of_mass().unwrap();
+        assert!((center.z - (3. + 1.128 * 15.999 / 28.010)).abs() < 1e-9);
+        let frame = molecule.standard_orientation();
+        let (carbon, oxygen) = (
+            frame * molecule.atom(0).unwrap().position(),
+            frame * molecule.atom(1).unwrap().position(),
+        );
+        assert!((carbon.x.abs() - 1.128 * 15.999 / 28.010).abs() < 1e-9);
+        assert!(carbon.y.abs() < 1e-9 && oxygen.z.abs() < 1e-9);
+        let dummy = Molecule::default()
+            .set_atoms(HashMap::from([(0, Some(Atom::new(0, Point3::origin())))]));
+        assert_eq!(dummy.center_of_mass(), None);
+    }
+
+    #[test]
+    fn measure_picks_the_quantity_by_atom_count() {
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            properties::Measurement,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        let points = [(0., 0., 0.), (1., 0., 0.), (1., 1., 0.), (1., 1., 1.)];
+        let molecule = Molecule::default().set_atoms(
+            points
+                .iter()
+                .enumerate()
+                .map(|(idx, (x, y, z))| (idx, Some(Atom::new(6, Point3::new(*x, *y, *z)))))
+                .collect::<HashMap<_, _>>(),
+        );
+        let value = |atoms: &[usize]| match molecule.measure(atoms).unwrap() {
+            Measurement::Distance(value)
+            | Measurement::Angle(value)
+            | Measurement::Dihedral(value) => value,
+        };
+        assert!(matches!(
+            molecule.measure(&[0, 1]),
+            Ok(Measurement::Distance(_))
+        ));
+        assert!((value(&[0, 2]) - 2f64.sqrt()).abs() < 1e-9);
+        assert!((value(&[0, 1, 2]) - 90.).abs() < 1e-9);
+        assert!((value(&[0, 1, 2, 3]).abs() - 90.).abs() < 1e-9);
+        assert!(matches!(
+            molecule.measure(&[0]),
+            Err(LMECoreError::MeasureArity(1))
+        ));
+        assert!(matches!(
+            molecule.measure(&[0, 7]),
+            Err(LMECoreError::MissingAtoms(missing)) if missing == vec![7]
+        ));
+    }
+}
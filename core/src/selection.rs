@@ -0,0 +1,196 @@
+use std::collections::BTreeSet;
+
+use nalgebra::Point3;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    elements,
+    entity::Atom,
+    error::LMECoreError,
+    AtomRef, Workspace,
+};
+
+/// A region of space atoms can be selected by.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum Region {
+    /// Points no farther than `radius` Å from `center`
+    Sphere {
+        #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+        center: Point3<f64>,
+        radius: f64,
+    },
+    /// Points of the axis-aligned box between the corners `min` and `max`
+    Box {
+        #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+        min: Point3<f64>,
+        #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+        max: Point3<f64>,
+    },
+}
+
+impl Region {
+    pub fn contains(&self, point: &Point3<f64>) -> bool {
+        match self {
+            Self::Sphere { center, radius } => (point - center).norm() <= *radius,
+            Self::Box { min, max } => {
+                (0..3).all(|axis| (min[axis]..=max[axis]).contains(&point[axis]))
+            }
+        }
+    }
+}
+
+/// A condition on atoms of a stack, see `Workspace::select`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum Predicate {
+    /// Atoms of the element with this symbol, in any letter case
+    Element(String),
+    /// Members of this group
+    Group(String),
+    /// Atoms with a name
+    Named,
+    /// These atoms, given by index or name
+    Atoms(Vec<AtomRef>),
+    Region(Region),
+    Not(Box<Predicate>),
+    /// Atoms matching every predicate, every atom if there are none
+    All(Vec<Predicate>),
+    /// Atoms matching some predicate, no atom if there are none
+    Any(Vec<Predicate>),
+}
+
+impl Predicate {
+    fn matches(
+        &self,
+        idx: usize,
+        atom: &Atom,
+        workspace: &Workspace,
+    ) -> Result<bool, LMECoreError> {
+        match self {
+            Self::Element(symbol) => elements::number(symbol)
+                .map(|element| atom.element() == element)
+                .ok_or_else(|| LMECoreError::UnknownElement(symbol.clone())),
+            Self::Group(group) => Ok(workspace.groups.contains(group, &idx)),
+            Self::Named => Ok(workspace.atom_names.get(&idx).is_some()),
+            Self::Atoms(atoms) => Ok(workspace.resolve_refs(atoms)?.contains(&idx)),
+            Self::Region(region) => Ok(region.contains(&atom.position())),
+            Self::Not(predicate) => Ok(!predicate.matches(idx, atom, workspace)?),
+            Self::All(predicates) => {
+                for predicate in predicates {
+                    if !predicate.matches(idx, atom, workspace)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Self::Any(predicates) => {
+                for predicate in predicates {
+                    if predicate.matches(idx, atom, workspace)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl Workspace {
+    /// Indices of the atoms of a stack matching `predicate`. Fails with
+    /// `UnknownElement` for an element symbol that is not in the periodic
+    /// table, or `NoSuchName` for an atom name no atom has, if an atom
+    /// reaches it.
+    pub fn select(
+        &self,
+        stack_idx: usize,
+        predicate: &Predicate,
+    ) -> Result<BTreeSet<usize>, LMECoreError> {
+        let mut selected = BTreeSet::new();
+        for (idx, atom) in self.read(stack_idx)?.atoms() {
+            if predicate.matches(idx, &atom, self)? {
+                selected.insert(idx);
+            }
+        }
+        Ok(selected)
+    }
+}
+
+mod test {
+    #[test]
+    fn regions_select_atoms_by_position() {
+        use super::Region;
+        use crate::{
+            entity::{Atom, Molecule},
+            spatial::SpatialIndex,
+        };
+        use nalgebra::Point3;
+        use std::collections::{BTreeSet, HashMap};
+
+        let molecule = Molecule::default().set_atoms(
+            [0., 1., 2., 5.]
+                .into_iter()
+                .enumerate()
+                .map(|(idx, x)| (idx, Some(Atom::new(6, Point3::new(x, 0., 0.)))))
+                .collect::<HashMap<_, _>>(),
+        );
+        let index = SpatialIndex::new(&molecule);
+        let sphere = Region::Sphere {
+            center: Point3::new(1., 0., 0.),
+            radius: 1.,
+        };
+        assert_eq!(index.atoms_in(&sphere), BTreeSet::from([0, 1, 2]));
+        let slab = Region::Box {
+            min: Point3::new(1.5, -1., -1.),
+            max: Point3::new(10., 1., 1.),
+        };
+        assert_eq!(index.atoms_in(&slab), BTreeSet::from([2, 3]));
+    }
+
+    #[test]
+    fn predicates_combine() {
+        use super::Predicate::{self, *};
+        use crate::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            operation::Operation,
+            AtomRef, Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::{BTreeSet, HashMap};
+
+        let molecule = Molecule::default().set_atoms(
+            [6, 6, 8, 6, 1]
+                .into_iter()
+                .enumerate()
+                .map(|(idx, element)| (idx, Some(Atom::new(element, Point3::origin()))))
+                .collect::<HashMap<_, _>>(),
+        );
+        let mut workspace = Workspace::new(molecule);
+        workspace
+            .apply(Operation::CreateStack { copies: 0 })
+            .unwrap();
+        let members = [0, 2, 3].map(|idx| (idx, "ligand".to_string())).to_vec();
+        workspace.apply(Operation::AddToGroups { members }).unwrap();
+        let name = Operation::SetAtomName {
+            atom_idx: 3,
+            name: "C3".to_string(),
+        };
+        workspace.apply(name).unwrap();
+
+        let select = |predicate: Predicate| workspace.select(0, &predicate).unwrap();
+        let ligand_carbons = All(vec![Element("c".to_string()), Group("ligand".to_string())]);
+        assert_eq!(select(ligand_carbons.clone()), BTreeSet::from([0, 3]));
+        let unnamed = All(vec![ligand_carbons, Not(Box::new(Named))]);
+        assert_eq!(select(unnamed), BTreeSet::from([0]));
+        let hetero = Any(vec![Element("O".to_string()), Element("H".to_string())]);
+        assert_eq!(select(hetero), BTreeSet::from([2, 4]));
+        assert_eq!(select(All(vec![])).len(), 5);
+        let listed = Atoms(vec![AtomRef::Name("C3".to_string()), AtomRef::Index(4)]);
+        assert_eq!(select(listed), BTreeSet::from([3, 4]));
+        assert!(matches!(
+            workspace.select(0, &Element("Xx".to_string())),
+            Err(LMECoreError::UnknownElement(symbol)) if symbol == "Xx"
+        ));
+    }
+}
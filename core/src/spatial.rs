@@ -0,0 +1,145 @@
+use std::collections::{BTreeSet, HashMap};
+
+use nalgebra::{Point3, Vector3};
+
+use crate::{entity::Molecule, selection::Region};
+
+/// Edge of the grid cells in Å. Queries reach over as many cells as their
+/// distance needs, so this only trades cells visited for atoms compared.
+const CELL_EDGE: f64 = 3.;
+
+fn cell_of(point: &Point3<f64>) -> [i64; 3] {
+    [0, 1, 2].map(|axis| (point[axis] / CELL_EDGE).floor() as i64)
+}
+
+/// The live atoms of a molecule sorted into a uniform grid, so distance
+/// queries compare each atom with its surroundings rather than with every
+/// other atom. See `Workspace::spatial_index` for the index of a stack.
+#[derive(Debug, Default)]
+pub struct SpatialIndex {
+    cells: HashMap<[i64; 3], Vec<(usize, Point3<f64>)>>,
+}
+
+impl SpatialIndex {
+    pub fn new(molecule: &Molecule) -> Self {
+        let mut cells = HashMap::<_, Vec<_>>::new();
+        for (idx, atom) in molecule.atoms() {
+            let position = atom.position();
+            cells.entry(cell_of(&position)).or_default().push((idx, position));
+        }
+        Self { cells }
+    }
+
+    /// The atoms of the cells overlapping the box between `min` and `max`,
+    /// or of every cell where the box spans more cells than hold atoms.
+    fn near(
+        &self,
+        min: &Point3<f64>,
+        max: &Point3<f64>,
+    ) -> Box<dyn Iterator<Item = &(usize, Point3<f64>)> + '_> {
+        let (low, high) = (cell_of(min), cell_of(max));
+        let spanned = (0..3)
+            .map(|axis| (high[axis] as i128 - low[axis] as i128 + 1).max(0))
+            .product::<i128>();
+        if spanned > self.cells.len() as i128 {
+            return Box::new(self.cells.values().flatten());
+        }
+        let keys = (low[0]..=high[0]).flat_map(move |x| {
+            (low[1]..=high[1]).flat_map(move |y| (low[2]..=high[2]).map(move |z| [x, y, z]))
+        });
+        Box::new(keys.filter_map(|key| self.cells.get(&key)).flatten())
+    }
+
+    /// Indices of the atoms positioned in `region`.
+    pub fn atoms_in(&self, region: &Region) -> BTreeSet<usize> {
+        let (min, max) = match region {
+            Region::Sphere { center, radius } => {
+                let reach = Vector3::repeat(*radius);
+                (center - reach, center + reach)
+            }
+            Region::Box { min, max } => (*min, *max),
+        };
+        self.near(&min, &max)
+            .filter(|(_, position)| region.contains(position))
+            .map(|(idx, _)| *idx)
+            .collect()
+    }
+
+    /// Pairs of atoms closer than `distance`, each as `(a, b, distance)`
+    /// with `a < b`, sorted.
+    pub fn pairs_within(&self, distance: f64) -> Vec<(usize, usize, f64)> {
+        let reach = Vector3::repeat(distance);
+        let mut pairs = vec![];
+        for (a, first) in self.cells.values().flatten() {
+            for (b, second) in self.near(&(first - reach), &(first + reach)) {
+                let between = (first - second).norm();
+                if a < b && between < distance {
+                    pairs.push((*a, *b, between));
+                }
+            }
+        }
+        pairs.sort_by_key(|(a, b, _)| (*a, *b));
+        pairs
+    }
+}
+
+mod test {
+    #[test]
+    fn grid_queries_match_all_pairs() {
+        use super::SpatialIndex;
+        use crate::{
+            entity::{Atom, Molecule},
+            selection::Region,
+        };
+        use nalgebra::Point3;
+        use std::collections::HashMap;
+
+        // a crooked chain crossing many cells, some atoms far off
+        let positions = (0..60)
+            .map(|n| {
+                let n = n as f64;
+                Point3::new(n * 0.9, (n * 1.7).sin() * 4., (n * 0.3).cos() * 7. - 2.)
+            })
+            .chain([Point3::new(1e6, 0., 0.), Point3::new(-40., -40., -40.)])
+            .collect::<Vec<_>>();
+        let molecule = Molecule::default().set_atoms(
+            positions
+                .iter()
+                .enumerate()
+                .map(|(idx, position)| (idx, Some(Atom::new(6, *position))))
+                .chain([(100, None)])
+                .collect::<HashMap<_, _>>(),
+        );
+        let index = SpatialIndex::new(&molecule);
+        for distance in [0.5, 2., 7.5] {
+            let mut expected = vec![];
+            for (a, first) in positions.iter().enumerate() {
+                for (b, second) in positions.iter().enumerate().skip(a + 1) {
+                    let between = (first - second).norm();
+                    if between < distance {
+                        expected.push((a, b, between));
+                    }
+                }
+            }
+            assert_eq!(index.pairs_within(distance), expected);
+        }
+
+        let sphere = Region::Sphere {
+            center: Point3::new(10., 0., 0.),
+            radius: 6.,
+        };
+        let everywhere = Region::Box {
+            min: Point3::new(-1e9, -1e9, -1e9),
+            max: Point3::new(1e9, 1e9, 1e9),
+        };
+        for region in [sphere, everywhere] {
+            let expected = positions
+                .iter()
+                .enumerate()
+                .filter(|(_, position)| region.contains(position))
+                .map(|(idx, _)| idx)
+                .collect();
+            assert_eq!(index.atoms_in(&region), expected);
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    f64::consts::{PI, TAU},
+};
+
+use nalgebra::{Matrix3, Point3, Rotation3, Transform3, Translation3, Unit, Vector3};
+use pair::Pair;
+use serde::{Deserialize, Serialize};
+
+use crate::{entity::Molecule, error::LMECoreError};
+
+/// Copies closer than this (in Å) to an atom of the same element are
+/// merged into it, so atoms on a symmetry element are not duplicated.
+const MERGE_DISTANCE: f64 = 1e-3;
+
+/// Largest point group generated from a set of operations.
+const MAX_ORDER: usize = 120;
+
+/// A symmetry element passing through the replication center.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum SymmetryOperation {
+    /// Rotation by 360°/`order` about `axis`
+    Rotation {
+        #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+        axis: Vector3<f64>,
+        order: usize,
+    },
+    /// Rotation by 360°/`order` about `axis` followed by a reflection
+    /// through the plane perpendicular to it
+    ImproperRotation {
+        #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+        axis: Vector3<f64>,
+        order: usize,
+    },
+    /// Reflection through the plane perpendicular to `normal`
+    Reflection {
+        #[cfg_attr(feature = "openapi", schema(value_type = [f64; 3]))]
+        normal: Vector3<f64>,
+    },
+    Inversion,
+}
+
+fn invalid(message: impl Into<String>) -> LMECoreError {
+    LMECoreError::InvalidSymmetry(message.into())
+}
+
+fn unit(axis: &Vector3<f64>) -> Result<Unit<Vector3<f64>>, LMECoreError> {
+    Unit::try_new(*axis, f64::EPSILON).ok_or_else(|| invalid("Axis or normal is zero"))
+}
+
+fn rotation(axis: &Vector3<f64>, order: usize) -> Result<Matrix3<f64>, LMECoreError> {
+    if order == 0 {
+        return Err(invalid("Rotation order is zero"));
+    }
+    Ok(Rotation3::from_axis_angle(&unit(axis)?, TAU / order as f64).into_inner())
+}
+
+fn reflection(normal: &Vector3<f64>) -> Result<Matrix3<f64>, LMECoreError> {
+    let normal = unit(normal)?;
+    Ok(Matrix3::identity() - 2. * normal.as_ref() * normal.transpose())
+}
+
+impl SymmetryOperation {
+    fn matrix(&self) -> Result<Matrix3<f64>, LMECoreError> {
+        match self {
+            Self::Rotation { axis, order } => rotation(axis, *order),
+            Self::ImproperRotation { axis, order } => {
+                Ok(reflection(axis)? * rotation(axis, *order)?)
+            }
+            Self::Reflection { normal } => reflection(normal),
+            Self::Inversion => Ok(-Matrix3::identity()),
+        }
+    }
+}
+
+/// The symmetry atoms are replicated with.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum Symmetry {
+    /// The group generated by these operations
+    Operations(Vec<SymmetryOperation>),
+    /// A point group in Schoenflies notation: `Ci`, `Cs`, `Cn`, `Cnv`,
+    /// `Cnh`, `Dn`, `Dnh`, `Dnd` or `Sn`. The principal axis is z, the
+    /// mirror plane of `Cs` is xy, `Cnv` has a mirror plane containing x
+    /// and the dihedral groups have a C2 axis along x.
+    PointGroup(String),
+}
+
+fn point_group(name: &str) -> Result<Vec<SymmetryOperation>, LMECoreError> {
+    use SymmetryOperation::*;
+    let (x, y, z) = (Vector3::x(), Vector3::y(), Vector3::z());
+    match name {
+        "Ci" => return Ok(vec![Inversion]),
+        "Cs" => return Ok(vec![Reflection { normal: z }]),
+        _ => {}
+    }
+    let unknown = || invalid(format!("Unknown point group {name}"));
+    let family = name.get(..1).ok_or_else(unknown)?;
+    let rest = &name[1..];
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let order = rest[..digits].parse::<usize>().map_err(|_| unknown())?;
+    let principal = Rotation { axis: z, order };
+    let operations = match (family, &rest[digits..]) {
+        ("C", "") => vec![principal],
+        ("C", "v") => vec![principal, Reflection { normal: y }],
+        ("C", "h") => vec![principal, Reflection { normal: z }],
+        ("D", "") => vec![principal, Rotation { axis: x, order: 2 }],
+        ("D", "h") => vec![
+            principal,
+            Rotation { axis: x, order: 2 },
+            Reflection { normal: z },
+        ],
+        ("D", "d") => {
+            // the dihedral planes bisect the C2 axes
+            let angle = PI / (2 * order.max(1)) as f64 + PI / 2.;
+            vec![
+                principal,
+                Rotation { axis: x, order: 2 },
+                Reflection {
+                    normal: Vector3::new(angle.cos(), angle.sin(), 0.),
+                },
+            ]
+        }
+        ("S", "") => vec![ImproperRotation { axis: z, order }],
+        _ => return Err(unknown()),
+    };
+    Ok(operations)
+}
+
+impl Symmetry {
+    /// Every element of the group besides the identity, as matrices acting
+    /// on positions relative to the center. Fails with `InvalidSymmetry`
+    /// for malformed operations and for groups above `MAX_ORDER` elements.
+    pub fn elements(&self) -> Result<Vec<Matrix3<f64>>, LMECoreError> {
+        let generators = match self {
+            Self::Operations(operations) => operations.clone(),
+            Self::PointGroup(name) => point_group(name)?,
+        }
+        .iter()
+        .map(SymmetryOperation::matrix)
+        .collect::<Result<Vec<_>, _>>()?;
+        let mut elements = vec![Matrix3::identity()];
+        let mut next = 0;
+        while let Some(element) = elements.get(next).copied() {
+            next += 1;
+            for generator in &generators {
+                let product = generator * element;
+                if elements.iter().any(|known| (known - product).norm() < 1e-6) {
+                    continue;
+                }
+                if elements.len() == MAX_ORDER {
+                    return Err(invalid(format!("Group has over {MAX_ORDER} elements")));
+                }
+                elements.push(product);
+            }
+        }
+        elements.remove(0);
+        Ok(elements)
+    }
+}
+
+/// A symmetric copy of some atoms, see `Layer::Replicate`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SymmetryCopy {
+    /// A 4x4 homogeneous matrix, column-major
+    #[cfg_attr(feature = "openapi", schema(value_type = [f64; 16]))]
+    pub transform: Transform3<f64>,
+    /// Index of the copy of each replicated atom
+    pub atoms: BTreeMap<usize, usize>,
+}
+
+impl SymmetryCopy {
+    /// One copy of `atoms` per element of `symmetry` besides the identity,
+    /// about `center`. Copies are numbered from `start` in element order,
+    /// then in atom index order.
+    pub fn expand(
+        symmetry: &Symmetry,
+        center: Point3<f64>,
+        atoms: &BTreeSet<usize>,
+        start: usize,
+    ) -> Result<Vec<Self>, LMECoreError> {
+        let to_center = Translation3::from(-center.coords).to_homogeneous();
+        let back = Translation3::from(center.coords).to_homogeneous();
+        let mut next = start;
+        let copies = symmetry
+            .elements()?
+            .into_iter()
+            .map(|element| {
+                let matrix = back * element.to_homogeneous() * to_center;
+                let atoms = atoms
+                    .iter()
+                    .map(|idx| {
+                        next += 1;
+                        (*idx, next - 1)
+                    })
+                    .collect();
+                Self {
+                    transform: Transform3::from_matrix_unchecked(matrix),
+                    atoms,
+                }
+            })
+            .collect();
+        Ok(copies)
+    }
+
+    /// Add the copies to `molecule`, along with the bonds between copied
+    /// atoms. A copy landing on an atom of the same element is merged into
+    /// it instead, and takes its bonds along.
+    pub fn replicate(copies: &[Self], molecule: Molecule) -> Molecule {
+        let mut atoms = molecule.atoms();
+        let mut patch = HashMap::new();
+        let mut bonds = HashMap::new();
+        for copy in copies {
+            let mut images = HashMap::new();
+            for (source, target) in &copy.atoms {
+                let Some(atom) = molecule.atom(*source) else {
+                    continue;
+                };
+                let image = atom.transform_position(&copy.transform);
+                let merged = atoms.iter().find(|(_, other)| {
+                    other.element() == image.element()
+                        && (other.position() - image.position()).norm() < MERGE_DISTANCE
+                });
+                if let Some((idx, _)) = merged {
+                    images.insert(*source, *idx);
+                } else {
+                    images.insert(*source, *target);
+                    atoms.push((*target, image));
+                    patch.insert(*target, Some(image));
+                }
+            }
+            for (pair, order) in molecule.bonds() {
+                let (a, b) = (*pair).into();
+                if let (Some(a), Some(b)) = (images.get(&a), images.get(&b)) {
+                    if a != b {
+                        bonds.insert(Pair::new_ordered(*a, *b), *order);
+                    }
+                }
+            }
+        }
+        let patch = Molecule::default().set_atoms(patch).set_bonds(bonds);
+        Molecule::merge(molecule, patch)
+    }
+}
+
+mod test {
+    #[test]
+    fn point_groups_have_their_order() {
+        use super::Symmetry;
+
+        let order = |name: &str| {
+            Symmetry::PointGroup(name.to_string())
+                .elements()
+                .unwrap()
+                .len()
+                + 1
+        };
+        let orders = [
+            "Ci", "Cs", "C1", "C3", "C2v", "C3h", "D3", "D3h", "D3d", "S4",
+        ]
+        .map(order);
+        assert_eq!(orders, [2, 2, 1, 3, 4, 6, 6, 12, 12, 4]);
+        assert!(Symmetry::PointGroup("Td".to_string()).elements().is_err());
+    }
+
+    #[test]
+    fn replication_merges_atoms_on_symmetry_elements() {
+        use super::{Symmetry, SymmetryCopy, SymmetryOperation};
+        use crate::entity::{Atom, Molecule};
+        use nalgebra::{Point3, Vector3};
+        use pair::Pair;
+        use std::collections::{BTreeSet, HashMap};
+
+        // one N-H of ammonia, with the nitrogen on the C3 axis
+        let ammonia = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, Some(Atom::new(7, Point3::new(0., 0., 0.4)))),
+                (1, Some(Atom::new(1, Point3::new(0.94, 0., 0.)))),
+            ]))
+            .set_bonds(HashMap::from([(Pair::new_ordered(0, 1), 1.)]));
+        let symmetry = Symmetry::Operations(vec![SymmetryOperation::Rotation {
+            axis: Vector3::z(),
+            order: 3,
+        }]);
+        let atoms = BTreeSet::from([0, 1]);
+        let copies = SymmetryCopy::expand(&symmetry, Point3::origin(), &atoms, 2).unwrap();
+        let replicated = SymmetryCopy::replicate(&copies, ammonia);
+        let indices = replicated
+            .atoms()
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, vec![0, 1, 3, 5]);
+        let mut bonds = replicated.bonds().keys().copied().collect::<Vec<_>>();
+        bonds.sort();
+        let expected = [1, 3, 5].map(|hydrogen| Pair::new_ordered(0, hydrogen));
+        assert_eq!(bonds, expected);
+        let hydrogen = replicated.atom(3).unwrap().position();
+        assert!((hydrogen - Point3::new(-0.47, 0.94 * 0.75f64.sqrt(), 0.)).norm() < 1e-9);
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tera::{Context, Tera};
+
+use crate::{elements, error::LMECoreError, Workspace};
+
+/// Name of the single template in the `Tera` of a `Template`
+const NAME: &str = "template";
+
+/// A number with `decimals` decimals.
+fn fixed(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let number = value.as_f64().ok_or("`fixed` only writes numbers")?;
+    let decimals = args.get("decimals").and_then(Value::as_u64);
+    let decimals = decimals.ok_or("`fixed` needs a number of `decimals`")? as usize;
+    Ok(Value::String(format!("{number:.decimals$}")))
+}
+
+/// A value left aligned in a field of `width`.
+fn pad(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let width = args.get("width").and_then(Value::as_u64);
+    let width = width.ok_or("`pad` needs a `width`")? as usize;
+    let text = match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    };
+    Ok(Value::String(format!("{text:<width$}")))
+}
+
+/// A Tera error followed by its causes, which say what went wrong.
+fn message(err: tera::Error) -> String {
+    let mut message = err.to_string();
+    let mut cause = err.source();
+    while let Some(err) = cause {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        cause = err.source();
+    }
+    message
+}
+
+/// A text template rendered against a JSON context by
+/// [Tera](https://keats.github.io/tera/docs/), such as
+/// `{% for atom in atoms %}{{ atom.symbol }} {{ atom.x }}{% endfor %}`.
+/// Besides the filters of Tera, `fixed(decimals=6)` writes a number with
+/// that many decimals and `pad(width=12)` writes a value left aligned in a
+/// field of that width, for the columns of input files. Nothing is
+/// escaped. (De)serialized as its source text.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    source: String,
+    tera: Tera,
+}
+
+impl PartialEq for Template {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let mut tera = Tera::default();
+        tera.register_filter("fixed", fixed);
+        tera.register_filter("pad", pad);
+        tera.add_raw_template(NAME, &source).map_err(message)?;
+        Ok(Self { source, tera })
+    }
+}
+
+impl From<Template> for String {
+    fn from(value: Template) -> Self {
+        value.source
+    }
+}
+
+impl Template {
+    /// Fails with `InvalidTemplate` for source Tera can't parse, such as
+    /// unclosed or unbalanced tags.
+    pub fn new(source: String) -> Result<Self, LMECoreError> {
+        Self::try_from(source).map_err(LMECoreError::InvalidTemplate)
+    }
+
+    /// Fails with `InvalidTemplate` when writing a missing variable, when
+    /// looping over something other than a list or a table, for unknown
+    /// filters and when `fixed` is given text.
+    pub fn render(&self, context: &Value) -> Result<String, LMECoreError> {
+        let context = Context::from_value(context.clone()).map_err(message);
+        context
+            .and_then(|context| self.tera.render(NAME, &context).map_err(message))
+            .map_err(LMECoreError::InvalidTemplate)
+    }
+}
+
+impl Workspace {
+    /// What a template is rendered against for a stack: its `name`,
+    /// `charge`, `multiplicity`, the `atoms` in index order and the
+    /// `bonds`, the members of each of the `groups`, and `params` as given.
+    /// Atoms are numbered by `serial` from 1 in index order; bonds and
+    /// groups refer to atoms by serial. Each atom has its `index`,
+    /// `element`, `symbol`, `x`, `y` and `z`, its workspace-wide `name`
+    /// (null if it has none) and `groups`, and its metadata fields.
+    pub fn template_context(&self, stack_idx: usize, params: Value) -> Result<Value, LMECoreError> {
+        let molecule = self.read(stack_idx)?;
+        let state = self.electronic_state(stack_idx)?;
+        let atoms = molecule.atoms();
+        let serials = atoms
+            .iter()
+            .enumerate()
+            .map(|(serial, (idx, _))| (*idx, serial + 1))
+            .collect::<BTreeMap<_, _>>();
+        let atoms = atoms
+            .iter()
+            .map(|(idx, atom)| {
+                let position = atom.position();
+                let mut groups = self.groups.get_right(idx).into_iter().collect::<Vec<_>>();
+                groups.sort();
+                let mut entry = json!({
+                    "index": idx,
+                    "serial": serials[idx],
+                    "element": atom.element(),
+                    "symbol": elements::symbol(atom.element()).unwrap_or("X"),
+                    "x": position.x,
+                    "y": position.y,
+                    "z": position.z,
+                    "name": self.atom_names.get(idx),
+                    "groups": groups,
+                });
+                if let Some(Value::Object(metadata)) =
+                    molecule.metadata(*idx).map(|metadata| json!(metadata))
+                {
+                    entry.as_object_mut().expect("Atom entry").extend(metadata);
+                }
+                entry
+            })
+            .collect::<Vec<_>>();
+        let mut bonds = molecule
+            .bonds()
+            .iter()
+            .filter_map(|(pair, order)| {
+                let (a, b) = (*pair).into();
+                let (a, b) = (*serials.get(&a)?, *serials.get(&b)?);
+                Some((a.min(b), a.max(b), *order))
+            })
+            .collect::<Vec<_>>();
+        bonds.sort_by_key(|(a, b, _)| (*a, *b));
+        let bonds = bonds
+            .into_iter()
+            .map(|(a, b, order)| json!({ "a": a, "b": b, "order": order }))
+            .collect::<Vec<_>>();
+        let mut groups = BTreeMap::<String, Vec<usize>>::new();
+        for (group, idx) in self.groups.clone() {
+            if let Some(serial) = serials.get(&idx) {
+                groups.entry(group).or_default().push(*serial);
+            }
+        }
+        groups.values_mut().for_each(|members| members.sort());
+        Ok(json!({
+            "name": self.stack_names()[stack_idx],
+            "charge": state.charge,
+            "multiplicity": state.multiplicity,
+            "atoms": atoms,
+            "bonds": bonds,
+            "groups": groups,
+            "params": params,
+        }))
+    }
+
+    /// A stack rendered with the library template `name`, see
+    /// `template_context`.
+    pub fn render_template(
+        &self,
+        stack_idx: usize,
+        name: &str,
+        params: Value,
+    ) -> Result<String, LMECoreError> {
+        let context = self.template_context(stack_idx, params)?;
+        self.template(name)?.render(&context)
+    }
+}
+
+mod test {
+    #[test]
+    fn templates_loop_over_atoms() {
+        use super::Template;
+        use crate::error::LMECoreError;
+        use serde_json::json;
+
+        let source = "\
+* xyz {{ charge }} {{ multiplicity }}
+{% for atom in atoms -%}
+{{ atom.symbol | pad(width=3) }}{{ atom.x | fixed(decimals=3) }}\
+{% if atom.isotope %} iso {{ atom.isotope }}{% endif %}
+{% endfor -%}
+{% if params.keywords -%}
+! {{ params.keywords | upper }}
+{% else -%}
+! SP
+{% endif -%}
+*{% for atom in atoms %}{% if loop.last %} {{ loop.index }}{% endif %}{% endfor %}";
+        let template = Template::new(source.to_string()).unwrap();
+        let context = json!({
+            "charge": -1,
+            "multiplicity": 2,
+            "atoms": [
+                { "symbol": "O", "x": 0.0 },
+                { "symbol": "H", "x": 0.9572, "isotope": 2 },
+            ],
+            "params": { "keywords": "opt" },
+        });
+        assert_eq!(
+            template.render(&context).unwrap(),
+            "* xyz -1 2\nO  0.000\nH  0.957 iso 2\n! OPT\n* 2"
+        );
+        assert_eq!(serde_json::to_value(&template).unwrap(), json!(source));
+
+        for failing in [
+            "{{ atoms.2.symbol }}",
+            "{{ charge | bold }}",
+            "{{ atoms.0.symbol | fixed(decimals=2) }}",
+        ] {
+            let template = Template::new(failing.to_string()).unwrap();
+            assert!(matches!(
+                template.render(&context),
+                Err(LMECoreError::InvalidTemplate(_))
+            ));
+        }
+        for broken in ["{% for atom in atoms %}", "{{ charge", "{% endif %}"] {
+            assert!(matches!(
+                Template::new(broken.to_string()),
+                Err(LMECoreError::InvalidTemplate(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+
+use pair::Pair;
+use serde::Serialize;
+
+use crate::{elements, entity::Molecule, spatial::SpatialIndex};
+
+/// Atoms closer than this (in Å) are reported as overlapping by default.
+pub const DEFAULT_OVERLAP_DISTANCE: f64 = 0.5;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ValenceProblem {
+    /// Sum of the orders of the atom's bonds
+    pub valence: f64,
+    /// Highest valence the element can have, see `elements::max_valence`
+    pub max: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Overlap {
+    #[cfg_attr(feature = "openapi", schema(value_type = [usize; 2]))]
+    pub atoms: Pair<usize>,
+    pub distance: f64,
+}
+
+/// Problems found in a molecule by `Molecule::validate`, sorted by atom.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Validation {
+    pub valence: BTreeMap<usize, ValenceProblem>,
+    pub overlaps: Vec<Overlap>,
+    /// Bonds to an atom the molecule does not have
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<[usize; 2]>))]
+    pub dangling_bonds: Vec<Pair<usize>>,
+}
+
+impl Validation {
+    pub fn is_valid(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl Molecule {
+    /// Atoms bonded beyond the valence their element can have, pairs of
+    /// atoms closer than `overlap` Å and bonds to missing atoms, with
+    /// `index` the spatial index of the molecule.
+    pub fn validate(&self, index: &SpatialIndex, overlap: f64) -> Validation {
+        let mut valences = BTreeMap::<usize, f64>::new();
+        let mut dangling_bonds = vec![];
+        for (pair, order) in self.bonds() {
+            let (a, b) = (*pair).into();
+            if self.atom(a).is_none() || self.atom(b).is_none() {
+                dangling_bonds.push(*pair);
+                continue;
+            }
+            *valences.entry(a).or_default() += order;
+            *valences.entry(b).or_default() += order;
+        }
+        dangling_bonds.sort();
+        let valence = valences
+            .into_iter()
+            .filter_map(|(idx, valence)| {
+                let max = elements::max_valence(self.atom(idx)?.element())?;
+                (valence > max as f64).then_some((idx, ValenceProblem { valence, max }))
+            })
+            .collect();
+        let overlaps = index
+            .pairs_within(overlap)
+            .into_iter()
+            .map(|(a, b, distance)| Overlap {
+                atoms: Pair::new_ordered(a, b),
+                distance,
+            })
+            .collect();
+        Validation {
+            valence,
+            overlaps,
+            dangling_bonds,
+        }
+    }
+}
+
+mod test {
+    #[test]
+    fn validation_reports_broken_structures() {
+        use crate::{
+            entity::{Atom, Molecule},
+            spatial::SpatialIndex,
+        };
+        use nalgebra::Point3;
+        use pair::Pair;
+        use std::collections::HashMap;
+
+        let atom = |element, x| Some(Atom::new(element, Point3::new(x, 0., 0.)));
+        // a pentavalent carbon, a hydrogen sitting on another and a bond to
+        // the missing atom 9
+        let molecule = Molecule::default()
+            .set_atoms(HashMap::from([
+                (0, atom(6, 0.)),
+                (1, atom(8, 1.2)),
+                (2, atom(8, -1.2)),
+                (3, atom(1, 3.)),
+                (4, atom(1, 3.1)),
+            ]))
+            .set_bonds(HashMap::from([
+                (Pair::new_ordered(0, 1), 2.),
+                (Pair::new_ordered(0, 2), 2.),
+                (Pair::new_ordered(0, 3), 1.),
+                (Pair::new_ordered(1, 9), 1.),
+            ]));
+        let validation = molecule.validate(&SpatialIndex::new(&molecule), 0.5);
+        assert_eq!(
+            validation.valence.keys().copied().collect::<Vec<_>>(),
+            vec![0]
+        );
+        assert_eq!(validation.valence[&0].valence, 5.);
+        assert_eq!(validation.overlaps.len(), 1);
+        assert_eq!(validation.overlaps[0].atoms, Pair::new_ordered(3, 4));
+        assert_eq!(validation.dangling_bonds, vec![Pair::new_ordered(1, 9)]);
+        assert!(!validation.is_valid());
+        let empty = Molecule::default();
+        assert!(empty.validate(&SpatialIndex::default(), 0.5).is_valid());
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use lme_core::{
+    entity::{Atom, Layer, Molecule},
+    error::LMECoreError,
+};
+use nalgebra::{Matrix4, Point3, Transform3};
+use pair::Pair;
+use tonic::Status;
+
+use crate::proto;
+
+/// Status of a failed operation: `NOT_FOUND` for missing workspace items,
+/// `ABORTED` for edits racing other edits and `INVALID_ARGUMENT` otherwise,
+/// with the error as its message.
+pub fn core_status(err: LMECoreError) -> Status {
+    let message = format!("{err:?}");
+    match err {
+        LMECoreError::NoSuchStack
+        | LMECoreError::NoSuchAtom
+        | LMECoreError::UnknownAtom(_)
+        | LMECoreError::NoSuchName(_)
+        | LMECoreError::NoSuchLayer(_)
+        | LMECoreError::NoSuchGroup(_)
+        | LMECoreError::NoSuchFragment(_)
+        | LMECoreError::NoSuchTemplate(_) => Status::not_found(message),
+        LMECoreError::StackChanged(_) => Status::aborted(message),
+        _ => Status::invalid_argument(message),
+    }
+}
+
+fn index(value: u64) -> Result<usize, Status> {
+    usize::try_from(value).map_err(|_| Status::invalid_argument(format!("Index {value} too large")))
+}
+
+impl From<&Molecule> for proto::Molecule {
+    fn from(molecule: &Molecule) -> Self {
+        let atoms = molecule
+            .atoms()
+            .into_iter()
+            .map(|(idx, atom)| {
+                let position = atom.position();
+                proto::Atom {
+                    index: idx as u64,
+                    element: atom.element() as u32,
+                    position: Some(proto::Vector {
+                        x: position.x,
+                        y: position.y,
+                        z: position.z,
+                    }),
+                }
+            })
+            .collect();
+        let mut bonds = molecule.bonds().iter().collect::<Vec<_>>();
+        bonds.sort_by_key(|(pair, _)| **pair);
+        let bonds = bonds
+            .into_iter()
+            .map(|(pair, order)| {
+                let (a, b) = (*pair).into();
+                proto::Bond {
+                    a: a as u64,
+                    b: b as u64,
+                    order: *order,
+                }
+            })
+            .collect();
+        Self {
+            atoms,
+            removed: vec![],
+            bonds,
+        }
+    }
+}
+
+impl TryFrom<proto::Molecule> for Molecule {
+    type Error = Status;
+
+    fn try_from(value: proto::Molecule) -> Result<Self, Self::Error> {
+        let mut atoms = HashMap::new();
+        for atom in value.atoms {
+            let proto::Vector { x, y, z } = atom.position.ok_or_else(|| {
+                Status::invalid_argument(format!("Atom {} has no position", atom.index))
+            })?;
+            let element = atom.element as usize;
+            atoms.insert(
+                index(atom.index)?,
+                Some(Atom::new(element, Point3::new(x, y, z))),
+            );
+        }
+        for removed in value.removed {
+            atoms.insert(index(removed)?, None);
+        }
+        let mut bonds = HashMap::new();
+        for bond in value.bonds {
+            bonds.insert(
+                Pair::new_ordered(index(bond.a)?, index(bond.b)?),
+                bond.order,
+            );
+        }
+        Ok(Molecule::default().set_atoms(atoms).set_bonds(bonds))
+    }
+}
+
+impl TryFrom<proto::Layer> for Layer {
+    type Error = Status;
+
+    fn try_from(value: proto::Layer) -> Result<Self, Self::Error> {
+        use proto::layer::Layer as Kind;
+
+        let kind = value
+            .layer
+            .ok_or_else(|| Status::invalid_argument("Layer is empty"))?;
+        Ok(match kind {
+            Kind::Fill(molecule) => Layer::Fill(Box::new(molecule.try_into()?)),
+            Kind::Transform(proto::Transform { matrix }) => {
+                if matrix.len() != 16 {
+                    return Err(Status::invalid_argument("Transforms take 16 numbers"));
+                }
+                let matrix = Matrix4::from_column_slice(&matrix);
+                Layer::Transform(Transform3::from_matrix_unchecked(matrix))
+            }
+            Kind::IgnoreBonds(_) => Layer::IgnoreBonds,
+            Kind::ReplaceElement(proto::ReplaceElement { from, to }) => {
+                Layer::ReplaceElement(from as usize, to as usize)
+            }
+            Kind::RemoveElement(element) => Layer::RemoveElement(element as usize),
+            Kind::Remap(proto::Remap { mapping }) => Layer::Remap(
+                mapping
+                    .into_iter()
+                    .map(|(from, to)| Ok((index(from)?, index(to)?)))
+                    .collect::<Result<_, Status>>()?,
+            ),
+            Kind::PluginFilter(proto::PluginFilter { plugin, args }) => {
+                Layer::PluginFilter(plugin, args)
+            }
+            Kind::Json(json) => serde_json::from_str(&json)
+                .map_err(|err| Status::invalid_argument(err.to_string()))?,
+        })
+    }
+}
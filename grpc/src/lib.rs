@@ -0,0 +1,189 @@
+//! gRPC interface to the workspaces of the server, for clients better
+//! served by protobuf than JSON, such as pipelines streaming large
+//! molecules. Every call is an `Operation` applied through `Workspaces`,
+//! the same way the HTTP routes apply them, so both interfaces can edit
+//! the same workspaces side by side.
+
+// `Status` is the error type of every tonic service method
+#![allow(clippy::result_large_err)]
+
+use futures::stream::{self, BoxStream, StreamExt};
+use lme_core::{
+    entity::Molecule,
+    error::LMECoreError,
+    operation::{Operation, OperationOutput},
+};
+use tonic::{Request, Response, Status};
+
+mod convert;
+
+pub use convert::core_status;
+
+pub mod proto {
+    tonic::include_proto!("lme");
+}
+
+use proto::{lme_server::LmeServer, Empty};
+
+/// The workspaces a `LmeService` works on, implemented by the server over
+/// its workspace map. Errors of the core library become a `Status` with
+/// `core_status`. `token` is the bearer token the call came with, if any,
+/// for implementations checking who may do what.
+#[tonic::async_trait]
+pub trait Workspaces: Send + Sync + 'static {
+    /// Add a workspace on `base`, failing with `ALREADY_EXISTS` if there is
+    /// one of this name.
+    async fn create(&self, token: Option<&str>, name: &str, base: Molecule)
+        -> Result<(), Status>;
+
+    /// Apply `operation` to a workspace under its lock, failing with
+    /// `NOT_FOUND` if there is none of this name.
+    async fn apply(
+        &self,
+        token: Option<&str>,
+        name: &str,
+        operation: Operation,
+    ) -> Result<OperationOutput, Status>;
+
+    /// Molecules of the stacks `start..start + range` of a workspace.
+    async fn read(
+        &self,
+        token: Option<&str>,
+        name: &str,
+        start: usize,
+        range: usize,
+    ) -> Result<Vec<Molecule>, Status>;
+}
+
+pub struct LmeService<W> {
+    workspaces: W,
+}
+
+impl<W: Workspaces> LmeService<W> {
+    pub fn new(workspaces: W) -> Self {
+        Self { workspaces }
+    }
+
+    /// The service, ready to be added to a `tonic::transport::Server`.
+    pub fn into_server(self) -> LmeServer<Self> {
+        LmeServer::new(self)
+    }
+}
+
+/// The bearer token of the `authorization` metadata of a call.
+fn token<T>(request: &Request<T>) -> Option<String> {
+    let value = request.metadata().get("authorization")?.to_str().ok()?;
+    Some(value.strip_prefix("Bearer ")?.trim().to_string())
+}
+
+fn index(value: u64) -> Result<usize, Status> {
+    usize::try_from(value).map_err(|_| Status::invalid_argument(format!("Index {value} too large")))
+}
+
+#[tonic::async_trait]
+impl<W: Workspaces> proto::lme_server::Lme for LmeService<W> {
+    type ReadStacksStream = BoxStream<'static, Result<proto::Molecule, Status>>;
+
+    async fn create_workspace(
+        &self,
+        request: Request<proto::CreateWorkspaceRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let token = token(&request);
+        let proto::CreateWorkspaceRequest { workspace, base } = request.into_inner();
+        let base = base
+            .map(Molecule::try_from)
+            .transpose()?
+            .unwrap_or_default();
+        self.workspaces.create(token.as_deref(), &workspace, base).await?;
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn create_stack(
+        &self,
+        request: Request<proto::CreateStackRequest>,
+    ) -> Result<Response<proto::StackIndex>, Status> {
+        let token = token(&request);
+        let proto::CreateStackRequest { workspace, copies } = request.into_inner();
+        let operation = Operation::CreateStack {
+            copies: index(copies)?,
+        };
+        match self.workspaces.apply(token.as_deref(), &workspace, operation).await? {
+            OperationOutput::Stack(idx) => {
+                Ok(Response::new(proto::StackIndex { index: idx as u64 }))
+            }
+            output => unreachable!("CreateStack returned {output:?}"),
+        }
+    }
+
+    async fn add_layers(
+        &self,
+        request: Request<proto::AddLayersRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let token = token(&request);
+        let proto::AddLayersRequest {
+            workspace,
+            stack,
+            layers,
+        } = request.into_inner();
+        let operation = Operation::AddLayers {
+            stack_idx: index(stack)?,
+            layers: layers
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+        };
+        self.workspaces.apply(token.as_deref(), &workspace, operation).await?;
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn read_stack(
+        &self,
+        request: Request<proto::StackRequest>,
+    ) -> Result<Response<proto::Molecule>, Status> {
+        let token = token(&request);
+        let proto::StackRequest { workspace, stack } = request.into_inner();
+        let molecules = self
+            .workspaces
+            .read(token.as_deref(), &workspace, index(stack)?, 1)
+            .await?;
+        let molecule = molecules
+            .first()
+            .ok_or_else(|| core_status(LMECoreError::NoSuchStack))?;
+        Ok(Response::new(molecule.into()))
+    }
+
+    async fn read_stacks(
+        &self,
+        request: Request<proto::StacksRequest>,
+    ) -> Result<Response<Self::ReadStacksStream>, Status> {
+        let token = token(&request);
+        let proto::StacksRequest {
+            workspace,
+            start,
+            range,
+        } = request.into_inner();
+        let molecules = self
+            .workspaces
+            .read(token.as_deref(), &workspace, index(start)?, index(range)?)
+            .await?;
+        // converted one at a time as the client takes them
+        let messages = stream::iter(molecules).map(|molecule| Ok((&molecule).into()));
+        Ok(Response::new(messages.boxed()))
+    }
+
+    async fn apply(
+        &self,
+        request: Request<proto::ApplyRequest>,
+    ) -> Result<Response<proto::ApplyResponse>, Status> {
+        let token = token(&request);
+        let proto::ApplyRequest {
+            workspace,
+            operation,
+        } = request.into_inner();
+        let operation = serde_json::from_str(&operation)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let output = self.workspaces.apply(token.as_deref(), &workspace, operation).await?;
+        let output = serde_json::to_string(&output).expect("Operation outputs serialize");
+        Ok(Response::new(proto::ApplyResponse { output }))
+    }
+}
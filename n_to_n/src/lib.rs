@@ -1,71 +1,154 @@
 use std::collections::hash_set::IntoIter;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 
-use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub struct NtoN<L: Eq + Hash, R: Eq + Hash>(HashSet<(L, R)>);
+/// Outcome of inserting a pair, so retried insertions can be told apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InsertResult {
+    /// The pair is new
+    Inserted,
+    /// The pair was already present; nothing was changed
+    Existed,
+}
+
+/// Many-to-many relation between `L` and `R`.
+///
+/// Besides the pairs themselves, both directions are indexed so lookups and
+/// removals by one side only touch the matching entries. The indices never
+/// hold empty sets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NtoN<L: Eq + Hash, R: Eq + Hash> {
+    pairs: HashSet<(L, R)>,
+    lefts: HashMap<L, HashSet<R>>,
+    rights: HashMap<R, HashSet<L>>,
+}
+
+impl<L: Eq + Hash, R: Eq + Hash> Default for NtoN<L, R> {
+    fn default() -> Self {
+        Self {
+            pairs: HashSet::new(),
+            lefts: HashMap::new(),
+            rights: HashMap::new(),
+        }
+    }
+}
 
 impl<L: Sync + Send + Eq + Hash + Clone, R: Sync + Send + Eq + Hash + Clone> NtoN<L, R> {
     pub fn new() -> Self {
-        Self(HashSet::new())
+        Self::default()
     }
 
     pub fn data(&self) -> &HashSet<(L, R)> {
-        &self.0
+        &self.pairs
+    }
+
+    pub fn len(&self) -> usize {
+        self.data().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data().is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&L, &R)> {
+        self.data().iter().map(|(l, r)| (l, r))
     }
 
-    fn data_mut(&mut self) -> &mut HashSet<(L, R)> {
-        &mut self.0
+    pub fn contains(&self, left: &L, right: &R) -> bool {
+        self.data().contains(&(left.clone(), right.clone()))
     }
 
     pub fn get_lefts(&self) -> HashSet<L> {
-        self.data().par_iter().map(|(l, _)| l).cloned().collect()
+        self.lefts.keys().cloned().collect()
     }
 
     pub fn get_rights(&self) -> HashSet<R> {
-        self.data().par_iter().map(|(_, r)| r).cloned().collect()
+        self.rights.keys().cloned().collect()
     }
 
     pub fn get_left(&self, left: &L) -> HashSet<R> {
-        self.data()
-            .iter()
-            .filter_map(|(l, r)| if l == left { Some(r) } else { None })
-            .cloned()
-            .collect()
+        self.lefts.get(left).cloned().unwrap_or_default()
     }
 
     pub fn get_right(&self, right: &R) -> HashSet<L> {
-        self.data()
-            .iter()
-            .filter_map(|(l, r)| if r == right { Some(l) } else { None })
-            .cloned()
-            .collect()
+        self.rights.get(right).cloned().unwrap_or_default()
+    }
+
+    /// Number of rights paired with `left`, without collecting them.
+    pub fn count_left(&self, left: &L) -> usize {
+        self.lefts.get(left).map_or(0, HashSet::len)
     }
 
-    pub fn insert(&mut self, left: L, right: R) -> bool {
-        self.data_mut().insert((left, right))
+    pub fn count_right(&self, right: &R) -> usize {
+        self.rights.get(right).map_or(0, HashSet::len)
+    }
+
+    pub fn insert(&mut self, left: L, right: R) -> InsertResult {
+        if link(&mut self.pairs, &mut self.lefts, &mut self.rights, left, right) {
+            InsertResult::Inserted
+        } else {
+            InsertResult::Existed
+        }
     }
 
     pub fn remove(&mut self, left: &L, right: &R) -> bool {
-        self.data_mut().remove(&(left.clone(), right.clone()))
+        if !self.pairs.remove(&(left.clone(), right.clone())) {
+            return false;
+        }
+        unlink(&mut self.lefts, left, right);
+        unlink(&mut self.rights, right, left);
+        true
     }
 
-    pub fn remove_left(&mut self, left: &L) {
-        self.data_mut().retain(|(l, _)| l != left)
+    /// Remove every pair with `left`, returning the rights it was paired with.
+    pub fn remove_left(&mut self, left: &L) -> Vec<R> {
+        let rights = Vec::from_iter(self.lefts.remove(left).unwrap_or_default());
+        for right in &rights {
+            unlink(&mut self.rights, right, left);
+            self.pairs.remove(&(left.clone(), right.clone()));
+        }
+        rights
     }
 
-    pub fn remove_right(&mut self, right: &R) {
-        self.data_mut().retain(|(_, r)| r != right)
+    /// Remove every pair with `right`, returning the lefts it was paired with.
+    pub fn remove_right(&mut self, right: &R) -> Vec<L> {
+        let lefts = Vec::from_iter(self.rights.remove(right).unwrap_or_default());
+        for left in &lefts {
+            unlink(&mut self.lefts, left, right);
+            self.pairs.remove(&(left.clone(), right.clone()));
+        }
+        lefts
+    }
+
+    /// Move every pair of `old` over to `new`, merging with the pairs `new`
+    /// already has. Returns how many rights `old` was paired with.
+    pub fn rename_left(&mut self, old: &L, new: L) -> usize {
+        let rights = self.lefts.remove(old).unwrap_or_default();
+        for right in &rights {
+            self.pairs.remove(&(old.clone(), right.clone()));
+            if let Some(lefts) = self.rights.get_mut(right) {
+                lefts.remove(old);
+            }
+            link(
+                &mut self.pairs,
+                &mut self.lefts,
+                &mut self.rights,
+                new.clone(),
+                right.clone(),
+            );
+        }
+        rights.len()
     }
 
     pub fn extend<I>(&mut self, iter: I)
     where
         I: IntoIterator<Item = (L, R)>,
     {
-        self.data_mut().extend(iter)
+        iter.into_iter().for_each(|(left, right)| {
+            self.insert(left, right);
+        })
     }
 
     pub fn overlay_to(&self, other: &Self) -> Self {
@@ -75,15 +158,65 @@ impl<L: Sync + Send + Eq + Hash + Clone, R: Sync + Send + Eq + Hash + Clone> Nto
     }
 }
 
-impl<L: Eq + Hash, R: Eq + Hash> From<HashSet<(L, R)>> for NtoN<L, R> {
+fn link<L: Eq + Hash + Clone, R: Eq + Hash + Clone>(
+    pairs: &mut HashSet<(L, R)>,
+    lefts: &mut HashMap<L, HashSet<R>>,
+    rights: &mut HashMap<R, HashSet<L>>,
+    left: L,
+    right: R,
+) -> bool {
+    if !pairs.insert((left.clone(), right.clone())) {
+        return false;
+    }
+    lefts.entry(left.clone()).or_default().insert(right.clone());
+    rights.entry(right).or_default().insert(left);
+    true
+}
+
+fn unlink<K: Eq + Hash, V: Eq + Hash>(index: &mut HashMap<K, HashSet<V>>, key: &K, value: &V) {
+    if let Some(values) = index.get_mut(key) {
+        values.remove(value);
+        if values.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+impl<L: Eq + Hash + Clone, R: Eq + Hash + Clone> From<HashSet<(L, R)>> for NtoN<L, R> {
     fn from(value: HashSet<(L, R)>) -> Self {
-        Self(value)
+        let mut relation = Self::default();
+        for (left, right) in value {
+            link(
+                &mut relation.pairs,
+                &mut relation.lefts,
+                &mut relation.rights,
+                left,
+                right,
+            );
+        }
+        relation
+    }
+}
+
+impl<L: Eq + Hash, R: Eq + Hash> From<NtoN<L, R>> for HashSet<(L, R)> {
+    fn from(value: NtoN<L, R>) -> Self {
+        value.pairs
     }
 }
 
-impl<L: Eq + Hash, R: Eq + Hash> Into<HashSet<(L, R)>> for NtoN<L, R> {
-    fn into(self) -> HashSet<(L, R)> {
-        self.0
+impl<L: Eq + Hash + Serialize, R: Eq + Hash + Serialize> Serialize for NtoN<L, R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.pairs.serialize(serializer)
+    }
+}
+
+impl<'de, L, R> Deserialize<'de> for NtoN<L, R>
+where
+    L: Eq + Hash + Clone + Deserialize<'de>,
+    R: Eq + Hash + Clone + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        HashSet::deserialize(deserializer).map(Self::from)
     }
 }
 
@@ -91,6 +224,83 @@ impl<L: Eq + Hash, R: Eq + Hash> IntoIterator for NtoN<L, R> {
     type Item = (L, R);
     type IntoIter = IntoIter<(L, R)>;
     fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+        self.pairs.into_iter()
+    }
+}
+
+mod test {
+    #[test]
+    fn size_and_membership() {
+        use crate::{InsertResult, NtoN};
+
+        let mut relation = NtoN::new();
+        assert!(relation.is_empty());
+        relation.insert("a", 1);
+        relation.insert("a", 2);
+        assert_eq!(relation.insert("b", 1), InsertResult::Inserted);
+        assert_eq!(relation.insert("a", 1), InsertResult::Existed);
+        assert_eq!(relation.len(), 3);
+        assert!(relation.contains(&"b", &1));
+        assert!(!relation.contains(&"b", &2));
+        let mut pairs = relation.iter().map(|(l, r)| (*l, *r)).collect::<Vec<_>>();
+        pairs.sort();
+        assert_eq!(pairs, vec![("a", 1), ("a", 2), ("b", 1)]);
+    }
+
+    #[test]
+    fn indices_follow_inserts_and_removes() {
+        use crate::NtoN;
+        use std::collections::HashSet;
+
+        let mut relation = NtoN::new();
+        relation.extend([("a", 1), ("a", 2), ("b", 1), ("b", 3), ("c", 3)]);
+        relation.remove(&"a", &1);
+        relation.remove_left(&"b");
+        relation.insert("d", 1);
+        relation.remove_right(&3);
+        assert_eq!(relation.get_left(&"a"), HashSet::from([2]));
+        assert_eq!(relation.get_right(&1), HashSet::from(["d"]));
+        assert!(relation.get_left(&"b").is_empty());
+        assert!(relation.get_right(&3).is_empty());
+        assert_eq!(relation.get_lefts(), HashSet::from(["a", "d"]));
+        assert_eq!(relation.get_rights(), HashSet::from([1, 2]));
+        assert_eq!((relation.count_left(&"a"), relation.count_left(&"b")), (1, 0));
+        assert_eq!(relation.count_right(&1), 1);
+        for (left, right) in relation.iter() {
+            assert!(relation.get_left(left).contains(right));
+            assert!(relation.get_right(right).contains(left));
+        }
+        let rebuilt = NtoN::from(HashSet::from(relation.clone()));
+        assert_eq!(rebuilt, relation);
+    }
+
+    #[test]
+    fn removals_return_the_removed_counterparts() {
+        use crate::NtoN;
+
+        let mut relation = NtoN::new();
+        relation.extend([("a", 1), ("a", 2), ("b", 1), ("c", 3)]);
+        let mut rights = relation.remove_left(&"a");
+        rights.sort();
+        assert_eq!(rights, vec![1, 2]);
+        assert_eq!(relation.remove_right(&1), vec!["b"]);
+        assert!(relation.remove_left(&"a").is_empty());
+        assert_eq!(relation.remove_right(&3), vec!["c"]);
+        assert!(relation.is_empty());
+    }
+
+    #[test]
+    fn rename_merges_into_existing_left() {
+        use crate::NtoN;
+        use std::collections::HashSet;
+
+        let mut relation = NtoN::new();
+        relation.extend([("old", 1), ("old", 2), ("new", 2), ("new", 3)]);
+        assert_eq!(relation.rename_left(&"old", "new"), 2);
+        assert_eq!(relation.get_left(&"new"), HashSet::from([1, 2, 3]));
+        assert!(relation.get_left(&"old").is_empty());
+        assert_eq!(relation.get_right(&2), HashSet::from(["new"]));
+        assert_eq!(relation.len(), 3);
+        assert_eq!(relation.rename_left(&"old", "new"), 0);
     }
 }
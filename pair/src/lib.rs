@@ -1,12 +1,19 @@
 use std::ops::Add;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(
-    Debug, Default, Deserialize, Serialize, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy,
-)]
+#[derive(Debug, Default, Serialize, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct Pair<T>(T, T);
 
+/// Deserialized pairs are always ordered (see `Pair::new_ordered`), so
+/// `[1, 2]` and `[2, 1]` read as the same pair.
+impl<'de, T: PartialOrd + Deserialize<'de>> Deserialize<'de> for Pair<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (a, b) = <(T, T)>::deserialize(deserializer)?;
+        Ok(Self::new_ordered(a, b))
+    }
+}
+
 impl<T:Add<Output = T> + Copy> Pair<T> {
     pub fn offset(self, offset: T) -> Self {
         let Self(a, b) = self;
@@ -20,6 +27,12 @@ impl<T> Pair<T> {
     }
 }
 
+impl<T> From<Pair<T>> for (T, T) {
+    fn from(Pair(a, b): Pair<T>) -> Self {
+        (a, b)
+    }
+}
+
 impl<T: PartialOrd> Pair<T> {
     pub fn new_ordered(a: T, b: T) -> Self {
         if a >= b {
@@ -92,4 +105,15 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn deserialized_pair_is_ordered() {
+        use crate::Pair;
+        use std::collections::HashSet;
+
+        let pair: Pair<usize> = serde_json::from_str("[1, 2]").unwrap();
+        assert_eq!(pair, Pair::new_ordered(2, 1));
+        let pairs = HashSet::from([Pair::new_ordered(1, 2)]);
+        assert!(pairs.contains(&pair));
+    }
 }
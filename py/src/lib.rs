@@ -0,0 +1,244 @@
+//! Python module `lme2`: molecules, layers and workspaces of lme-core, so
+//! the layered model can be driven from a notebook without the server.
+//!
+//! Errors of the core library are raised as `ValueError` naming the error,
+//! such as `NoSuchStack`.
+
+use std::collections::HashMap;
+
+use lme_core::{
+    entity::{Atom, Layer, Molecule},
+    error::LMECoreError,
+    operation::{Operation, OperationOutput},
+    Workspace, WorkspaceExport,
+};
+use nalgebra::{Point3, Transform3, Translation3};
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+fn core_error(err: LMECoreError) -> PyErr {
+    PyValueError::new_err(format!("{err:?}"))
+}
+
+fn json_error(err: serde_json::Error) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+fn unknown_format(format: &str) -> PyErr {
+    PyValueError::new_err(format!("Unknown format {format}"))
+}
+
+#[pyclass(name = "Molecule")]
+#[derive(Clone, Default)]
+struct PyMolecule(Molecule);
+
+#[pymethods]
+impl PyMolecule {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse `text` as a file of `format`: xyz, pdb, mol2, mol, zmat or cif.
+    #[staticmethod]
+    fn read(format: &str, text: &str) -> PyResult<Self> {
+        let molecule = match format {
+            "xyz" => Molecule::from_xyz(text),
+            "pdb" => Molecule::from_pdb(text),
+            "mol2" => Molecule::from_mol2(text),
+            "mol" => Molecule::from_molfile(text),
+            "zmat" => Molecule::from_zmatrix(text),
+            "cif" => Molecule::from_cif(text),
+            format => return Err(unknown_format(format)),
+        };
+        molecule.map(Self).map_err(core_error)
+    }
+
+    /// Every record of an SDF file.
+    #[staticmethod]
+    fn read_sdf(text: &str) -> PyResult<Vec<Self>> {
+        let molecules = Molecule::from_sdf(text).map_err(core_error)?;
+        Ok(molecules.into_iter().map(Self).collect())
+    }
+
+    /// The molecule as a file of `format`: xyz, pdb, mol2, mol, zmat,
+    /// smiles or cif. `title` is the comment, name or data block where the
+    /// format has one.
+    #[pyo3(signature = (format, title = ""))]
+    fn write(&self, format: &str, title: &str) -> PyResult<String> {
+        match format {
+            "xyz" => Ok(self.0.to_xyz(title)),
+            "pdb" => Ok(self.0.to_pdb()),
+            "mol2" => Ok(self.0.to_mol2(title)),
+            "mol" => Ok(self.0.to_molfile(title)),
+            "zmat" => self.0.to_zmatrix().map_err(core_error),
+            "smiles" => Ok(self.0.to_smiles()),
+            "cif" => self.0.to_cif(title).map_err(core_error),
+            format => Err(unknown_format(format)),
+        }
+    }
+
+    /// The molecule in the JSON form of the HTTP API.
+    #[staticmethod]
+    fn from_json(text: &str) -> PyResult<Self> {
+        serde_json::from_str(text).map(Self).map_err(json_error)
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&self.0).map_err(json_error)
+    }
+
+    /// Add an atom at the next free index and return that index.
+    fn add_atom(&mut self, element: usize, position: (f64, f64, f64)) -> usize {
+        let (x, y, z) = position;
+        let idx = self.0.next_index();
+        let atom = Atom::new(element, Point3::new(x, y, z));
+        let patch = Molecule::default().set_atoms(HashMap::from([(idx, Some(atom))]));
+        self.0 = Molecule::merge(self.0.clone(), patch);
+        idx
+    }
+
+    /// `(index, element, (x, y, z))` of each atom, by index.
+    fn atoms(&self) -> Vec<(usize, usize, (f64, f64, f64))> {
+        self.0
+            .atoms()
+            .into_iter()
+            .map(|(idx, atom)| {
+                let position = atom.position();
+                (idx, atom.element(), (position.x, position.y, position.z))
+            })
+            .collect()
+    }
+
+    /// `(a, b, order)` of each bond.
+    fn bonds(&self) -> Vec<(usize, usize, f64)> {
+        let mut bonds = self
+            .0
+            .bonds()
+            .iter()
+            .map(|(pair, order)| {
+                let (a, b) = (*pair).into();
+                (a, b, *order)
+            })
+            .collect::<Vec<_>>();
+        bonds.sort_by_key(|&(a, b, _)| (a, b));
+        bonds
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.atoms().len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Molecule({} atoms)", self.0.atoms().len())
+    }
+}
+
+#[pyclass(name = "Layer")]
+#[derive(Clone)]
+struct PyLayer(Layer);
+
+#[pymethods]
+impl PyLayer {
+    /// A layer overlaying the atoms and bonds of `molecule`.
+    #[staticmethod]
+    fn fill(molecule: &PyMolecule) -> Self {
+        Self(Layer::Fill(Box::new(molecule.0.clone())))
+    }
+
+    /// A layer moving every atom by `(x, y, z)`.
+    #[staticmethod]
+    fn translate(x: f64, y: f64, z: f64) -> Self {
+        let matrix = Translation3::new(x, y, z).to_homogeneous();
+        Self(Layer::Transform(Transform3::from_matrix_unchecked(matrix)))
+    }
+
+    /// The layer in the JSON form of the HTTP API, such as
+    /// `{"ReplaceElement": [1, 9]}`.
+    #[staticmethod]
+    fn from_json(text: &str) -> PyResult<Self> {
+        serde_json::from_str(text).map(Self).map_err(json_error)
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&self.0).map_err(json_error)
+    }
+
+    /// Overlay the layer on `molecule`.
+    fn apply(&self, molecule: &PyMolecule) -> PyResult<PyMolecule> {
+        let molecule = self.0.filter(molecule.0.clone()).map_err(core_error)?;
+        Ok(PyMolecule(molecule))
+    }
+}
+
+#[pyclass(name = "Workspace")]
+struct PyWorkspace(Workspace);
+
+#[pymethods]
+impl PyWorkspace {
+    #[new]
+    fn new(base: &PyMolecule) -> Self {
+        Self(Workspace::new(base.0.clone()))
+    }
+
+    /// Create `copies + 1` empty stacks and return the first one's index.
+    #[pyo3(signature = (copies = 0))]
+    fn create_stack(&mut self, copies: usize) -> PyResult<usize> {
+        match self.0.apply(Operation::CreateStack { copies }) {
+            Ok(OperationOutput::Stack(idx)) => Ok(idx),
+            Ok(output) => unreachable!("CreateStack returned {output:?}"),
+            Err(err) => Err(core_error(err)),
+        }
+    }
+
+    fn add_layers(&mut self, stack: usize, layers: Vec<PyLayer>) -> PyResult<()> {
+        let layers = layers.into_iter().map(|layer| layer.0).collect();
+        self.0
+            .apply(Operation::AddLayers {
+                stack_idx: stack,
+                layers,
+            })
+            .map(|_| ())
+            .map_err(core_error)
+    }
+
+    /// The molecule stack `stack` builds.
+    fn read(&self, stack: usize) -> PyResult<PyMolecule> {
+        self.0.read(stack).map(PyMolecule).map_err(core_error)
+    }
+
+    /// Apply any operation, given in the JSON form of the HTTP API, and
+    /// return its output as JSON.
+    fn apply(&mut self, operation: &str) -> PyResult<String> {
+        let operation: Operation = serde_json::from_str(operation).map_err(json_error)?;
+        let output = self.0.apply(operation).map_err(core_error)?;
+        serde_json::to_string(&output).map_err(json_error)
+    }
+
+    fn undo(&mut self, stack: usize) -> PyResult<()> {
+        self.0.undo(stack).map_err(core_error)
+    }
+
+    fn redo(&mut self, stack: usize) -> PyResult<()> {
+        self.0.redo(stack).map_err(core_error)
+    }
+
+    /// The workspace as an export, the JSON files the server saves.
+    fn export_json(&self) -> PyResult<String> {
+        let export = WorkspaceExport::try_from(&self.0).map_err(core_error)?;
+        serde_json::to_string(&export).map_err(json_error)
+    }
+
+    #[staticmethod]
+    fn import_json(text: &str) -> PyResult<Self> {
+        let export: WorkspaceExport = serde_json::from_str(text).map_err(json_error)?;
+        Workspace::try_from(&export).map(Self).map_err(core_error)
+    }
+}
+
+#[pymodule]
+fn lme2(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyMolecule>()?;
+    module.add_class::<PyLayer>()?;
+    module.add_class::<PyWorkspace>()?;
+    Ok(())
+}
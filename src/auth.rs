@@ -0,0 +1,378 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, Method, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+
+/// What the holder of a token may do, besides everything the roles before
+/// it may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read and export workspaces
+    Reader,
+    /// Edit, create and remove workspaces
+    Editor,
+    /// Save and load all workspaces, and manage the tokens
+    Admin,
+}
+
+impl Role {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Reader => "reader",
+            Self::Editor => "editor",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+/// API tokens and their roles. While there are none, requests need no
+/// token at all.
+pub type Tokens = Arc<RwLock<HashMap<String, Role>>>;
+
+/// Who a request is from, as recorded in audit trails: the role of its
+/// token and a fingerprint of it, such as `editor-5f3a09c1`, as the token
+/// itself is a secret.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Actor(pub String);
+
+impl Actor {
+    fn new(token: &str, role: Role) -> Self {
+        let mut hasher = DefaultHasher::new();
+        token.hash(&mut hasher);
+        Self(format!("{}-{:08x}", role.name(), hasher.finish() as u32))
+    }
+}
+
+/// Role needed for a request. Reads and exports need a reader, even when
+/// posted; the command channel needs an editor, though opened with a GET.
+fn required_role(method: &Method, path: &str) -> Role {
+    let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+    match segments.as_slice() {
+        ["admin", ..] | ["save"] | ["load"] => Role::Admin,
+        ["ws", _, "channel"] => Role::Editor,
+        _ if matches!(*method, Method::GET | Method::HEAD) => Role::Reader,
+        ["ws", _, "export"] | ["ws", _, "stack" | "stacks", _, "export", ..]
+            if *method == Method::POST =>
+        {
+            Role::Reader
+        }
+        _ => Role::Editor,
+    }
+}
+
+/// Why a caller was refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Denied {
+    /// The caller's token is missing or unknown
+    Unauthorized,
+    /// The caller's token is of a lesser role than this one
+    Forbidden(Role),
+}
+
+impl Denied {
+    pub fn message(&self) -> String {
+        match self {
+            Self::Unauthorized => "A valid API token is required".to_string(),
+            Self::Forbidden(required) => format!("Only {} tokens may do this", required.name()),
+        }
+    }
+}
+
+/// Who holds `token`, if it is of the `required` role or above. While no
+/// tokens are configured everyone may do anything, and is nobody in
+/// particular.
+pub async fn authorize(
+    tokens: &Tokens,
+    token: Option<&str>,
+    required: Role,
+) -> Result<Option<Actor>, Denied> {
+    let tokens = tokens.read().await;
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+    let token = token.map(str::trim).ok_or(Denied::Unauthorized)?;
+    let role = *tokens.get(token).ok_or(Denied::Unauthorized)?;
+    match role < required {
+        true => Err(Denied::Forbidden(required)),
+        false => Ok(Some(Actor::new(token, role))),
+    }
+}
+
+/// Refuse requests without a bearer token of the role they need, with 401
+/// for missing or unknown tokens and 403 for tokens of a lesser role.
+/// Accepted requests carry their `Actor` as an extension.
+async fn auth_middleware<B>(
+    State(tokens): State<Tokens>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let required = required_role(req.method(), req.uri().path());
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match authorize(&tokens, token, required).await {
+        Err(denied @ Denied::Unauthorized) => {
+            let error = ApiError::new(StatusCode::UNAUTHORIZED, "Unauthorized", denied.message());
+            ([(header::WWW_AUTHENTICATE, "Bearer")], error).into_response()
+        }
+        Err(denied @ Denied::Forbidden(_)) => {
+            ApiError::new(StatusCode::FORBIDDEN, "Forbidden", denied.message()).into_response()
+        }
+        Ok(actor) => {
+            if let Some(actor) = actor {
+                req.extensions_mut().insert(actor);
+            }
+            next.run(req).await
+        }
+    }
+}
+
+/// API tokens and their roles.
+#[utoipa::path(
+    get,
+    path = "/admin/tokens",
+    tag = "server",
+    responses((status = 200, description = "OK", body = BTreeMap<String, Role>))
+)]
+async fn list_tokens(State(tokens): State<Tokens>) -> Json<BTreeMap<String, Role>> {
+    let tokens = tokens.read().await;
+    Json(
+        tokens
+            .iter()
+            .map(|(token, role)| (token.clone(), *role))
+            .collect(),
+    )
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct NewToken {
+    role: Role,
+}
+
+/// Issue a random token of a role, returning it.
+#[utoipa::path(
+    post,
+    path = "/admin/tokens",
+    tag = "server",
+    request_body = NewToken,
+    responses((status = 200, description = "OK", body = String, content_type = "application/json"))
+)]
+async fn create_token(
+    State(tokens): State<Tokens>,
+    Json(NewToken { role }): Json<NewToken>,
+) -> Json<String> {
+    let token = nanoid::nanoid!(32);
+    tokens.write().await.insert(token.clone(), role);
+    Json(token)
+}
+
+/// Revoke a token. The last admin token can not be revoked, as the server
+/// would then have no one to manage its tokens, or, once no token is left,
+/// take requests without any.
+#[utoipa::path(
+    delete,
+    path = "/admin/tokens/{token}",
+    tag = "server",
+    params(("token" = String, Path, description = "Token")),
+    responses((status = 200, description = "OK"))
+)]
+async fn revoke_token(
+    State(tokens): State<Tokens>,
+    Path(token): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let mut tokens = tokens.write().await;
+    let role = tokens.get(&token).copied().ok_or_else(|| {
+        ApiError::new(StatusCode::NOT_FOUND, "NoSuchToken", "No such token")
+    })?;
+    let admins = tokens.values().filter(|role| **role == Role::Admin).count();
+    if role == Role::Admin && admins == 1 {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "LastAdminToken",
+            "The last admin token can not be revoked",
+        ));
+    }
+    tokens.remove(&token);
+    Ok(StatusCode::OK)
+}
+
+/// `router` behind the token check, along with the `/admin/tokens` routes
+/// managing `tokens`.
+pub fn authenticated(router: Router, tokens: Tokens) -> Router {
+    Router::new()
+        .route("/admin/tokens", get(list_tokens).post(create_token))
+        .route("/admin/tokens/:token", delete(revoke_token))
+        .fallback_service(router)
+        .layer(middleware::from_fn_with_state(
+            tokens.clone(),
+            auth_middleware,
+        ))
+        .with_state(tokens)
+}
+
+mod test {
+    #[test]
+    fn reads_and_exports_need_a_reader() {
+        use super::{required_role, Role};
+        use axum::http::Method;
+
+        let cases = [
+            (Method::GET, "/ws/test/stack/0", Role::Reader),
+            (Method::POST, "/ws/test/export", Role::Reader),
+            (
+                Method::POST,
+                "/ws/test/stacks/ethane/export/gaussian",
+                Role::Reader,
+            ),
+            (Method::POST, "/ws/test/stacks/export/undo", Role::Editor),
+            (Method::GET, "/ws/test/channel", Role::Editor),
+            (Method::DELETE, "/ws/test", Role::Editor),
+            (Method::POST, "/save", Role::Admin),
+            (Method::GET, "/admin/tokens", Role::Admin),
+        ];
+        for (method, path, role) in cases {
+            assert_eq!(required_role(&method, path), role, "{method} {path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_grant_their_role() {
+        use super::{authenticated, Role};
+        use crate::{
+            router,
+            test_util::{json, request, send, with_token},
+        };
+        use axum::http::{Method, StatusCode};
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        let tokens = HashMap::from([
+            ("reading".to_string(), Role::Reader),
+            ("editing".to_string(), Role::Editor),
+            ("managing".to_string(), Role::Admin),
+        ]);
+        let router = authenticated(
+            router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default()),
+            Arc::new(RwLock::new(tokens)),
+        );
+        let call = |method: Method, uri: &str, token: Option<&str>, body: &'static str| {
+            let request = request(method, uri, body);
+            let request = match token {
+                Some(token) => with_token(request, token),
+                None => request,
+            };
+            send(&router, request)
+        };
+        const BASE: &str = r#"{"atoms":{},"bonds":[],"groups":[]}"#;
+        let cases = [
+            (
+                Method::POST,
+                "/ws/test",
+                None,
+                BASE,
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                Method::POST,
+                "/ws/test",
+                Some("unknown"),
+                BASE,
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                Method::POST,
+                "/ws/test",
+                Some("reading"),
+                BASE,
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::POST,
+                "/ws/test",
+                Some("editing"),
+                BASE,
+                StatusCode::OK,
+            ),
+            (
+                Method::GET,
+                "/ws/test/stacks",
+                Some("reading"),
+                "",
+                StatusCode::OK,
+            ),
+            (
+                Method::GET,
+                "/admin/tokens",
+                Some("editing"),
+                "",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::DELETE,
+                "/admin/tokens/editing",
+                Some("managing"),
+                "",
+                StatusCode::OK,
+            ),
+            (
+                Method::DELETE,
+                "/admin/tokens/managing",
+                Some("managing"),
+                "",
+                StatusCode::CONFLICT,
+            ),
+            (
+                Method::DELETE,
+                "/ws/test",
+                Some("editing"),
+                "",
+                StatusCode::UNAUTHORIZED,
+            ),
+        ];
+        for (method, uri, token, body, status) in cases {
+            let response = call(method.clone(), uri, token, body).await;
+            assert_eq!(response.status(), status, "{method} {uri} {token:?}");
+        }
+
+        let response = call(
+            Method::POST,
+            "/admin/tokens",
+            Some("managing"),
+            r#"{"role":"editor"}"#,
+        );
+        let token: String = json(response.await).await;
+        let response = call(Method::DELETE, "/ws/test", Some(&token), "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // with another admin token, the first one may go
+        let response = call(
+            Method::POST,
+            "/admin/tokens",
+            Some("managing"),
+            r#"{"role":"admin"}"#,
+        );
+        let admin: String = json(response.await).await;
+        let response = call(Method::DELETE, "/admin/tokens/managing", Some(&admin), "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = call(Method::GET, "/admin/tokens", Some("managing"), "").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,373 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::{Subcommand, ValueEnum};
+use lme_core::{
+    entity::Molecule,
+    operation::{AuditEntry, Operation},
+    Workspace, WorkspaceExport,
+};
+use serde::Serialize;
+
+use crate::handler::write_atomically;
+
+/// Commands working on a workspace file rather than serving it, for batch
+/// jobs that can't keep a server running. `FILE` is a file written by
+/// `POST /save`, or by `POST /ws/:ws/export` for a single workspace.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Write a new workspace file holding one workspace, without stacks
+    Create {
+        /// Workspace file to write
+        file: PathBuf,
+        /// Chemical file of the base molecule [default: an empty molecule]
+        #[arg(long)]
+        base: Option<PathBuf>,
+        /// Format of the base file [default: from its extension]
+        #[arg(long)]
+        format: Option<Format>,
+        /// Name of the workspace
+        #[arg(short, long, default_value = "default")]
+        workspace: String,
+    },
+    /// Apply operations, in the JSON form of the HTTP API, to a workspace
+    /// and save it. Their outputs are printed as JSON, one per line.
+    /// Nothing is saved if any of them fails
+    Apply {
+        /// Workspace file
+        file: PathBuf,
+        /// JSON file of an operation or a list of operations, `-` for stdin
+        operations: PathBuf,
+        /// Name of the workspace, needed if the file holds several
+        #[arg(short, long)]
+        workspace: Option<String>,
+        /// File to save the workspace to [default: FILE]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print the molecule read from a stack as JSON
+    Resolve {
+        /// Workspace file
+        file: PathBuf,
+        /// Stack, by index or name
+        stack: String,
+        /// Name of the workspace, needed if the file holds several
+        #[arg(short, long)]
+        workspace: Option<String>,
+    },
+    /// Write the molecule read from a stack as a chemical file
+    Export {
+        /// Workspace file
+        file: PathBuf,
+        /// Stack, by index or name
+        stack: String,
+        /// Name of the workspace, needed if the file holds several
+        #[arg(short, long)]
+        workspace: Option<String>,
+        /// Format to write [default: from the extension of the output file]
+        #[arg(long)]
+        format: Option<Format>,
+        /// File to write to [default: stdout]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Chemical file formats, named as their usual extensions.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Xyz,
+    Pdb,
+    Mol,
+    Mol2,
+    Sdf,
+    Zmat,
+    Cif,
+    Smiles,
+}
+
+impl Format {
+    /// The format `--format` names, or else the extension of `path`.
+    fn of(format: Option<Format>, path: Option<&Path>) -> Result<Self, String> {
+        if let Some(format) = format {
+            return Ok(format);
+        }
+        let extension = path.and_then(Path::extension).and_then(|ext| ext.to_str());
+        let extension = extension.ok_or("No --format given nor a file extension to tell it")?;
+        Format::from_str(extension, true).map_err(|_| format!("Unknown format {extension}"))
+    }
+
+    fn read(self, text: &str) -> Result<Molecule, String> {
+        let molecule = match self {
+            Self::Json => return serde_json::from_str(text).map_err(|err| err.to_string()),
+            Self::Xyz => Molecule::from_xyz(text),
+            Self::Pdb => Molecule::from_pdb(text),
+            Self::Mol => Molecule::from_molfile(text),
+            Self::Mol2 => Molecule::from_mol2(text),
+            Self::Sdf => match Molecule::from_sdf(text).map(Vec::into_iter) {
+                Ok(mut records) => match (records.next(), records.next()) {
+                    (Some(molecule), None) => Ok(molecule),
+                    _ => return Err("The SDF file must hold a single record".to_string()),
+                },
+                Err(err) => Err(err),
+            },
+            Self::Zmat => Molecule::from_zmatrix(text),
+            Self::Cif => Molecule::from_cif(text),
+            Self::Smiles => return Err("SMILES can only be written".to_string()),
+        };
+        molecule.map_err(|err| format!("{err:?}"))
+    }
+
+    /// The molecule of stack `idx` called `name`, titled as the export
+    /// routes of the stack title it.
+    fn write(self, molecule: &Molecule, idx: usize, name: &str) -> Result<String, String> {
+        let text = match self {
+            Self::Json => return serde_json::to_string(molecule).map_err(|err| err.to_string()),
+            Self::Xyz => Ok(molecule.to_xyz(&format!("stack {idx}"))),
+            Self::Pdb => Ok(molecule.to_pdb()),
+            Self::Mol => Ok(molecule.to_molfile(name)),
+            Self::Mol2 => Ok(molecule.to_mol2(name)),
+            Self::Sdf => Ok(Molecule::to_sdf([(name, molecule)])),
+            Self::Zmat => molecule.to_zmatrix(),
+            Self::Cif => molecule.to_cif(&format!("stack_{idx}")),
+            Self::Smiles => Ok(molecule.to_smiles()),
+        };
+        text.map_err(|err| format!("{err:?}"))
+    }
+}
+
+/// The workspaces of a file, in either of the shapes the server writes.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum WorkspaceFile {
+    Export(Box<WorkspaceExport>),
+    Saved(HashMap<String, WorkspaceExport>),
+}
+
+impl WorkspaceFile {
+    async fn load(path: &Path) -> Result<Self, String> {
+        let content = tokio::fs::read(path)
+            .await
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        let invalid = |err: serde_json::Error| format!("Invalid {}: {err}", path.display());
+        let value: serde_json::Value = serde_json::from_slice(&content).map_err(invalid)?;
+        // only an export has a base molecule at the top
+        match value.get("base") {
+            Some(_) => serde_json::from_value(value).map(Self::Export),
+            None => serde_json::from_value(value).map(Self::Saved),
+        }
+        .map_err(invalid)
+    }
+
+    async fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_vec(self).map_err(|err| err.to_string())?;
+        write_atomically(path, &content)
+            .await
+            .map_err(|err| format!("Failed to write {}: {err}", path.display()))
+    }
+
+    /// The export of the workspace called `name`, which may be left out if
+    /// there is only one.
+    fn export(&mut self, name: Option<&str>) -> Result<&mut WorkspaceExport, String> {
+        let exports = match self {
+            Self::Export(export) => return Ok(export),
+            Self::Saved(exports) => exports,
+        };
+        if name.is_none() && exports.len() > 1 {
+            let mut names = exports.keys().map(String::as_str).collect::<Vec<_>>();
+            names.sort();
+            return Err(format!("Pick a workspace with --workspace: {}", names.join(", ")));
+        }
+        match name {
+            Some(name) => exports
+                .get_mut(name)
+                .ok_or_else(|| format!("No workspace {name} in the file")),
+            None => exports.values_mut().next().ok_or("No workspace in the file".to_string()),
+        }
+    }
+
+    fn workspace(&mut self, name: Option<&str>) -> Result<Workspace, String> {
+        Workspace::try_from(&*self.export(name)?).map_err(|err| format!("{err:?}"))
+    }
+}
+
+/// Index of `stack`, given by index or by name.
+fn stack_index(workspace: &Workspace, stack: &str) -> Result<usize, String> {
+    let idx = match stack.parse() {
+        Ok(idx) => idx,
+        Err(_) => workspace
+            .stack_index(stack)
+            .map_err(|_| format!("No stack {stack}"))?,
+    };
+    match idx < workspace.stacks() {
+        true => Ok(idx),
+        false => Err(format!("No stack {stack}")),
+    }
+}
+
+fn read_stack(workspace: &Workspace, stack: &str) -> Result<(Molecule, usize), String> {
+    let idx = stack_index(workspace, stack)?;
+    let molecule = workspace.read(idx).map_err(|err| format!("{err:?}"))?;
+    Ok((molecule, idx))
+}
+
+pub async fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::Create {
+            file,
+            base,
+            format,
+            workspace,
+        } => {
+            let base = match base {
+                Some(path) => {
+                    let text = tokio::fs::read_to_string(&path)
+                        .await
+                        .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+                    Format::of(format, Some(&path))?.read(&text)?
+                }
+                None => Molecule::default(),
+            };
+            let export = Workspace::new(base).snapshot().into_export();
+            let exports = HashMap::from([(workspace, export)]);
+            WorkspaceFile::Saved(exports).save(&file).await
+        }
+        Command::Apply {
+            file,
+            operations,
+            workspace: name,
+            output,
+        } => {
+            let mut text = String::new();
+            let read = match operations.as_os_str() == "-" {
+                true => std::io::stdin().read_to_string(&mut text).map(|_| text),
+                false => tokio::fs::read_to_string(&operations).await,
+            };
+            let text = read.map_err(|err| format!("Failed to read operations: {err}"))?;
+            let operations = match text.trim_start().starts_with('[') {
+                true => serde_json::from_str(&text),
+                false => serde_json::from_str(&text).map(|operation| vec![operation]),
+            };
+            let operations: Vec<Operation> =
+                operations.map_err(|err| format!("Invalid operations: {err}"))?;
+            let mut saved = WorkspaceFile::load(&file).await?;
+            let mut workspace = saved.workspace(name.as_deref())?;
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as u64);
+            let mut outputs = vec![];
+            for (position, operation) in operations.into_iter().enumerate() {
+                let change = operation.change();
+                let output = workspace
+                    .apply(operation.clone())
+                    .map_err(|err| format!("Operation {position} failed: {err:?}"))?;
+                workspace.record(AuditEntry {
+                    time,
+                    actor: None,
+                    stacks: change.stacks,
+                    kind: change.kind,
+                    operation: Some(operation),
+                });
+                outputs.push(output);
+            }
+            *saved.export(name.as_deref())? = workspace.snapshot().into_export();
+            saved.save(output.as_ref().unwrap_or(&file)).await?;
+            for output in outputs {
+                println!("{}", serde_json::to_string(&output).map_err(|err| err.to_string())?);
+            }
+            Ok(())
+        }
+        Command::Resolve {
+            file,
+            stack,
+            workspace,
+        } => {
+            let workspace = WorkspaceFile::load(&file).await?.workspace(workspace.as_deref())?;
+            let (molecule, _) = read_stack(&workspace, &stack)?;
+            println!("{}", serde_json::to_string(&molecule).map_err(|err| err.to_string())?);
+            Ok(())
+        }
+        Command::Export {
+            file,
+            stack,
+            workspace,
+            format,
+            output,
+        } => {
+            let workspace = WorkspaceFile::load(&file).await?.workspace(workspace.as_deref())?;
+            let format = Format::of(format, output.as_deref())?;
+            let (molecule, idx) = read_stack(&workspace, &stack)?;
+            let text = format.write(&molecule, idx, &workspace.stack_names()[idx])?;
+            match output {
+                Some(path) => tokio::fs::write(&path, text)
+                    .await
+                    .map_err(|err| format!("Failed to write {}: {err}", path.display())),
+                None => {
+                    print!("{text}");
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+mod test {
+    #[tokio::test]
+    async fn workspace_files_are_edited_offline() {
+        use super::{run, Command};
+        use clap::Parser;
+        use std::fs;
+
+        #[derive(Parser)]
+        struct Cli {
+            #[command(subcommand)]
+            command: Command,
+        }
+        let directory = std::env::temp_dir().join(format!("lme2-cli-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        let run = |args: &[&str]| {
+            let args = ["lme2-core"].into_iter().chain(args.iter().copied());
+            run(Cli::parse_from(args).command)
+        };
+        let water = "3\nwater\nO 0 0 0\nH 0.96 0 0\nH -0.24 0.93 0\n";
+        fs::write(path("water.xyz"), water).unwrap();
+        let operations = r#"[{"CreateNamedStack": {"name": "sulfide"}},
+            {"AddLayers": {"stack_idx": 0, "layers": [{"ReplaceElement": [8, 16]}]}}]"#;
+        fs::write(path("edit.json"), operations).unwrap();
+        let failing = r#"[{"CreateStack": {"copies": 0}}, {"RemoveStack": {"stack_idx": 7}}]"#;
+        fs::write(path("failing.json"), failing).unwrap();
+        fs::write(path("one.json"), r#"{"CloneStack": {"stack_idx": 0, "copies": 1}}"#).unwrap();
+
+        let saved = path("saved.json");
+        run(&["create", &saved, "--base", &path("water.xyz")]).await.unwrap();
+        run(&["apply", &saved, &path("edit.json")]).await.unwrap();
+        run(&["export", &saved, "sulfide", "-o", &path("out.pdb")]).await.unwrap();
+        let pdb = fs::read_to_string(path("out.pdb")).unwrap();
+        assert!(pdb.starts_with("HETATM    1  S1  UNL"));
+        assert_eq!(pdb.matches("HETATM").count(), 3);
+
+        let error = run(&["apply", &saved, &path("failing.json")]).await.unwrap_err();
+        assert!(error.starts_with("Operation 1 failed"));
+        let error = run(&["resolve", &saved, "1"]).await.unwrap_err();
+        assert_eq!(error, "No stack 1");
+
+        // a workspace exported on its own is edited the same way
+        let exports: serde_json::Value = serde_json::from_str(&fs::read_to_string(&saved).unwrap())
+            .unwrap();
+        assert_eq!(exports["default"]["audit"].as_array().unwrap().len(), 2);
+        fs::write(path("export.json"), exports["default"].to_string()).unwrap();
+        let single = path("export.json");
+        let output = ["apply", &single, &path("one.json"), "-o", &path("again.json")];
+        run(&output).await.unwrap();
+        let again: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path("again.json")).unwrap()).unwrap();
+        assert_eq!(again["stack_names"].as_array().unwrap().len(), 3);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use axum::http::{header, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Let pages of `origins` other than the server's own, such as
+/// `http://localhost:5173` or `*` for any origin, call it with `methods`
+/// and read the responses, `ETag` included. Requests from other origins
+/// pass without CORS headers, so browsers withhold the responses from
+/// them. Fails on invalid origins or methods.
+pub fn cors_layer(origins: &[String], methods: &[String]) -> Result<CorsLayer, String> {
+    let origins = match origins.iter().any(|origin| origin == "*") {
+        true => AllowOrigin::any(),
+        false => AllowOrigin::list(
+            origins
+                .iter()
+                .map(|origin| {
+                    HeaderValue::from_str(origin).map_err(|_| format!("Invalid origin {origin}"))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+    };
+    let methods = methods
+        .iter()
+        .map(|method| {
+            Method::from_bytes(method.trim().to_uppercase().as_bytes())
+                .map_err(|_| format!("Invalid method {method}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE, header::IF_MATCH])
+        .expose_headers([header::ETAG])
+        .max_age(Duration::from_secs(86400)))
+}
+
+mod test {
+    #[tokio::test]
+    async fn allowed_origins_get_cors_headers() {
+        use super::cors_layer;
+        use axum::{
+            body::Body,
+            http::{header, Method, Request, StatusCode},
+            routing::get,
+            Router,
+        };
+        use tower::ServiceExt;
+
+        let origins = ["http://localhost:5173".to_string()];
+        let cors = cors_layer(&origins, &["get".to_string(), "put".to_string()]).unwrap();
+        let router = Router::new().route("/", get(|| async { "ok" })).layer(cors);
+        let request = |method: Method, origin: &str| {
+            Request::builder()
+                .method(method)
+                .uri("/")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let preflight = request(Method::OPTIONS, "http://localhost:5173");
+        let response = router.clone().oneshot(preflight).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS],
+            "GET,PUT"
+        );
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_MAX_AGE], "86400");
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "http://localhost:5173"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "http://localhost:5173"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_EXPOSE_HEADERS], "etag");
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::OPTIONS, "http://elsewhere.org"))
+            .await
+            .unwrap();
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(cors_layer(&origins, &["NOT A METHOD".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,389 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use lme_core::error::{LMECoreError, LayerError};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use unique_value_map::UniqueValueError;
+use utoipa::ToSchema;
+
+/// An error as sent to clients: a status and a JSON body like
+/// `{"code":"MissingAtoms","message":"Stack has no atoms [4]","atoms":[4]}`.
+/// Errors of the core library are coded by their variant name.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    body: Box<ErrorBody>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(example = json!({
+    "code": "MissingAtoms",
+    "message": "Stack has no atoms [4]",
+    "atoms": [4]
+}))]
+pub(crate) struct ErrorBody {
+    /// Name of the error, such as `NoSuchStack`
+    code: String,
+    message: String,
+    /// Atoms the error is about
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    atoms: Vec<usize>,
+    /// Bonds the error is about, as pairs of atoms
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bonds: Vec<[usize; 2]>,
+    /// Why each failed edit of a batch failed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    edits: Vec<EditError>,
+    /// Atoms that would share each name given to more than one of them
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    names: BTreeMap<String, Vec<usize>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct EditError {
+    edit: usize,
+    #[serde(flatten)]
+    error: ErrorBody,
+}
+
+impl ApiError {
+    /// An error not coming from the core library, such as a request the
+    /// server can't route.
+    pub fn new(status: StatusCode, code: &str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: Box::new(ErrorBody {
+                code: code.to_string(),
+                message: message.into(),
+                atoms: vec![],
+                bonds: vec![],
+                edits: vec![],
+                names: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
+
+/// Give every other client error, mostly the plain text rejections of
+/// axum's `Json`, `Query` and `Path` extractors, an `ErrorBody` coded by
+/// its status, such as `UnprocessableEntity`, with the text as message.
+pub async fn structured_rejections(response: Response) -> Response {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|kind| kind.as_bytes().starts_with(b"application/json"));
+    if !response.status().is_client_error() || is_json {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let reason = parts.status.canonical_reason().unwrap_or("Client Error");
+    let message = match hyper::body::to_bytes(body).await {
+        Ok(text) if !text.is_empty() => String::from_utf8_lossy(&text).into_owned(),
+        _ => reason.to_string(),
+    };
+    let code = reason.split_whitespace().collect::<String>();
+    let mut structured = ApiError::new(parts.status, &code, message).into_response();
+    parts.headers.remove(header::CONTENT_TYPE);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    structured.headers_mut().extend(parts.headers);
+    structured
+}
+
+/// Name of the variant `value` serializes as, externally tagged.
+fn variant_name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => Some(name),
+        Ok(serde_json::Value::Object(variant)) => variant.keys().next().cloned(),
+        _ => None,
+    }
+    .unwrap_or_else(|| "Unknown".to_string())
+}
+
+/// Atoms and bonds an error names.
+fn subjects(err: &LMECoreError) -> (Vec<usize>, Vec<[usize; 2]>) {
+    match err {
+        LMECoreError::UnknownAtom(atom) | LMECoreError::NotTerminal(atom) => (vec![*atom], vec![]),
+        LMECoreError::LayerError(LayerError::RemapCollision(atom)) => (vec![*atom], vec![]),
+        LMECoreError::MissingAtoms(atoms) | LMECoreError::SharedAtoms(atoms) => {
+            (atoms.clone(), vec![])
+        }
+        LMECoreError::NotBonded(a, b) | LMECoreError::RingBond(a, b) => (vec![], vec![[*a, *b]]),
+        LMECoreError::LayerRejected(_, err) | LMECoreError::ReplayFailed(_, err) => subjects(err),
+        _ => (vec![], vec![]),
+    }
+}
+
+fn describe_layer_error(err: &LayerError) -> (StatusCode, String) {
+    match err {
+        LayerError::UnknownPlugin(name) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("No plugin {name}"),
+        ),
+        LayerError::InvalidPluginName(name) => (
+            StatusCode::BAD_REQUEST,
+            format!("Plugin name {name} is not a file of the plugin directory"),
+        ),
+        LayerError::PluginRejected(Some(code)) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Plugin exited with code {code}"),
+        ),
+        LayerError::PluginRejected(None) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Plugin was terminated by a signal".to_string(),
+        ),
+        LayerError::RemapCollision(atom) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Remap sends two atoms to {atom}"),
+        ),
+        LayerError::InvalidPluginOutput(message) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Plugin output is not a molecule: {message}"),
+        ),
+        LayerError::PluginIo(message) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Talking to the plugin failed: {message}"),
+        ),
+    }
+}
+
+fn describe(err: &LMECoreError) -> (StatusCode, String) {
+    let message = |status, message: &str| (status, message.to_string());
+    match err {
+        LMECoreError::NoSuchStack => message(StatusCode::NOT_FOUND, "No such stack"),
+        LMECoreError::NoSuchAtom => message(StatusCode::NOT_FOUND, "No such atom"),
+        LMECoreError::UnknownAtom(index) => (
+            StatusCode::NOT_FOUND,
+            format!("No stack has an atom {index}"),
+        ),
+        LMECoreError::AtomCountMismatch(index) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Stack {index} has a different atom count"),
+        ),
+        LMECoreError::IncompatibleStacks(stacks) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Stacks {stacks:?} have a different atom count"),
+        ),
+        LMECoreError::DuplicatedName(name) => (
+            StatusCode::CONFLICT,
+            format!("Atom name {name} is already in use"),
+        ),
+        LMECoreError::NotTerminal(atom) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Atom {atom} is not bonded to exactly one atom"),
+        ),
+        LMECoreError::NoSuchName(name) => {
+            (StatusCode::NOT_FOUND, format!("No atom is named {name}"))
+        }
+        LMECoreError::NoSuchFragment(name) => {
+            (StatusCode::NOT_FOUND, format!("No fragment {name}"))
+        }
+        LMECoreError::InvalidFragment(message)
+        | LMECoreError::InvalidSymmetry(message)
+        | LMECoreError::InvalidTemplate(message) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, message.clone())
+        }
+        LMECoreError::NotEnoughMatchedAtoms(count) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Only {count} atoms are matched, too few to superpose the stacks"),
+        ),
+        LMECoreError::MeasureArity(count) => (
+            StatusCode::BAD_REQUEST,
+            format!("Can not measure {count} atoms, give 2, 3 or 4"),
+        ),
+        LMECoreError::NotBonded(a, b) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Atoms {a} and {b} are not bonded"),
+        ),
+        LMECoreError::RingBond(a, b) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Bond {a}-{b} is in a ring"),
+        ),
+        LMECoreError::InvalidTarget(value) => (
+            StatusCode::BAD_REQUEST,
+            format!("Target value {value} is out of range"),
+        ),
+        LMECoreError::UnknownElement(symbol) => (
+            StatusCode::BAD_REQUEST,
+            format!("No element has the symbol {symbol}"),
+        ),
+        LMECoreError::InvalidMultiplicity(multiplicity) => (
+            StatusCode::BAD_REQUEST,
+            format!("Spin multiplicity {multiplicity} is below 1"),
+        ),
+        LMECoreError::InvalidGaussianJob(message) => (StatusCode::BAD_REQUEST, message.clone()),
+        LMECoreError::NoSuchTemplate(name) => {
+            (StatusCode::NOT_FOUND, format!("No template {name}"))
+        }
+        LMECoreError::StackChanged(idx) => (
+            StatusCode::CONFLICT,
+            format!("Stack {idx} changed in the meantime"),
+        ),
+        LMECoreError::BatchFailed(failures) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Failed edits of the batch: {}", failures.len()),
+        ),
+        LMECoreError::ZeroNormal => message(StatusCode::BAD_REQUEST, "Mirror plane normal is zero"),
+        LMECoreError::NoSuchLayer(position) => (
+            StatusCode::NOT_FOUND,
+            format!("Stack has no layer {position}"),
+        ),
+        LMECoreError::NoSuchGroup(group) => (
+            StatusCode::NOT_FOUND,
+            format!("Group {group} has no members"),
+        ),
+        LMECoreError::LayerRejected(position, err) => (
+            StatusCode::BAD_REQUEST,
+            format!("Layer {position} cannot be applied: {}", describe(err).1),
+        ),
+        LMECoreError::MissingAtoms(atoms) => (
+            StatusCode::BAD_REQUEST,
+            format!("Stack has no atoms {atoms:?}"),
+        ),
+        LMECoreError::SharedAtoms(atoms) => (
+            StatusCode::CONFLICT,
+            format!("Atoms {atoms:?} are named or grouped and other stacks have them"),
+        ),
+        LMECoreError::EmptyStack(index) => {
+            (StatusCode::CONFLICT, format!("Stack {index} has no layers"))
+        }
+        LMECoreError::MalformedTree => message(
+            StatusCode::BAD_REQUEST,
+            "Stack trees must hold every stack index exactly once",
+        ),
+        LMECoreError::InvalidPermutation(count) => (
+            StatusCode::BAD_REQUEST,
+            format!("Stack order must hold each of the {count} stack indices exactly once"),
+        ),
+        LMECoreError::NothingToUndo => message(StatusCode::NOT_FOUND, "Nothing to undo"),
+        LMECoreError::NothingToRedo => message(StatusCode::NOT_FOUND, "Nothing to redo"),
+        LMECoreError::NoCommonPrefix => message(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Selected stacks share no common layer prefix",
+        ),
+        LMECoreError::DisconnectedFragments => message(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Molecule consists of disconnected fragments",
+        ),
+        LMECoreError::NoCell => message(StatusCode::UNPROCESSABLE_ENTITY, "No unit cell is set"),
+        LMECoreError::SingularCell => message(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Unit cell vectors are linearly dependent",
+        ),
+        LMECoreError::ParseError(line, message) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Line {line}: {message}"),
+        ),
+        LMECoreError::UnknownProperty(name) => {
+            (StatusCode::BAD_REQUEST, format!("Unknown property {name}"))
+        }
+        LMECoreError::ReplayFailed(position, err) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Operation {position} failed to replay: {}", describe(err).1),
+        ),
+        LMECoreError::LayerError(err) => describe_layer_error(err),
+    }
+}
+
+/// Atom names held by more than one atom, as found in bulk imports.
+impl From<UniqueValueError<usize, String>> for ApiError {
+    fn from(err: UniqueValueError<usize, String>) -> Self {
+        let names = err
+            .conflicts
+            .into_iter()
+            .map(|(name, mut atoms)| {
+                atoms.sort();
+                (name, atoms)
+            })
+            .collect::<BTreeMap<_, _>>();
+        let shared = names
+            .iter()
+            .map(|(name, atoms)| format!("{name} by atoms {atoms:?}"))
+            .collect::<Vec<_>>();
+        let message = format!("Atom names would be shared: {}", shared.join(", "));
+        let mut error = Self::new(StatusCode::CONFLICT, "DuplicatedNames", message);
+        error.body.names = names;
+        error
+    }
+}
+
+impl From<LMECoreError> for ApiError {
+    fn from(err: LMECoreError) -> Self {
+        let (status, message) = describe(&err);
+        let code = match &err {
+            LMECoreError::LayerError(err) => variant_name(err),
+            err => variant_name(err),
+        };
+        let (atoms, bonds) = subjects(&err);
+        let edits = match err {
+            LMECoreError::BatchFailed(failures) => failures
+                .into_iter()
+                .map(|failure| EditError {
+                    edit: failure.edit,
+                    error: *ApiError::from(failure.error).body,
+                })
+                .collect(),
+            _ => vec![],
+        };
+        Self {
+            status,
+            body: Box::new(ErrorBody {
+                code,
+                message,
+                atoms,
+                bonds,
+                edits,
+                names: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+mod test {
+    #[test]
+    fn core_errors_name_their_code_and_atoms() {
+        use crate::error::ApiError;
+        use axum::http::StatusCode;
+        use lme_core::{error::LMECoreError, operation::BatchFailure};
+        use serde_json::json;
+
+        let rejected =
+            LMECoreError::LayerRejected(2, Box::new(LMECoreError::MissingAtoms(vec![4])));
+        let error = ApiError::from(rejected);
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            serde_json::to_value(&error.body).unwrap(),
+            json!({
+                "code": "LayerRejected",
+                "message": "Layer 2 cannot be applied: Stack has no atoms [4]",
+                "atoms": [4],
+            })
+        );
+
+        let failed = LMECoreError::BatchFailed(vec![BatchFailure {
+            edit: 1,
+            error: LMECoreError::RingBond(0, 5),
+        }]);
+        assert_eq!(
+            serde_json::to_value(&ApiError::from(failed).body).unwrap(),
+            json!({
+                "code": "BatchFailed",
+                "message": "Failed edits of the batch: 1",
+                "edits": [{
+                    "edit": 1,
+                    "code": "RingBond",
+                    "message": "Bond 0-5 is in a ring",
+                    "bonds": [[0, 5]],
+                }],
+            })
+        );
+    }
+}
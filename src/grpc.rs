@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use lme2_grpc::{core_status, Workspaces};
+use lme_core::{
+    entity::Molecule,
+    operation::{Operation, OperationOutput},
+    Workspace,
+};
+use tonic::Status;
+
+use crate::{
+    auth::{authorize, Actor, Denied, Role, Tokens},
+    handler::new_handle,
+    journal::Journal,
+    ServerState, WorkspaceAccessor,
+};
+
+/// The workspaces of the server as the gRPC service sees them. Calls need
+/// the same tokens as the HTTP routes doing the same, and edits go through
+/// `WorkspaceHandle::lock` like theirs, so they are journaled, announced
+/// and recorded in the audit trail alike.
+pub struct GrpcWorkspaces {
+    pub state: ServerState,
+    pub tokens: Tokens,
+    pub history_depth: usize,
+    pub journal: Option<Arc<Journal>>,
+}
+
+impl GrpcWorkspaces {
+    async fn caller(&self, token: Option<&str>, required: Role) -> Result<Option<String>, Status> {
+        match authorize(&self.tokens, token, required).await {
+            Ok(actor) => Ok(actor.map(|Actor(actor)| actor)),
+            Err(denied @ Denied::Unauthorized) => Err(Status::unauthenticated(denied.message())),
+            Err(denied @ Denied::Forbidden(_)) => Err(Status::permission_denied(denied.message())),
+        }
+    }
+
+    async fn workspace(&self, name: &str) -> Result<WorkspaceAccessor, Status> {
+        let workspace = self.state.read().await.get(name).cloned();
+        workspace.ok_or_else(|| Status::not_found(format!("No workspace {name}")))
+    }
+}
+
+#[tonic::async_trait]
+impl Workspaces for GrpcWorkspaces {
+    async fn create(&self, token: Option<&str>, name: &str, base: Molecule) -> Result<(), Status> {
+        self.caller(token, Role::Editor).await?;
+        let mut state = self.state.write().await;
+        if state.contains_key(name) {
+            return Err(Status::already_exists(format!("Workspace {name} exists")));
+        }
+        let workspace = Workspace::new(base).with_history_depth(self.history_depth);
+        let handle = new_handle(name, workspace, self.journal.as_ref());
+        state.insert(name.to_string(), handle);
+        Ok(())
+    }
+
+    async fn apply(
+        &self,
+        token: Option<&str>,
+        name: &str,
+        operation: Operation,
+    ) -> Result<OperationOutput, Status> {
+        let actor = self.caller(token, Role::Editor).await?;
+        let workspace = self.workspace(name).await?.for_actor(actor);
+        let output = workspace.lock().await.apply(operation);
+        output.map_err(core_status)
+    }
+
+    async fn read(
+        &self,
+        token: Option<&str>,
+        name: &str,
+        start: usize,
+        range: usize,
+    ) -> Result<Vec<Molecule>, Status> {
+        self.caller(token, Role::Reader).await?;
+        let workspace = self.workspace(name).await?;
+        let workspace = workspace.read().await;
+        let end = start
+            .checked_add(range)
+            .ok_or_else(|| Status::invalid_argument("Too many stacks"))?;
+        let molecules: Result<_, _> = (start..end).map(|idx| workspace.read(idx)).collect();
+        molecules.map_err(core_status)
+    }
+}
+
+mod test {
+    #[tokio::test]
+    async fn edits_over_grpc_are_authorized_and_audited() {
+        use super::GrpcWorkspaces;
+        use crate::auth::Role;
+        use lme2_grpc::{
+            proto::{
+                layer, lme_client::LmeClient, AddLayersRequest, Atom, CreateStackRequest,
+                CreateWorkspaceRequest, Layer, Molecule, StackRequest, Vector,
+            },
+            LmeService,
+        };
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::{net::TcpListener, sync::RwLock};
+        use tokio_stream::wrappers::TcpListenerStream;
+        use tonic::{Code, Request};
+
+        let state = Arc::new(RwLock::new(HashMap::new()));
+        let tokens = HashMap::from([("editing".to_string(), Role::Editor)]);
+        let service = GrpcWorkspaces {
+            state: state.clone(),
+            tokens: Arc::new(RwLock::new(tokens)),
+            history_depth: 4,
+            journal: None,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(LmeService::new(service).into_server())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let mut client = LmeClient::connect(format!("http://{address}")).await.unwrap();
+        fn authorized<T>(message: T) -> Request<T> {
+            let mut request = Request::new(message);
+            request
+                .metadata_mut()
+                .insert("authorization", "Bearer editing".parse().unwrap());
+            request
+        }
+
+        let create = CreateWorkspaceRequest {
+            workspace: "ws".to_string(),
+            base: None,
+        };
+        let refused = client.create_workspace(create.clone()).await.unwrap_err();
+        assert_eq!(refused.code(), Code::Unauthenticated);
+        client.create_workspace(authorized(create)).await.unwrap();
+        let stack = CreateStackRequest {
+            workspace: "ws".to_string(),
+            copies: 1,
+        };
+        client.create_stack(authorized(stack)).await.unwrap();
+        let oxygen = Atom {
+            index: 0,
+            element: 8,
+            position: Some(Vector { x: 0., y: 0., z: 1. }),
+        };
+        let fill = Layer {
+            layer: Some(layer::Layer::Fill(Molecule {
+                atoms: vec![oxygen],
+                removed: vec![],
+                bonds: vec![],
+            })),
+        };
+        let layers = AddLayersRequest {
+            workspace: "ws".to_string(),
+            stack: 0,
+            layers: vec![fill],
+        };
+        client.add_layers(authorized(layers)).await.unwrap();
+
+        let read = StackRequest {
+            workspace: "ws".to_string(),
+            stack: 0,
+        };
+        let molecule = client.read_stack(authorized(read)).await.unwrap().into_inner();
+        assert_eq!(molecule.atoms.len(), 1);
+        assert_eq!(molecule.atoms[0].element, 8);
+
+        let workspace = state.read().await["ws"].clone();
+        let workspace = workspace.read().await;
+        let kinds = workspace.audit().iter().map(|entry| entry.kind.as_str());
+        assert_eq!(kinds.collect::<Vec<_>>(), ["CreateStack", "AddLayers"]);
+        assert!(workspace.audit().iter().all(|entry| entry
+            .actor
+            .as_deref()
+            .is_some_and(|actor| actor.starts_with("editor-"))));
+    }
+}
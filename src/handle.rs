@@ -0,0 +1,458 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use lme_core::{
+    error::LMECoreError,
+    operation::{AuditEntry, Change, Operation, OperationOutput},
+    Workspace, WorkspaceSnapshot,
+};
+use unique_value_map::UniqueValueMap;
+use n_to_n::NtoN;
+use tokio::sync::{broadcast, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::journal::{JournalSlot, Record};
+
+/// Number of changes a slow watcher may fall behind before it misses some.
+const CHANGE_CAPACITY: usize = 256;
+
+/// A workspace behind its lock, together with the channel announcing its
+/// changes to watchers. Edits take the lock alone with `lock`, while
+/// requests that only read share it with `read`.
+pub struct WorkspaceHandle {
+    workspace: Arc<RwLock<Workspace>>,
+    changes: broadcast::Sender<Change>,
+    stack: Option<StackAccess>,
+    /// Who the request is from, recorded in the audit trail
+    actor: Option<String>,
+    journal: Option<Arc<JournalSlot>>,
+}
+
+/// The stack a request is about, the version of it the client expects,
+/// and the version the request saw.
+struct StackAccess {
+    idx: usize,
+    expected: std::sync::Mutex<Option<u64>>,
+    seen: Arc<std::sync::Mutex<Option<u64>>>,
+}
+
+impl WorkspaceHandle {
+    pub fn new(workspace: Workspace) -> Self {
+        Self {
+            workspace: Arc::new(RwLock::new(workspace)),
+            changes: broadcast::channel(CHANGE_CAPACITY).0,
+            stack: None,
+            actor: None,
+            journal: None,
+        }
+    }
+
+    /// The same workspace, with its changes journaled to `slot`.
+    pub fn journaled(self, slot: Arc<JournalSlot>) -> Self {
+        Self {
+            journal: Some(slot),
+            ..self
+        }
+    }
+
+    /// Stop journaling the workspace, as it is removed.
+    pub fn retire(&self) {
+        if let Some(slot) = &self.journal {
+            slot.remove();
+        }
+    }
+
+    /// A snapshot of the workspace, with the sequence number of the last
+    /// record of its journal, for autosaving it. `None` if it was removed.
+    pub async fn journal_snapshot(&self) -> Option<(u64, WorkspaceSnapshot)> {
+        let workspace = self.workspace.read().await;
+        let seq = match &self.journal {
+            Some(slot) => slot.seq()?,
+            None => 0,
+        };
+        Some((seq, workspace.snapshot()))
+    }
+
+    /// The same workspace, for a request from `actor`. Operations applied
+    /// through `lock` are recorded in the audit trail as theirs.
+    pub fn for_actor(&self, actor: Option<String>) -> Self {
+        Self {
+            workspace: self.workspace.clone(),
+            changes: self.changes.clone(),
+            stack: None,
+            actor,
+            journal: self.journal.clone(),
+        }
+    }
+
+    /// The same workspace, for a request about stack `idx`. With an
+    /// `expected` version, operations applied through `lock` fail with
+    /// `StackChanged` when they edit the stack and it has another version.
+    /// The version of the stack the request saw last is put in `seen`.
+    pub fn for_stack(
+        &self,
+        idx: usize,
+        expected: Option<u64>,
+        seen: Arc<std::sync::Mutex<Option<u64>>>,
+    ) -> Self {
+        Self {
+            workspace: self.workspace.clone(),
+            changes: self.changes.clone(),
+            stack: Some(StackAccess {
+                idx,
+                expected: std::sync::Mutex::new(expected),
+                seen,
+            }),
+            actor: self.actor.clone(),
+            journal: self.journal.clone(),
+        }
+    }
+
+    /// Lock the workspace for an edit. Operations applied through the
+    /// guard are announced once it is dropped, i.e. after the lock is
+    /// released.
+    pub async fn lock(&self) -> WorkspaceGuard<'_> {
+        let workspace = self.workspace.write().await;
+        let names = (self.changes.receiver_count() > 0)
+            .then(|| (workspace.atom_names.clone(), workspace.groups.clone()));
+        let guard = WorkspaceGuard {
+            logged: workspace.log().total(),
+            workspace: Some(workspace),
+            replaced: None,
+            changes: &self.changes,
+            stack: self.stack.as_ref(),
+            actor: self.actor.as_deref(),
+            journal: self.journal.as_deref(),
+            names,
+        };
+        guard.see_version();
+        guard
+    }
+
+    /// Lock the workspace for reading, along with other readers.
+    pub async fn read(&self) -> RwLockReadGuard<'_, Workspace> {
+        let workspace = self.workspace.read().await;
+        if let Some(access) = &self.stack {
+            access.see_version(&workspace);
+        }
+        workspace
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Change> {
+        self.changes.subscribe()
+    }
+}
+
+impl StackAccess {
+    fn see_version(&self, workspace: &Workspace) {
+        let version = workspace.stack_version(self.idx).ok();
+        *self.seen.lock().expect("Version lock is never poisoned") = version;
+    }
+}
+
+pub struct WorkspaceGuard<'a> {
+    workspace: Option<RwLockWriteGuard<'a, Workspace>>,
+    /// Log length when the lock was taken
+    logged: usize,
+    replaced: Option<String>,
+    changes: &'a broadcast::Sender<Change>,
+    stack: Option<&'a StackAccess>,
+    actor: Option<&'a str>,
+    journal: Option<&'a JournalSlot>,
+    /// Names and groups when the lock was taken, kept while someone is
+    /// watching to tell them which changed
+    names: Option<(UniqueValueMap<usize, String>, NtoN<String, usize>)>,
+}
+
+impl WorkspaceGuard<'_> {
+    /// `Workspace::apply`, first checking the version of the stack of the
+    /// request if the operation edits it and the client expects a version.
+    /// The expectation holds for the version the request itself leaves
+    /// behind, so a request may apply several operations.
+    pub fn apply(&mut self, operation: Operation) -> Result<OperationOutput, LMECoreError> {
+        let Some(access) = self.stack else {
+            return Workspace::apply(self, operation);
+        };
+        let mut expected = access
+            .expected
+            .lock()
+            .expect("Version lock is never poisoned");
+        if let Some(version) = *expected {
+            if operation.change().stacks.contains(&access.idx)
+                && self.stack_version(access.idx).ok() != Some(version)
+            {
+                return Err(LMECoreError::StackChanged(access.idx));
+            }
+        }
+        let output = Workspace::apply(self, operation)?;
+        if expected.is_some() {
+            *expected = self.stack_version(access.idx).ok();
+        }
+        drop(expected);
+        self.see_version();
+        Ok(output)
+    }
+
+    fn see_version(&self) {
+        if let Some(access) = self.stack {
+            access.see_version(self);
+        }
+    }
+
+    /// Swap in a whole new workspace (import, replay). Watchers get a single
+    /// change of this `kind` instead of the operations in its log, and the
+    /// audit trail of the workspace replaced is kept with one entry of
+    /// this `kind` added.
+    pub fn replace(&mut self, workspace: Workspace, kind: &str) {
+        self.logged = workspace.log().total();
+        let audit = self.take_audit();
+        **self = workspace.with_audit(audit);
+        self.replaced = Some(kind.to_string());
+    }
+}
+
+impl Deref for WorkspaceGuard<'_> {
+    type Target = Workspace;
+
+    fn deref(&self) -> &Self::Target {
+        self.workspace
+            .as_ref()
+            .expect("Guard holds the lock until dropped")
+    }
+}
+
+impl DerefMut for WorkspaceGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.workspace
+            .as_mut()
+            .expect("Guard holds the lock until dropped")
+    }
+}
+
+impl Drop for WorkspaceGuard<'_> {
+    fn drop(&mut self) {
+        let mut workspace = self.workspace.take().expect("Guard is dropped once");
+        let log = workspace.log();
+        let fresh = log
+            .total()
+            .saturating_sub(self.logged)
+            .min(log.operations().len());
+        let mut applied = log
+            .operations()
+            .iter()
+            .skip(log.operations().len() - fresh)
+            .map(|operation| (operation.change(), Some(operation.clone())))
+            .collect::<Vec<_>>();
+        let replaced = self.replaced.is_some();
+        if let Some(kind) = self.replaced.take() {
+            applied.push((Change::new(kind, vec![]), None));
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        let mut entries = vec![];
+        for (change, operation) in &applied {
+            let entry = AuditEntry {
+                time,
+                actor: self.actor.map(str::to_string),
+                stacks: change.stacks.clone(),
+                kind: change.kind.clone(),
+                operation: operation.clone(),
+            };
+            workspace.record(entry.clone());
+            entries.push(entry);
+        }
+        // journaled before unlocking, in the order the changes were made;
+        // a replaced workspace is journaled whole, audit trail included
+        match self.journal {
+            Some(slot) if replaced => {
+                let export = workspace.snapshot().into_export();
+                slot.append(Record::Restored(Box::new(export)));
+            }
+            Some(slot) => entries
+                .into_iter()
+                .for_each(|entry| slot.append(Record::Applied(Box::new(entry)))),
+            None => {}
+        }
+        // watchers get the stacks as the request left them, and the names
+        // and groups it changed along with its last change
+        let watched = self.changes.receiver_count() > 0;
+        let mut changes = applied
+            .into_iter()
+            .map(|(change, _)| match watched {
+                true => change.with_molecules(&workspace),
+                false => change,
+            })
+            .collect::<Vec<_>>();
+        if let (Some((names, groups)), Some(last)) = (self.names.take(), changes.pop()) {
+            changes.push(last.with_names(&names, &groups, &workspace));
+        }
+        drop(workspace);
+        // sending only fails when nobody is watching
+        changes.into_iter().for_each(|change| {
+            let _ = self.changes.send(change);
+        });
+    }
+}
+
+mod test {
+    #[tokio::test]
+    async fn expected_version_guards_stack_edits() {
+        use crate::handle::WorkspaceHandle;
+        use lme_core::{
+            entity::{Atom, Molecule},
+            error::LMECoreError,
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::sync::{Arc, Mutex};
+
+        let handle = WorkspaceHandle::new(Workspace::new(Molecule::default()));
+        handle
+            .lock()
+            .await
+            .apply(Operation::CreateStack { copies: 0 })
+            .unwrap();
+        let read = handle.lock().await.stack_version(0).unwrap();
+        let append = || Operation::AppendAtom {
+            stack_idx: 0,
+            atom: Atom::new(8, Point3::origin()),
+        };
+
+        let seen = Arc::new(Mutex::new(None));
+        let request = handle.for_stack(0, Some(read), seen.clone());
+        {
+            let mut workspace = request.lock().await;
+            workspace.apply(append()).unwrap();
+            // the request's own edit does not count as a conflict
+            workspace.apply(append()).unwrap();
+        }
+        let written = handle.lock().await.stack_version(0).unwrap();
+        assert_eq!(*seen.lock().unwrap(), Some(written));
+
+        let stale = handle.for_stack(0, Some(read), Arc::new(Mutex::new(None)));
+        assert!(matches!(
+            stale.lock().await.apply(append()),
+            Err(LMECoreError::StackChanged(0))
+        ));
+        let unconditional = handle.for_stack(0, None, Arc::new(Mutex::new(None)));
+        unconditional.lock().await.apply(append()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn readers_share_the_workspace() {
+        use crate::handle::WorkspaceHandle;
+        use lme_core::{entity::Molecule, Workspace};
+        use std::{sync::Arc, time::Duration};
+
+        let handle = WorkspaceHandle::new(Workspace::new(Molecule::default()));
+        let patience = Duration::from_millis(50);
+        let reading = handle.read().await;
+        let seen = Arc::new(std::sync::Mutex::new(None));
+        let stack = handle.for_stack(0, None, seen.clone());
+        assert!(tokio::time::timeout(patience, stack.read()).await.is_ok());
+        assert!(tokio::time::timeout(patience, handle.lock()).await.is_err());
+        drop(reading);
+        assert!(tokio::time::timeout(patience, handle.lock()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn applied_operations_reach_watchers() {
+        use crate::handle::WorkspaceHandle;
+        use lme_core::{entity::Molecule, operation::Operation, Workspace};
+
+        let handle = WorkspaceHandle::new(Workspace::new(Molecule::default()));
+        let mut changes = handle.subscribe();
+        {
+            let mut workspace = handle.lock().await;
+            workspace
+                .apply(Operation::CreateStack { copies: 1 })
+                .unwrap();
+            workspace
+                .apply(Operation::Undo { stack_idx: 1 })
+                .unwrap_err();
+            workspace
+                .apply(Operation::RemoveStack { stack_idx: 1 })
+                .unwrap();
+        }
+        handle
+            .lock()
+            .await
+            .replace(Workspace::new(Molecule::default()), "WorkspaceImport");
+
+        let received = [
+            changes.recv().await.unwrap(),
+            changes.recv().await.unwrap(),
+            changes.recv().await.unwrap(),
+        ];
+        let received = received
+            .iter()
+            .map(|change| (change.kind.as_str(), change.stacks.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            received,
+            vec![
+                ("CreateStack", vec![]),
+                ("RemoveStack", vec![1]),
+                ("WorkspaceImport", vec![])
+            ]
+        );
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn changes_carry_molecules_names_and_groups() {
+        use crate::handle::WorkspaceHandle;
+        use lme_core::{
+            entity::{Atom, Molecule},
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::collections::BTreeMap;
+
+        let handle = WorkspaceHandle::new(Workspace::new(Molecule::default()));
+        let create = Operation::CreateStack { copies: 0 };
+        handle.lock().await.apply(create).unwrap();
+        let mut changes = handle.subscribe();
+        {
+            let mut workspace = handle.lock().await;
+            let atom = Atom::new(8, Point3::origin());
+            workspace
+                .apply(Operation::AppendAtom { stack_idx: 0, atom })
+                .unwrap();
+            let name = "O1".to_string();
+            workspace
+                .apply(Operation::SetAtomName { atom_idx: 0, name })
+                .unwrap();
+            let group = "water".to_string();
+            workspace
+                .apply(Operation::AddToGroup { atom_idx: 0, group })
+                .unwrap();
+        }
+        let appended = changes.recv().await.unwrap();
+        assert_eq!(appended.molecules[&0].atom(0).unwrap().element(), 8);
+        assert!(appended.names.is_empty());
+        let named = changes.recv().await.unwrap();
+        assert!(named.molecules.is_empty() && named.names.is_empty());
+        let grouped = changes.recv().await.unwrap();
+        assert_eq!(grouped.names, BTreeMap::from([(0, Some("O1".to_string()))]));
+        assert_eq!(grouped.groups, BTreeMap::from([("water".to_string(), vec![0])]));
+
+        let rename = Operation::RenameGroup {
+            from: "water".to_string(),
+            to: "solvent".to_string(),
+        };
+        handle.lock().await.apply(rename).unwrap();
+        let renamed = changes.recv().await.unwrap();
+        assert!(renamed.names.is_empty());
+        let groups = BTreeMap::from([
+            ("solvent".to_string(), vec![0]),
+            ("water".to_string(), vec![]),
+        ]);
+        assert_eq!(renamed.groups, groups);
+    }
+}
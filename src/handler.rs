@@ -1,102 +1,521 @@
 mod state_handler {
-    use std::sync::Arc;
+    use std::{
+        collections::{hash_map::Entry, HashMap},
+        io,
+        path::{Path as FilePath, PathBuf},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+    };
 
     use axum::{
         extract::{Path, State},
-        http::{Request, StatusCode},
+        http::{header, HeaderValue, Request, StatusCode},
         middleware::Next,
         response::{IntoResponse, Response},
-        Json,
+        Extension, Json,
     };
-    use lme_core::{entity::Molecule, Workspace};
+    use lme_core::{entity::Molecule, Workspace, WorkspaceExport};
     use serde::Deserialize;
-    use tokio::sync::Mutex;
+    use tokio::{fs, io::AsyncWriteExt};
+    use utoipa::{IntoParams, ToSchema};
+
+    use crate::{
+        auth::Actor, error::ApiError, handle::WorkspaceHandle, journal::Journal, HistoryDepth,
+        ServerState, WorkspaceAccessor,
+    };
 
-    use crate::ServerState;
+    fn no_such_workspace() -> ApiError {
+        ApiError::new(StatusCode::NOT_FOUND, "NoSuchWorkspace", "No such workspace")
+    }
+
+    /// A handle of the new workspace `ws`, journaled when autosaving.
+    pub fn new_handle(
+        ws: &str,
+        workspace: Workspace,
+        journal: Option<&Arc<Journal>>,
+    ) -> WorkspaceAccessor {
+        let slot = journal.map(|journal| journal.track(ws, &workspace));
+        let handle = WorkspaceHandle::new(workspace);
+        Arc::new(match slot {
+            Some(slot) => handle.journaled(slot),
+            None => handle,
+        })
+    }
 
-    #[derive(Deserialize)]
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
     pub struct WorkspaceParam {
+        /// Workspace name
         ws: String,
     }
 
+    /// Create a workspace on a base molecule.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}",
+        tag = "server",
+        request_body = Molecule,
+        responses((status = 200, description = "OK"))
+    )]
     pub async fn create_workspace(
         State(state): State<ServerState>,
+        Extension(HistoryDepth(history_depth)): Extension<HistoryDepth>,
+        journal: Option<Extension<Arc<Journal>>>,
         Path(WorkspaceParam { ws }): Path<WorkspaceParam>,
         Json(base): Json<Molecule>,
-    ) -> StatusCode {
+    ) -> Result<StatusCode, ApiError> {
         let mut state = state.write().await;
-        if state.contains_key(&ws) {
-            StatusCode::CONFLICT
+        if let Entry::Vacant(entry) = state.entry(ws) {
+            let workspace = Workspace::new(base).with_history_depth(history_depth);
+            let journal = journal.as_ref().map(|Extension(journal)| journal);
+            let handle = new_handle(entry.key(), workspace, journal);
+            entry.insert(handle);
+            Ok(StatusCode::OK)
         } else {
-            state.insert(ws, Arc::new(Mutex::new(Workspace::new(base))));
-            StatusCode::OK
+            let message = "A workspace of this name exists";
+            Err(ApiError::new(StatusCode::CONFLICT, "WorkspaceExists", message))
         }
     }
 
+    /// Remove a workspace.
+    #[utoipa::path(
+        delete,
+        path = "/ws/{ws}",
+        tag = "server",
+        responses((status = 200, description = "OK"))
+    )]
     pub async fn remove_workspace(
         State(state): State<ServerState>,
         Path(WorkspaceParam { ws }): Path<WorkspaceParam>,
-    ) -> StatusCode {
+    ) -> Result<StatusCode, ApiError> {
         let mut state = state.write().await;
-        if state.remove(&ws).is_some() {
-            StatusCode::OK
-        } else {
-            StatusCode::NOT_FOUND
+        match state.remove(&ws) {
+            Some(workspace) => {
+                workspace.retire();
+                Ok(StatusCode::OK)
+            }
+            None => Err(no_such_workspace()),
+        }
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct SaveParam {
+        #[schema(value_type = String)]
+        path: PathBuf,
+    }
+
+    /// Write every workspace, keyed by name and serialized like `/export`,
+    /// to `path`. The file is written next to its destination first and
+    /// renamed over it, so an interrupted save leaves the previous file.
+    #[utoipa::path(
+        post,
+        path = "/save",
+        tag = "server",
+        request_body = SaveParam,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn save_workspaces(
+        State(state): State<ServerState>,
+        Json(SaveParam { path }): Json<SaveParam>,
+    ) -> Result<StatusCode, ApiError> {
+        let io_error = |err: &dyn ToString| {
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Io", err.to_string())
+        };
+        let mut exports = HashMap::new();
+        for (name, workspace) in state.read().await.iter() {
+            let snapshot = workspace.read().await.snapshot();
+            let export = WorkspaceExport::try_from(snapshot).map_err(|err| {
+                let message = format!("Workspace {name} can't be exported: {err:?}");
+                ApiError::new(StatusCode::CONFLICT, "UnsavedWorkspace", message)
+            })?;
+            exports.insert(name.clone(), export);
+        }
+        let content = serde_json::to_vec(&exports).map_err(|err| io_error(&err))?;
+        write_atomically(&path, &content)
+            .await
+            .map_err(|err| io_error(&err))?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Replace `path` with `content` whole: it is written and synced to a
+    /// file of its own next to `path` first, named after the process and a
+    /// count of writes so that concurrent writes don't share it.
+    pub async fn write_atomically(path: &FilePath, content: &[u8]) -> io::Result<()> {
+        static WRITES: AtomicU64 = AtomicU64::new(0);
+        let write = WRITES.fetch_add(1, Ordering::Relaxed);
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(format!(".{}.{write}.tmp", std::process::id()));
+        let written = async {
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temporary)
+                .await?;
+            file.write_all(content).await?;
+            file.sync_all().await?;
+            fs::rename(&temporary, path).await
+        }
+        .await;
+        if written.is_err() {
+            let _ = fs::remove_file(&temporary).await;
+        }
+        written
+    }
+
+    /// Replace every workspace with those saved to `path`. Nothing is
+    /// replaced if the file cannot be read or holds an invalid workspace.
+    #[utoipa::path(
+        post,
+        path = "/load",
+        tag = "server",
+        request_body = SaveParam,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn load_saved_workspaces(
+        State(state): State<ServerState>,
+        Extension(HistoryDepth(history_depth)): Extension<HistoryDepth>,
+        journal: Option<Extension<Arc<Journal>>>,
+        Json(SaveParam { path }): Json<SaveParam>,
+    ) -> Result<StatusCode, ApiError> {
+        let workspaces = load_workspaces(&path, history_depth)
+            .await
+            .map_err(|err| {
+                let status = match err.kind() {
+                    io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                    io::ErrorKind::InvalidData => StatusCode::UNPROCESSABLE_ENTITY,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                };
+                ApiError::new(status, "Io", err.to_string())
+            })?;
+        let mut state = state.write().await;
+        state.values().for_each(|workspace| workspace.retire());
+        *state = workspaces
+            .into_iter()
+            .map(|(name, workspace)| {
+                let journal = journal.as_ref().map(|Extension(journal)| journal);
+                let handle = new_handle(&name, workspace, journal);
+                (name, handle)
+            })
+            .collect();
+        Ok(StatusCode::OK)
+    }
+
+    /// Workspaces from a file written by `save_workspaces`.
+    pub async fn load_workspaces(
+        path: &FilePath,
+        history_depth: usize,
+    ) -> io::Result<HashMap<String, Workspace>> {
+        let content = fs::read(path).await?;
+        let exports: HashMap<String, WorkspaceExport> = serde_json::from_slice(&content)?;
+        exports
+            .into_iter()
+            .map(|(name, export)| {
+                let workspace = Workspace::try_from(&export).map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Workspace {name}: {err:?}"),
+                    )
+                })?;
+                Ok((name, workspace.with_history_depth(history_depth)))
+            })
+            .collect()
+    }
+
+    /// Resolve `/ws/:ws/stacks/:name/...` to the index route
+    /// `/ws/:ws/stack/:idx/...` of the stack currently called `name`. Runs
+    /// before routing, so every index route is also reachable by name.
+    /// Names are compared with the raw path segment, without percent-decoding.
+    pub async fn stack_name_middleware<B>(
+        State(state): State<ServerState>,
+        mut req: Request<B>,
+        next: Next<B>,
+    ) -> Response {
+        let path = req.uri().path().to_string();
+        let segments = path.split('/').collect::<Vec<_>>();
+        let ["", "ws", ws, "stacks", name, rest @ ..] = segments.as_slice() else {
+            return next.run(req).await;
+        };
+        let workspace = state.read().await.get(*ws).cloned();
+        let Some(workspace) = workspace else {
+            return no_such_workspace().into_response();
+        };
+        let index = workspace.read().await.stack_index(name);
+        let index = match index {
+            Ok(index) => index,
+            Err(err) => return ApiError::from(err).into_response(),
+        };
+        let mut rewritten = format!("/ws/{ws}/stack/{index}");
+        rest.iter().for_each(|segment| {
+            rewritten.push('/');
+            rewritten.push_str(segment);
+        });
+        if let Some(query) = req.uri().query() {
+            rewritten.push('?');
+            rewritten.push_str(query);
+        }
+        match rewritten.parse() {
+            Ok(uri) => *req.uri_mut() = uri,
+            Err(_) => {
+                let message = "Stack name makes an invalid path";
+                return ApiError::new(StatusCode::BAD_REQUEST, "InvalidPath", message)
+                    .into_response();
+            }
         }
+        next.run(req).await
+    }
+
+    /// A stack version given as an entity tag, `"5"` or just `5`.
+    fn parse_version(value: &HeaderValue) -> Option<u64> {
+        let value = value.to_str().ok()?.trim();
+        let value = value.strip_prefix("W/").unwrap_or(value);
+        value.trim_matches('"').parse().ok()
     }
 
+    /// Hand the workspace to the handler. On the routes of one stack,
+    /// `/stack/:idx/...`, an `If-Match` header holding a version makes
+    /// edits of the stack fail with 409 unless it still has this version,
+    /// and the response carries the version of the stack the request saw
+    /// as its `ETag`, see `WorkspaceHandle::for_stack`.
     pub async fn workspace_middleware<B>(
         State(state): State<ServerState>,
         Path(WorkspaceParam { ws }): Path<WorkspaceParam>,
         mut req: Request<B>,
         next: Next<B>,
     ) -> Response {
-        let workspace = state.read().await.get(&ws).cloned();
-        if let Some(workspace) = workspace {
-            req.extensions_mut().insert(workspace);
-            next.run(req).await
-        } else {
-            (StatusCode::NOT_FOUND, "No such workspace").into_response()
+        let Some(workspace) = state.read().await.get(&ws).cloned() else {
+            return no_such_workspace().into_response();
+        };
+        let actor = req.extensions().get::<Actor>().map(|Actor(actor)| actor.clone());
+        let workspace = workspace.for_actor(actor);
+        // the path below `/ws/:ws`, with stack names already resolved by
+        // `stack_name_middleware`
+        let segments = req.uri().path().split('/').collect::<Vec<_>>();
+        let stack_idx = match segments.as_slice() {
+            ["", "stack", idx, ..] => idx.parse::<usize>().ok(),
+            _ => None,
+        };
+        let Some(stack_idx) = stack_idx else {
+            let accessor: WorkspaceAccessor = Arc::new(workspace);
+            req.extensions_mut().insert(accessor);
+            return next.run(req).await;
+        };
+        let expected = match req.headers().get(header::IF_MATCH).map(parse_version) {
+            None => None,
+            Some(Some(version)) => Some(version),
+            Some(None) => {
+                let message = "If-Match must hold a stack version";
+                return ApiError::new(StatusCode::BAD_REQUEST, "InvalidVersion", message)
+                    .into_response();
+            }
+        };
+        let seen = Arc::new(std::sync::Mutex::new(None));
+        let accessor: WorkspaceAccessor =
+            Arc::new(workspace.for_stack(stack_idx, expected, seen.clone()));
+        req.extensions_mut().insert(accessor);
+        let mut response = next.run(req).await;
+        let seen = *seen.lock().expect("Version lock is never poisoned");
+        if let Some(version) = seen {
+            let etag = HeaderValue::from_str(&format!("\"{version}\"")).expect("Digits and quotes");
+            response.headers_mut().insert(header::ETAG, etag);
         }
+        response
     }
 }
 
 mod workspace_handler {
     use axum::{
-        http::StatusCode,
-        response::{ErrorResponse, Result},
+        extract::rejection::JsonRejection,
+        http::{header, HeaderMap, StatusCode},
+        response::{IntoResponse, Response, Result},
+    };
+    use std::{
+        collections::{BTreeMap, BTreeSet, HashMap},
+        num::NonZeroUsize,
     };
-    use std::{ops::Deref, sync::Arc};
 
-    use axum::{extract::Query, Extension, Json};
+    use axum::{
+        extract::{Path, Query},
+        Extension, Json,
+    };
     use lme_core::{
-        entity::{Layer, Molecule, Stack},
+        diff::MoleculeDiff,
+        entity::{AtomMetadata, Layer, LayerMeta, Mirror, Molecule, MoleculePatch},
+        fragment::{Fragment, FragmentRef},
+        geometry,
+        graph::{self, Mapping},
+        error::LMECoreError,
+        operation::{
+            AuditEntry, BatchEdit, Operation, OperationLog, OperationOutput, RigidMotion,
+        },
+        properties::{Measurement, Property},
+        selection::{Predicate, Region},
+        symmetry::Symmetry,
+        template::Template,
+        validation::{Validation, DEFAULT_OVERLAP_DISTANCE},
+        AtomRef, ElectronicState, LayerSummary, StackSummary, TreeNode, Workspace,
         WorkspaceExport,
     };
-    use serde::Deserialize;
+    use nalgebra::Point3;
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+    use unique_value_map::InsertResult;
+    use utoipa::{IntoParams, ToSchema};
 
-    use crate::WorkspaceAccessor;
+    use crate::{
+        error::ApiError,
+        streaming::{accepted, accepts_gzip, streamed, BodyFormat},
+        Optimizers, WorkspaceAccessor,
+    };
 
-    #[derive(Deserialize)]
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
     pub struct StacksSelect {
+        /// First stack
         pub start: usize,
+        /// Number of stacks
         pub range: usize,
     }
 
+    #[derive(Deserialize, ToSchema, Default)]
+    #[serde(rename_all = "lowercase")]
+    pub enum Coordinates {
+        #[default]
+        Cartesian,
+        Fractional,
+    }
+
+    /// Coordinate system of the atom positions in a request or response body.
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct CoordinatesParam {
+        /// Coordinate system of the positions, cartesian by default
+        #[serde(default)]
+        pub coords: Coordinates,
+    }
+
+    /// Molecules of a range of stacks.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}",
+        tag = "stacks",
+        params(StacksSelect, CoordinatesParam),
+        responses((status = 200, description = "OK", body = Vec<Molecule>))
+    )]
     pub async fn read_stacks(
         Extension(workspace): Extension<WorkspaceAccessor>,
         Query(StacksSelect { start, range }): Query<StacksSelect>,
-    ) -> Result<Json<Vec<Molecule>>> {
-        let workspace = workspace.lock().await;
-        (start..start + range)
-            .map(|index| workspace.read(index))
-            .collect::<Option<Vec<_>>>()
-            .map(|result| Json(result))
-            .ok_or(ErrorResponse::from(StatusCode::NOT_FOUND))
+        Query(CoordinatesParam { coords }): Query<CoordinatesParam>,
+    ) -> Result<Json<Vec<Molecule>>, ApiError> {
+        let workspace = workspace.read().await;
+        let molecules = (start..start + range)
+            .map(|index| {
+                let molecule = workspace.read(index)?;
+                match coords {
+                    Coordinates::Cartesian => Ok(molecule),
+                    Coordinates::Fractional => molecule.to_fractional(),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Json(molecules))
     }
 
-    #[derive(Deserialize)]
+    /// Formats a single stack can be read in.
+    #[derive(Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum StackFormat {
+        Json,
+        Xyz,
+        Pdb,
+        Sdf,
+    }
+
+    impl StackFormat {
+        const MEDIA_TYPES: [(&'static str, Self); 4] = [
+            ("application/json", Self::Json),
+            ("chemical/x-xyz", Self::Xyz),
+            ("chemical/x-pdb", Self::Pdb),
+            ("chemical/x-mdl-sdfile", Self::Sdf),
+        ];
+
+        fn media_type(self) -> &'static str {
+            let (media_type, _) = Self::MEDIA_TYPES
+                .into_iter()
+                .find(|(_, format)| *format == self)
+                .expect("Every format has a media type");
+            media_type
+        }
+
+        /// The format an `Accept` header prefers, JSON by default, see
+        /// `streaming::accepted`.
+        fn accepted(headers: &HeaderMap) -> Option<Self> {
+            accepted(headers, &Self::MEDIA_TYPES, Self::Json)
+        }
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct StackFormatParam {
+        /// Format of the molecule, overriding `Accept`
+        pub format: Option<StackFormat>,
+    }
+
+    /// The molecule read from a stack, in the format asked for with
+    /// `?format=` or else the `Accept` header, written as the export routes
+    /// write it. Coordinates are only made fractional in JSON.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}",
+        tag = "stacks",
+        params(StackParam, CoordinatesParam, StackFormatParam),
+        responses((status = 200, description = "OK", content(
+            ("application/json" = Molecule),
+            ("chemical/x-xyz" = String),
+            ("chemical/x-pdb" = String),
+            ("chemical/x-mdl-sdfile" = String)
+        )))
+    )]
+    pub async fn read_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Query(CoordinatesParam { coords }): Query<CoordinatesParam>,
+        Query(StackFormatParam { format }): Query<StackFormatParam>,
+        headers: HeaderMap,
+    ) -> Result<Response, ApiError> {
+        let format = format.or_else(|| StackFormat::accepted(&headers)).ok_or_else(|| {
+            let media_types = StackFormat::MEDIA_TYPES.map(|(media_type, _)| media_type);
+            let message = format!("A stack is read as one of {}", media_types.join(", "));
+            ApiError::new(StatusCode::NOT_ACCEPTABLE, "NotAcceptable", message)
+        })?;
+        let workspace = workspace.read().await;
+        let molecule = workspace.read(idx)?;
+        let text = match format {
+            StackFormat::Json => {
+                let molecule = match coords {
+                    Coordinates::Cartesian => molecule,
+                    Coordinates::Fractional => molecule.to_fractional()?,
+                };
+                let vary = [(header::VARY, "accept")];
+                return Ok((vary, Json(molecule)).into_response());
+            }
+            StackFormat::Xyz => molecule.to_xyz(&format!("stack {idx}")),
+            StackFormat::Pdb => molecule.to_pdb(),
+            StackFormat::Sdf => {
+                Molecule::to_sdf([(workspace.stack_names()[idx].as_str(), &molecule)])
+            }
+        };
+        let headers = [
+            (header::CONTENT_TYPE, format.media_type()),
+            (header::VARY, "accept"),
+        ];
+        Ok((headers, text).into_response())
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
     pub struct StackCreationParam {
+        /// Stacks created besides the first
         copies: usize,
     }
 
@@ -106,86 +525,2969 @@ mod workspace_handler {
         }
     }
 
+    /// Create empty stacks, returning the index of the first.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack",
+        tag = "stacks",
+        params(StackCreationParam),
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
     pub async fn create_stack(
         Extension(workspace): Extension<WorkspaceAccessor>,
         Query(StackCreationParam { copies }): Query<StackCreationParam>,
-    ) -> Json<usize> {
-        let mut workspace = workspace.lock().await;
-        Json(workspace.create_stack(Arc::new(Stack::new(vec![])), copies))
+    ) -> Result<Json<usize>, ApiError> {
+        match workspace.lock().await.apply(Operation::CreateStack { copies })? {
+            OperationOutput::Stack(index) => Ok(Json(index)),
+            output => unreachable!("CreateStack returned {output:?}"),
+        }
     }
 
+    /// Write a molecule patch onto a range of stacks. The patch may give
+    /// atoms by name as well as by index; an unknown name fails the write.
+    #[utoipa::path(
+        put,
+        path = "/ws/{ws}/stack/write",
+        tag = "stacks",
+        params(StacksSelect, CoordinatesParam),
+        request_body = MoleculePatch,
+        responses((
+            status = 200,
+            description = "OK",
+            body = bool,
+            content_type = "application/json"
+        ))
+    )]
     pub async fn write_to_stack(
         Extension(workspace): Extension<WorkspaceAccessor>,
         Query(StacksSelect { start, range }): Query<StacksSelect>,
-        Json(data): Json<Molecule>
-    ) -> Json<bool> {
-        Json(
-            workspace
-                .lock()
-                .await
-                .write_to_stack(start, range, data),
-        )
+        Query(CoordinatesParam { coords }): Query<CoordinatesParam>,
+        Json(patch): Json<MoleculePatch>
+    ) -> Result<Json<bool>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let data = patch.resolve(|atom| atom.resolve(&workspace))?;
+        match coords {
+            Coordinates::Cartesian => workspace.apply(Operation::Write { start, range, data })?,
+            Coordinates::Fractional => {
+                workspace.apply(Operation::WriteFractional { start, range, data })?
+            }
+        };
+        Ok(Json(true))
     }
 
+    /// Push a layer onto a range of stacks.
+    #[utoipa::path(
+        put,
+        path = "/ws/{ws}/stack/layer",
+        tag = "stacks",
+        params(StacksSelect),
+        request_body = Layer,
+        responses((
+            status = 200,
+            description = "OK",
+            body = bool,
+            content_type = "application/json"
+        ))
+    )]
     pub async fn add_layer_to_stack(
         Extension(workspace): Extension<WorkspaceAccessor>,
         Query(StacksSelect { start, range }): Query<StacksSelect>,
         Json(layer): Json<Layer>
-    ) -> Json<bool> {
-        Json(
-            workspace
-                .lock()
-                .await
-                .add_layer_to_stack(start, range, Arc::new(layer)),
-        )
+    ) -> Result<Json<bool>, ApiError> {
+        workspace.lock().await.apply(Operation::AddLayer {
+            start,
+            range,
+            layer,
+        })?;
+        Ok(Json(true))
     }
 
-    #[derive(Deserialize)]
+    #[derive(Deserialize, ToSchema)]
     pub struct CloneStack {
         stack_idx: usize,
         copies: usize,
     }
 
+    /// Copy a stack, returning the index of the first copy.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/clone_stack",
+        tag = "stacks",
+        request_body = CloneStack,
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
     pub async fn clone_stack(
         Extension(workspace): Extension<WorkspaceAccessor>,
         Json(CloneStack { stack_idx, copies }): Json<CloneStack>,
-    ) -> Result<Json<usize>> {
-        workspace
+    ) -> Result<Json<usize>, ApiError> {
+        match workspace
             .lock()
             .await
-            .clone_stack(stack_idx, copies)
-            .map(|start| Json(start))
-            .ok_or(ErrorResponse::from(StatusCode::NOT_FOUND))
+            .apply(Operation::CloneStack { stack_idx, copies })?
+        {
+            OperationOutput::Stack(index) => Ok(Json(index)),
+            output => unreachable!("CloneStack returned {output:?}"),
+        }
     }
 
+    /// Copy a stack without its top layer, returning the index of the first
+    /// copy.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/clone_base",
+        tag = "stacks",
+        request_body = CloneStack,
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
     pub async fn clone_base(
         Extension(workspace): Extension<WorkspaceAccessor>,
         Json(CloneStack { stack_idx, copies }): Json<CloneStack>,
-    ) -> Result<Json<usize>> {
+    ) -> Result<Json<usize>, ApiError> {
+        match workspace
+            .lock()
+            .await
+            .apply(Operation::CloneBase { stack_idx, copies })?
+        {
+            OperationOutput::Stack(index) => Ok(Json(index)),
+            output => unreachable!("CloneBase returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct StackParam {
+        /// Stack index
+        pub idx: usize,
+    }
+
+    /// Remove a stack. Indices of the following stacks shift down by one.
+    /// The workspace base is not a stack, so index 0 is not special: every
+    /// stack reads on top of the base and can be removed.
+    #[utoipa::path(
+        delete,
+        path = "/ws/{ws}/stack/{idx}",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn remove_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::RemoveStack { stack_idx: idx };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Put the stacks in a new order, given as the current index of the
+    /// stack for each new position, see `Workspace::reorder_stacks`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/reorder",
+        tag = "stacks",
+        request_body = Vec<usize>,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn reorder_stacks(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Json(order): Json<Vec<usize>>,
+    ) -> Result<StatusCode, ApiError> {
         workspace
             .lock()
             .await
-            .clone_base(stack_idx, copies)
-            .map(|start| Json(start))
-            .ok_or(ErrorResponse::from(StatusCode::NOT_FOUND))
+            .apply(Operation::ReorderStacks { order })?;
+        Ok(StatusCode::OK)
     }
 
-    pub async fn workspace_export(
+    /// Branch a single copy off a stack, returning the new stack's index.
+    /// Layers are shared, so later edits to either stack leave the other alone.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/clone",
+        tag = "stacks",
+        params(StackParam),
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn clone_stack_at(
         Extension(workspace): Extension<WorkspaceAccessor>,
-    ) -> Json<WorkspaceExport> {
-        Json(WorkspaceExport::from(workspace.lock().await.deref()))
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<Json<usize>, ApiError> {
+        let operation = Operation::CloneStack {
+            stack_idx: idx,
+            copies: 0,
+        };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Stack(index) => Ok(Json(index)),
+            output => unreachable!("CloneStack returned {output:?}"),
+        }
     }
-}
 
-mod chemistry_handler {
-    use std::collections::HashMap;
+    /// Set the name or comment of a stack's top layer; fields left out of
+    /// the body keep their current value.
+    #[utoipa::path(
+        patch,
+        path = "/ws/{ws}/stack/{idx}/meta",
+        tag = "stacks",
+        params(StackParam),
+        request_body = LayerMeta,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn annotate_layer(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(meta): Json<LayerMeta>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::AnnotateLayer {
+            stack_idx: idx,
+            meta,
+        };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct CherryPick {
+        source: usize,
+        position: usize,
+    }
+
+    /// Push the layer at `position` of stack `source` onto this stack, see
+    /// `Workspace::cherry_pick`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/cherry-pick",
+        tag = "stacks",
+        params(StackParam),
+        request_body = CherryPick,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn cherry_pick(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(CherryPick { source, position }): Json<CherryPick>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::CherryPick {
+            target: idx,
+            source,
+            position,
+        };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Push several layers onto a stack at once, see `Workspace::add_layers`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/transaction",
+        tag = "stacks",
+        params(StackParam),
+        request_body = Vec<Layer>,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn stack_transaction(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(layers): Json<Vec<Layer>>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::AddLayers {
+            stack_idx: idx,
+            layers,
+        };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Remove atoms of a stack.
+    #[utoipa::path(
+        delete,
+        path = "/ws/{ws}/stack/{idx}/atoms",
+        tag = "stacks",
+        params(StackParam),
+        request_body = Vec<AtomRef>,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn remove_atoms(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(atoms): Json<Vec<AtomRef>>,
+    ) -> Result<StatusCode, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let operation = Operation::RemoveAtoms {
+            stack_idx: idx,
+            atoms: workspace.resolve_refs(&atoms)?,
+        };
+        workspace.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// A `RigidMotion` whose atoms may be given by name.
+    #[derive(Deserialize, ToSchema)]
+    pub struct Motion {
+        #[serde(default)]
+        atoms: Vec<AtomRef>,
+        #[serde(flatten)]
+        motion: RigidMotion,
+    }
+
+    /// Rotate and shift some atoms of a stack as one layer, returning how
+    /// many atoms moved, see `Workspace::move_atoms`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/move",
+        tag = "stacks",
+        params(StackParam),
+        request_body = Motion,
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn move_atoms(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(Motion { atoms, motion }): Json<Motion>,
+    ) -> Result<Json<usize>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let motion = RigidMotion {
+            atoms: workspace.resolve_refs(&atoms)?,
+            ..motion
+        };
+        let operation = Operation::MoveAtoms {
+            stack_idx: idx,
+            motion,
+        };
+        match workspace.apply(operation)? {
+            OperationOutput::Affected(count) => Ok(Json(count)),
+            output => unreachable!("MoveAtoms returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct CopyParam {
+        /// Stack index
+        idx: usize,
+        /// Name of the stack copied from, else its index
+        source: String,
+    }
+
+    /// Copy atoms of another stack into this one with the bonds among
+    /// them, moved as in `move_atoms`, returning the source to target index
+    /// mapping, see `Workspace::copy_atoms`. Every atom is copied if none
+    /// are selected.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/import-from/{source}",
+        tag = "stacks",
+        params(CopyParam),
+        request_body = Motion,
+        responses((status = 200, description = "OK", body = HashMap<usize, usize>))
+    )]
+    pub async fn copy_atoms(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(CopyParam { idx, source }): Path<CopyParam>,
+        Json(Motion { atoms, motion }): Json<Motion>,
+    ) -> Result<Json<HashMap<usize, usize>>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let source = match workspace.stack_index(&source) {
+            Ok(source) => source,
+            Err(err) => source.parse().map_err(|_| err)?,
+        };
+        let motion = RigidMotion {
+            atoms: workspace.resolve_refs(&atoms)?,
+            ..motion
+        };
+        let operation = Operation::CopyAtoms {
+            target: idx,
+            source,
+            motion,
+        };
+        match workspace.apply(operation)? {
+            OperationOutput::Mapping(mapping) => Ok(Json(mapping)),
+            output => unreachable!("CopyAtoms returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct Torsion {
+        bond: [AtomRef; 2],
+        angle: f64,
+    }
+
+    /// Turn one side of a bond about it as one layer, returning how many
+    /// atoms moved, see `Workspace::rotate_torsion`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/torsion",
+        tag = "stacks",
+        params(StackParam),
+        request_body = Torsion,
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn rotate_torsion(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(Torsion { bond, angle }): Json<Torsion>,
+    ) -> Result<Json<usize>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let [a, b] = bond;
+        let operation = Operation::RotateTorsion {
+            stack_idx: idx,
+            bond: [a.resolve(&workspace)?, b.resolve(&workspace)?],
+            angle,
+        };
+        match workspace.apply(operation)? {
+            OperationOutput::Affected(count) => Ok(Json(count)),
+            output => unreachable!("RotateTorsion returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct BondLength {
+        bond: [AtomRef; 2],
+        length: f64,
+    }
+
+    /// Stretch or shorten a bond as one layer, returning how many atoms
+    /// moved, see `Workspace::set_bond_length`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/bond-length",
+        tag = "stacks",
+        params(StackParam),
+        request_body = BondLength,
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn set_bond_length(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(BondLength { bond, length }): Json<BondLength>,
+    ) -> Result<Json<usize>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let [a, b] = bond;
+        let operation = Operation::SetBondLength {
+            stack_idx: idx,
+            bond: [a.resolve(&workspace)?, b.resolve(&workspace)?],
+            length,
+        };
+        match workspace.apply(operation)? {
+            OperationOutput::Affected(count) => Ok(Json(count)),
+            output => unreachable!("SetBondLength returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct BondAngle {
+        atoms: [AtomRef; 3],
+        angle: f64,
+    }
+
+    /// Open or close an angle as one layer, returning how many atoms moved,
+    /// see `Workspace::set_bond_angle`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/bond-angle",
+        tag = "stacks",
+        params(StackParam),
+        request_body = BondAngle,
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn set_bond_angle(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(BondAngle { atoms, angle }): Json<BondAngle>,
+    ) -> Result<Json<usize>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let [a, b, c] = atoms;
+        let atoms = [
+            a.resolve(&workspace)?,
+            b.resolve(&workspace)?,
+            c.resolve(&workspace)?,
+        ];
+        let operation = Operation::SetBondAngle {
+            stack_idx: idx,
+            atoms,
+            angle,
+        };
+        match workspace.apply(operation)? {
+            OperationOutput::Affected(count) => Ok(Json(count)),
+            output => unreachable!("SetBondAngle returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct MirrorSelection {
+        #[serde(default)]
+        atoms: Vec<AtomRef>,
+        group: Option<String>,
+        mirror: Mirror,
+    }
+
+    /// Reflect atoms of a stack as one layer, returning how many atoms were
+    /// reflected, see `Workspace::mirror_atoms`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/mirror",
+        tag = "stacks",
+        params(StackParam),
+        request_body = MirrorSelection,
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn mirror_atoms(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(MirrorSelection {
+            atoms,
+            group,
+            mirror,
+        }): Json<MirrorSelection>,
+    ) -> Result<Json<usize>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let operation = Operation::MirrorAtoms {
+            stack_idx: idx,
+            atoms: workspace.resolve_refs(&atoms)?,
+            group,
+            mirror,
+        };
+        match workspace.apply(operation)? {
+            OperationOutput::Affected(count) => Ok(Json(count)),
+            output => unreachable!("MirrorAtoms returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct Substitution {
+        atom: AtomRef,
+        fragment: FragmentRef,
+    }
+
+    /// Replace a terminal atom of a stack with a fragment, given in full or
+    /// by its name in the fragment library, returning the new index of each
+    /// fragment atom, see `Workspace::substitute`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/substitute",
+        tag = "stacks",
+        params(StackParam),
+        request_body = Substitution,
+        responses((status = 200, description = "OK", body = HashMap<usize, usize>))
+    )]
+    pub async fn substitute(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(Substitution { atom, fragment }): Json<Substitution>,
+    ) -> Result<Json<HashMap<usize, usize>>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let operation = Operation::Substitute {
+            stack_idx: idx,
+            atom_idx: atom.resolve(&workspace)?,
+            fragment,
+        };
+        match workspace.apply(operation)? {
+            OperationOutput::Mapping(indices) => Ok(Json(indices)),
+            output => unreachable!("Substitute returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct FragmentParam {
+        /// Name
+        name: String,
+    }
+
+    /// Names of the fragments in the library, sorted.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/fragments",
+        tag = "library",
+        responses((status = 200, description = "OK", body = Vec<String>))
+    )]
+    pub async fn fragment_names(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+    ) -> Json<Vec<String>> {
+        Json(workspace.read().await.fragments().keys().cloned().collect())
+    }
+
+    /// A fragment of the library.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/fragments/{name}",
+        tag = "library",
+        params(FragmentParam),
+        responses((status = 200, description = "OK", body = Fragment))
+    )]
+    pub async fn read_fragment(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(FragmentParam { name }): Path<FragmentParam>,
+    ) -> Result<Json<Fragment>, ApiError> {
+        Ok(Json(workspace.read().await.fragment(&name)?.clone()))
+    }
+
+    /// Store a fragment in the library, replacing any fragment of that name.
+    #[utoipa::path(
+        put,
+        path = "/ws/{ws}/fragments/{name}",
+        tag = "library",
+        params(FragmentParam),
+        request_body = Fragment,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn set_fragment(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(FragmentParam { name }): Path<FragmentParam>,
+        Json(fragment): Json<Fragment>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::SetFragment { name, fragment };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Remove a fragment from the library.
+    #[utoipa::path(
+        delete,
+        path = "/ws/{ws}/fragments/{name}",
+        tag = "library",
+        params(FragmentParam),
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn remove_fragment(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(FragmentParam { name }): Path<FragmentParam>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::RemoveFragment { name };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct TemplateParam {
+        /// Name
+        name: String,
+    }
+
+    /// Names of the templates in the library, sorted.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/templates",
+        tag = "library",
+        responses((status = 200, description = "OK", body = Vec<String>))
+    )]
+    pub async fn template_names(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+    ) -> Json<Vec<String>> {
+        Json(workspace.read().await.templates().keys().cloned().collect())
+    }
+
+    /// Source text of a template.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/templates/{name}",
+        tag = "library",
+        params(TemplateParam),
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn read_template(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(TemplateParam { name }): Path<TemplateParam>,
+    ) -> Result<String, ApiError> {
+        Ok(workspace.read().await.template(&name)?.clone().into())
+    }
+
+    /// Store the template given as the request body, replacing any template
+    /// of that name. Templates that don't parse are rejected.
+    #[utoipa::path(
+        put,
+        path = "/ws/{ws}/templates/{name}",
+        tag = "library",
+        params(TemplateParam),
+        request_body(content = String, content_type = "text/plain"),
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn set_template(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(TemplateParam { name }): Path<TemplateParam>,
+        source: String,
+    ) -> Result<StatusCode, ApiError> {
+        let template = Template::new(source)?;
+        let operation = Operation::SetTemplate { name, template };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Remove a template from the library.
+    #[utoipa::path(
+        delete,
+        path = "/ws/{ws}/templates/{name}",
+        tag = "library",
+        params(TemplateParam),
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn remove_template(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(TemplateParam { name }): Path<TemplateParam>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::RemoveTemplate { name };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct Replication {
+        #[serde(default)]
+        atoms: Vec<AtomRef>,
+        group: Option<String>,
+        symmetry: Symmetry,
+        #[schema(value_type = Option<[f64; 3]>)]
+        center: Option<Point3<f64>>,
+    }
+
+    /// Add symmetric copies of atoms of a stack as one layer, returning the
+    /// indices of the new atoms, see `Workspace::replicate_atoms`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/replicate",
+        tag = "stacks",
+        params(StackParam),
+        request_body = Replication,
+        responses((status = 200, description = "OK", body = Vec<usize>))
+    )]
+    pub async fn replicate_atoms(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(Replication {
+            atoms,
+            group,
+            symmetry,
+            center,
+        }): Json<Replication>,
+    ) -> Result<Json<Vec<usize>>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let operation = Operation::ReplicateAtoms {
+            stack_idx: idx,
+            atoms: workspace.resolve_refs(&atoms)?,
+            group,
+            symmetry,
+            center,
+        };
+        match workspace.apply(operation)? {
+            OperationOutput::Atoms(atoms) => Ok(Json(atoms)),
+            output => unreachable!("ReplicateAtoms returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct Supercell {
+        #[schema(value_type = [usize; 3])]
+        repeats: [NonZeroUsize; 3],
+    }
+
+    /// Repeat the cell of a stack along each of its vectors, returning the
+    /// indices of the new atoms, see `Workspace::supercell`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/supercell",
+        tag = "stacks",
+        params(StackParam),
+        request_body = Supercell,
+        responses((status = 200, description = "OK", body = Vec<usize>))
+    )]
+    pub async fn supercell(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(Supercell { repeats }): Json<Supercell>,
+    ) -> Result<Json<Vec<usize>>, ApiError> {
+        let operation = Operation::Supercell {
+            stack_idx: idx,
+            repeats,
+        };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Atoms(atoms) => Ok(Json(atoms)),
+            output => unreachable!("Supercell returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct NamedStackParam {
+        /// Generated if not given
+        name: Option<String>,
+    }
+
+    /// Create one empty stack, returning its name (generated if not given).
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stacks",
+        tag = "stacks",
+        params(NamedStackParam),
+        responses((
+            status = 200,
+            description = "OK",
+            body = String,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn create_named_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Query(NamedStackParam { name }): Query<NamedStackParam>,
+    ) -> Result<Json<String>, ApiError> {
+        match workspace
+            .lock()
+            .await
+            .apply(Operation::CreateNamedStack { name })?
+        {
+            OperationOutput::StackName(name) => Ok(Json(name)),
+            output => unreachable!("CreateNamedStack returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct StackNameParam {
+        /// Stack index
+        idx: usize,
+        /// Name
+        name: String,
+    }
+
+    /// Rename a stack, returning its previous name.
+    #[utoipa::path(
+        put,
+        path = "/ws/{ws}/stack/{idx}/name/{name}",
+        tag = "stacks",
+        params(StackNameParam),
+        responses((
+            status = 200,
+            description = "OK",
+            body = String,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn rename_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackNameParam { idx, name }): Path<StackNameParam>,
+    ) -> Result<Json<String>, ApiError> {
+        let operation = Operation::RenameStack {
+            stack_idx: idx,
+            name,
+        };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::StackName(previous) => Ok(Json(previous)),
+            output => unreachable!("RenameStack returned {output:?}"),
+        }
+    }
+
+    /// Index, name, atom count and depth of every stack, by stack index.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stacks",
+        tag = "stacks",
+        responses((status = 200, description = "OK", body = Vec<StackSummary>))
+    )]
+    pub async fn stack_summaries(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+    ) -> Result<Json<Vec<StackSummary>>, ApiError> {
+        Ok(Json(workspace.read().await.stack_summaries()?))
+    }
+
+    /// Every layer of a stack, bottom first, with the number of atoms it
+    /// changes, see `Workspace::layer_summaries`.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/layers",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = Vec<LayerSummary>))
+    )]
+    pub async fn layer_summaries(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<Json<Vec<LayerSummary>>, ApiError> {
+        Ok(Json(workspace.read().await.layer_summaries(idx)?))
+    }
+
+    /// Undo the last edit of a stack.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/undo",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn undo_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::Undo { stack_idx: idx };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Redo the last undone edit of a stack.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/redo",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn redo_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::Redo { stack_idx: idx };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Renumber the atoms of a stack into their canonical order, returning the
+    /// old to new mapping.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/renumber",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = HashMap<usize, usize>))
+    )]
+    pub async fn renumber_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<Json<HashMap<usize, usize>>, ApiError> {
+        let operation = Operation::Renumber { stack_idx: idx };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Mapping(mapping) => Ok(Json(mapping)),
+            output => unreachable!("Renumber returned {output:?}"),
+        }
+    }
+
+    /// Renumber the atoms of a stack to close the gaps of removed atoms,
+    /// returning the old to new mapping.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/compact",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = HashMap<usize, usize>))
+    )]
+    pub async fn compact_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<Json<HashMap<usize, usize>>, ApiError> {
+        let operation = Operation::Compact { stack_idx: idx };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Mapping(mapping) => Ok(Json(mapping)),
+            output => unreachable!("Compact returned {output:?}"),
+        }
+    }
+
+    /// Remove the hydrogens of a stack, returning how many each atom lost.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/remove-hydrogens",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = HashMap<usize, usize>))
+    )]
+    pub async fn remove_hydrogens(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<Json<HashMap<usize, usize>>, ApiError> {
+        let operation = Operation::RemoveHydrogens { stack_idx: idx };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Counts(counts) => Ok(Json(counts)),
+            output => unreachable!("RemoveHydrogens returned {output:?}"),
+        }
+    }
+
+    /// Saturate the atoms of a stack with hydrogens in a new layer, returning
+    /// the new atoms.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/add-hydrogens",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = Vec<usize>))
+    )]
+    pub async fn add_hydrogens(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<Json<Vec<usize>>, ApiError> {
+        let operation = Operation::AddHydrogens { stack_idx: idx };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Atoms(added) => Ok(Json(added)),
+            output => unreachable!("AddHydrogens returned {output:?}"),
+        }
+    }
+
+    /// Comma separated property names, see `properties::Property`.
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct PropsParam {
+        /// Comma separated property names
+        #[serde(default)]
+        props: String,
+    }
+
+    #[derive(Serialize, ToSchema)]
+    pub struct FullStack {
+        molecule: Molecule,
+        properties: BTreeMap<Property, Value>,
+    }
+
+    #[derive(Serialize, ToSchema)]
+    pub struct Formula {
+        composition: BTreeMap<&'static str, usize>,
+        formula: String,
+        weight: Option<f64>,
+        exact_mass: Option<f64>,
+    }
+
+    /// Molecular formula and masses of a stack.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/formula",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = Formula))
+    )]
+    pub async fn stack_formula(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<Json<Formula>, ApiError> {
+        let molecule = workspace.read().await.read(idx)?;
+        Ok(Json(Formula {
+            composition: molecule.composition(),
+            formula: molecule.formula(),
+            weight: molecule.molecular_weight(),
+            exact_mass: molecule.exact_mass(),
+        }))
+    }
 
-    use axum::{extract::Query, Extension, Json};
+    /// Comma separated atom indices or names
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct MeasureQuery {
+        /// 2, 3 or 4 comma separated atom indices or names
+        atoms: String,
+    }
+
+    /// Distance, angle or dihedral of 2, 3 or 4 atoms of the stack, see
+    /// `Molecule::measure`.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/measure",
+        tag = "stacks",
+        params(StackParam, MeasureQuery),
+        responses((status = 200, description = "OK", body = Measurement))
+    )]
+    pub async fn measure(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Query(MeasureQuery { atoms }): Query<MeasureQuery>,
+    ) -> Result<Json<Measurement>, ApiError> {
+        let workspace = workspace.read().await;
+        let atoms = workspace.resolve_atoms(&atoms)?;
+        Ok(Json(workspace.read(idx)?.measure(&atoms)?))
+    }
+
+    /// Connected components of a stack, see `graph::components`.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/fragments",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = Vec<Vec<usize>>))
+    )]
+    pub async fn stack_components(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<Json<Vec<Vec<usize>>>, ApiError> {
+        let molecule = workspace.read().await.read(idx)?;
+        Ok(Json(graph::components(&molecule)))
+    }
+
+    /// Smallest set of smallest rings of a stack, see `graph::rings`.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/rings",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = Vec<Vec<usize>>))
+    )]
+    pub async fn stack_rings(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<Json<Vec<Vec<usize>>>, ApiError> {
+        let molecule = workspace.read().await.read(idx)?;
+        Ok(Json(graph::rings(&molecule)))
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct Search {
+        pattern: Molecule,
+        /// Group the matched atoms are added to
+        #[serde(default)]
+        group: Option<String>,
+    }
 
-    use crate::{StacksSelect, WorkspaceAccessor};
+    /// Occurrences of a pattern in a stack, see `graph::substructures`, the
+    /// matched atoms also added to `group` if one is given.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/search",
+        tag = "stacks",
+        params(StackParam),
+        request_body = Search,
+        responses((status = 200, description = "OK", body = Vec<BTreeMap<usize, usize>>))
+    )]
+    pub async fn search_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(Search { pattern, group }): Json<Search>,
+    ) -> Result<Json<Vec<Mapping>>, ApiError> {
+        let Some(group) = group else {
+            let molecule = workspace.read().await.read(idx)?;
+            return Ok(Json(graph::substructures(&pattern, &molecule)));
+        };
+        let mut workspace = workspace.lock().await;
+        let matches = graph::substructures(&pattern, &workspace.read(idx)?);
+        let members = matches
+            .iter()
+            .flat_map(|mapping| mapping.values())
+            .map(|idx| (*idx, group.clone()))
+            .collect();
+        workspace.apply(Operation::AddToGroups { members })?;
+        Ok(Json(matches))
+    }
 
-    pub fn modify_bonds(Extension(workspace): Extension<WorkspaceAccessor>, Query(StacksSelect {start, range}): Query<StacksSelect>, Json(bonds): Json<HashMap<Pair<usize>, f64>>) -> Json<bool> {}
+    /// Occurrences of a pattern in each stack, in stack order.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/search",
+        tag = "stacks",
+        request_body = Molecule,
+        responses((status = 200, description = "OK", body = Vec<Vec<BTreeMap<usize, usize>>>))
+    )]
+    pub async fn search_stacks(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Json(pattern): Json<Molecule>,
+    ) -> Result<Json<Vec<Vec<Mapping>>>, ApiError> {
+        let workspace = workspace.read().await;
+        let matches = (0..workspace.stacks())
+            .map(|idx| Ok(graph::substructures(&pattern, &workspace.read(idx)?)))
+            .collect::<Result<Vec<_>, ApiError>>()?;
+        Ok(Json(matches))
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct PrefixQuery {
+        /// Prefix of the group names
+        prefix: String,
+    }
+
+    /// Put each connected component of a stack in a group of its own,
+    /// `prefix` followed by its 1-based position in `stack_components`, and
+    /// return the components.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/fragments",
+        tag = "stacks",
+        params(StackParam, PrefixQuery),
+        responses((status = 200, description = "OK", body = Vec<Vec<usize>>))
+    )]
+    pub async fn group_components(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Query(PrefixQuery { prefix }): Query<PrefixQuery>,
+    ) -> Result<Json<Vec<Vec<usize>>>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let components = graph::components(&workspace.read(idx)?);
+        let members = components
+            .iter()
+            .enumerate()
+            .flat_map(|(nth, component)| {
+                let group = format!("{prefix}{}", nth + 1);
+                component.iter().map(move |idx| (*idx, group.clone()))
+            })
+            .collect();
+        workspace.apply(Operation::AddToGroups { members })?;
+        Ok(Json(components))
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct ValidateQuery {
+        /// Distance in Å below which atoms overlap
+        overlap: Option<f64>,
+    }
+
+    /// Problems found in a stack, see `Molecule::validate`. Atoms closer
+    /// than `overlap` Å overlap, `DEFAULT_OVERLAP_DISTANCE` if not given.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/validate",
+        tag = "stacks",
+        params(StackParam, ValidateQuery),
+        responses((status = 200, description = "OK", body = Validation))
+    )]
+    pub async fn validate_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Query(ValidateQuery { overlap }): Query<ValidateQuery>,
+    ) -> Result<Json<Validation>, ApiError> {
+        let workspace = workspace.read().await;
+        let (molecule, index) = (workspace.read_shared(idx)?, workspace.spatial_index(idx)?);
+        Ok(Json(molecule.validate(&index, overlap.unwrap_or(DEFAULT_OVERLAP_DISTANCE))))
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct RegionSelection {
+        region: Region,
+        /// Group the selected atoms are added to
+        #[serde(default)]
+        group: Option<String>,
+    }
+
+    /// Atoms of the stack in a region, see `SpatialIndex::atoms_in`, also added
+    /// to `group` if one is given.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/select/region",
+        tag = "stacks",
+        params(StackParam),
+        request_body = RegionSelection,
+        responses((status = 200, description = "OK", body = BTreeSet<usize>))
+    )]
+    pub async fn select_region(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(RegionSelection { region, group }): Json<RegionSelection>,
+    ) -> Result<Json<BTreeSet<usize>>, ApiError> {
+        let Some(group) = group else {
+            let index = workspace.read().await.spatial_index(idx)?;
+            return Ok(Json(index.atoms_in(&region)));
+        };
+        let mut workspace = workspace.lock().await;
+        let atoms = workspace.spatial_index(idx)?.atoms_in(&region);
+        let members = atoms.iter().map(|idx| (*idx, group.clone())).collect();
+        workspace.apply(Operation::AddToGroups { members })?;
+        Ok(Json(atoms))
+    }
+
+    /// Atoms of the stack matching a predicate, see `Workspace::select`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/select",
+        tag = "stacks",
+        params(StackParam),
+        request_body = Predicate,
+        responses((status = 200, description = "OK", body = BTreeSet<usize>))
+    )]
+    pub async fn select_atoms(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(predicate): Json<Predicate>,
+    ) -> Result<Json<BTreeSet<usize>>, ApiError> {
+        Ok(Json(workspace.read().await.select(idx, &predicate)?))
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct GroupQuery {
+        /// Only the members of this group
+        #[serde(default)]
+        group: Option<String>,
+    }
+
+    #[derive(Serialize, ToSchema)]
+    pub struct Center {
+        #[schema(value_type = [f64; 3])]
+        centroid: Point3<f64>,
+        #[schema(value_type = Option<[f64; 3]>)]
+        center_of_mass: Option<Point3<f64>>,
+    }
+
+    /// Centroid and center of mass of a stack, or of the members of `group`.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/center",
+        tag = "stacks",
+        params(StackParam, GroupQuery),
+        responses((status = 200, description = "OK", body = Center))
+    )]
+    pub async fn stack_center(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Query(GroupQuery { group }): Query<GroupQuery>,
+    ) -> Result<Json<Center>, ApiError> {
+        let molecule = workspace.read().await.read_group(idx, group.as_deref())?;
+        Ok(Json(Center {
+            centroid: geometry::centroid(&molecule.positions()),
+            center_of_mass: molecule.center_of_mass(),
+        }))
+    }
+
+    /// Move a stack to the standard orientation of a group or of all its
+    /// atoms, see `Workspace::orient`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/orient",
+        tag = "stacks",
+        params(StackParam, GroupQuery),
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn orient_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Query(GroupQuery { group }): Query<GroupQuery>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::Orient {
+            stack_idx: idx,
+            group,
+        };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Charge and multiplicity of a stack.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/electronic-state",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = ElectronicState))
+    )]
+    pub async fn stack_electronic_state(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<Json<ElectronicState>, ApiError> {
+        Ok(Json(workspace.read().await.electronic_state(idx)?))
+    }
+
+    /// Set the charge and multiplicity of a stack, see
+    /// `Workspace::set_electronic_state`.
+    #[utoipa::path(
+        put,
+        path = "/ws/{ws}/stack/{idx}/electronic-state",
+        tag = "stacks",
+        params(StackParam),
+        request_body = ElectronicState,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn set_stack_electronic_state(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(state): Json<ElectronicState>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::SetElectronicState {
+            stack_idx: idx,
+            state,
+        };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// An optimizer of the settings, by name.
+    #[derive(Deserialize, ToSchema)]
+    pub struct OptimizerChoice {
+        optimizer: String,
+    }
+
+    /// Run an optimizer of the settings on a stack and push the new geometry
+    /// as a layer named after the method, see `Optimizer::run`. The
+    /// workspace is not locked while the program runs; if the stack changes
+    /// in the meantime, the result is dropped with 409.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/optimize",
+        tag = "stacks",
+        params(StackParam),
+        request_body = OptimizerChoice,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn optimize_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Extension(Optimizers(optimizers)): Extension<Optimizers>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(OptimizerChoice { optimizer }): Json<OptimizerChoice>,
+    ) -> Result<StatusCode, ApiError> {
+        let Some(optimizer) = optimizers.get(&optimizer).cloned() else {
+            let message = format!("No optimizer {optimizer}");
+            return Err(ApiError::new(StatusCode::NOT_FOUND, "NoSuchOptimizer", message));
+        };
+        let molecule = workspace.read().await.read(idx)?;
+        let input = molecule.clone();
+        let layer = tokio::task::spawn_blocking(move || optimizer.run(&input))
+            .await
+            .map_err(|err| {
+                let message = format!("Optimizer task failed: {err}");
+                ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "OptimizerFailed", message)
+            })??;
+        let mut workspace = workspace.lock().await;
+        if workspace.read(idx)? != molecule {
+            return Err(LMECoreError::StackChanged(idx).into());
+        }
+        let operation = Operation::AddLayers {
+            stack_idx: idx,
+            layers: vec![layer],
+        };
+        workspace.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Metadata of the atoms of a stack that have some.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/metadata",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = HashMap<usize, AtomMetadata>))
+    )]
+    pub async fn stack_metadata(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<Json<HashMap<usize, AtomMetadata>>, ApiError> {
+        let molecule = workspace.read().await.read(idx)?;
+        Ok(Json(molecule.all_metadata().clone()))
+    }
+
+    /// Overlay metadata on atoms of a stack, see `Workspace::set_metadata`.
+    /// Responds with the number of atoms written.
+    #[utoipa::path(
+        patch,
+        path = "/ws/{ws}/stack/{idx}/metadata",
+        tag = "stacks",
+        params(StackParam),
+        request_body = HashMap<usize, AtomMetadata>,
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn set_stack_metadata(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(metadata): Json<HashMap<usize, AtomMetadata>>,
+    ) -> Result<Json<usize>, ApiError> {
+        let operation = Operation::SetMetadata {
+            stack_idx: idx,
+            metadata,
+        };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Affected(count) => Ok(Json(count)),
+            output => unreachable!("SetMetadata returned {output:?}"),
+        }
+    }
+
+    /// Apply several edits to a stack at once, see `Workspace::batch`.
+    /// Responds with the failure of each edit if any edit fails.
+    #[utoipa::path(
+        patch,
+        path = "/ws/{ws}/stack/{idx}/batch",
+        tag = "stacks",
+        params(StackParam),
+        request_body = Vec<BatchEdit>,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn batch_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(edits): Json<Vec<BatchEdit>>,
+    ) -> Result<StatusCode, ApiError> {
+        let operation = Operation::Batch {
+            stack_idx: idx,
+            edits,
+        };
+        workspace.lock().await.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct DiffParam {
+        /// Stack index
+        a: usize,
+        /// Stack index
+        b: usize,
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct DiffQuery {
+        /// Atoms moved no further, in Å, are unchanged
+        #[serde(default)]
+        tolerance: f64,
+    }
+
+    /// Atoms to superpose on, see `Workspace::superposition`
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct MatchQuery {
+        /// Comma separated atom indices or names
+        #[serde(default)]
+        atoms: String,
+        /// Group to superpose on
+        #[serde(default)]
+        group: Option<String>,
+    }
+
+    /// RMSD between stack `a` and stack `b` after superposing them.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{a}/rmsd/{b}",
+        tag = "stacks",
+        params(DiffParam, MatchQuery),
+        responses((status = 200, description = "OK", body = f64, content_type = "application/json"))
+    )]
+    pub async fn stack_rmsd(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(DiffParam { a, b }): Path<DiffParam>,
+        Query(MatchQuery { atoms, group }): Query<MatchQuery>,
+    ) -> Result<Json<f64>, ApiError> {
+        let workspace = workspace.read().await;
+        let atoms = workspace.resolve_atoms(&atoms)?;
+        let (_, rmsd) = workspace.superposition(a, b, atoms, group)?;
+        Ok(Json(rmsd))
+    }
+
+    /// Superpose stack `a` onto stack `b` as a layer of `a`, returning the
+    /// RMSD left, see `Workspace::align_to`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{a}/align-to/{b}",
+        tag = "stacks",
+        params(DiffParam, MatchQuery),
+        responses((status = 200, description = "OK", body = f64, content_type = "application/json"))
+    )]
+    pub async fn align_stack(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(DiffParam { a, b }): Path<DiffParam>,
+        Query(MatchQuery { atoms, group }): Query<MatchQuery>,
+    ) -> Result<Json<f64>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let operation = Operation::AlignTo {
+            stack_idx: a,
+            reference: b,
+            atoms: workspace.resolve_atoms(&atoms)?,
+            group,
+        };
+        match workspace.apply(operation)? {
+            OperationOutput::Rmsd(rmsd) => Ok(Json(rmsd)),
+            output => unreachable!("AlignTo returned {output:?}"),
+        }
+    }
+
+    /// Changes from stack `a` to stack `b`, see `Molecule::diff`. Atoms
+    /// moved by no more than `tolerance` (0 if not given) are not listed.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/diff/{a}/{b}",
+        tag = "stacks",
+        params(DiffParam, DiffQuery),
+        responses((status = 200, description = "OK", body = MoleculeDiff))
+    )]
+    pub async fn diff_stacks(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(DiffParam { a, b }): Path<DiffParam>,
+        Query(DiffQuery { tolerance }): Query<DiffQuery>,
+    ) -> Result<Json<MoleculeDiff>, ApiError> {
+        let (a, b) = {
+            let workspace = workspace.read().await;
+            (workspace.read(a)?, workspace.read(b)?)
+        };
+        Ok(Json(a.diff(&b, tolerance)))
+    }
+
+    /// Molecule of a stack along with computed properties.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/full",
+        tag = "stacks",
+        params(StackParam, PropsParam),
+        responses((status = 200, description = "OK", body = FullStack))
+    )]
+    pub async fn read_full(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Query(PropsParam { props }): Query<PropsParam>,
+    ) -> Result<Json<FullStack>, ApiError> {
+        let requested = props
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(str::parse::<Property>)
+            .collect::<Result<Vec<_>, _>>()?;
+        let molecule = workspace.read().await.read(idx)?;
+        let properties = requested
+            .into_iter()
+            .map(|property| (property, molecule.property(property)))
+            .collect();
+        Ok(Json(FullStack {
+            molecule,
+            properties,
+        }))
+    }
+
+    /// The workspace as a `WorkspaceExport`, streamed to the client as it
+    /// is serialized and gzipped if its `Accept-Encoding` allows. It is
+    /// JSON, or MessagePack or CBOR if the `Accept` header asks for
+    /// `application/msgpack` or `application/cbor`. Only the atoms of the
+    /// groups named in the query are exported if any are, each as a `class`
+    /// parameter or together, comma separated, as `groups`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/export",
+        tag = "workspace",
+        params(
+            ("class" = Option<Vec<String>>, Query, description = "A group, repeatable; only the \
+                atoms of the groups given are exported"),
+            ("groups" = Option<String>, Query, description = "Comma separated groups, another \
+                way to give them"),
+            ("Accept" = Option<String>, Header, description = "Format of the body, JSON if none"),
+            ("Accept-Encoding" = Option<String>, Header, description = "`gzip` for a gzipped body"),
+        ),
+        responses((status = 200, description = "OK", content(
+            ("application/json" = WorkspaceExport),
+            ("application/msgpack" = WorkspaceExport),
+            ("application/cbor" = WorkspaceExport)
+        )))
+    )]
+    pub async fn workspace_export(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Query(params): Query<Vec<(String, String)>>,
+        headers: HeaderMap,
+    ) -> Result<Response, ApiError> {
+        let format = BodyFormat::accepted(&headers).ok_or_else(|| {
+            let media_types = BodyFormat::MEDIA_TYPES.map(|(media_type, _)| media_type);
+            let message = format!("A workspace is exported as one of {}", media_types.join(", "));
+            ApiError::new(StatusCode::NOT_ACCEPTABLE, "NotAcceptable", message)
+        })?;
+        let mut snapshot = workspace.read().await.snapshot();
+        let mut filtered = false;
+        let mut groups = vec![];
+        for (key, value) in params {
+            match key.as_str() {
+                "class" => groups.push(value),
+                "groups" => groups.extend(
+                    value
+                        .split(',')
+                        .filter(|group| !group.is_empty())
+                        .map(str::to_string),
+                ),
+                _ => continue,
+            }
+            filtered = true;
+        }
+        if filtered {
+            snapshot = snapshot.restrict(&groups);
+        }
+        // the stack trees share their layers with the workspace
+        let export = WorkspaceExport::try_from(snapshot)?;
+        Ok(streamed(export, format, accepts_gzip(&headers)))
+    }
+
+    /// Replace the whole workspace with one rebuilt from an export, the
+    /// inverse of `workspace_export`. Besides the export, the body may hold
+    /// `names`, atom names by atom index such as an id map kept by another
+    /// tool, replacing the names of the export for those atoms. Invalid
+    /// bodies (including atom names shared by two atoms) are rejected with
+    /// 400 and leave it untouched, as are names of atoms no stack has (422)
+    /// and `names` that would be shared (409, with the atoms of each).
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/import",
+        tag = "workspace",
+        request_body = WorkspaceExport,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn workspace_import(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        body: Result<Json<Value>, JsonRejection>,
+    ) -> Result<StatusCode, ApiError> {
+        let invalid = |err: String| ApiError::new(StatusCode::BAD_REQUEST, "InvalidBody", err);
+        let Json(mut body) = body.map_err(|rejection| invalid(rejection.body_text()))?;
+        let names = body.as_object_mut().and_then(|body| body.remove("names"));
+        let export: WorkspaceExport =
+            serde_json::from_value(body).map_err(|err| invalid(err.to_string()))?;
+        let names: HashMap<usize, String> = names
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(|err| invalid(err.to_string()))?
+            .unwrap_or_default();
+        let mut imported = Workspace::try_from(&export)?;
+        for atom_idx in names.keys() {
+            imported.check_atom(*atom_idx)?;
+        }
+        imported.atom_names.extend(names)?;
+        let mut workspace = workspace.lock().await;
+        let history_depth = workspace.history_depth();
+        workspace.replace(imported.with_history_depth(history_depth), "WorkspaceImport");
+        Ok(StatusCode::OK)
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    pub struct StacksParam {
+        stacks: Vec<usize>,
+    }
+
+    /// Make stacks share their equal leading layers, returning how many.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/tree/promote",
+        tag = "stacks",
+        request_body = StacksParam,
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn promote_prefix(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Json(StacksParam { stacks }): Json<StacksParam>,
+    ) -> Result<Json<usize>, ApiError> {
+        match workspace
+            .lock()
+            .await
+            .apply(Operation::PromotePrefix { stacks })?
+        {
+            OperationOutput::PrefixLength(length) => Ok(Json(length)),
+            output => unreachable!("PromotePrefix returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct AtomNameParam {
+        /// Index or name of the atom, see `Workspace::resolve_atom`
+        idx: String,
+        /// Name
+        name: String,
+    }
+
+    /// Name an atom, which may be given by its current name to rename it.
+    #[utoipa::path(
+        put,
+        path = "/ws/{ws}/names/{idx}/{name}",
+        tag = "names and groups",
+        params(AtomNameParam),
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn set_atom_name(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(AtomNameParam { idx, name }): Path<AtomNameParam>,
+    ) -> Result<StatusCode, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let operation = Operation::SetAtomName {
+            atom_idx: workspace.resolve_atom(&idx)?,
+            name,
+        };
+        workspace.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Every atom name, by atom index.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/names",
+        tag = "names and groups",
+        responses((status = 200, description = "OK", body = BTreeMap<usize, String>))
+    )]
+    pub async fn atom_names(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+    ) -> Json<BTreeMap<usize, String>> {
+        let workspace = workspace.read().await;
+        let names = workspace.atom_names.iter();
+        Json(names.map(|(idx, name)| (*idx, name.clone())).collect())
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct NameParam {
+        /// Name
+        name: String,
+    }
+
+    /// The atom holding a name, 404 if none does.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/names/{name}",
+        tag = "names and groups",
+        params(NameParam),
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn named_atom(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(NameParam { name }): Path<NameParam>,
+    ) -> Result<Json<usize>, ApiError> {
+        let workspace = workspace.read().await;
+        let atom = workspace.atom_names.get_by_value(&name);
+        Ok(Json(*atom.ok_or(LMECoreError::NoSuchName(name.clone()))?))
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct GroupParam {
+        /// Index or name of the atom, see `Workspace::resolve_atom`
+        idx: String,
+        /// Group name
+        group: String,
+    }
+
+    /// Add an atom to a group. Reports `Existed` rather than failing when
+    /// the atom is in the group already, so retried requests are harmless.
+    #[utoipa::path(
+        put,
+        path = "/ws/{ws}/groups/{idx}/{group}",
+        tag = "names and groups",
+        params(GroupParam),
+        responses((
+            status = 200,
+            description = "OK",
+            body = String,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn add_to_group(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(GroupParam { idx, group }): Path<GroupParam>,
+    ) -> Result<Json<n_to_n::InsertResult>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let operation = Operation::AddToGroup {
+            atom_idx: workspace.resolve_atom(&idx)?,
+            group,
+        };
+        match workspace.apply(operation)? {
+            OperationOutput::Membership(result) => Ok(Json(result)),
+            output => unreachable!("AddToGroup returned {output:?}"),
+        }
+    }
+
+    /// Take an atom out of a group.
+    #[utoipa::path(
+        delete,
+        path = "/ws/{ws}/groups/{idx}/{group}",
+        tag = "names and groups",
+        params(GroupParam),
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn remove_from_group(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(GroupParam { idx, group }): Path<GroupParam>,
+    ) -> Result<StatusCode, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let operation = Operation::RemoveFromGroup {
+            atom_idx: workspace.resolve_atom(&idx)?,
+            group,
+        };
+        workspace.apply(operation)?;
+        Ok(StatusCode::OK)
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct GroupNameParam {
+        /// Group name
+        group: String,
+    }
+
+    /// Every group with the number of atoms in it.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/groups",
+        tag = "names and groups",
+        responses((status = 200, description = "OK", body = BTreeMap<String, usize>))
+    )]
+    pub async fn group_sizes(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+    ) -> Json<BTreeMap<String, usize>> {
+        let workspace = workspace.read().await;
+        let groups = &workspace.groups;
+        let sizes = groups.get_lefts().into_iter().map(|group| {
+            let size = groups.count_left(&group);
+            (group, size)
+        });
+        Json(sizes.collect())
+    }
+
+    /// Atoms of a group in ascending order, empty for a group nobody is in.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/groups/{group}",
+        tag = "names and groups",
+        params(GroupNameParam),
+        responses((status = 200, description = "OK", body = Vec<usize>))
+    )]
+    pub async fn group_members(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(GroupNameParam { group }): Path<GroupNameParam>,
+    ) -> Json<Vec<usize>> {
+        let mut members = Vec::from_iter(workspace.read().await.groups.get_left(&group));
+        members.sort();
+        Json(members)
+    }
+
+    /// Atoms to add to a group at once: listed, or selected from a stack
+    /// with a predicate as `select_atoms` would.
+    #[derive(Deserialize, ToSchema)]
+    pub enum GroupAssignment {
+        Atoms(Vec<AtomRef>),
+        Selection {
+            stack_idx: usize,
+            predicate: Predicate,
+        },
+    }
+
+    /// Add many atoms to a group in one operation, returning them in
+    /// ascending order. Nothing is added if a listed atom is not live.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/groups/{group}",
+        tag = "names and groups",
+        params(GroupNameParam),
+        request_body = GroupAssignment,
+        responses((status = 200, description = "OK", body = BTreeSet<usize>))
+    )]
+    pub async fn assign_group(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(GroupNameParam { group }): Path<GroupNameParam>,
+        Json(assignment): Json<GroupAssignment>,
+    ) -> Result<Json<BTreeSet<usize>>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let atoms = match assignment {
+            GroupAssignment::Atoms(atoms) => BTreeSet::from_iter(workspace.resolve_refs(&atoms)?),
+            GroupAssignment::Selection {
+                stack_idx,
+                predicate,
+            } => workspace.select(stack_idx, &predicate)?,
+        };
+        let members = atoms.iter().map(|idx| (*idx, group.clone())).collect();
+        workspace.apply(Operation::AddToGroups { members })?;
+        Ok(Json(atoms))
+    }
+
+    /// Dissolve a group, returning its former members.
+    #[utoipa::path(
+        delete,
+        path = "/ws/{ws}/groups/{group}",
+        tag = "names and groups",
+        params(GroupNameParam),
+        responses((status = 200, description = "OK", body = Vec<usize>))
+    )]
+    pub async fn remove_group(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(GroupNameParam { group }): Path<GroupNameParam>,
+    ) -> Result<Json<Vec<usize>>, ApiError> {
+        match workspace
+            .lock()
+            .await
+            .apply(Operation::RemoveGroup { group })?
+        {
+            OperationOutput::Atoms(members) => Ok(Json(members)),
+            output => unreachable!("RemoveGroup returned {output:?}"),
+        }
+    }
+
+    /// Shares its path with the `:idx/:group` membership routes, so the
+    /// parameter names are borrowed from those.
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct GroupRenameParam {
+        /// Current group name
+        #[serde(rename = "idx")]
+        from: String,
+        /// New group name
+        #[serde(rename = "group")]
+        to: String,
+    }
+
+    /// Rename a group, merging it into `to` if that group has members
+    /// already. Returns the number of atoms moved.
+    #[utoipa::path(
+        patch,
+        path = "/ws/{ws}/groups/{idx}/{group}",
+        tag = "names and groups",
+        params(GroupRenameParam),
+        responses((
+            status = 200,
+            description = "OK",
+            body = usize,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn rename_group(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(GroupRenameParam { from, to }): Path<GroupRenameParam>,
+    ) -> Result<Json<usize>, ApiError> {
+        match workspace
+            .lock()
+            .await
+            .apply(Operation::RenameGroup { from, to })?
+        {
+            OperationOutput::Affected(affected) => Ok(Json(affected)),
+            output => unreachable!("RenameGroup returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct AtomParam {
+        /// Index or name of the atom, see `Workspace::resolve_atom`
+        idx: String,
+    }
+
+    /// Take an atom out of every group, returning the groups it left.
+    #[utoipa::path(
+        delete,
+        path = "/ws/{ws}/atoms/{idx}/groups",
+        tag = "names and groups",
+        params(AtomParam),
+        responses((status = 200, description = "OK", body = Vec<String>))
+    )]
+    pub async fn remove_from_all_groups(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(AtomParam { idx }): Path<AtomParam>,
+    ) -> Result<Json<Vec<String>>, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let atom_idx = workspace.resolve_atom(&idx)?;
+        match workspace.apply(Operation::RemoveFromAllGroups { atom_idx })? {
+            OperationOutput::Groups(groups) => Ok(Json(groups)),
+            output => unreachable!("RemoveFromAllGroups returned {output:?}"),
+        }
+    }
+
+    /// Groups an atom belongs to, sorted by name.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/atoms/{idx}/groups",
+        tag = "names and groups",
+        params(AtomParam),
+        responses((status = 200, description = "OK", body = Vec<String>))
+    )]
+    pub async fn atom_groups(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(AtomParam { idx }): Path<AtomParam>,
+    ) -> Result<Json<Vec<String>>, ApiError> {
+        let workspace = workspace.read().await;
+        let atom_idx = workspace.resolve_atom(&idx)?;
+        let mut groups = Vec::from_iter(workspace.groups.get_right(&atom_idx));
+        groups.sort();
+        Ok(Json(groups))
+    }
+
+    /// Name many atoms under one lock, see `Workspace::set_atom_names` for
+    /// how conflicts are handled.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/names",
+        tag = "names and groups",
+        request_body = HashMap<usize, String>,
+        responses((status = 200, description = "OK", body = BTreeMap<usize, Object>))
+    )]
+    pub async fn set_atom_names(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Json(names): Json<HashMap<usize, String>>,
+    ) -> Result<Json<BTreeMap<usize, InsertResult<usize, String>>>, ApiError> {
+        match workspace
+            .lock()
+            .await
+            .apply(Operation::SetAtomNames { names })?
+        {
+            OperationOutput::Names(results) => Ok(Json(results)),
+            output => unreachable!("SetAtomNames returned {output:?}"),
+        }
+    }
+
+    /// Add many atoms to groups under one lock; nothing is added if any
+    /// atom is not live.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/groups",
+        tag = "names and groups",
+        request_body = Vec<(AtomRef, String)>,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn add_to_groups(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Json(members): Json<Vec<(AtomRef, String)>>,
+    ) -> Result<StatusCode, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let members = members
+            .into_iter()
+            .map(|(atom, group)| Ok((atom.resolve(&workspace)?, group)))
+            .collect::<Result<_, LMECoreError>>()?;
+        workspace.apply(Operation::AddToGroups { members })?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Operations applied to the workspace.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/log",
+        tag = "workspace",
+        responses((status = 200, description = "OK", body = OperationLog))
+    )]
+    pub async fn operation_log(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+    ) -> Json<OperationLog> {
+        Json(workspace.read().await.log().clone())
+    }
+
+    /// Audit trail entries to list, all of them by default.
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct HistoryFilter {
+        /// Only entries editing this stack
+        stack: Option<usize>,
+        /// Only entries from this time on, in milliseconds since the epoch
+        since: Option<u64>,
+        /// Only entries from before this time
+        until: Option<u64>,
+    }
+
+    /// The audit trail of the workspace, oldest entry first.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/history",
+        tag = "workspace",
+        params(HistoryFilter),
+        responses((status = 200, description = "OK", body = Vec<AuditEntry>))
+    )]
+    pub async fn audit_history(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Query(filter): Query<HistoryFilter>,
+    ) -> Json<Vec<AuditEntry>> {
+        let workspace = workspace.read().await;
+        let entries = workspace
+            .audit()
+            .iter()
+            .filter(|entry| filter.stack.is_none_or(|idx| entry.stacks.contains(&idx)))
+            .filter(|entry| filter.since.is_none_or(|since| entry.time >= since))
+            .filter(|entry| filter.until.is_none_or(|until| entry.time < until))
+            .cloned()
+            .collect();
+        Json(entries)
+    }
+
+    /// Replace the workspace with a fresh one on the same base, rebuilt by
+    /// applying `operations` in order. The workspace is left untouched if
+    /// any operation fails.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/replay",
+        tag = "workspace",
+        request_body = Vec<Operation>,
+        responses((status = 200, description = "OK"))
+    )]
+    pub async fn replay(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Json(operations): Json<Vec<Operation>>,
+    ) -> Result<StatusCode, ApiError> {
+        let mut workspace = workspace.lock().await;
+        let replayed = workspace.replay(operations)?;
+        workspace.replace(replayed, "Replay");
+        Ok(StatusCode::OK)
+    }
+
+    /// The stacks as a tree of shared layers.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/tree",
+        tag = "stacks",
+        responses((status = 200, description = "OK", body = Vec<TreeNode>))
+    )]
+    pub async fn workspace_tree(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+    ) -> Result<Json<Vec<TreeNode>>, ApiError> {
+        Ok(Json(workspace.read().await.tree()?))
+    }
+}
+
+mod chemistry_handler {
+    use std::collections::HashMap;
+
+    use axum::{
+        extract::{Path, Query},
+        http::StatusCode,
+        Extension, Json,
+    };
+    use lme_core::{
+        entity::Molecule,
+        graph::DEFAULT_BOND_TOLERANCE,
+        operation::{Operation, OperationOutput},
+    };
+    use pair::Pair;
+    use serde::Deserialize;
+    use utoipa::IntoParams;
+
+    use crate::{error::ApiError, StackParam, StacksSelect, WorkspaceAccessor};
+
+    /// Set bond orders in a range of stacks.
+    #[utoipa::path(
+        put,
+        path = "/ws/{ws}/stack/bonds",
+        tag = "stacks",
+        params(StacksSelect),
+        request_body = Vec<([usize; 2], f64)>,
+        responses((
+            status = 200,
+            description = "OK",
+            body = bool,
+            content_type = "application/json"
+        ))
+    )]
+    pub async fn modify_bonds(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Query(StacksSelect { start, range }): Query<StacksSelect>,
+        Json(bonds): Json<HashMap<Pair<usize>, f64>>,
+    ) -> Result<Json<bool>, ApiError> {
+        let data = Molecule::default().set_bonds(bonds);
+        workspace
+            .lock()
+            .await
+            .apply(Operation::Write { start, range, data })?;
+        Ok(Json(true))
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct ToleranceParam {
+        /// Slack on the covalent radii
+        tolerance: Option<f64>,
+    }
+
+    /// Bond the atoms of a stack closer than their covalent radii allow, in
+    /// a new layer, returning the new bonds. The slack on the radii is
+    /// `graph::DEFAULT_BOND_TOLERANCE` unless `tolerance` is given.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/perceive-bonds",
+        tag = "stacks",
+        params(StackParam, ToleranceParam),
+        responses((status = 200, description = "OK", body = Vec<[usize; 2]>))
+    )]
+    pub async fn perceive_bonds(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Query(ToleranceParam { tolerance }): Query<ToleranceParam>,
+    ) -> Result<Json<Vec<Pair<usize>>>, ApiError> {
+        let operation = Operation::PerceiveBonds {
+            stack_idx: idx,
+            tolerance: tolerance.unwrap_or(DEFAULT_BOND_TOLERANCE),
+        };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Bonds(bonds) => Ok(Json(bonds)),
+            output => unreachable!("PerceiveBonds returned {output:?}"),
+        }
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct ClusterParam {
+        /// RMSD threshold of a cluster
+        rmsd: f64,
+    }
+
+    /// Stacks clustered by RMSD.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/cluster",
+        tag = "stacks",
+        params(ClusterParam),
+        responses((status = 200, description = "OK", body = Vec<Vec<usize>>))
+    )]
+    pub async fn cluster_stacks(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Query(ClusterParam { rmsd }): Query<ClusterParam>,
+    ) -> Result<Json<Vec<Vec<usize>>>, ApiError> {
+        Ok(Json(workspace.read().await.cluster(rmsd)?))
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct RmsdMatrixParam {
+        /// Comma separated stack indices
+        stacks: String,
+        /// Superpose the stacks first
+        #[serde(default)]
+        align: bool,
+    }
+
+    /// RMSD between every pair of the given stacks.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/rmsd-matrix",
+        tag = "stacks",
+        params(RmsdMatrixParam),
+        responses((status = 200, description = "OK", body = Vec<Vec<f64>>))
+    )]
+    pub async fn rmsd_matrix(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Query(RmsdMatrixParam { stacks, align }): Query<RmsdMatrixParam>,
+    ) -> Result<Json<Vec<Vec<f64>>>, ApiError> {
+        let stacks = stacks
+            .split(',')
+            .filter(|index| !index.is_empty())
+            .map(str::parse::<usize>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| {
+                ApiError::new(StatusCode::BAD_REQUEST, "InvalidStacks", err.to_string())
+            })?;
+        let matrix = workspace.read().await.rmsd_matrix(&stacks, align)?;
+        Ok(Json(matrix.as_ref().clone()))
+    }
+}
+
+mod format_handler {
+    use axum::{extract::Path, Extension, Json};
+    use lme_core::{
+        entity::Molecule,
+        formats::gaussian::GaussianJob,
+        operation::{Operation, OperationOutput},
+    };
+    use serde::Deserialize;
+    use serde_json::Value;
+    use utoipa::IntoParams;
+
+    use crate::{error::ApiError, StackParam, WorkspaceAccessor};
+
+    /// Stack as a Z-matrix.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/export/zmat",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn export_zmat(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<String, ApiError> {
+        Ok(workspace.read().await.read(idx)?.to_zmatrix()?)
+    }
+
+    /// Stack as a CIF file; needs a unit cell.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/export/cif",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn export_cif(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<String, ApiError> {
+        Ok(workspace.read().await.read(idx)?.to_cif(&format!("stack_{idx}"))?)
+    }
+
+    /// Stack as an XYZ file, extended XYZ with `Lattice` and `pbc` when it has
+    /// a unit cell.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/export/xyz",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn export_xyz(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<String, ApiError> {
+        Ok(workspace.read().await.read(idx)?.to_xyz(&format!("stack {idx}")))
+    }
+
+    /// Gaussian input of a stack, see `Molecule::to_gaussian`. The title
+    /// defaults to the stack name.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/export/gaussian",
+        tag = "stacks",
+        params(StackParam),
+        request_body = GaussianJob,
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn export_gaussian(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        Json(job): Json<GaussianJob>,
+    ) -> Result<String, ApiError> {
+        let workspace = workspace.read().await;
+        let molecule = workspace.read(idx)?;
+        let state = workspace.electronic_state(idx)?;
+        let title = &workspace.stack_names()[idx];
+        Ok(molecule.to_gaussian(&job, state, title, &workspace.groups)?)
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Path)]
+    pub struct TemplateExportParam {
+        /// Stack index
+        idx: usize,
+        /// Name
+        name: String,
+    }
+
+    /// A stack rendered with a library template, see
+    /// `Workspace::template_context` for what the template is given. The
+    /// request body, if any, is passed to the template as `params`.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/export/template/{name}",
+        tag = "stacks",
+        params(TemplateExportParam),
+        request_body = Option<Object>,
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn export_template(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(TemplateExportParam { idx, name }): Path<TemplateExportParam>,
+        params: Option<Json<Value>>,
+    ) -> Result<String, ApiError> {
+        let params = params.map_or(Value::Null, |Json(params)| params);
+        Ok(workspace.read().await.render_template(idx, &name, params)?)
+    }
+
+    /// Stack as an MDL molfile.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/export/mol",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn export_mol(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<String, ApiError> {
+        let workspace = workspace.read().await;
+        let molecule = workspace.read(idx)?;
+        Ok(molecule.to_molfile(&workspace.stack_names()[idx]))
+    }
+
+    /// Stack as a Tripos MOL2 file.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/export/mol2",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn export_mol2(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<String, ApiError> {
+        let workspace = workspace.read().await;
+        let molecule = workspace.read(idx)?;
+        Ok(molecule.to_mol2(&workspace.stack_names()[idx]))
+    }
+
+    /// Add the atoms of a MOL2 file to a stack, returning their indices.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/import/mol2",
+        tag = "stacks",
+        params(StackParam),
+        request_body(content = String, content_type = "text/plain"),
+        responses((status = 200, description = "OK", body = Vec<usize>))
+    )]
+    pub async fn import_mol2(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        body: String,
+    ) -> Result<Json<Vec<usize>>, ApiError> {
+        let operation = Operation::Import {
+            stack_idx: idx,
+            molecule: Molecule::from_mol2(&body)?,
+        };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Atoms(added) => Ok(Json(added)),
+            output => unreachable!("Import returned {output:?}"),
+        }
+    }
+
+    /// Stack as a PDB file.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/export/pdb",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn export_pdb(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<String, ApiError> {
+        Ok(workspace.read().await.read(idx)?.to_pdb())
+    }
+
+    /// Add the atoms of a PDB file to a stack, returning their indices.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/import/pdb",
+        tag = "stacks",
+        params(StackParam),
+        request_body(content = String, content_type = "text/plain"),
+        responses((status = 200, description = "OK", body = Vec<usize>))
+    )]
+    pub async fn import_pdb(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        body: String,
+    ) -> Result<Json<Vec<usize>>, ApiError> {
+        let operation = Operation::Import {
+            stack_idx: idx,
+            molecule: Molecule::from_pdb(&body)?,
+        };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Atoms(added) => Ok(Json(added)),
+            output => unreachable!("Import returned {output:?}"),
+        }
+    }
+
+    /// Stack as a SMILES string.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/stack/{idx}/smiles",
+        tag = "stacks",
+        params(StackParam),
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn export_smiles(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+    ) -> Result<String, ApiError> {
+        Ok(workspace.read().await.read(idx)?.to_smiles())
+    }
+
+    /// One SDF record per stack, titled by the stack name.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/export/sdf",
+        tag = "workspace",
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn export_sdf(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+    ) -> Result<String, ApiError> {
+        let workspace = workspace.read().await;
+        let molecules = (0..workspace.stack_names().len())
+            .map(|idx| workspace.read(idx))
+            .collect::<Result<Vec<_>, _>>()?;
+        let records = workspace
+            .stack_names()
+            .iter()
+            .map(String::as_str)
+            .zip(&molecules);
+        Ok(Molecule::to_sdf(records))
+    }
+
+    /// The layer tree as a Graphviz digraph, see `Workspace::to_dot`.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/export/dot",
+        tag = "stacks",
+        responses((status = 200, description = "OK", body = String, content_type = "text/plain"))
+    )]
+    pub async fn export_dot(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+    ) -> Result<String, ApiError> {
+        Ok(workspace.read().await.to_dot()?)
+    }
+
+    /// Add the atoms of a molfile to a stack, returning their indices.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/import/mol",
+        tag = "stacks",
+        params(StackParam),
+        request_body(content = String, content_type = "text/plain"),
+        responses((status = 200, description = "OK", body = Vec<usize>))
+    )]
+    pub async fn import_mol(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        body: String,
+    ) -> Result<Json<Vec<usize>>, ApiError> {
+        let operation = Operation::Import {
+            stack_idx: idx,
+            molecule: Molecule::from_molfile(&body)?,
+        };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Atoms(added) => Ok(Json(added)),
+            output => unreachable!("Import returned {output:?}"),
+        }
+    }
+
+    /// Add the atoms of an XYZ file to a stack, returning their indices; the
+    /// cell of an extended XYZ file is set as the stack cell.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/import/xyz",
+        tag = "stacks",
+        params(StackParam),
+        request_body(content = String, content_type = "text/plain"),
+        responses((status = 200, description = "OK", body = Vec<usize>))
+    )]
+    pub async fn import_xyz(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        body: String,
+    ) -> Result<Json<Vec<usize>>, ApiError> {
+        let operation = Operation::Import {
+            stack_idx: idx,
+            molecule: Molecule::from_xyz(&body)?,
+        };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Atoms(added) => Ok(Json(added)),
+            output => unreachable!("Import returned {output:?}"),
+        }
+    }
+
+    /// Add the atoms of the first data block of a CIF file to a stack with
+    /// every image of its symmetry operations, returning their indices; its
+    /// cell is set as the stack cell.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/import/cif",
+        tag = "stacks",
+        params(StackParam),
+        request_body(content = String, content_type = "text/plain"),
+        responses((status = 200, description = "OK", body = Vec<usize>))
+    )]
+    pub async fn import_cif(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        body: String,
+    ) -> Result<Json<Vec<usize>>, ApiError> {
+        let operation = Operation::Import {
+            stack_idx: idx,
+            molecule: Molecule::from_cif(&body)?,
+        };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Atoms(added) => Ok(Json(added)),
+            output => unreachable!("Import returned {output:?}"),
+        }
+    }
+
+    /// Add the atoms of a Z-matrix to a stack, returning their indices.
+    #[utoipa::path(
+        post,
+        path = "/ws/{ws}/stack/{idx}/import/zmat",
+        tag = "stacks",
+        params(StackParam),
+        request_body(content = String, content_type = "text/plain"),
+        responses((status = 200, description = "OK", body = Vec<usize>))
+    )]
+    pub async fn import_zmat(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        Path(StackParam { idx }): Path<StackParam>,
+        body: String,
+    ) -> Result<Json<Vec<usize>>, ApiError> {
+        let operation = Operation::Import {
+            stack_idx: idx,
+            molecule: Molecule::from_zmatrix(&body)?,
+        };
+        match workspace.lock().await.apply(operation)? {
+            OperationOutput::Atoms(added) => Ok(Json(added)),
+            output => unreachable!("Import returned {output:?}"),
+        }
+    }
+}
+
+mod channel_handler {
+    use axum::{
+        extract::ws::{Message, WebSocket, WebSocketUpgrade},
+        response::Response,
+        Extension,
+    };
+    use lme_core::operation::{Change, Operation};
+    use serde_json::json;
+    use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+    use crate::WorkspaceAccessor;
+
+    /// Command channel: every text message is a JSON `Operation`, applied to
+    /// the workspace under its lock and answered with `{"Ok": output}` or
+    /// `{"Err": error}` in the order received.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/channel",
+        tag = "workspace",
+        responses((status = 101, description = "Switched to a WebSocket"))
+    )]
+    pub async fn workspace_channel(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        upgrade: WebSocketUpgrade,
+    ) -> Response {
+        upgrade.on_upgrade(move |socket| serve_channel(socket, workspace))
+    }
+
+    async fn serve_channel(mut socket: WebSocket, workspace: WorkspaceAccessor) {
+        while let Some(Ok(message)) = socket.recv().await {
+            let reply = match message {
+                Message::Text(text) => match serde_json::from_str::<Operation>(&text) {
+                    Ok(operation) => {
+                        let result = workspace.lock().await.apply(operation);
+                        serde_json::to_string(&result).expect("Operation results serialize")
+                    }
+                    Err(err) => {
+                        json!({ "Err": { "InvalidOperation": err.to_string() } }).to_string()
+                    }
+                },
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            if socket.send(Message::Text(reply)).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Change feed: every operation applied to the workspace, by any client,
+    /// is sent as a JSON `Change`, with the molecules of the stacks it edited
+    /// and the names and groups it changed, so watchers can follow along
+    /// without reading the workspace back. A watcher too slow to keep up gets
+    /// `{"Lagged": missed}` and continues with the following changes.
+    #[utoipa::path(
+        get,
+        path = "/ws/{ws}/events",
+        tag = "workspace",
+        responses((status = 101, description = "Switched to a WebSocket"))
+    )]
+    pub async fn workspace_events(
+        Extension(workspace): Extension<WorkspaceAccessor>,
+        upgrade: WebSocketUpgrade,
+    ) -> Response {
+        // only the receiver is kept, so the feed ends with the workspace
+        let changes = workspace.subscribe();
+        upgrade.on_upgrade(move |socket| watch_changes(socket, changes))
+    }
+
+    async fn watch_changes(mut socket: WebSocket, mut changes: Receiver<Change>) {
+        loop {
+            let message = tokio::select! {
+                change = changes.recv() => match change {
+                    Ok(change) => serde_json::to_string(&change).expect("Changes serialize"),
+                    Err(RecvError::Lagged(missed)) => json!({ "Lagged": missed }).to_string(),
+                    Err(RecvError::Closed) => break,
+                },
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+            };
+            if socket.send(Message::Text(message)).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+mod docs_handler {
+    use lme_core::{
+        cell::Cell,
+        diff::{AtomChange, BondChange, MoleculeDiff},
+        entity::{Atom, AtomMetadata, Layer, LayerMeta, Mirror, Molecule, MoleculePatch, Residue},
+        formats::gaussian::{GaussianJob, Oniom},
+        fragment::{Fragment, FragmentRef},
+        operation::{AuditEntry, BatchEdit, Operation, OperationLog, RigidMotion},
+        properties::{Measurement, Property},
+        selection::{Predicate, Region},
+        symmetry::{Symmetry, SymmetryCopy, SymmetryOperation},
+        validation::{Overlap, ValenceProblem, Validation},
+        AtomRef, ElectronicState, LayerSummary, StackSummary, StackTree, TreeNode, WorkspaceExport,
+    };
+    use utoipa::{
+        openapi::{
+            path::{ParameterBuilder, ParameterIn},
+            schema::Ref,
+            security::{Http, HttpAuthScheme, SecurityScheme},
+            ContentBuilder, ObjectBuilder, Required, ResponseBuilder, SchemaType,
+        },
+        IntoParams, Modify, OpenApi,
+    };
+
+    use super::*;
+    use crate::{
+        auth::{NewToken, Role},
+        error::{EditError, ErrorBody},
+    };
+
+    /// OpenAPI description of every route and its bodies, served under
+    /// `/docs` along with Swagger UI.
+    #[derive(OpenApi)]
+    #[openapi(
+        info(
+            title = "LME",
+            description = "Layered molecule editing. Every route under `/ws/{ws}/stack/{idx}` is \
+                also reachable as `/ws/{ws}/stacks/{name}` by the name of the stack. Once the \
+                server has API tokens, requests need one of the role they ask for as a bearer \
+                token: `reader` to read and export, `editor` to edit, `admin` to save, load and \
+                manage tokens."
+        ),
+        paths(
+            create_workspace, remove_workspace, save_workspaces, load_saved_workspaces, read_stacks,
+            read_stack, create_stack, write_to_stack, add_layer_to_stack, clone_stack, clone_base,
+            remove_stack, reorder_stacks, clone_stack_at, annotate_layer, cherry_pick,
+            stack_transaction, remove_atoms, move_atoms, copy_atoms, rotate_torsion,
+            set_bond_length, set_bond_angle, mirror_atoms, substitute, fragment_names,
+            read_fragment, set_fragment, remove_fragment, template_names, read_template,
+            set_template, remove_template, replicate_atoms, supercell, create_named_stack,
+            rename_stack, stack_summaries, layer_summaries, undo_stack, redo_stack, renumber_stack,
+            compact_stack, remove_hydrogens, add_hydrogens, stack_formula, measure,
+            stack_components, stack_rings, search_stack, search_stacks, group_components,
+            validate_stack, select_region, select_atoms, stack_center, orient_stack,
+            stack_electronic_state, set_stack_electronic_state, optimize_stack, stack_metadata,
+            set_stack_metadata, batch_stack, stack_rmsd, align_stack, diff_stacks, read_full,
+            workspace_export, workspace_import, promote_prefix, set_atom_name, atom_names,
+            named_atom, add_to_group, remove_from_group, group_sizes, group_members, assign_group,
+            remove_group, rename_group, remove_from_all_groups, atom_groups, set_atom_names,
+            add_to_groups, operation_log, audit_history, replay, workspace_tree, modify_bonds,
+            perceive_bonds, cluster_stacks, rmsd_matrix, export_zmat, export_cif, export_xyz,
+            export_gaussian, export_template, export_mol, export_mol2, import_mol2, export_pdb,
+            import_pdb, export_smiles, export_sdf, export_dot, import_mol, import_xyz, import_cif,
+            import_zmat, workspace_channel, workspace_events, crate::auth::list_tokens,
+            crate::auth::create_token, crate::auth::revoke_token,
+        ),
+        components(schemas(
+            Atom, AtomMetadata, Layer, LayerMeta, Mirror, Molecule, MoleculePatch, Residue, Cell,
+            Fragment, FragmentRef, Region, Predicate, OptimizerChoice, AtomChange, BondChange,
+            MoleculeDiff, ValenceProblem, Overlap, Validation, Property, Measurement, Oniom,
+            GaussianJob, SymmetryOperation, Symmetry, SymmetryCopy, RigidMotion, BatchEdit,
+            Operation, OperationLog, AuditEntry, WorkspaceExport, ElectronicState, StackSummary,
+            LayerSummary, AtomRef, StackTree, TreeNode, SaveParam, Coordinates, StackFormat,
+            CloneStack, CherryPick, Motion, Torsion, BondLength, BondAngle, MirrorSelection,
+            Substitution, Replication, Supercell, Search, RegionSelection, GroupAssignment,
+            StacksParam, FullStack, Formula, Center, Role, NewToken, ErrorBody, EditError,
+        )),
+        modifiers(&Conventions),
+        security(("token" = [])),
+        tags(
+            (name = "server"),
+            (name = "workspace"),
+            (name = "stacks"),
+            (name = "names and groups"),
+            (name = "library")
+        )
+    )]
+    pub struct ApiDoc;
+
+    /// What every route shares instead of declaring it itself, as the
+    /// middlewares handle it: the bearer token, the `ws` of the workspace
+    /// routes, `If-Match` on the stack routes and the `Error` response of
+    /// every failure. Summaries are the first sentence of the doc comment of
+    /// a handler, the whole of which is the description.
+    struct Conventions;
+
+    impl Modify for Conventions {
+        fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+            let components = openapi.components.get_or_insert_with(Default::default);
+            let bearer = SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer));
+            components.add_security_scheme("token", bearer);
+            let error = ResponseBuilder::new()
+                .description("Error")
+                .content(
+                    "application/json",
+                    ContentBuilder::new()
+                        .schema(Ref::from_schema_name("ErrorBody"))
+                        .build(),
+                )
+                .build();
+            components.responses.insert("Error".to_string(), error.into());
+
+            let workspace = WorkspaceParam::into_params(|| Some(ParameterIn::Path));
+            let if_match = ParameterBuilder::new()
+                .name("If-Match")
+                .parameter_in(ParameterIn::Header)
+                .required(Required::False)
+                .description(Some(
+                    "Version of the stack, from its ETag; edits fail with 409 if the stack has \
+                    changed since",
+                ))
+                .schema(Some(ObjectBuilder::new().schema_type(SchemaType::String)))
+                .build();
+            for (path, item) in openapi.paths.paths.iter_mut() {
+                for operation in item.operations.values_mut() {
+                    // utoipa splits doc comments after their first line
+                    let doc = [operation.summary.take(), operation.description.take()]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                        .replace('\n', " ");
+                    let summary = doc.split(". ").next().unwrap_or_default();
+                    operation.summary = Some(summary.trim_end_matches('.').to_string());
+                    operation.description = (summary.len() < doc.len()).then_some(doc);
+                    let parameters = operation.parameters.get_or_insert_with(Vec::new);
+                    if path.starts_with("/ws/{ws}") {
+                        parameters.splice(0..0, workspace.iter().cloned());
+                    }
+                    if path.starts_with("/ws/{ws}/stack/{idx}") {
+                        parameters.push(if_match.clone());
+                    }
+                    let default = Ref::from_response_name("Error").into();
+                    operation.responses.responses.insert("default".to_string(), default);
+                }
+            }
+        }
+    }
 }
 
+pub use channel_handler::*;
+pub use chemistry_handler::*;
+pub use docs_handler::*;
+pub use format_handler::*;
 pub use state_handler::*;
 pub use workspace_handler::*;
@@ -0,0 +1,391 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use lme_core::{operation::AuditEntry, Workspace, WorkspaceExport};
+use serde::{Deserialize, Serialize};
+
+use crate::{write_atomically, ServerState};
+
+const SNAPSHOT: &str = "snapshot.json";
+
+/// What happened to a workspace, in enough detail to do it again.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Record {
+    /// The workspace was created, loaded, imported or replayed
+    Restored(Box<WorkspaceExport>),
+    Removed,
+    /// An operation was applied, the one of the entry
+    Applied(Box<AuditEntry>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Line {
+    seq: u64,
+    ws: String,
+    record: Record,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    /// Journal files of this generation on hold the records since
+    generation: u64,
+    /// Sequence number of the last record written before the snapshot
+    seq: u64,
+    workspaces: HashMap<String, SavedWorkspace>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedWorkspace {
+    /// Sequence number of the last record the export includes
+    seq: u64,
+    export: WorkspaceExport,
+}
+
+/// Autosave of every workspace into a directory: a full snapshot taken
+/// now and then, `snapshot.json`, and a journal of the records since,
+/// `journal-<generation>.jsonl`. Records are appended before the workspace
+/// they are about is unlocked, so the journal has them in the order they
+/// happened. Each record has a sequence number, with which recovery skips
+/// records a snapshot already includes.
+///
+/// Records reach the operating system before the request is answered, so
+/// they survive the server crashing but not necessarily the machine.
+pub struct Journal {
+    directory: PathBuf,
+    writer: Mutex<Writer>,
+}
+
+struct Writer {
+    file: File,
+    generation: u64,
+    /// Sequence number of the last record written
+    seq: u64,
+}
+
+impl Writer {
+    fn write(&mut self, ws: &str, record: Record) -> io::Result<u64> {
+        self.seq += 1;
+        let line = Line {
+            seq: self.seq,
+            ws: ws.to_string(),
+            record,
+        };
+        let mut content = serde_json::to_vec(&line)?;
+        content.push(b'\n');
+        self.file.write_all(&content)?;
+        Ok(self.seq)
+    }
+}
+
+fn journal_path(directory: &Path, generation: u64) -> PathBuf {
+    directory.join(format!("journal-{generation}.jsonl"))
+}
+
+/// Generations of the journal files in `directory`, oldest first.
+fn generations(directory: &Path) -> io::Result<Vec<u64>> {
+    let mut generations = vec![];
+    for entry in fs::read_dir(directory)? {
+        let name = entry?.file_name();
+        let generation = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("journal-"))
+            .and_then(|name| name.strip_suffix(".jsonl"))
+            .and_then(|generation| generation.parse::<u64>().ok());
+        generations.extend(generation);
+    }
+    generations.sort();
+    Ok(generations)
+}
+
+fn restore(export: &WorkspaceExport, history_depth: usize) -> Result<Workspace, String> {
+    Workspace::try_from(export)
+        .map(|workspace| workspace.with_history_depth(history_depth))
+        .map_err(|err| format!("{err:?}"))
+}
+
+/// Workspaces recovered so far, with the sequence number of the last
+/// record each includes.
+type Recovered = HashMap<String, (u64, Workspace)>;
+
+/// Do the record of `line` again, unless the workspace already has it.
+fn redo(recovered: &mut Recovered, line: Line, history_depth: usize) -> Result<(), String> {
+    if let Some((seq, _)) = recovered.get(&line.ws) {
+        if *seq >= line.seq {
+            return Ok(());
+        }
+    }
+    match line.record {
+        Record::Restored(export) => {
+            let workspace = restore(&export, history_depth)?;
+            recovered.insert(line.ws, (line.seq, workspace));
+        }
+        Record::Removed => {
+            recovered.remove(&line.ws);
+        }
+        Record::Applied(entry) => {
+            let Some((seq, workspace)) = recovered.get_mut(&line.ws) else {
+                return Err("No such workspace".to_string());
+            };
+            *seq = line.seq;
+            if let Some(operation) = entry.operation.clone() {
+                workspace
+                    .apply(operation)
+                    .map_err(|err| format!("{err:?}"))?;
+            }
+            workspace.record(*entry);
+        }
+    }
+    Ok(())
+}
+
+impl Journal {
+    /// Recover the workspaces autosaved to `directory`, creating it if
+    /// needed: those of the snapshot, with the journaled records done
+    /// again. Records that fail again, like undoing an edit made before
+    /// the snapshot, whose undo history isn't saved, are skipped with a
+    /// warning, as is a record cut short by a crash.
+    pub fn open(
+        directory: &Path,
+        history_depth: usize,
+    ) -> io::Result<(Arc<Self>, HashMap<String, Workspace>)> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        fs::create_dir_all(directory)?;
+        let snapshot = match fs::read(directory.join(SNAPSHOT)) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Snapshot {
+                generation: 0,
+                seq: 0,
+                workspaces: HashMap::new(),
+            },
+            Err(err) => return Err(err),
+        };
+        let mut recovered = Recovered::new();
+        for (name, saved) in snapshot.workspaces {
+            let workspace = restore(&saved.export, history_depth)
+                .map_err(|err| invalid(format!("Workspace {name}: {err}")))?;
+            recovered.insert(name, (saved.seq, workspace));
+        }
+        let mut seq = snapshot.seq;
+        let generations = generations(directory)?;
+        for generation in &generations {
+            if *generation < snapshot.generation {
+                continue;
+            }
+            let path = journal_path(directory, *generation);
+            for (number, content) in BufReader::new(File::open(&path)?).lines().enumerate() {
+                let location = format!("{}:{}", path.display(), number + 1);
+                let line = match serde_json::from_str::<Line>(&content?) {
+                    Ok(line) => line,
+                    Err(err) => {
+                        eprintln!("Skipping the rest of {location}: {err}");
+                        break;
+                    }
+                };
+                seq = seq.max(line.seq);
+                if let Err(err) = redo(&mut recovered, line, history_depth) {
+                    eprintln!("Skipping the record at {location}: {err}");
+                }
+            }
+        }
+        let generation = generations
+            .last()
+            .map_or(snapshot.generation, |last| last + 1)
+            .max(snapshot.generation);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(journal_path(directory, generation))?;
+        let journal = Self {
+            directory: directory.to_path_buf(),
+            writer: Mutex::new(Writer {
+                file,
+                generation,
+                seq,
+            }),
+        };
+        let workspaces = recovered
+            .into_iter()
+            .map(|(name, (_, workspace))| (name, workspace))
+            .collect();
+        Ok((Arc::new(journal), workspaces))
+    }
+
+    fn writer(&self) -> std::sync::MutexGuard<'_, Writer> {
+        self.writer.lock().expect("Journal lock is never poisoned")
+    }
+
+    /// The journal of workspace `ws`, which the next snapshot will hold.
+    pub fn slot(self: &Arc<Self>, ws: &str) -> Arc<JournalSlot> {
+        Arc::new(JournalSlot {
+            journal: self.clone(),
+            ws: ws.to_string(),
+            state: Mutex::default(),
+        })
+    }
+
+    /// The journal of the new workspace `ws`, starting with a record of it
+    /// as it is.
+    pub fn track(self: &Arc<Self>, ws: &str, workspace: &Workspace) -> Arc<JournalSlot> {
+        let slot = self.slot(ws);
+        slot.append(Record::Restored(Box::new(
+            workspace.snapshot().into_export(),
+        )));
+        slot
+    }
+
+    /// Write a snapshot of every workspace of `state` and remove the
+    /// journal files it makes obsolete. Later records go to a new journal
+    /// file, so workspaces are only locked one at a time, each while its
+    /// snapshot is taken.
+    pub async fn snapshot(&self, state: &ServerState) -> io::Result<()> {
+        let (generation, seq, handles) = {
+            // workspaces are added and removed under the write lock of the
+            // state, so each is either in the list or journaled afterwards
+            let state = state.read().await;
+            let mut writer = self.writer();
+            let generation = writer.generation + 1;
+            writer.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(journal_path(&self.directory, generation))?;
+            writer.generation = generation;
+            let handles = state
+                .iter()
+                .map(|(name, handle)| (name.clone(), handle.clone()))
+                .collect::<Vec<_>>();
+            (generation, writer.seq, handles)
+        };
+        let mut workspaces = HashMap::new();
+        for (name, handle) in handles {
+            if let Some((seq, snapshot)) = handle.journal_snapshot().await {
+                let export = snapshot.into_export();
+                workspaces.insert(name, SavedWorkspace { seq, export });
+            }
+        }
+        let snapshot = Snapshot {
+            generation,
+            seq,
+            workspaces,
+        };
+        let content = serde_json::to_vec(&snapshot)?;
+        write_atomically(&self.directory.join(SNAPSHOT), &content).await?;
+        for old in generations(&self.directory)? {
+            if old < generation {
+                fs::remove_file(journal_path(&self.directory, old))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The journal of one workspace. Once the workspace is removed, nothing
+/// more is written to it, even by requests still holding it.
+pub struct JournalSlot {
+    journal: Arc<Journal>,
+    ws: String,
+    state: Mutex<SlotState>,
+}
+
+#[derive(Default)]
+struct SlotState {
+    /// Sequence number of the last record of the workspace
+    seq: u64,
+    removed: bool,
+}
+
+impl JournalSlot {
+    fn write(&self, record: Record, removal: bool) {
+        let mut writer = self.journal.writer();
+        let mut state = self.state.lock().expect("Journal lock is never poisoned");
+        if state.removed {
+            return;
+        }
+        match writer.write(&self.ws, record) {
+            // a removal is left for recovery to do even when a snapshot
+            // still holds the workspace
+            Ok(_) if removal => state.removed = true,
+            Ok(seq) => state.seq = seq,
+            Err(err) => eprintln!("Autosave of workspace {} failed: {err}", self.ws),
+        }
+    }
+
+    /// Journal `record`, unless the workspace was removed. A failure to
+    /// write is only reported, as the change it records is already made.
+    pub fn append(&self, record: Record) {
+        self.write(record, false)
+    }
+
+    pub fn remove(&self) {
+        self.write(Record::Removed, true)
+    }
+
+    /// Sequence number of the last record of the workspace, unless it was
+    /// removed.
+    pub fn seq(&self) -> Option<u64> {
+        let state = self.state.lock().expect("Journal lock is never poisoned");
+        (!state.removed).then_some(state.seq)
+    }
+}
+
+mod test {
+    #[tokio::test]
+    async fn journaled_edits_survive_a_restart() {
+        use super::Journal;
+        use crate::{handle::WorkspaceHandle, ServerState};
+        use lme_core::{
+            entity::{Atom, Molecule},
+            operation::Operation,
+            Workspace,
+        };
+        use nalgebra::Point3;
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        let directory = std::env::temp_dir().join(format!("lme2-journal-{}", std::process::id()));
+        let (journal, recovered) = Journal::open(&directory, 4).unwrap();
+        assert!(recovered.is_empty());
+        let state: ServerState = Arc::new(RwLock::new(HashMap::new()));
+        let mut workspaces = vec![];
+        for name in ["kept", "removed"] {
+            let workspace = Workspace::new(Molecule::default());
+            let slot = journal.track(name, &workspace);
+            let handle = Arc::new(WorkspaceHandle::new(workspace).journaled(slot));
+            state.write().await.insert(name.to_string(), handle.clone());
+            workspaces.push(handle);
+        }
+        let append = || Operation::AppendAtom {
+            stack_idx: 0,
+            atom: Atom::new(8, Point3::origin()),
+        };
+        let mut kept = workspaces[0].lock().await;
+        kept.apply(Operation::CreateStack { copies: 0 }).unwrap();
+        kept.apply(append()).unwrap();
+        drop(kept);
+        journal.snapshot(&state).await.unwrap();
+        workspaces[0].lock().await.apply(append()).unwrap();
+        state.write().await.remove("removed");
+        workspaces[1].retire();
+        workspaces[1]
+            .lock()
+            .await
+            .apply(Operation::CreateStack { copies: 0 })
+            .unwrap();
+        drop(journal);
+
+        let (_, recovered) = Journal::open(&directory, 4).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(recovered.keys().collect::<Vec<_>>(), ["kept"]);
+        let kept = &recovered["kept"];
+        assert_eq!(kept.read(0).unwrap().atoms().len(), 2);
+        let kinds = kept.audit().iter().map(|entry| entry.kind.as_str());
+        assert_eq!(
+            kinds.collect::<Vec<_>>(),
+            ["CreateStack", "AppendAtom", "AppendAtom"]
+        );
+    }
+}
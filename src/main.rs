@@ -1,53 +1,1344 @@
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use axum::{
+    error_handling::HandleErrorLayer,
+    http::StatusCode,
     middleware,
-    routing::{delete, post, put, get},
-    Router,
+    routing::{delete, get, patch, post, put},
+    BoxError, Extension, Router,
 };
+use auth::{authenticated, Role, Tokens};
+use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
+use cors::cors_layer;
+use error::ApiError;
+use grpc::GrpcWorkspaces;
+use serde::Deserialize;
 use handler::*;
-use lme_core::Workspace;
-use tokio::sync::{Mutex, RwLock};
+use lme2_grpc::LmeService;
+use lme_core::{history::DEFAULT_HISTORY_DEPTH, optimizer::Optimizer};
+use handle::WorkspaceHandle;
+use journal::Journal;
+use tokio::sync::RwLock;
+use tower::{limit::GlobalConcurrencyLimitLayer, load_shed::LoadShedLayer, ServiceBuilder};
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+mod auth;
+mod cli;
+mod cors;
 mod error;
+mod grpc;
+mod handle;
 mod handler;
+mod journal;
+mod streaming;
+#[cfg(test)]
+mod test_util;
 
-#[derive(Parser, Debug)]
+/// Server options, each taken from the command line, else from the
+/// environment, else from the settings file, else from its default.
+#[derive(Parser, Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
 struct Args {
-    #[arg(short, long)]
+    /// Work on a workspace file instead of serving
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<cli::Command>,
+    /// Settings file holding any of the other options by their long name in
+    /// snake case: TOML if it ends in `.toml`, e.g. `history_depth = 64`,
+    /// else YAML, e.g. `history_depth: 64`
+    #[arg(long, env = "LME_CONFIG")]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+    /// Address to listen on [default: 127.0.0.1:10810]
+    #[arg(short, long, env = "LME_LISTEN")]
+    listen: Option<SocketAddr>,
+    /// Port to listen on, in place of the port of the listen address
+    #[arg(short, long, env = "LME_PORT")]
+    port: Option<u16>,
+    /// Port to serve the gRPC service on, at the address listened on
+    /// [default: no gRPC]
+    #[arg(long, env = "LME_GRPC_PORT")]
+    grpc_port: Option<u16>,
+    /// PEM certificate chain to serve HTTPS (and gRPC over TLS) with,
+    /// along with `tls_key` [default: plain HTTP]
+    #[arg(long, env = "LME_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the certificate
+    #[arg(long, env = "LME_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+    /// Maximum number of concurrent requests on cheap routes (reads and
+    /// edits) [default: 1024]
+    #[arg(long, env = "LME_LIGHT_CONCURRENCY")]
+    light_concurrency: Option<usize>,
+    /// Maximum number of concurrent requests on expensive routes (exports
+    /// and analyses) [default: 16]
+    #[arg(long, env = "LME_HEAVY_CONCURRENCY")]
+    heavy_concurrency: Option<usize>,
+    /// Number of earlier versions kept per stack for undo [default: 64]
+    #[arg(long, env = "LME_HISTORY_DEPTH")]
+    history_depth: Option<usize>,
+    /// Start with the workspaces of a file written by `POST /save`, unless
+    /// the autosave directory holds some
+    #[arg(long, env = "LME_LOAD")]
+    load: Option<PathBuf>,
+    /// Directory to autosave every workspace to, journaling each edit, and
+    /// to recover them from on startup [default: no autosave]
+    #[arg(long, env = "LME_AUTOSAVE")]
+    autosave: Option<PathBuf>,
+    /// Seconds between full snapshots of the autosave, after which the
+    /// journal starts over [default: 600]
+    #[arg(long, env = "LME_SNAPSHOT_INTERVAL")]
+    snapshot_interval: Option<u64>,
+    /// Directory of the plugin executables [default: ./plugins]
+    #[arg(long, env = "LME_PLUGIN_DIRECTORY")]
+    plugin_directory: Option<PathBuf>,
+    /// Optimizers clients may run on stacks, by the name they give, each a
+    /// program of the plugin directory with fixed arguments; only read
+    /// from the settings file
+    #[arg(skip)]
+    optimizers: Option<HashMap<String, Optimizer>>,
+    /// Origins of the pages allowed to call the server, comma separated,
+    /// or `*` for any [default: none]
+    #[arg(long, env = "LME_CORS_ORIGINS", value_delimiter = ',')]
+    cors_origins: Option<Vec<String>>,
+    /// Methods those pages may use, comma separated [default: GET, POST,
+    /// PUT, PATCH, DELETE]
+    #[arg(long, env = "LME_CORS_METHODS", value_delimiter = ',')]
+    cors_methods: Option<Vec<String>>,
+    /// API tokens allowed to read and export workspaces, comma separated.
+    /// Without any tokens, requests need none
+    #[arg(long, env = "LME_READER_TOKENS", value_delimiter = ',')]
+    reader_tokens: Option<Vec<String>>,
+    /// API tokens allowed to edit workspaces as well, comma separated
+    #[arg(long, env = "LME_EDITOR_TOKENS", value_delimiter = ',')]
+    editor_tokens: Option<Vec<String>>,
+    /// API tokens allowed to save and load all workspaces and to manage
+    /// tokens as well, comma separated
+    #[arg(long, env = "LME_ADMIN_TOKENS", value_delimiter = ',')]
+    admin_tokens: Option<Vec<String>>,
+}
+
+/// `Args` with every default filled in.
+#[derive(Debug, PartialEq)]
+struct Settings {
     listen: SocketAddr,
+    grpc: Option<SocketAddr>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    light_concurrency: usize,
+    heavy_concurrency: usize,
+    history_depth: usize,
+    load: Option<PathBuf>,
+    autosave: Option<PathBuf>,
+    snapshot_interval: Duration,
+    plugin_directory: Option<PathBuf>,
+    optimizers: HashMap<String, Optimizer>,
+    cors_origins: Vec<String>,
+    cors_methods: Vec<String>,
+    tokens: HashMap<String, Role>,
 }
 
-pub type WorkspaceAccessor = Arc<Mutex<Workspace>>;
+impl Args {
+    /// Options of a settings file at `path` holding `content`, parsed as
+    /// TOML or YAML by the extension of `path` like `--config`.
+    fn from_file(path: &Path, content: &str) -> Result<Self, String> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => toml::from_str(content).map_err(|err| err.to_string()),
+            _ => serde_yaml::from_str(content).map_err(|err| err.to_string()),
+        }
+    }
+
+    /// Fill the options not given with those of `file`, then with defaults.
+    /// A token given for several roles gets the highest.
+    fn settings(self, file: Args) -> Settings {
+        let tokens = [
+            (self.reader_tokens.or(file.reader_tokens), Role::Reader),
+            (self.editor_tokens.or(file.editor_tokens), Role::Editor),
+            (self.admin_tokens.or(file.admin_tokens), Role::Admin),
+        ]
+        .into_iter()
+        .flat_map(|(tokens, role)| {
+            let tokens = tokens.unwrap_or_default();
+            tokens.into_iter().map(move |token| (token, role))
+        })
+        .collect();
+        let listen = self
+            .listen
+            .or(file.listen)
+            .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 10810)));
+        let port = self.port.or(file.port).unwrap_or(listen.port());
+        let grpc = self.grpc_port.or(file.grpc_port);
+        Settings {
+            listen: SocketAddr::new(listen.ip(), port),
+            grpc: grpc.map(|port| SocketAddr::new(listen.ip(), port)),
+            tls_cert: self.tls_cert.or(file.tls_cert),
+            tls_key: self.tls_key.or(file.tls_key),
+            light_concurrency: self
+                .light_concurrency
+                .or(file.light_concurrency)
+                .unwrap_or(1024),
+            heavy_concurrency: self
+                .heavy_concurrency
+                .or(file.heavy_concurrency)
+                .unwrap_or(16),
+            history_depth: self
+                .history_depth
+                .or(file.history_depth)
+                .unwrap_or(DEFAULT_HISTORY_DEPTH),
+            load: self.load.or(file.load),
+            autosave: self.autosave.or(file.autosave),
+            snapshot_interval: Duration::from_secs(
+                self.snapshot_interval
+                    .or(file.snapshot_interval)
+                    .unwrap_or(600),
+            ),
+            plugin_directory: self.plugin_directory.or(file.plugin_directory),
+            optimizers: self.optimizers.or(file.optimizers).unwrap_or_default(),
+            cors_origins: self.cors_origins.or(file.cors_origins).unwrap_or_default(),
+            cors_methods: self.cors_methods.or(file.cors_methods).unwrap_or_else(|| {
+                ["GET", "POST", "PUT", "PATCH", "DELETE"].map(String::from).to_vec()
+            }),
+            tokens,
+        }
+    }
+}
+
+pub type WorkspaceAccessor = Arc<WorkspaceHandle>;
 pub type ServerState = Arc<RwLock<HashMap<String, WorkspaceAccessor>>>;
 
-#[tokio::main]
-async fn main() {
-    let Args { listen } = Args::parse();
+/// Undo history depth given to newly created workspaces.
+#[derive(Clone, Copy)]
+pub struct HistoryDepth(pub usize);
 
-    let state: ServerState = Arc::new(RwLock::new(HashMap::new()));
+/// Optimizers of the settings, by the name clients run them by.
+#[derive(Clone, Default)]
+pub struct Optimizers(pub Arc<HashMap<String, Optimizer>>);
 
-    let ws_router = Router::new()
+/// Share one concurrency budget among all routes of `router`, answering
+/// requests beyond `max` with 429 instead of queueing them.
+fn concurrency_limit<S>(router: Router<S>, max: usize) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.route_layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(|_: BoxError| async {
+                ApiError::new(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "TooManyRequests",
+                    "Too many concurrent requests",
+                )
+            }))
+            .layer(LoadShedLayer::new())
+            .layer(GlobalConcurrencyLimitLayer::new(max)),
+    )
+}
+
+/// All routes of the server: workspace creation and removal, and the
+/// per-workspace routes nested under `/ws/:ws`, where stacks can also be
+/// addressed by name under `/stacks/:name` (see `stack_name_middleware`).
+/// New routes are also described by a `#[utoipa::path]` on their handler
+/// listed in `ApiDoc`, which is served under `/docs`.
+fn router(
+    state: ServerState,
+    light_concurrency: usize,
+    heavy_concurrency: usize,
+    history_depth: usize,
+    optimizers: Optimizers,
+) -> Router {
+    let light_router = Router::new()
         .route("/stack/clone_stack", post(clone_stack))
         .route("/stack/clone_base", post(clone_base))
+        .route("/stack/reorder", post(reorder_stacks))
         .route("/stack/layer", put(add_layer_to_stack))
         .route("/stack/write", put(write_to_stack))
+        .route("/stack/bonds", put(modify_bonds))
+        .route("/stack/:idx/clone", post(clone_stack_at))
+        .route("/stack/:idx/atoms", delete(remove_atoms))
+        .route("/stack/:idx/move", post(move_atoms))
+        .route("/stack/:idx/torsion", post(rotate_torsion))
+        .route("/stack/:idx/bond-length", post(set_bond_length))
+        .route("/stack/:idx/bond-angle", post(set_bond_angle))
+        .route("/stack/:idx/mirror", post(mirror_atoms))
+        .route("/stack/:idx/replicate", post(replicate_atoms))
+        .route("/stack/:idx/supercell", post(supercell))
+        .route("/stack/:idx/substitute", post(substitute))
+        .route("/stack/:idx/transaction", post(stack_transaction))
+        .route("/stack/:idx/cherry-pick", post(cherry_pick))
+        .route("/stack/:idx/import-from/:source", post(copy_atoms))
+        .route("/stack/:idx/meta", patch(annotate_layer))
+        .route("/stack/:idx/layers", get(layer_summaries))
+        .route("/stack/:idx/name/:name", put(rename_stack))
+        .route("/stack/:idx/undo", post(undo_stack))
+        .route("/stack/:idx/redo", post(redo_stack))
+        .route("/stack/:idx/renumber", post(renumber_stack))
+        .route("/stack/:idx/compact", post(compact_stack))
+        .route("/stack/:idx/remove-hydrogens", post(remove_hydrogens))
+        .route("/stack/:idx/add-hydrogens", post(add_hydrogens))
+        .route("/stack/:idx/perceive-bonds", post(perceive_bonds))
+        .route("/stack/:idx/export/zmat", get(export_zmat))
+        .route("/stack/:idx/import/zmat", post(import_zmat))
+        .route("/stack/:idx/export/cif", get(export_cif))
+        .route("/stack/:idx/import/cif", post(import_cif))
+        .route("/stack/:idx/export/xyz", get(export_xyz))
+        .route("/stack/:idx/import/xyz", post(import_xyz))
+        .route("/stack/:idx/export/mol", get(export_mol))
+        .route("/stack/:idx/import/mol", post(import_mol))
+        .route("/stack/:idx/export/mol2", get(export_mol2))
+        .route("/stack/:idx/import/mol2", post(import_mol2))
+        .route("/stack/:idx/export/pdb", get(export_pdb))
+        .route("/stack/:idx/export/gaussian", post(export_gaussian))
+        .route("/stack/:idx/export/template/:name", post(export_template))
+        .route("/stack/:idx/import/pdb", post(import_pdb))
+        .route("/stack/:idx/full", get(read_full))
+        .route("/stack/:idx/formula", get(stack_formula))
+        .route("/stack/:idx/measure", get(measure))
+        .route("/stack/:a/rmsd/:b", get(stack_rmsd))
+        .route("/stack/:a/align-to/:b", post(align_stack))
+        .route("/stack/:idx/center", get(stack_center))
+        .route("/stack/:idx/rings", get(stack_rings))
+        .route("/stack/:idx/validate", get(validate_stack))
+        .route(
+            "/stack/:idx/fragments",
+            get(stack_components).post(group_components),
+        )
+        .route("/stack/:idx/orient", post(orient_stack))
+        .route(
+            "/stack/:idx/metadata",
+            get(stack_metadata).patch(set_stack_metadata),
+        )
+        .route("/stack/:idx/batch", patch(batch_stack))
+        .route(
+            "/stack/:idx/electronic-state",
+            get(stack_electronic_state).put(set_stack_electronic_state),
+        )
+        .route("/stack/:idx/select", post(select_atoms))
+        .route("/stack/:idx/select/region", post(select_region))
+        .route("/stack/:idx/smiles", get(export_smiles))
+        .route("/stack/:idx", get(read_stack).delete(remove_stack))
         .route("/stack", post(create_stack))
+        .route("/stacks", get(stack_summaries).post(create_named_stack))
+        .route("/names", get(atom_names).post(set_atom_names))
+        .route("/names/:name", get(named_atom))
+        .route("/names/:idx/:name", put(set_atom_name))
+        .route("/fragments", get(fragment_names))
+        .route(
+            "/fragments/:name",
+            get(read_fragment).put(set_fragment).delete(remove_fragment),
+        )
+        .route("/templates", get(template_names))
+        .route(
+            "/templates/:name",
+            get(read_template).put(set_template).delete(remove_template),
+        )
+        .route("/groups", get(group_sizes).post(add_to_groups))
+        .route(
+            "/groups/:group",
+            get(group_members).post(assign_group).delete(remove_group),
+        )
+        .route("/atoms/:idx/groups", get(atom_groups).delete(remove_from_all_groups))
+        .route(
+            "/groups/:idx/:group",
+            put(add_to_group)
+                .delete(remove_from_group)
+                .patch(rename_group),
+        )
+        .route("/diff/:a/:b", get(diff_stacks))
+        .route("/export/sdf", get(export_sdf))
+        .route("/tree/promote", post(promote_prefix))
+        .route("/log", get(operation_log))
+        .route("/history", get(audit_history))
+        .route("/channel", get(workspace_channel))
+        .route("/events", get(workspace_events))
+        .route("/", get(read_stacks));
+
+    let heavy_router = Router::new()
         .route("/export", post(workspace_export))
-        .route("/", get(read_stacks))
+        .route("/import", post(workspace_import))
+        .route("/cluster", get(cluster_stacks))
+        .route("/rmsd-matrix", get(rmsd_matrix))
+        .route("/search", post(search_stacks))
+        .route("/stack/:idx/search", post(search_stack))
+        .route("/replay", post(replay))
+        .route("/stack/:idx/optimize", post(optimize_stack))
+        .route("/tree", get(workspace_tree))
+        .route("/export/dot", get(export_dot));
+
+    let ws_router = concurrency_limit(light_router, light_concurrency)
+        .merge(concurrency_limit(heavy_router, heavy_concurrency))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             workspace_middleware,
         ));
 
-    let router = Router::new()
+    let routes = Router::new()
         .nest("/ws/:ws", ws_router)
         .route("/ws/:ws", delete(remove_workspace))
         .route("/ws/:ws", post(create_workspace))
-        .with_state(state);
+        .route("/save", post(save_workspaces))
+        .route("/load", post(load_saved_workspaces))
+        .merge(SwaggerUi::new("/docs").url("/docs/openapi.json", ApiDoc::openapi()))
+        .layer(Extension(HistoryDepth(history_depth)))
+        .layer(Extension(optimizers))
+        .with_state(state.clone());
+
+    // layers of `routes` run after routing, too late to rewrite the path
+    Router::new()
+        .fallback_service(
+            ServiceBuilder::new()
+                .layer(middleware::from_fn_with_state(state, stack_name_middleware))
+                .service(routes),
+        )
+        .layer(middleware::map_response(error::structured_rejections))
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = Args::parse();
+    let file = match &args.config {
+        Some(path) => {
+            let content = std::fs::read_to_string(path)
+                .unwrap_or_else(|err| panic!("Failed to read {}: {err}", path.display()));
+            Args::from_file(path, &content)
+                .unwrap_or_else(|err| panic!("Invalid settings in {}: {err}", path.display()))
+        }
+        None => Args::default(),
+    };
+    let command = args.command.take();
+    let Settings {
+        listen,
+        grpc,
+        tls_cert,
+        tls_key,
+        light_concurrency,
+        heavy_concurrency,
+        history_depth,
+        load,
+        autosave,
+        snapshot_interval,
+        plugin_directory,
+        optimizers,
+        cors_origins,
+        cors_methods,
+        tokens,
+    } = args.settings(file);
+    if let Some(directory) = plugin_directory {
+        lme_core::entity::set_plugin_directory(directory);
+    }
+    if let Some(command) = command {
+        if let Err(err) = cli::run(command).await {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let (journal, mut workspaces) = match &autosave {
+        Some(directory) => {
+            let (journal, recovered) = Journal::open(directory, history_depth)
+                .unwrap_or_else(|err| panic!("Failed to recover {}: {err}", directory.display()));
+            (Some(journal), recovered)
+        }
+        None => (None, HashMap::new()),
+    };
+    if let (Some(path), true) = (load, workspaces.is_empty()) {
+        workspaces = load_workspaces(&path, history_depth)
+            .await
+            .unwrap_or_else(|err| panic!("Failed to load {}: {err}", path.display()));
+    }
+    let workspaces = workspaces
+        .into_iter()
+        .map(|(name, workspace)| {
+            let handle = WorkspaceHandle::new(workspace);
+            let handle = match &journal {
+                Some(journal) => handle.journaled(journal.slot(&name)),
+                None => handle,
+            };
+            (name, Arc::new(handle))
+        })
+        .collect();
+    let state: ServerState = Arc::new(RwLock::new(workspaces));
+
+    let mut router = router(
+        state.clone(),
+        light_concurrency,
+        heavy_concurrency,
+        history_depth,
+        Optimizers(Arc::new(optimizers)),
+    );
+    let tls = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => {
+            let read = |path: &PathBuf| {
+                std::fs::read(path)
+                    .unwrap_or_else(|err| panic!("Failed to read {}: {err}", path.display()))
+            };
+            Some((read(&cert), read(&key)))
+        }
+        (None, None) => None,
+        _ => panic!("tls_cert and tls_key are needed together"),
+    };
+    let tokens: Tokens = Arc::new(RwLock::new(tokens));
+    if let Some(grpc) = grpc {
+        let service = GrpcWorkspaces {
+            state: state.clone(),
+            tokens: tokens.clone(),
+            history_depth,
+            journal: journal.clone(),
+        };
+        let mut server = tonic::transport::Server::builder();
+        if let Some((cert, key)) = &tls {
+            let identity = tonic::transport::Identity::from_pem(cert, key);
+            server = server
+                .tls_config(tonic::transport::ServerTlsConfig::new().identity(identity))
+                .unwrap_or_else(|err| panic!("Invalid TLS settings: {err}"));
+        }
+        let server = server
+            .add_service(LmeService::new(service).into_server())
+            .serve(grpc);
+        tokio::spawn(async move {
+            if let Err(err) = server.await {
+                eprintln!("gRPC server failed: {err}");
+            }
+        });
+    }
+    if let Some(journal) = journal {
+        // the recovered workspaces are only in the snapshot taken now
+        journal
+            .snapshot(&state)
+            .await
+            .unwrap_or_else(|err| panic!("Failed to autosave: {err}"));
+        router = router.layer(Extension(journal.clone()));
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(snapshot_interval);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(err) = journal.snapshot(&state).await {
+                    eprintln!("Autosave snapshot failed: {err}");
+                }
+            }
+        });
+    }
+    let mut router = authenticated(router, tokens);
+    if !cors_origins.is_empty() {
+        let cors = cors_layer(&cors_origins, &cors_methods)
+            .unwrap_or_else(|err| panic!("Invalid CORS settings: {err}"));
+        router = router.layer(cors);
+    }
+    match tls {
+        Some((cert, key)) => {
+            let config = RustlsConfig::from_pem(cert, key)
+                .await
+                .unwrap_or_else(|err| panic!("Invalid TLS settings: {err}"));
+            axum_server::bind_rustls(listen, config)
+                .serve(router.into_make_service())
+                .await
+                .unwrap()
+        }
+        None => axum::Server::bind(&listen)
+            .serve(router.into_make_service())
+            .await
+            .unwrap(),
+    }
+}
+
+mod test {
+    #[test]
+    fn settings_prefer_arguments_over_the_file() {
+        use crate::{auth::Role, Args, Settings};
+        use clap::{CommandFactory, Parser};
+        use lme_core::optimizer::Optimizer;
+        use std::{
+            collections::HashMap,
+            path::{Path, PathBuf},
+            time::Duration,
+        };
+
+        Args::command().debug_assert();
+        let xtb = Optimizer {
+            program: "xtb-opt".to_string(),
+            args: vec!["--gfn".to_string(), "2".to_string()],
+        };
+        let yaml = Args::from_file(
+            Path::new("lme.yaml"),
+            "listen: 0.0.0.0:8000\nhistory_depth: 8\nload: saved.json\nheavy_concurrency: 2\n\
+             cors_methods: [GET, PUT]\nreader_tokens: [secret]\nadmin_tokens: [secret]\n\
+             tls_cert: cert.pem\noptimizers:\n  xtb: {program: xtb-opt, args: [--gfn, \"2\"]}\n",
+        )
+        .unwrap();
+        let file = Args::from_file(
+            Path::new("lme.toml"),
+            "listen = \"0.0.0.0:8000\"\nhistory_depth = 8\nload = \"saved.json\"\n\
+             heavy_concurrency = 2\ncors_methods = [\"GET\", \"PUT\"]\n\
+             reader_tokens = [\"secret\"]\nadmin_tokens = [\"secret\"]\ntls_cert = \"cert.pem\"\n\
+             [optimizers.xtb]\nprogram = \"xtb-opt\"\nargs = [\"--gfn\", \"2\"]\n",
+        )
+        .unwrap();
+        assert_eq!(format!("{file:?}"), format!("{yaml:?}"));
+        let args = Args::parse_from([
+            "lme2-core",
+            "--port",
+            "9000",
+            "--history-depth",
+            "4",
+            "--cors-origins",
+            "http://localhost:5173,*",
+            "--autosave",
+            "autosave",
+            "--tls-key",
+            "key.pem",
+        ]);
+        assert_eq!(
+            args.settings(file),
+            Settings {
+                listen: "0.0.0.0:9000".parse().unwrap(),
+                grpc: None,
+                tls_cert: Some(PathBuf::from("cert.pem")),
+                tls_key: Some(PathBuf::from("key.pem")),
+                light_concurrency: 1024,
+                heavy_concurrency: 2,
+                history_depth: 4,
+                load: Some(PathBuf::from("saved.json")),
+                autosave: Some(PathBuf::from("autosave")),
+                snapshot_interval: Duration::from_secs(600),
+                plugin_directory: None,
+                optimizers: HashMap::from([("xtb".to_string(), xtb)]),
+                cors_origins: vec!["http://localhost:5173".to_string(), "*".to_string()],
+                cors_methods: vec!["GET".to_string(), "PUT".to_string()],
+                tokens: HashMap::from([("secret".to_string(), Role::Admin)]),
+            }
+        );
+        assert!(Args::from_file(Path::new("lme.yml"), "config: other.yaml\n").is_err());
+        assert!(Args::from_file(Path::new("lme.yml"), "listen_port: 80\n").is_err());
+        assert!(Args::from_file(Path::new("lme.toml"), "listen_port = 80\n").is_err());
+        assert!(Args::from_file(Path::new("lme.toml"), "history_depth: 8\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn atom_routes_bind_every_path_param() {
+        use crate::{
+            router,
+            test_util::{call, json, text},
+        };
+        use axum::http::{Method, StatusCode};
+        use serde_json::Value;
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str =
+            r#"{"atoms":{"3":{"element":8,"position":[0,0,0]}},"bonds":[],"groups":[]}"#;
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        let requests = [
+            (Method::POST, "/ws/test", BASE),
+            (Method::PUT, "/ws/test/names/3/OH", ""),
+            (Method::PUT, "/ws/test/groups/3/hydroxyl", ""),
+            (Method::DELETE, "/ws/test/groups/3/hydroxyl", ""),
+            (Method::PUT, "/ws/test/groups/OH/oxygens", ""),
+            (Method::POST, "/ws/test/groups/qm", r#"{"Atoms":["OH"]}"#),
+        ];
+        for (method, uri, body) in requests {
+            let response = call(&router, method, uri, body).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+        let missing = [
+            (Method::DELETE, "/ws/test/groups/3/hydroxyl"),
+            (Method::GET, "/ws/test/stack/0"),
+            (Method::GET, "/ws/test/names/HO"),
+            (Method::GET, "/ws/test/atoms/HO/groups"),
+        ];
+        for (method, uri) in missing {
+            let response = call(&router, method, uri, "").await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+        let listings = [
+            ("/ws/test/names", r#"{"3":"OH"}"#),
+            ("/ws/test/names/OH", "3"),
+            ("/ws/test/groups", r#"{"oxygens":1,"qm":1}"#),
+            ("/ws/test/groups/oxygens", "[3]"),
+            ("/ws/test/atoms/OH/groups", r#"["oxygens","qm"]"#),
+        ];
+        for (uri, expected) in listings {
+            let response = call(&router, Method::GET, uri, "").await;
+            assert_eq!(text(response).await, expected, "{uri}");
+        }
+        let selection = r#"{"Selection":{"stack_idx":0,"predicate":{"Element":"O"}}}"#;
+        let response = call(&router, Method::POST, "/ws/test/groups/qm", selection).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = call(&router, Method::POST, "/ws/test/stack?copies=0", "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let patch = r#"{"atoms":{"OH":{"element":8,"position":[1,0,0]}},"bonds":[],"groups":[]}"#;
+        let write = "/ws/test/stack/write?start=0&range=1";
+        let response = call(&router, Method::PUT, write, patch).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let molecule: Value = json(call(&router, Method::GET, "/ws/test/stack/0", "").await).await;
+        assert_eq!(molecule["atoms"]["3"]["position"], serde_json::json!([1.0, 0.0, 0.0]));
+        // unknown names and unknown indices are both not found
+        let unknown = [
+            (Method::PUT, "/ws/test/names/4/OH", ""),
+            (Method::POST, "/ws/test/groups/qm", r#"{"Atoms":[3,4]}"#),
+            (Method::POST, "/ws/test/groups/qm", r#"{"Atoms":["OH","HO"]}"#),
+            (
+                Method::PUT,
+                write,
+                r#"{"atoms":{},"bonds":[[["OH","HO"],1.0]],"groups":[]}"#,
+            ),
+        ];
+        for (method, uri, body) in unknown {
+            let response = call(&router, method, uri, body).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri} {body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn only_configured_optimizers_run() {
+        use crate::{router, test_util::call, Optimizers};
+        use axum::http::{Method, StatusCode};
+        use lme_core::optimizer::Optimizer;
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str =
+            r#"{"atoms":{"0":{"element":8,"position":[0,0,0]}},"bonds":[],"groups":[]}"#;
+        let escaping = Optimizer {
+            program: "../../bin/sh".to_string(),
+            args: vec![],
+        };
+        let optimizers = Optimizers(Arc::new(HashMap::from([("sh".to_string(), escaping)])));
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, optimizers);
+        let cases = [
+            ("/ws/test", BASE, StatusCode::OK),
+            ("/ws/test/stack?copies=0", "", StatusCode::OK),
+            ("/ws/test/stack/0/optimize", r#"{"optimizer":"xtb"}"#, StatusCode::NOT_FOUND),
+            ("/ws/test/stack/0/optimize", r#"{"optimizer":"sh"}"#, StatusCode::BAD_REQUEST),
+        ];
+        for (uri, body, status) in cases {
+            let response = call(&router, Method::POST, uri, body).await;
+            assert_eq!(response.status(), status, "{uri} {body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn export_refuses_empty_stacks() {
+        use crate::{router, test_util::call};
+        use axum::http::{Method, StatusCode};
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str = r#"{"atoms":{},"bonds":[],"groups":[]}"#;
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        let requests = [
+            ("/ws/test", BASE),
+            ("/ws/test/stack?copies=0", ""),
+            // the base of a single-layer stack has no layers at all
+            ("/ws/test/stack/clone_base", r#"{"stack_idx":0,"copies":0}"#),
+        ];
+        for (uri, body) in requests {
+            let response = call(&router, Method::POST, uri, body).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+        let response = call(&router, Method::POST, "/ws/test/export", "").await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn rejections_carry_an_error_body() {
+        use crate::{
+            router,
+            test_util::{call, json},
+        };
+        use axum::http::{Method, StatusCode};
+        use serde_json::Value;
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str = r#"{"atoms":{},"bonds":[],"groups":[]}"#;
+        const PATCH: &str =
+            r#"{"atoms":{"0":{"element":6,"position":[0,0,0]}},"bonds":[],"groups":[]}"#;
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        let response = call(&router, Method::POST, "/ws/test", BASE).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let cases = [
+            ("/ws/test/stack/write?start=3&range=1", PATCH, StatusCode::NOT_FOUND, "NoSuchStack"),
+            ("/ws/test/stack/write?start=0", PATCH, StatusCode::BAD_REQUEST, "BadRequest"),
+            ("/ws/test/stack/write?start=0&range=1", "{", StatusCode::BAD_REQUEST, "BadRequest"),
+            (
+                "/ws/test/stack/write?start=0&range=1",
+                r#"{"atoms":[]}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "UnprocessableEntity",
+            ),
+        ];
+        for (uri, body, status, code) in cases {
+            let response = call(&router, Method::PUT, uri, body).await;
+            assert_eq!(response.status(), status, "{uri} {body}");
+            let error: Value = json(response).await;
+            assert_eq!(error["code"], code, "{uri}");
+            assert!(!error["message"].as_str().unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn saved_workspaces_load_back() {
+        use crate::{load_workspaces, router, test_util::call};
+        use axum::http::{Method, StatusCode};
+        use lme_core::WorkspaceExport;
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        let path = std::env::temp_dir().join(format!("lme2-save-{}.json", std::process::id()));
+        let state = Arc::new(RwLock::new(HashMap::new()));
+        let router = router(state.clone(), 16, 16, 4, Default::default());
+        const BASE: &str =
+            r#"{"atoms":{"0":{"element":6,"position":[0,0,0]}},"bonds":[],"groups":[]}"#;
+        let requests = [
+            ("/ws/saved", BASE.to_string()),
+            ("/save", format!(r#"{{"path":{:?}}}"#, path)),
+            ("/ws/unsaved", BASE.to_string()),
+            ("/load", format!(r#"{{"path":{:?}}}"#, path)),
+        ];
+        for (uri, body) in requests {
+            let response = call(&router, Method::POST, uri, body).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+        let loaded = load_workspaces(&path, 4).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!state.read().await.contains_key("unsaved"));
+        let saved = state.read().await["saved"].lock().await.clone();
+        assert_eq!(
+            WorkspaceExport::try_from(&loaded["saved"]).unwrap(),
+            WorkspaceExport::try_from(&saved).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_replace_the_file_whole() {
+        use crate::write_atomically;
+
+        let directory = std::env::temp_dir().join(format!("lme2-write-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("saved.json");
+        let contents = (0..8).map(|n| n.to_string().repeat(4096)).collect::<Vec<_>>();
+        let writes = contents.iter().map(|content| write_atomically(&path, content.as_bytes()));
+        for written in futures::future::join_all(writes).await {
+            written.unwrap();
+        }
+        assert!(contents.contains(&std::fs::read_to_string(&path).unwrap()));
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn group_queries_list_both_directions() {
+        use crate::{
+            router,
+            test_util::{call, text},
+        };
+        use axum::http::{Method, StatusCode};
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str = r#"{"atoms":{"0":{"element":6,"position":[0,0,0]},"1":{"element":8,"position":[1,0,0]}},"bonds":[],"groups":[]}"#;
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        let requests = [
+            ("/ws/test", BASE),
+            ("/ws/test/groups", r#"[[0,"carbonyl"],[1,"carbonyl"],[0,"backbone"]]"#),
+        ];
+        for (uri, body) in requests {
+            let response = call(&router, Method::POST, uri, body).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+        let reads = [
+            ("/ws/test/groups/carbonyl", "[0,1]"),
+            ("/ws/test/groups/backbone", "[0]"),
+            ("/ws/test/groups/empty", "[]"),
+            ("/ws/test/atoms/0/groups", r#"["backbone","carbonyl"]"#),
+            ("/ws/test/atoms/1/groups", r#"["carbonyl"]"#),
+        ];
+        let removals = [
+            ("/ws/test/atoms/0/groups", r#"["backbone","carbonyl"]"#),
+            ("/ws/test/groups/carbonyl", "[1]"),
+            ("/ws/test/groups/carbonyl", "[]"),
+        ];
+        let insertions = [
+            ("/ws/test/groups/1/carbonyl", r#""Inserted""#),
+            ("/ws/test/groups/1/carbonyl", r#""Existed""#),
+        ];
+        let requests = reads
+            .into_iter()
+            .map(|read| (Method::GET, read))
+            .chain(removals.into_iter().map(|removal| (Method::DELETE, removal)))
+            .chain(insertions.into_iter().map(|insertion| (Method::PUT, insertion)));
+        for (method, (uri, expected)) in requests {
+            let response = call(&router, method, uri, "").await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            assert_eq!(text(response).await, expected, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn stack_routes_carry_the_stack_version() {
+        use crate::{
+            router,
+            test_util::{call, request, send},
+        };
+        use axum::http::{header, HeaderValue, Method, StatusCode};
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str =
+            r#"{"atoms":{"0":{"element":6,"position":[0,0,0]}},"bonds":[],"groups":[]}"#;
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        call(&router, Method::POST, "/ws/test", BASE).await;
+        call(&router, Method::POST, "/ws/test/stack?copies=0", "").await;
+        call(&router, Method::PUT, "/ws/test/names/0/stack", "").await;
+        call(&router, Method::PUT, "/ws/test/stack/write?start=0&range=1", BASE).await;
+
+        let response = call(&router, Method::GET, "/ws/test/stack/0", "").await;
+        let etag = response.headers()[header::ETAG].clone();
+        let mut undo = request(Method::POST, "/ws/test/stack/0/undo", "");
+        undo.headers_mut().insert(header::IF_MATCH, HeaderValue::from_static("\"99\""));
+        assert_eq!(send(&router, undo).await.status(), StatusCode::CONFLICT);
+        let mut undo = request(Method::POST, "/ws/test/stack/0/undo", "");
+        undo.headers_mut().insert(header::IF_MATCH, etag);
+        assert_eq!(send(&router, undo).await.status(), StatusCode::OK);
+        // an atom called `stack` doesn't make a stack route
+        let response = call(&router, Method::PUT, "/ws/test/groups/stack/0", "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(header::ETAG));
+    }
+
+    #[tokio::test]
+    async fn stacks_resolve_by_name() {
+        use crate::{
+            router,
+            test_util::{call, text},
+        };
+        use axum::http::{Method, StatusCode};
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str = r#"{"atoms":{},"bonds":[],"groups":[]}"#;
+        const PATCH: &str =
+            r#"{"atoms":{"0":{"element":6,"position":[0,0,0]}},"bonds":[],"groups":[]}"#;
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        call(&router, Method::POST, "/ws/test", BASE).await;
+        let response = call(&router, Method::POST, "/ws/test/stacks", "").await;
+        assert_eq!(text(response).await, r#""stack-1""#);
+        let response = call(&router, Method::POST, "/ws/test/stacks?name=ethane", "").await;
+        assert_eq!(text(response).await, r#""ethane""#);
+        let response = call(&router, Method::POST, "/ws/test/stacks?name=ethane", "").await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = call(&router, Method::DELETE, "/ws/test/stacks/stack-1", "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let write = "/ws/test/stack/write?start=0&range=1";
+        let response = call(&router, Method::PUT, write, PATCH).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = call(&router, Method::GET, "/ws/test/stacks/ethane/export/xyz", "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(text(response).await.starts_with("1\n"));
+        let response = call(&router, Method::GET, "/ws/test/stacks", "").await;
+        assert_eq!(
+            text(response).await,
+            r#"[{"index":0,"name":"ethane","atoms":1,"layers":1,"version":4}]"#
+        );
+        let response = call(&router, Method::GET, "/ws/test/stacks/stack-1", "").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let rename = "/ws/test/stacks/ethane/name/ethene";
+        let response = call(&router, Method::PUT, rename, "").await;
+        assert_eq!(text(response).await, r#""ethane""#);
+        call(&router, Method::POST, "/ws/test/stacks?name=ethyne", "").await;
+        let response = call(&router, Method::PUT, "/ws/test/stack/1/name/ethene", "").await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = call(&router, Method::GET, "/ws/test/stacks/ethene/export/xyz", "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call(&router, Method::POST, "/ws/test/stack/reorder", "[0,0]").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = call(&router, Method::POST, "/ws/test/stack/reorder", "[1,0]").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = call(&router, Method::GET, "/ws/test/stacks", "").await;
+        assert_eq!(
+            text(response).await,
+            concat!(
+                r#"[{"index":0,"name":"ethyne","atoms":0,"layers":0,"version":7},"#,
+                r#"{"index":1,"name":"ethene","atoms":1,"layers":1,"version":7}]"#
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn history_records_who_edited_which_stack() {
+        use crate::{
+            auth::authenticated,
+            auth::Role,
+            router,
+            test_util::{json, request, send, with_token},
+        };
+        use axum::http::{Method, StatusCode};
+        use serde_json::Value;
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str = r#"{"atoms":{},"bonds":[],"groups":[]}"#;
+        const PATCH: &str =
+            r#"{"atoms":{"0":{"element":6,"position":[0,0,0]}},"bonds":[],"groups":[]}"#;
+        let tokens = HashMap::from([("editing".to_string(), Role::Editor)]);
+        let router = &authenticated(
+            router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default()),
+            Arc::new(RwLock::new(tokens)),
+        );
+        let history = |uri: &'static str| async move {
+            let request = with_token(request(Method::GET, uri, ""), "editing");
+            let response = send(router, request).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            json::<Vec<Value>>(response).await
+        };
+        let edits = [
+            (Method::POST, "/ws/test", BASE),
+            (Method::POST, "/ws/test/stack?copies=1", ""),
+            (Method::PUT, "/ws/test/stack/write?start=1&range=1", PATCH),
+        ];
+        for (method, uri, body) in edits {
+            let response = send(router, with_token(request(method, uri, body), "editing")).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+
+        let entries = history("/ws/test/history").await;
+        let kinds = entries.iter().map(|entry| entry["kind"].clone()).collect::<Vec<_>>();
+        assert_eq!(kinds, ["CreateStack", "Write"]);
+        let written = history("/ws/test/history?stack=1").await;
+        assert_eq!(written, entries[1..]);
+        assert_eq!(written[0]["stacks"], serde_json::json!([1]));
+        assert!(written[0]["operation"]["Write"]["data"]["atoms"]["0"].is_object());
+        let actor = written[0]["actor"].as_str().unwrap();
+        assert!(actor.starts_with("editor-") && !actor.contains("editing"));
+        assert!(history("/ws/test/history?stack=0").await.is_empty());
+        assert!(history("/ws/test/history?until=1").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn imports_name_atoms_from_id_maps() {
+        use crate::{
+            router,
+            test_util::{call, json},
+        };
+        use axum::http::{Method, StatusCode};
+        use serde_json::{json, Value};
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str = r#"{"atoms":{"0":{"element":8,"position":[0,0,0]},"1":{"element":1,"position":[1,0,0]}},"bonds":[],"groups":[]}"#;
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        call(&router, Method::POST, "/ws/test", BASE).await;
+        call(&router, Method::POST, "/ws/test/stack?copies=0", "").await;
+        let layer = r#""IgnoreBonds""#;
+        call(&router, Method::PUT, "/ws/test/stack/layer?start=0&range=1", layer).await;
+        call(&router, Method::PUT, "/ws/test/names/0/O1", "").await;
+        let export: Value = json(call(&router, Method::POST, "/ws/test/export", "").await).await;
+        let import = |names: Value| {
+            let mut body = export.clone();
+            body["names"] = names;
+            call(&router, Method::POST, "/ws/test/import", body.to_string())
+        };
+
+        let response = import(json!({"1": "O1"})).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(json::<Value>(response).await["names"], json!({"O1": [0, 1]}));
+        let response = import(json!({"7": "X"})).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = import(json!({"0": "O", "1": "O1"})).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = call(&router, Method::POST, "/ws/test/export", "").await;
+        assert_eq!(json::<Value>(response).await["atom_names"], json!({"0": "O", "1": "O1"}));
+    }
+
+    #[tokio::test]
+    async fn exports_are_restricted_to_the_groups_queried() {
+        use crate::{
+            router,
+            test_util::{call, json},
+        };
+        use axum::http::Method;
+        use serde_json::Value;
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str = r#"{"atoms":{"0":{"element":8,"position":[0,0,0]},"1":{"element":1,"position":[1,0,0]},"2":{"element":1,"position":[0,1,0]}},"bonds":[],"groups":[]}"#;
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        call(&router, Method::POST, "/ws/test", BASE).await;
+        call(&router, Method::POST, "/ws/test/stack?copies=0", "").await;
+        let layer = r#""IgnoreBonds""#;
+        call(&router, Method::PUT, "/ws/test/stack/layer?start=0&range=1", layer).await;
+        call(&router, Method::PUT, "/ws/test/groups/0/oxygen", "").await;
+        call(&router, Method::PUT, "/ws/test/groups/1/hydrogen", "").await;
+        let exported = |uri: &'static str| async {
+            let export: Value = json(call(&router, Method::POST, uri, "").await).await;
+            let atoms = export["base"]["atoms"].as_object().unwrap();
+            atoms.keys().cloned().collect::<Vec<_>>()
+        };
+
+        assert_eq!(exported("/ws/test/export").await, ["0", "1", "2"]);
+        assert_eq!(exported("/ws/test/export?class=oxygen&class=hydrogen").await, ["0", "1"]);
+        assert_eq!(exported("/ws/test/export?groups=oxygen,hydrogen").await, ["0", "1"]);
+        assert_eq!(exported("/ws/test/export?class=hydrogen").await, ["1"]);
+        assert_eq!(exported("/ws/test/export?class=").await, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn exports_are_sent_in_the_accepted_format() {
+        use crate::{
+            router,
+            test_util::{call, request, send},
+        };
+        use axum::http::{header, HeaderValue, Method, StatusCode};
+        use lme_core::WorkspaceExport;
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str = r#"{"atoms":{"0":{"element":8,"position":[0,0,0]}},"bonds":[],"groups":[]}"#;
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        call(&router, Method::POST, "/ws/test", BASE).await;
+        call(&router, Method::POST, "/ws/test/stack?copies=1", "").await;
+        let layer = r#""IgnoreBonds""#;
+        call(&router, Method::PUT, "/ws/test/stack/layer?start=0&range=2", layer).await;
+        let export = |accept: &'static str| {
+            let mut request = request(Method::POST, "/ws/test/export", "");
+            request.headers_mut().insert(header::ACCEPT, HeaderValue::from_static(accept));
+            send(&router, request)
+        };
+        let exported = |accept: &'static str| async move {
+            let response = export(accept).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let content_type = response.headers()[header::CONTENT_TYPE].clone();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            (content_type, body)
+        };
+
+        let (content_type, json) = exported("application/json").await;
+        assert_eq!(content_type, "application/json");
+        let export_value = serde_json::from_slice::<WorkspaceExport>(&json).unwrap();
+        let (content_type, packed) = exported("application/msgpack").await;
+        assert_eq!(content_type, "application/msgpack");
+        assert_eq!(rmp_serde::from_slice::<WorkspaceExport>(&packed).unwrap(), export_value);
+        assert!(packed.len() < json.len());
+        let (content_type, cbor) = exported("application/cbor").await;
+        assert_eq!(content_type, "application/cbor");
+        let unpacked = ciborium::from_reader::<WorkspaceExport, _>(&cbor[..]).unwrap();
+        assert_eq!(unpacked, export_value);
+        assert_eq!(export("text/html").await.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[tokio::test]
+    async fn atoms_are_copied_between_named_stacks() {
+        use crate::{
+            router,
+            test_util::{call, json},
+        };
+        use axum::http::{Method, StatusCode};
+        use serde_json::{json, Value};
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str = r#"{"atoms":{"0":{"element":8,"position":[0,0,0]},"1":{"element":1,"position":[1,0,0]},"2":{"element":1,"position":[0,1,0]}},"bonds":[[[0,1],1],[[0,2],1]],"groups":[]}"#;
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        call(&router, Method::POST, "/ws/test", BASE).await;
+        call(&router, Method::POST, "/ws/test/stacks?name=water", "").await;
+        call(&router, Method::POST, "/ws/test/stacks?name=dimer", "").await;
+
+        let copy = r#"{"atoms":[0,1],"translation":[0,0,3]}"#;
+        let uri = "/ws/test/stacks/dimer/import-from/water";
+        let response = call(&router, Method::POST, uri, copy).await;
+        assert_eq!(json::<Value>(response).await, json!({"0": 3, "1": 4}));
+        let response = call(&router, Method::GET, "/ws/test/stacks/dimer", "").await;
+        let dimer: Value = json(response).await;
+        assert_eq!(dimer["atoms"]["4"]["position"], json!([1., 0., 3.]));
+        let bonds = dimer["bonds"].as_array().unwrap();
+        assert!(bonds.contains(&json!([[4, 3], 1.])));
+        assert_eq!(bonds.len(), 3);
+        let response = call(&router, Method::POST, "/ws/test/stack/1/import-from/ice", copy).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // names come before indices, stack 1 has five atoms and the one
+        // called `1` the three of the base
+        call(&router, Method::POST, "/ws/test/stacks?name=1", "").await;
+        let everything = r#"{"atoms":[],"translation":[0,0,6]}"#;
+        let response = call(&router, Method::POST, "/ws/test/stack/0/import-from/1", everything);
+        assert_eq!(json::<HashMap<usize, usize>>(response.await).await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn imported_cells_grow_into_supercells() {
+        use crate::{
+            router,
+            test_util::{call, text},
+        };
+        use axum::http::{Method, StatusCode};
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const EMPTY: &str = r#"{"atoms":{},"bonds":[],"groups":[]}"#;
+        const CIF: &str = "data_po\n_cell_length_a 3.35\n_cell_length_b 3.35\n\
+            _cell_length_c 3.35\nloop_\n_atom_site_label\n_atom_site_fract_x\n\
+            _atom_site_fract_y\n_atom_site_fract_z\nPo1 0 0 0\n";
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        call(&router, Method::POST, "/ws/test", EMPTY).await;
+        call(&router, Method::POST, "/ws/test/stack?copies=0", "").await;
+        let (supercell, repeats) = ("/ws/test/stack/0/supercell", r#"{"repeats":[2,2,1]}"#);
+        let response = call(&router, Method::POST, supercell, repeats).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = call(&router, Method::POST, "/ws/test/stack/0/import/cif", CIF).await;
+        assert_eq!(text(response).await, "[0]");
+        let response = call(&router, Method::POST, supercell, repeats).await;
+        assert_eq!(text(response).await, "[1,2,3]");
+        let zero = r#"{"repeats":[0,1,1]}"#;
+        let response = call(&router, Method::POST, supercell, zero).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = call(&router, Method::GET, "/ws/test/stack/0/export/xyz", "").await;
+        let xyz = text(response).await;
+        assert!(xyz.starts_with("4\nLattice=\"6.700000 0.000000 0.000000 0.000000 6.700000"));
+        assert!(xyz.contains(r#"pbc="T T T" stack 0"#));
+    }
+
+    #[tokio::test]
+    async fn stacks_are_read_in_the_accepted_format() {
+        use crate::{
+            router,
+            test_util::{call, request, send, text},
+        };
+        use axum::http::{header, HeaderValue, Method, StatusCode};
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+
+        const BASE: &str = r#"{"atoms":{"0":{"element":8,"position":[0,0,0]},"1":{"element":1,"position":[1,0,0]}},"bonds":[[[0,1],1]],"groups":[]}"#;
+        let router = router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default());
+        let read = |uri: &str, accept: Option<&'static str>| {
+            let mut request = request(Method::GET, uri, "");
+            if let Some(accept) = accept {
+                request.headers_mut().insert(header::ACCEPT, HeaderValue::from_static(accept));
+            }
+            send(&router, request)
+        };
+        call(&router, Method::POST, "/ws/test", BASE).await;
+        call(&router, Method::POST, "/ws/test/stacks?name=water", "").await;
+
+        let response = read("/ws/test/stacks/water", None).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let accept = "text/html, chemical/x-xyz;q=0.5, chemical/x-pdb;q=0.9";
+        let response = read("/ws/test/stack/0", Some(accept)).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "chemical/x-pdb");
+        assert_eq!(response.headers()[header::VARY], "accept");
+        assert!(text(response).await.starts_with("HETATM    1  O1  UNL"));
+        let response = read("/ws/test/stack/0", Some("chemical/x-xyz")).await;
+        assert!(text(response).await.starts_with("2\nstack 0\nO 0.000000"));
+        let uri = "/ws/test/stacks/water?format=sdf";
+        let response = read(uri, Some("chemical/x-xyz")).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "chemical/x-mdl-sdfile");
+        let sdf = text(response).await;
+        assert!(sdf.starts_with("water\n") && sdf.ends_with("M  END\n$$$$\n"));
+        let response = read("/ws/test/stack/0", Some("text/html, */*;q=0.1")).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+
+        let response = read("/ws/test/stack/0", Some("text/html")).await;
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+        let response = read("/ws/test/stack/0", Some("chemical/x-pdb;q=0")).await;
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+        let response = read("/ws/test/stack/0?format=cml", None).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn documented_routes_are_routed() {
+        use crate::{
+            auth::authenticated,
+            router,
+            test_util::{call, json},
+        };
+        use axum::http::{Method, StatusCode};
+        use serde_json::Value;
+        use std::{
+            collections::{BTreeSet, HashMap},
+            sync::Arc,
+        };
+        use tokio::sync::RwLock;
+
+        let router = authenticated(
+            router(Arc::new(RwLock::new(HashMap::new())), 16, 16, 4, Default::default()),
+            Arc::new(RwLock::new(HashMap::new())),
+        );
+        const BASE: &str = r#"{"atoms":{},"bonds":[],"groups":[]}"#;
+        let response = call(&router, Method::POST, "/ws/test", BASE).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = call(&router, Method::GET, "/docs/", "").await;
+        assert_eq!(response.status(), StatusCode::OK);
 
-    axum::Server::bind(&listen)
-        .serve(router.into_make_service())
-        .await
-        .unwrap()
+        let spec: Value = json(call(&router, Method::GET, "/docs/openapi.json", "").await).await;
+        let paths = spec["paths"].as_object().unwrap();
+        // the paths given to `route` by `router`, whose routes before `routes`
+        // are nested under `/ws/:ws`, and by `authenticated`
+        let server = include_str!("main.rs");
+        let start = server.find("fn router(").unwrap();
+        let server = &server[start..server.find("#[tokio::main]").unwrap()];
+        let (nested, outer) = server.split_once("let routes = Router::new()").unwrap();
+        let auth = include_str!("auth.rs");
+        let auth = &auth[auth.find("pub fn authenticated").unwrap()..];
+        let route_paths = |source: &'static str| {
+            let routes = source.split(".route(").skip(1);
+            routes.map(|route| route.split('"').nth(1).unwrap().to_string())
+        };
+        let routed = route_paths(nested)
+            .map(|path| format!("/ws/:ws{}", path.trim_end_matches('/')))
+            .chain(route_paths(outer))
+            .chain(route_paths(auth))
+            .map(|path| {
+                let segments = path.split('/').map(|segment| match segment.strip_prefix(':') {
+                    Some(param) => format!("{{{param}}}"),
+                    None => segment.to_string(),
+                });
+                segments.collect::<Vec<_>>().join("/")
+            })
+            .collect::<BTreeSet<_>>();
+        let documented = paths.keys().cloned().collect::<BTreeSet<_>>();
+        let undocumented = routed.difference(&documented).collect::<Vec<_>>();
+        assert!(undocumented.is_empty(), "Routed, not documented: {undocumented:?}");
+        let unrouted = documented.difference(&routed).collect::<Vec<_>>();
+        assert!(unrouted.is_empty(), "Documented, not routed: {unrouted:?}");
+        // every schema and response referred to is among the components
+        let text = spec.to_string();
+        for reference in text.split(r#""$ref":""#).skip(1) {
+            let reference = &reference[..reference.find('"').unwrap()];
+            let pointer = reference.trim_start_matches('#');
+            assert!(spec.pointer(pointer).is_some(), "{reference}");
+        }
+        for (path, operations) in paths {
+            for method in operations.as_object().unwrap().keys() {
+                let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+                // keep the workspace for the routes after its removal
+                let removal = path == "/ws/{ws}" && method == Method::DELETE;
+                let ws = if removal { "gone" } else { "test" };
+                let uri = path
+                    .replace("{ws}", ws)
+                    .replace("{idx}", "0")
+                    .replace("{a}", "0")
+                    .replace("{b}", "0")
+                    .replace(['{', '}'], "");
+                let response = call(&router, method.clone(), &uri, "").await;
+                let status = response.status();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                let error = serde_json::from_slice::<Value>(&body).unwrap_or_default();
+                // the router answers paths it doesn't know with a bare 404
+                let unrouted = status == StatusCode::NOT_FOUND && error["code"] == "NotFound";
+                assert!(!unrouted && status != StatusCode::METHOD_NOT_ALLOWED, "{method} {path}");
+            }
+        }
+    }
 }
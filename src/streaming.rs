@@ -0,0 +1,251 @@
+use std::io::{self, Write};
+
+use axum::{
+    body::{Bytes, StreamBody},
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use flate2::{write::GzEncoder, Compression};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+/// Bytes sent to the client at a time
+const CHUNK: usize = 1 << 16;
+/// Chunks serialized ahead of the client
+const CHUNKS_AHEAD: usize = 4;
+
+/// Hands what is written to it to a response body, in chunks.
+struct ChunkWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn send(&mut self) -> io::Result<()> {
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK));
+        self.sender
+            .blocking_send(Ok(chunk.into()))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Client went away"))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.buffer.is_empty() {
+            true => Ok(()),
+            false => self.send(),
+        }
+    }
+}
+
+/// Of `media_types`, the one an `Accept` header prefers, by quality and
+/// then by order. Wildcards and a missing header mean `default`; `None` if
+/// the header names types but none of these.
+pub fn accepted<T: Copy>(headers: &HeaderMap, media_types: &[(&str, T)], default: T) -> Option<T> {
+    let ranges = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter(|range| !range.trim().is_empty())
+        .collect::<Vec<_>>();
+    if ranges.is_empty() {
+        return Some(default);
+    }
+    let mut best = None;
+    for range in ranges {
+        let mut parts = range.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let quality = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .map_or(Some(1.), |q| q.parse::<f32>().ok());
+        let format = match name.as_str() {
+            "*/*" | "application/*" => Some(default),
+            name => media_types
+                .iter()
+                .find(|(media_type, _)| *media_type == name)
+                .map(|(_, format)| *format),
+        };
+        if let (Some(format), Some(quality)) = (format, quality) {
+            let better = best.is_none_or(|(_, best)| quality > best);
+            if quality > 0. && better {
+                best = Some((format, quality));
+            }
+        }
+    }
+    best.map(|(format, _)| format)
+}
+
+/// Whether an `Accept-Encoding` header allows gzip, by name or as `*`.
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| {
+                let quality = param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok());
+                quality == Some(0.)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+}
+
+/// Serializations a streamed body can be sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFormat {
+    Json,
+    /// MessagePack, with structs as maps so fields keep their names
+    MessagePack,
+    Cbor,
+}
+
+impl BodyFormat {
+    pub const MEDIA_TYPES: [(&'static str, Self); 4] = [
+        ("application/json", Self::Json),
+        ("application/msgpack", Self::MessagePack),
+        ("application/x-msgpack", Self::MessagePack),
+        ("application/cbor", Self::Cbor),
+    ];
+
+    fn media_type(self) -> &'static str {
+        let (media_type, _) = Self::MEDIA_TYPES
+            .into_iter()
+            .find(|(_, format)| *format == self)
+            .expect("Every format has a media type");
+        media_type
+    }
+
+    /// The format an `Accept` header prefers, JSON by default, see
+    /// `accepted`.
+    pub fn accepted(headers: &HeaderMap) -> Option<Self> {
+        accepted(headers, &Self::MEDIA_TYPES, Self::Json)
+    }
+
+    fn write<T: Serialize, W: Write>(self, value: &T, mut writer: W) -> io::Result<()> {
+        match self {
+            Self::Json => serde_json::to_writer(writer, value).map_err(io::Error::from),
+            Self::MessagePack => {
+                rmp_serde::encode::write_named(&mut writer, value).map_err(io::Error::other)
+            }
+            Self::Cbor => ciborium::into_writer(value, writer).map_err(|err| match err {
+                ciborium::ser::Error::Io(err) => err,
+                ciborium::ser::Error::Value(message) => io::Error::other(message),
+            }),
+        }
+    }
+}
+
+/// `value` as a response body in `format`, serialized on a blocking thread
+/// while it is sent so the whole body is never held in memory, and gzipped
+/// when `gzip` is set. Since the status is sent first, a failure to
+/// serialize can only cut the body short.
+pub fn streamed<T>(value: T, format: BodyFormat, gzip: bool) -> Response
+where
+    T: Serialize + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::channel(CHUNKS_AHEAD);
+    tokio::task::spawn_blocking(move || {
+        let errors = sender.clone();
+        let mut sender = ChunkWriter {
+            sender,
+            buffer: Vec::with_capacity(CHUNK),
+        };
+        let written = if gzip {
+            let mut encoder = GzEncoder::new(sender, Compression::default());
+            format
+                .write(&value, &mut encoder)
+                .and_then(|_| encoder.finish())
+                .and_then(|mut sender| sender.flush())
+        } else {
+            format
+                .write(&value, &mut sender)
+                .and_then(|_| sender.flush())
+        };
+        if let Err(err) = written {
+            // reaches the client as an aborted body, if it is still there
+            let _ = errors.blocking_send(Err(err));
+        }
+    });
+    let body = StreamBody::new(futures::stream::poll_fn(move |cx| receiver.poll_recv(cx)));
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.media_type()),
+    );
+    headers.insert(
+        header::VARY,
+        HeaderValue::from_static("accept, accept-encoding"),
+    );
+    if gzip {
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    }
+    (headers, body).into_response()
+}
+
+mod test {
+    #[tokio::test]
+    async fn bodies_stream_in_the_accepted_format_and_encoding() {
+        use super::{accepts_gzip, streamed, BodyFormat};
+        use axum::http::{header, HeaderMap, HeaderValue};
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let accepting = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static(value));
+            accepts_gzip(&headers)
+        };
+        assert!(accepting("br, gzip;q=0.8"));
+        assert!(accepting("*"));
+        assert!(!accepting("gzip;q=0, deflate"));
+        assert!(!accepting("identity"));
+        assert!(!accepts_gzip(&HeaderMap::new()));
+        let format = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+            BodyFormat::accepted(&headers)
+        };
+        assert_eq!(format("application/cbor"), Some(BodyFormat::Cbor));
+        let preferred = format("application/json;q=0.5, application/x-msgpack");
+        assert_eq!(preferred, Some(BodyFormat::MessagePack));
+        assert_eq!(format("*/*"), Some(BodyFormat::Json));
+        assert_eq!(format("text/html"), None);
+
+        let value = (0..20000).map(|idx| idx % 7).collect::<Vec<_>>();
+        let plain = streamed(value.clone(), BodyFormat::Json, false);
+        assert!(plain.headers().get(header::CONTENT_ENCODING).is_none());
+        let body = hyper::body::to_bytes(plain.into_body()).await.unwrap();
+        assert_eq!(body, serde_json::to_vec(&value).unwrap());
+
+        let gzipped = streamed(value.clone(), BodyFormat::Json, true);
+        assert_eq!(gzipped.headers()[header::CONTENT_ENCODING], "gzip");
+        let body = hyper::body::to_bytes(gzipped.into_body()).await.unwrap();
+        assert!(body.len() < 4096);
+        let mut text = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut text).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<i32>>(&text).unwrap(), value);
+
+        let packed = streamed(value.clone(), BodyFormat::MessagePack, true);
+        assert_eq!(packed.headers()[header::CONTENT_TYPE], "application/msgpack");
+        let body = hyper::body::to_bytes(packed.into_body()).await.unwrap();
+        let unpacked: Vec<i32> = rmp_serde::from_read(GzDecoder::new(&body[..])).unwrap();
+        assert_eq!(unpacked, value);
+
+        let cbor = streamed(value.clone(), BodyFormat::Cbor, false);
+        assert_eq!(cbor.headers()[header::CONTENT_TYPE], "application/cbor");
+        let body = hyper::body::to_bytes(cbor.into_body()).await.unwrap();
+        assert_eq!(ciborium::from_reader::<Vec<i32>, _>(&body[..]).unwrap(), value);
+    }
+}
@@ -0,0 +1,47 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Method, Request},
+    response::Response,
+    Router,
+};
+use serde::de::DeserializeOwned;
+use tower::ServiceExt;
+
+/// A request with a JSON body, which may be empty.
+pub fn request(method: Method, uri: &str, body: impl Into<Body>) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .unwrap()
+}
+
+/// `request` from the holder of `token`.
+pub fn with_token(mut request: Request<Body>, token: &str) -> Request<Body> {
+    let bearer = HeaderValue::from_str(&format!("Bearer {token}")).unwrap();
+    request.headers_mut().insert(header::AUTHORIZATION, bearer);
+    request
+}
+
+/// The answer of `router` to `request`.
+pub async fn send(router: &Router, request: Request<Body>) -> Response {
+    router.clone().oneshot(request).await.unwrap()
+}
+
+/// The answer of `router` to a request with a JSON body, see `request`.
+pub async fn call(router: &Router, method: Method, uri: &str, body: impl Into<Body>) -> Response {
+    send(router, request(method, uri, body)).await
+}
+
+/// The body of `response`, as text.
+pub async fn text(response: Response) -> String {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+/// The body of `response`, read as JSON.
+pub async fn json<T: DeserializeOwned>(response: Response) -> T {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
@@ -0,0 +1,355 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A map whose values are unique as well as its keys, so it can be looked
+/// up in both directions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniqueValueMap<K: Eq + Hash, V: Eq + Hash> {
+    map: HashMap<K, V>,
+    reverse: HashMap<V, K>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InsertResult<K, V> {
+    /// The key had no value before
+    Inserted,
+    /// The key was holding the returned value, which has been replaced
+    Updated(V),
+    /// The value is held by the returned key; nothing was changed
+    Duplicated(K),
+}
+
+/// Values held by more than one key, each with all of its keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniqueValueError<K, V: Eq + Hash> {
+    pub conflicts: HashMap<V, Vec<K>>,
+}
+
+impl<K: fmt::Debug, V: Eq + Hash + fmt::Debug> fmt::Display for UniqueValueError<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut conflicts = self.conflicts.iter().peekable();
+        while let Some((value, keys)) = conflicts.next() {
+            write!(f, "{value:?} is held by keys {keys:?}")?;
+            if conflicts.peek().is_some() {
+                f.write_str(", ")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K: fmt::Debug, V: Eq + Hash + fmt::Debug> std::error::Error for UniqueValueError<K, V> {}
+
+impl<K: Eq + Hash, V: Eq + Hash> Default for UniqueValueMap<K, V> {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+            reverse: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Eq + Hash + Clone> UniqueValueMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build from a plain map, failing with every value found under more
+    /// than one key.
+    pub fn from_map(map: HashMap<K, V>) -> Result<Self, UniqueValueError<K, V>> {
+        let mut keys = HashMap::<V, Vec<K>>::with_capacity(map.len());
+        for (key, value) in &map {
+            keys.entry(value.clone()).or_default().push(key.clone());
+        }
+        if keys.values().any(|keys| keys.len() > 1) {
+            keys.retain(|_, keys| keys.len() > 1);
+            return Err(UniqueValueError { conflicts: keys });
+        }
+        let reverse = keys
+            .into_iter()
+            .map(|(value, mut keys)| (value, keys.remove(0)))
+            .collect();
+        Ok(Self { map, reverse })
+    }
+
+    pub fn data(&self) -> &HashMap<K, V> {
+        &self.map
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.map.iter()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    pub fn get_by_value(&self, value: &V) -> Option<&K> {
+        self.reverse.get(value)
+    }
+
+    pub fn contains_value(&self, value: &V) -> bool {
+        self.reverse.contains_key(value)
+    }
+
+    /// Like `insert`, but a value held by another key is an error carrying
+    /// that key. Otherwise returns the value the key was holding, if any.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, K> {
+        match self.insert(key, value.clone()) {
+            InsertResult::Inserted => Ok(None),
+            InsertResult::Updated(old) if old == value => Ok(None),
+            InsertResult::Updated(old) => Ok(Some(old)),
+            InsertResult::Duplicated(holder) => Err(holder),
+        }
+    }
+
+    /// Build from pairs, a later pair of a key replacing the earlier ones,
+    /// failing like `from_map`.
+    pub fn try_from_iter<I>(iter: I) -> Result<Self, UniqueValueError<K, V>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        Self::from_map(iter.into_iter().collect())
+    }
+
+    /// Insert many pairs at once, a later pair of a key replacing the
+    /// earlier ones. If any value would be held by more than one key,
+    /// nothing is inserted and the error has every such value with all of
+    /// the keys that would hold it, those already in the map included.
+    pub fn extend<I>(&mut self, iter: I) -> Result<(), UniqueValueError<K, V>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let entries = iter.into_iter().collect::<HashMap<_, _>>();
+        let mut keys = HashMap::<V, Vec<K>>::new();
+        for (key, value) in &entries {
+            keys.entry(value.clone()).or_default().push(key.clone());
+        }
+        for (value, keys) in keys.iter_mut() {
+            match self.reverse.get(value) {
+                Some(holder) if !entries.contains_key(holder) => keys.push(holder.clone()),
+                _ => {}
+            }
+        }
+        keys.retain(|_, keys| keys.len() > 1);
+        if !keys.is_empty() {
+            return Err(UniqueValueError { conflicts: keys });
+        }
+        for (key, value) in entries {
+            if let Some(old) = self.map.insert(key.clone(), value.clone()) {
+                // another key of the batch may have taken the old value already
+                if self.reverse.get(&old) == Some(&key) {
+                    self.reverse.remove(&old);
+                }
+            }
+            self.reverse.insert(value, key);
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> InsertResult<K, V> {
+        match self.reverse.get(&value) {
+            Some(holder) if holder == &key => InsertResult::Updated(value),
+            Some(holder) => InsertResult::Duplicated(holder.clone()),
+            None => {
+                self.reverse.insert(value.clone(), key.clone());
+                match self.map.insert(key, value) {
+                    Some(old) => {
+                        self.reverse.remove(&old);
+                        InsertResult::Updated(old)
+                    }
+                    None => InsertResult::Inserted,
+                }
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.map.remove(key)?;
+        self.reverse.remove(&value);
+        Some(value)
+    }
+
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let reverse = &mut self.reverse;
+        self.map.retain(|key, value| {
+            let kept = keep(key, value);
+            if !kept {
+                reverse.remove(value);
+            }
+            kept
+        })
+    }
+
+    /// Move every entry to the key given by `rekey`. Fails with the first
+    /// value whose new key collides with another entry.
+    pub fn map_keys<F>(self, mut rekey: F) -> Result<Self, V>
+    where
+        F: FnMut(&K) -> K,
+    {
+        let mut map = HashMap::with_capacity(self.map.len());
+        for (key, value) in self.map {
+            if let Some(collided) = map.insert(rekey(&key), value) {
+                return Err(collided);
+            }
+        }
+        let reverse = map
+            .iter()
+            .map(|(key, value)| (value.clone(), key.clone()))
+            .collect();
+        Ok(Self { map, reverse })
+    }
+}
+
+/// Serialized as the plain key to value map.
+impl<K: Eq + Hash + Serialize, V: Eq + Hash + Serialize> Serialize for UniqueValueMap<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.map.serialize(serializer)
+    }
+}
+
+/// Deserialized through `from_map`, so a value held by two keys is an error.
+impl<'de, K, V> Deserialize<'de> for UniqueValueMap<K, V>
+where
+    K: Eq + Hash + Clone + fmt::Debug + Deserialize<'de>,
+    V: Eq + Hash + Clone + fmt::Debug + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let map = HashMap::<K, V>::deserialize(deserializer)?;
+        Self::from_map(map).map_err(D::Error::custom)
+    }
+}
+
+impl<K: Eq + Hash, V: Eq + Hash> IntoIterator for UniqueValueMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = std::collections::hash_map::IntoIter<K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.into_iter()
+    }
+}
+
+mod test {
+    #[test]
+    fn lookup_by_value() {
+        use crate::{InsertResult, UniqueValueMap};
+
+        let mut names = UniqueValueMap::new();
+        assert_eq!(names.insert(1, "CA"), InsertResult::Inserted);
+        assert_eq!(names.get_by_value(&"CA"), Some(&1));
+
+        assert_eq!(names.insert(1, "CB"), InsertResult::Updated("CA"));
+        assert_eq!(names.get_by_value(&"CA"), None);
+        assert_eq!(names.get_by_value(&"CB"), Some(&1));
+
+        assert_eq!(names.insert(2, "CB"), InsertResult::Duplicated(1));
+        assert_eq!(names.get(&2), None);
+
+        assert_eq!(names.remove(&1), Some("CB"));
+        assert_eq!(names.get_by_value(&"CB"), None);
+        assert!(names.is_empty());
+    }
+
+    #[test]
+    fn try_insert_fails_on_shared_values() {
+        use crate::UniqueValueMap;
+
+        let mut names = UniqueValueMap::new();
+        assert_eq!(names.try_insert(1, "CA".to_string()), Ok(None));
+        assert_eq!(names.try_insert(1, "CA".to_string()), Ok(None));
+        assert_eq!(names.try_insert(2, "CB".to_string()), Ok(None));
+        assert_eq!(names.try_insert(2, "CA".to_string()), Err(1));
+        assert_eq!(
+            names.try_insert(1, "N".to_string()),
+            Ok(Some("CA".to_string()))
+        );
+
+        assert!(names.contains_value(&"N".to_string()));
+        assert!(!names.contains_value(&"CA".to_string()));
+        assert_eq!(names.get(&2).map(String::as_str), Some("CB"));
+        let mut entries = names.iter().collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![(&1, &"N".to_string()), (&2, &"CB".to_string())]
+        );
+
+        let mut keyed_by_name = UniqueValueMap::new();
+        keyed_by_name.insert("CA".to_string(), 1);
+        assert_eq!(keyed_by_name.get("CA"), Some(&1));
+    }
+
+    #[test]
+    fn from_map_rejects_shared_values() {
+        use crate::UniqueValueMap;
+        use std::collections::HashMap;
+
+        let mut conflicts =
+            UniqueValueMap::from_map(HashMap::from([(1, "CA"), (2, "CA"), (3, "CB"), (4, "CA")]))
+                .unwrap_err()
+                .conflicts;
+        conflicts.values_mut().for_each(|keys| keys.sort());
+        assert_eq!(conflicts, HashMap::from([("CA", vec![1, 2, 4])]));
+    }
+
+    #[test]
+    fn bulk_inserts_are_all_or_nothing() {
+        use crate::UniqueValueMap;
+        use std::collections::HashMap;
+
+        let mut names = UniqueValueMap::try_from_iter([(1, "CA"), (2, "CB"), (1, "N")]).unwrap();
+        assert_eq!(names.get(&1), Some(&"N"));
+        assert!(UniqueValueMap::try_from_iter([(1, "CA"), (2, "CA")]).is_err());
+
+        let mut conflicts = names
+            .extend([(3, "CB"), (4, "CB"), (5, "O"), (6, "N")])
+            .unwrap_err()
+            .conflicts;
+        conflicts.values_mut().for_each(|keys| keys.sort());
+        assert_eq!(conflicts, HashMap::from([("CB", vec![2, 3, 4]), ("N", vec![1, 6])]));
+        assert_eq!(names.len(), 2);
+
+        // swapping the values of two keys is no conflict
+        names.extend([(1, "CB"), (2, "N"), (3, "O")]).unwrap();
+        assert_eq!(names.get_by_value(&"CB"), Some(&1));
+        assert_eq!(names.get_by_value(&"N"), Some(&2));
+        assert_eq!(names.get_by_value(&"O"), Some(&3));
+        assert_eq!(names.len(), 3);
+    }
+
+    #[test]
+    fn deserialize_keeps_values_unique() {
+        use crate::UniqueValueMap;
+
+        let names: UniqueValueMap<usize, String> =
+            serde_json::from_str(r#"{"1": "CA", "2": "CB"}"#).unwrap();
+        assert_eq!(names.get_by_value(&"CB".to_string()), Some(&2));
+        assert_eq!(serde_json::to_value(&names).unwrap()["1"], "CA");
+
+        let duplicated =
+            serde_json::from_str::<UniqueValueMap<usize, String>>(r#"{"1": "CA", "3": "CA"}"#);
+        let message = duplicated.unwrap_err().to_string();
+        assert!(
+            message.starts_with(r#""CA" is held by keys ["#),
+            "{message}"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! lme-core for the browser: a frontend can edit a workspace locally and
+//! only send its export or operation log to the server now and then.
+//!
+//! Molecules, layers, operations and exports cross the boundary as plain
+//! JavaScript objects of the same shape as the JSON of the HTTP API. Errors
+//! of the core library are thrown as those objects too, e.g. `"NoSuchStack"`
+//! or `{"MissingAtoms": [4]}`.
+
+use lme_core::{
+    entity::{Layer, Molecule},
+    operation::{Operation, OperationOutput},
+    Workspace, WorkspaceExport,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use wasm_bindgen::prelude::*;
+
+fn from_js<T: DeserializeOwned>(value: JsValue) -> Result<T, JsValue> {
+    serde_wasm_bindgen::from_value(value).map_err(JsValue::from)
+}
+
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(value).map_err(JsValue::from)
+}
+
+fn throw<T: Serialize>(err: T) -> JsValue {
+    to_js(&err).unwrap_or_else(|err| err)
+}
+
+/// Overlay `layers` on `molecule` in order, without a workspace.
+#[wasm_bindgen]
+pub fn overlay(molecule: JsValue, layers: JsValue) -> Result<JsValue, JsValue> {
+    let molecule: Molecule = from_js(molecule)?;
+    let layers: Vec<Layer> = from_js(layers)?;
+    let molecule = layers
+        .iter()
+        .try_fold(molecule, |molecule, layer| layer.filter(molecule))
+        .map_err(throw)?;
+    to_js(&molecule)
+}
+
+/// A workspace kept in the page.
+#[wasm_bindgen]
+pub struct Editor(Workspace);
+
+#[wasm_bindgen]
+impl Editor {
+    #[wasm_bindgen(constructor)]
+    pub fn new(base: JsValue) -> Result<Editor, JsValue> {
+        Ok(Self(Workspace::new(from_js(base)?)))
+    }
+
+    /// Rebuild a workspace from an export, such as one the server saved.
+    pub fn import(export: JsValue) -> Result<Editor, JsValue> {
+        let export: WorkspaceExport = from_js(export)?;
+        Workspace::try_from(&export).map(Self).map_err(throw)
+    }
+
+    pub fn export(&self) -> Result<JsValue, JsValue> {
+        to_js(&WorkspaceExport::try_from(&self.0).map_err(throw)?)
+    }
+
+    /// Create `copies + 1` empty stacks and return the first one's index.
+    #[wasm_bindgen(js_name = createStack)]
+    pub fn create_stack(&mut self, copies: usize) -> Result<usize, JsValue> {
+        match self
+            .0
+            .apply(Operation::CreateStack { copies })
+            .map_err(throw)?
+        {
+            OperationOutput::Stack(idx) => Ok(idx),
+            output => unreachable!("CreateStack returned {output:?}"),
+        }
+    }
+
+    /// Add `layers` on top of stack `stack`.
+    pub fn write(&mut self, stack: usize, layers: JsValue) -> Result<(), JsValue> {
+        let operation = Operation::AddLayers {
+            stack_idx: stack,
+            layers: from_js(layers)?,
+        };
+        self.0.apply(operation).map(|_| ()).map_err(throw)
+    }
+
+    /// The molecule stack `stack` builds.
+    pub fn resolve(&self, stack: usize) -> Result<JsValue, JsValue> {
+        to_js(&self.0.read(stack).map_err(throw)?)
+    }
+
+    /// Apply any operation and return its output.
+    pub fn apply(&mut self, operation: JsValue) -> Result<JsValue, JsValue> {
+        let output = self.0.apply(from_js(operation)?).map_err(throw)?;
+        to_js(&output)
+    }
+
+    pub fn undo(&mut self, stack: usize) -> Result<(), JsValue> {
+        self.0.undo(stack).map_err(throw)
+    }
+
+    pub fn redo(&mut self, stack: usize) -> Result<(), JsValue> {
+        self.0.redo(stack).map_err(throw)
+    }
+}